@@ -1,14 +1,24 @@
-use terminal_emulator::{render_grid, MouseMode, TerminalGrid};
+use terminal_emulator::{
+    encode_function_key, encode_key, parse_color, prepare_paste, prompt_marker_quads,
+    render_grid, scroll_mode_action, scrollbar_quad, scrollbar_thumb, CellInfo,
+    ClipboardType, Clipboards, FunctionKey, Key, KeyModifiers, KeyboardProtocol,
+    MouseMode, NavKey, OutputQueue, PasteMode, ReadBuffer, ScrollAction, ScrollConfig,
+    Scrollbar, TerminalGrid, DEFAULT_LINES_PER_NOTCH, MAX_GRID_DIMENSION,
+    OUTPUT_BYTES_PER_FRAME,
+};
 
-use jni::objects::{JClass, JObject, JString};
-use jni::sys::{jboolean, jfloat, jint};
+use jni::objects::{JClass, JIntArray, JObject, JString};
+use jni::sys::{jboolean, jfloat, jint, jlong};
 use jni::JNIEnv;
 use raw_window_handle::{
     AndroidDisplayHandle, AndroidNdkWindowHandle, RawDisplayHandle, RawWindowHandle,
 };
+use std::ffi::{CStr, CString};
+use std::os::fd::{AsRawFd, OwnedFd};
 use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
 use std::sync::mpsc;
-use std::sync::Mutex;
+use std::sync::{Mutex, Once};
 use std::thread;
 use sugarloaf::layout::RootStyle;
 use sugarloaf::{
@@ -19,6 +29,43 @@ use tungstenite::Message;
 
 static TERMINAL_MANAGER: Mutex<Option<TerminalManager>> = Mutex::new(None);
 
+/// Active log level as a `log::LevelFilter` discriminant. Adjustable at
+/// runtime via `setLogLevel` so verbose logs can be captured without a rebuild.
+static LOG_LEVEL: AtomicUsize = AtomicUsize::new(log::LevelFilter::Info as usize);
+
+/// How pastes are treated when the app has not enabled bracketed paste.
+/// Global so it survives surface recreation, like the log level.
+static PASTE_MODE: Mutex<PasteMode> = Mutex::new(PasteMode::Sanitize);
+
+/// Text copied from the grid, per clipboard. Android has no primary
+/// selection, so it is kept here for middle-click pastes.
+static CLIPBOARDS: Mutex<Clipboards> = Mutex::new(Clipboards::new());
+
+/// Ask servers to deflate PTY output on new connections, to save data on
+/// metered networks
+static COMPRESSION: AtomicBool = AtomicBool::new(false);
+
+/// Draw a scrollbar overlay while scrolled back, set with `setScrollbar`
+static SCROLLBAR: AtomicBool = AtomicBool::new(true);
+
+/// Scroll speed and direction, set with `setScrollConfig`
+static SCROLL_CONFIG: Mutex<ScrollConfig> = Mutex::new(ScrollConfig::DEFAULT);
+
+/// Scrolling on the alternate screen sends arrow keys when the application
+/// takes no mouse reports, set with `setAlternateScroll`
+static ALTERNATE_SCROLL: AtomicBool = AtomicBool::new(true);
+
+/// Response header a server sets when it will send compressed frames
+const COMPRESSION_HEADER: &str = "x-omni-compression";
+
+/// Word separators set through `setWordSeparators`, applied to sessions
+/// created later. `None` keeps the grid default.
+static WORD_SEPARATORS: Mutex<Option<String>> = Mutex::new(None);
+
+/// Cursor color set through `setCursorColor`, applied to sessions created
+/// later. `None` draws the cursor by inversion.
+static CURSOR_COLOR: Mutex<Option<[f32; 4]>> = Mutex::new(None);
+
 /// Sessions preserved across surface destruction (app minimized, etc.).
 /// When the GPU surface is torn down we move live sessions here so they
 /// survive until a new surface is created.
@@ -30,6 +77,22 @@ struct PreservedState {
     shell_counter: usize,
 }
 
+/// Run `f` on the live sessions, whether they belong to the manager or are
+/// preserved while the surface is gone (app in the background).
+fn with_sessions<R>(f: impl FnOnce(&mut [Session]) -> R) -> Option<R> {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(ref mut m) = *mgr {
+        return Some(f(&mut m.sessions));
+    }
+    drop(mgr);
+    let mut preserved = PRESERVED_SESSIONS.lock().unwrap();
+    preserved.as_mut().map(|state| f(&mut state.sessions))
+}
+
+/// Minimum time between bell events reported for one session, so a burst
+/// of bells raises a single notification.
+const BELL_DEBOUNCE: std::time::Duration = std::time::Duration::from_secs(3);
+
 /// Messages sent from JNI to the PTY/WebSocket thread.
 enum PtyCommand {
     /// Send raw bytes to the PTY (keyboard input).
@@ -40,11 +103,93 @@ enum PtyCommand {
     Disconnect,
 }
 
+/// Sending half of a session's command channel. Every send also signals an
+/// eventfd so the worker thread can block in `poll()` instead of spinning.
+struct CommandSender {
+    tx: mpsc::Sender<PtyCommand>,
+    wake: std::sync::Arc<EventFd>,
+}
+
+impl CommandSender {
+    fn send(&self, cmd: PtyCommand) -> Result<(), mpsc::SendError<PtyCommand>> {
+        self.tx.send(cmd)?;
+        self.wake.notify();
+        Ok(())
+    }
+}
+
+impl Drop for CommandSender {
+    fn drop(&mut self) {
+        // Let the worker observe the disconnected channel promptly
+        self.wake.notify();
+    }
+}
+
+/// Receiving half of a session's command channel, owned by the worker thread.
+struct CommandReceiver {
+    rx: mpsc::Receiver<PtyCommand>,
+    wake: std::sync::Arc<EventFd>,
+}
+
+impl CommandReceiver {
+    fn try_recv(&self) -> Result<PtyCommand, mpsc::TryRecvError> {
+        self.rx.try_recv()
+    }
+}
+
+/// Create a command channel whose receiver can be waited on with `poll()`.
+fn command_channel() -> (CommandSender, CommandReceiver) {
+    let (tx, rx) = mpsc::channel::<PtyCommand>();
+    let wake = std::sync::Arc::new(EventFd::new().expect("eventfd failed"));
+    (
+        CommandSender {
+            tx,
+            wake: wake.clone(),
+        },
+        CommandReceiver { rx, wake },
+    )
+}
+
+/// Non-blocking, close-on-exec eventfd used to wake threads blocked in `poll()`.
+struct EventFd(i32);
+
+impl EventFd {
+    fn new() -> std::io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self(fd))
+    }
+
+    fn notify(&self) {
+        let one: u64 = 1;
+        unsafe {
+            libc::write(self.0, (&raw const one).cast(), std::mem::size_of::<u64>());
+        }
+    }
+
+    /// Reset the counter after a wakeup.
+    fn drain(&self) {
+        let mut value: u64 = 0;
+        unsafe {
+            libc::read(self.0, (&raw mut value).cast(), std::mem::size_of::<u64>());
+        }
+    }
+}
+
+impl Drop for EventFd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
 struct Session {
     grid: TerminalGrid,
-    parser: copa::Parser,
+    /// PTY output received but not yet parsed into `grid`
+    output: OutputQueue,
     /// Send commands to the WebSocket/PTY thread.
-    ws_tx: Option<mpsc::Sender<PtyCommand>>,
+    ws_tx: Option<CommandSender>,
     /// Receive PTY output from the WebSocket/PTY thread.
     ws_rx: Option<mpsc::Receiver<Vec<u8>>>,
     /// Session UUID (set after "created" response, remote only).
@@ -63,13 +208,34 @@ struct Session {
     label: String,
     /// Whether the backing process/connection has exited.
     exited: bool,
+    /// PID of the shell for plain local sessions (not proot or remote).
+    child_pid: Option<i32>,
+    /// Bell rung since the last `takeBellEvent`.
+    bell_pending: bool,
+    /// When a bell event was last reported, for debouncing.
+    last_bell_event: Option<std::time::Instant>,
+    /// Keys navigate scrollback instead of reaching the PTY.
+    scroll_mode: bool,
+    /// Last scroll mode search, repeated by `n`.
+    last_search: Option<String>,
+    /// Fraction of a line scrolled but not yet moved.
+    scroll_carry: f32,
+    /// Keyboard and paste input reach the PTY. Cleared by `setInputEnabled`
+    /// for a display-only viewer; output, selection and mouse reporting
+    /// carry on.
+    input_enabled: bool,
 }
 
 impl Session {
     fn new(cols: usize, rows: usize, label: String) -> Self {
+        let mut grid = TerminalGrid::new(cols, rows);
+        if let Some(separators) = WORD_SEPARATORS.lock().unwrap().as_deref() {
+            grid.set_word_separators(separators);
+        }
+        grid.set_cursor_color(*CURSOR_COLOR.lock().unwrap());
         Self {
-            grid: TerminalGrid::new(cols, rows),
-            parser: copa::Parser::new(),
+            grid,
+            output: OutputQueue::new(),
             ws_tx: None,
             ws_rx: None,
             session_id: None,
@@ -80,11 +246,74 @@ impl Session {
             files_dir: None,
             label,
             exited: false,
+            child_pid: None,
+            bell_pending: false,
+            last_bell_event: None,
+            scroll_mode: false,
+            last_search: None,
+            scroll_carry: 0.0,
+            input_enabled: true,
+        }
+    }
+
+    /// Handle a key in scroll mode. The search prompt (`/`) is up to the UI,
+    /// which calls `searchScrollback`.
+    fn scroll_mode_key(&mut self, key: NavKey) {
+        match scroll_mode_action(key) {
+            Some(ScrollAction::SearchNext) => {
+                if let Some(query) = &self.last_search {
+                    self.grid.search_backward(query);
+                }
+            }
+            Some(ScrollAction::Exit) => self.exit_scroll_mode(),
+            Some(action) => {
+                action.apply(&mut self.grid);
+            }
+            None => {}
+        }
+        self.dirty = true;
+    }
+
+    fn exit_scroll_mode(&mut self) {
+        self.scroll_mode = false;
+        self.grid.scroll_to_bottom();
+        self.grid.selection_clear();
+        self.dirty = true;
+    }
+
+    /// Blank the screen and home the cursor, dropping the scrollback too
+    /// when `scrollback` is set. With `redraw`, Ctrl+L is sent so the shell
+    /// reprints its prompt.
+    fn clear(&mut self, scrollback: bool, redraw: bool) {
+        self.grid.clear(scrollback);
+        self.dirty = true;
+        if redraw {
+            self.send_typed(b"\x0c");
         }
     }
 
-    /// Drain pending PTY/WebSocket output into the grid.
-    fn drain_output(&mut self) {
+    /// Directory a new local shell opened from this one should start in:
+    /// the shell-reported (OSC 7) directory, else the process's current one.
+    /// Only plain local shells qualify, since remote and proot paths mean
+    /// nothing on the host, and directories that no longer exist are skipped.
+    fn inheritable_cwd(&self) -> Option<String> {
+        let pid = self.child_pid?;
+        let proc_cwd = || {
+            std::fs::read_link(format!("/proc/{pid}/cwd"))
+                .ok()
+                .map(|path| path.to_string_lossy().into_owned())
+        };
+        let is_dir = |dir: &String| std::path::Path::new(dir).is_dir();
+        self.grid
+            .cwd()
+            .map(str::to_string)
+            .filter(is_dir)
+            .or_else(|| proc_cwd().filter(is_dir))
+    }
+
+    /// Receive pending PTY output and parse up to `budget` bytes of it. Any
+    /// remainder is parsed on later calls, in order.
+    fn drain_output(&mut self, budget: usize) {
         let mut incoming: Vec<Vec<u8>> = Vec::new();
         if let Some(ref rx) = self.ws_rx {
             loop {
@@ -100,23 +329,55 @@ impl Session {
         }
         for data in incoming {
             if self.local_mode {
-                self.parser.advance(&mut self.grid, &data);
-                self.dirty = true;
+                self.output.push(&data);
             } else {
+                // Binary PTY output: first 16 bytes = session UUID. Checked
+                // before sniffing for JSON, as a frame can be valid UTF-8
+                // starting with `{` too.
+                let is_output = self
+                    .session_id
+                    .is_some_and(|sid| data.len() > 16 && data[..16] == sid);
+                if is_output {
+                    self.output.push(&data[16..]);
+                    continue;
+                }
                 if let Ok(text) = std::str::from_utf8(&data) {
                     if text.starts_with('{') {
                         self.handle_control_message(text);
                         continue;
                     }
                 }
-                // Binary PTY output: first 16 bytes = session UUID
-                if data.len() > 16 {
-                    let pty_data = &data[16..];
-                    self.parser.advance(&mut self.grid, pty_data);
-                    self.dirty = true;
-                }
+                log::debug!("Dropping {} byte frame for another session", data.len());
             }
         }
+        if !self.output.is_empty() {
+            self.output.advance(&mut self.grid, budget);
+            self.dirty = true;
+        }
+        if self.grid.take_bell() {
+            self.bell_pending = true;
+        }
+        // Answer queries found in the output (size reports, etc.)
+        if !self.grid.pending_writes.is_empty() {
+            let data = std::mem::take(&mut self.grid.pending_writes);
+            self.send_input(&data);
+        }
+    }
+
+    /// Consume a pending bell, reporting it unless one was reported within
+    /// `BELL_DEBOUNCE`.
+    fn take_bell_event(&mut self, now: std::time::Instant) -> bool {
+        if !std::mem::take(&mut self.bell_pending) {
+            return false;
+        }
+        if self
+            .last_bell_event
+            .is_some_and(|last| now.duration_since(last) < BELL_DEBOUNCE)
+        {
+            return false;
+        }
+        self.last_bell_event = Some(now);
+        true
     }
 
     fn handle_control_message(&mut self, text: &str) {
@@ -149,6 +410,34 @@ impl Session {
         }
     }
 
+    /// Paste `text`, framed with bracketed paste when the application
+    /// enabled it and sanitized per `mode` otherwise. Returns false without
+    /// sending when a multi-line paste in warn mode is not yet `confirmed`.
+    fn paste(&mut self, text: &str, mode: PasteMode, confirmed: bool) -> bool {
+        let bracketed = self.grid.bracketed_paste();
+        let paste = prepare_paste(text, bracketed, mode);
+        if paste.multiline && !bracketed {
+            if mode == PasteMode::Warn && !confirmed {
+                return false;
+            }
+            log::warn!("Pasting multi-line text without bracketed paste ({mode:?})");
+        }
+        self.send_typed(&paste.bytes);
+        true
+    }
+
+    /// Send typed or pasted input and snap to the bottom, unless input is
+    /// disabled. Returns whether the input was sent.
+    fn send_typed(&mut self, data: &[u8]) -> bool {
+        if !self.input_enabled {
+            return false;
+        }
+        self.send_input(data);
+        // Snap to bottom on user input
+        self.grid.scroll_to_bottom();
+        true
+    }
+
     fn send_input(&self, data: &[u8]) {
         if let Some(ref tx) = self.ws_tx {
             if self.local_mode {
@@ -197,6 +486,9 @@ struct TerminalManager {
     dims_confirmed: bool,
     /// Monotonic counter for local shell labels (avoids duplicates on close/reopen).
     shell_counter: usize,
+    /// Scrollbar overlay fade state, timed from `last_frame`
+    scrollbar: Scrollbar,
+    last_frame: std::time::Instant,
 }
 
 impl TerminalManager {
@@ -209,13 +501,25 @@ impl TerminalManager {
     }
 
     /// Create a new local shell session and switch to it. Returns the new session index.
-    fn create_local_session(&mut self, files_dir: &str, native_lib_dir: &str) -> usize {
+    /// Start the shell in `cwd` when given, otherwise in `$HOME`.
+    fn create_local_session(
+        &mut self,
+        files_dir: &str,
+        native_lib_dir: &str,
+        cwd: Option<&str>,
+    ) -> usize {
         let label = self.next_shell_label();
         let mut session = Session::new(self.total_cols, self.total_rows, label);
 
         session.files_dir = Some(files_dir.to_string());
-        let (cmd_tx, out_rx) =
-            spawn_local_pty(files_dir, native_lib_dir, self.total_cols, self.total_rows);
+        let (cmd_tx, out_rx, child_pid) = spawn_local_pty(
+            files_dir,
+            native_lib_dir,
+            cwd,
+            self.total_cols,
+            self.total_rows,
+        );
+        session.child_pid = child_pid;
         session.ws_tx = Some(cmd_tx);
         session.ws_rx = Some(out_rx);
         session.connected = true;
@@ -264,7 +568,7 @@ impl TerminalManager {
     }
 
     /// Create a new remote WebSocket session and switch to it. Returns the new session index.
-    fn create_remote_session(&mut self, url: &str) -> usize {
+    fn create_remote_session(&mut self, url: &str, trust: TlsTrust) -> usize {
         let label = url::Url::parse(url)
             .ok()
             .and_then(|u| u.host_str().map(|h| h.to_string()))
@@ -273,7 +577,7 @@ impl TerminalManager {
         let mut session = Session::new(self.total_cols, self.total_rows, label);
 
         let (cmd_tx, out_rx) =
-            spawn_ws_thread(url.to_string(), self.total_cols, self.total_rows);
+            spawn_ws_thread(url.to_string(), self.total_cols, self.total_rows, trust);
         session.ws_tx = Some(cmd_tx);
         session.ws_rx = Some(out_rx);
         session.connected = true;
@@ -325,13 +629,34 @@ impl TerminalManager {
         }
 
         // Drain output from all sessions (background tabs stay up to date)
+        let dims = self.sugarloaf.get_rich_text_dimensions(&self.rt_id);
         for session in &mut self.sessions {
-            session.drain_output();
+            session.grid.set_cell_size(dims.width, dims.height);
+            session.drain_output(OUTPUT_BYTES_PER_FRAME);
+        }
+
+        // Keep frames coming while the scrollbar fades out
+        let now = std::time::Instant::now();
+        let dt = now.duration_since(self.last_frame).as_secs_f32();
+        self.last_frame = now;
+        let scrolled_back = self
+            .active_session()
+            .is_some_and(|session| session.grid.display_offset > 0);
+        let scrollbar_opacity = if SCROLLBAR.load(Ordering::Relaxed) {
+            self.scrollbar.update(scrolled_back, dt)
+        } else {
+            0.0
+        };
+        if self.scrollbar.fading() {
+            if let Some(session) = self.active_session_mut() {
+                session.dirty = true;
+            }
         }
 
-        // Render only the active session
+        // Render only the active session, holding frames while it is in
+        // the middle of a synchronized update
         let needs_render = if let Some(session) = self.sessions.get(self.active) {
-            session.dirty || !session.connected
+            (session.dirty && !session.grid.synchronized_output()) || !session.connected
         } else {
             true
         };
@@ -350,23 +675,40 @@ impl TerminalManager {
             self.render_status_screen();
         }
 
-        // Center the grid horizontally: distribute leftover space equally
-        let pad_px = PADDING_DP * self.scale;
-        let dims = self.sugarloaf.get_rich_text_dimensions(&self.rt_id);
-        let cell_w = if dims.width > 0.0 {
-            dims.width
-        } else {
-            18.0 * 0.6 * self.scale
-        };
-        let text_width = self.total_cols as f32 * cell_w;
-        let leftover = self.surface_width - text_width - 2.0 * pad_px;
-        let x_offset = pad_px + (leftover / 2.0).max(0.0);
-
-        self.sugarloaf.set_objects(vec![Object::RichText(RichText {
+        let (x_offset, y_offset) = self.grid_origin();
+        let mut objects = vec![Object::RichText(RichText {
             id: self.rt_id,
-            position: [x_offset, 0.0],
+            position: [x_offset, y_offset],
             lines: None,
-        })]);
+        })];
+        let (cell_w, cell_h) = cell_size(&mut self.sugarloaf, &self.rt_id, self.scale);
+        let area = [
+            x_offset,
+            y_offset,
+            self.total_cols as f32 * cell_w,
+            self.total_rows as f32 * cell_h,
+        ];
+        let grid = self
+            .active_session()
+            .filter(|session| session.connected)
+            .map(|session| &session.grid);
+        if let Some(grid) = grid {
+            objects.extend(
+                prompt_marker_quads(grid, area, self.scale)
+                    .into_iter()
+                    .map(Object::Quad),
+            );
+        }
+        let thumb = grid.and_then(scrollbar_thumb);
+        if let Some(thumb) = thumb.filter(|_| scrollbar_opacity > 0.0) {
+            objects.push(Object::Quad(scrollbar_quad(
+                thumb,
+                scrollbar_opacity,
+                area,
+                self.scale,
+            )));
+        }
+        self.sugarloaf.set_objects(objects);
         self.sugarloaf.render();
 
         if let Some(session) = self.sessions.get_mut(self.active) {
@@ -374,6 +716,42 @@ impl TerminalManager {
         }
     }
 
+    /// Pixel position of the grid's top-left corner on the surface.
+    fn grid_origin(&mut self) -> (f32, f32) {
+        let cell = cell_size(&mut self.sugarloaf, &self.rt_id, self.scale);
+        let padding = PADDING.lock().unwrap().scaled(self.scale);
+        grid_origin(
+            self.surface_width,
+            self.surface_height,
+            (self.total_cols, self.total_rows),
+            cell,
+            padding,
+        )
+    }
+
+    /// Fit the grid to the surface and padding again, resizing every
+    /// session when the size changes.
+    fn regrid(&mut self) {
+        let (cols, rows) = calc_grid(
+            self.surface_width,
+            self.surface_height,
+            self.scale,
+            &mut self.sugarloaf,
+            &self.rt_id,
+        );
+        if cols != self.total_cols || rows != self.total_rows {
+            self.total_cols = cols;
+            self.total_rows = rows;
+            for session in &mut self.sessions {
+                session.grid.resize(cols, rows);
+                session.send_resize(cols, rows);
+            }
+        }
+        if let Some(session) = self.sessions.get_mut(self.active) {
+            session.dirty = true;
+        }
+    }
+
     fn render_status_screen(&mut self) {
         let green = FragmentStyle {
             color: [0.0, 0.85, 0.4, 1.0],
@@ -424,19 +802,34 @@ impl TerminalManager {
     }
 }
 
+/// How a `wss://` connection authenticates the server certificate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TlsTrust {
+    /// Verify the chain against the system CA store and check the hostname.
+    Verify,
+    /// Accept only a leaf certificate with this SHA-256 fingerprint, for
+    /// self-signed servers whose fingerprint is known ahead of time.
+    Pinned([u8; 32]),
+    /// Accept any certificate. Anyone on the network path can impersonate the
+    /// server and read or inject terminal traffic, so this is for trusted
+    /// development networks only.
+    Insecure,
+}
+
 /// Spawn a WebSocket client thread that connects to the server.
 fn spawn_ws_thread(
     ws_url: String,
     cols: usize,
     rows: usize,
-) -> (mpsc::Sender<PtyCommand>, mpsc::Receiver<Vec<u8>>) {
-    let (cmd_tx, cmd_rx) = mpsc::channel::<PtyCommand>();
+    trust: TlsTrust,
+) -> (CommandSender, mpsc::Receiver<Vec<u8>>) {
+    let (cmd_tx, cmd_rx) = command_channel();
     let (out_tx, out_rx) = mpsc::channel::<Vec<u8>>();
 
     thread::Builder::new()
         .name("ws-client".into())
         .spawn(move || {
-            ws_thread_main(&ws_url, cols, rows, &cmd_rx, &out_tx);
+            ws_thread_main(&ws_url, cols, rows, trust, &cmd_rx, &out_tx);
         })
         .expect("Failed to spawn WebSocket thread");
 
@@ -447,7 +840,8 @@ fn ws_thread_main(
     ws_url: &str,
     cols: usize,
     rows: usize,
-    cmd_rx: &mpsc::Receiver<PtyCommand>,
+    trust: TlsTrust,
+    cmd_rx: &CommandReceiver,
     out_tx: &mpsc::Sender<Vec<u8>>,
 ) {
     let max_retries: u32 = 3;
@@ -469,13 +863,37 @@ fn ws_thread_main(
     let addr = format!("{host}:{port}");
     let use_tls = parsed.scheme() == "wss";
 
+    let tls_setup = if use_tls {
+        let server_name = rustls::pki_types::ServerName::try_from(host.clone())
+            .map_err(|e| format!("Invalid TLS server name {host}: {e}"));
+        match server_name.and_then(|name| Ok((tls_client_config(trust)?, name))) {
+            Ok(setup) => Some(setup),
+            Err(e) => {
+                log::error!("{e}");
+                let _ = out_tx
+                    .send(br#"{"type":"error","message":"TLS setup failed"}"#.to_vec());
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
     loop {
         attempt += 1;
         log::info!("WebSocket connecting to {ws_url} (attempt {attempt}/{max_retries})");
 
+        let mut request_url = parsed.clone();
+        if COMPRESSION.load(Ordering::Relaxed) {
+            request_url
+                .query_pairs_mut()
+                .append_pair("compress", "deflate");
+        }
+
         // Resolve DNS
         log::info!("Resolving {addr}");
         use std::net::ToSocketAddrs;
+        use std::os::fd::AsRawFd;
         let sock_addr = match addr.to_socket_addrs() {
             Ok(mut addrs) => match addrs.next() {
                 Some(a) => a,
@@ -540,27 +958,24 @@ fn ws_thread_main(
         };
 
         // Upgrade to WebSocket (with TLS for wss://), run event loop
-        let clean_close = if use_tls {
-            let _ = rustls::crypto::ring::default_provider().install_default();
-            let tls_config = rustls::ClientConfig::builder()
-                .dangerous()
-                .with_custom_certificate_verifier(std::sync::Arc::new(AcceptAnyCert))
-                .with_no_client_auth();
+        let clean_close = if let Some((tls_config, server_name)) = &tls_setup {
             let connector = rustls::StreamOwned::new(
                 rustls::ClientConnection::new(
-                    std::sync::Arc::new(tls_config),
-                    host.as_str()
-                        .try_into()
-                        .unwrap_or_else(|_| "localhost".try_into().unwrap()),
+                    std::sync::Arc::clone(tls_config),
+                    server_name.clone(),
                 )
                 .expect("failed to create TLS connection"),
                 tcp_stream,
             );
-            match tungstenite::client(parsed.as_str(), connector) {
-                Ok((mut ws, _response)) => {
+            match tungstenite::client(request_url.as_str(), connector) {
+                Ok((mut ws, response)) => {
                     let _ = ws.get_ref().sock.set_nonblocking(true);
+                    let socket_fd = ws.get_ref().sock.as_raw_fd();
                     attempt = 0; // Reset on successful connection
-                    ws_event_loop(&mut ws, cols, rows, cmd_rx, out_tx)
+                    let compressed = accepts_compression(&response);
+                    ws_event_loop(
+                        &mut ws, socket_fd, cols, rows, compressed, cmd_rx, out_tx,
+                    )
                 }
                 Err(e) => {
                     log::error!("WebSocket handshake failed for {ws_url}: {e}");
@@ -578,11 +993,15 @@ fn ws_thread_main(
                 }
             }
         } else {
-            match tungstenite::client(parsed.as_str(), tcp_stream) {
-                Ok((mut ws, _response)) => {
+            match tungstenite::client(request_url.as_str(), tcp_stream) {
+                Ok((mut ws, response)) => {
                     let _ = ws.get_ref().set_nonblocking(true);
+                    let socket_fd = ws.get_ref().as_raw_fd();
                     attempt = 0; // Reset on successful connection
-                    ws_event_loop(&mut ws, cols, rows, cmd_rx, out_tx)
+                    let compressed = accepts_compression(&response);
+                    ws_event_loop(
+                        &mut ws, socket_fd, cols, rows, compressed, cmd_rx, out_tx,
+                    )
                 }
                 Err(e) => {
                     log::error!("WebSocket handshake failed for {ws_url}: {e}");
@@ -628,16 +1047,50 @@ fn ws_thread_main(
     log::info!("WebSocket thread exiting");
 }
 
+/// Whether the server agreed to send compressed output frames. Servers that
+/// predate compression ignore the request and send plain frames.
+fn accepts_compression(response: &tungstenite::handshake::client::Response) -> bool {
+    response
+        .headers()
+        .get(COMPRESSION_HEADER)
+        .is_some_and(|value| value == "deflate")
+}
+
+/// Turn a compressed-connection output frame (session id, flag byte, then
+/// output that is deflated when the flag is 1) back into a plain one.
+fn inflate_frame(frame: &[u8]) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    if frame.len() <= 16 {
+        return Ok(frame.to_vec());
+    }
+    let (id, body) = frame.split_at(16);
+    let mut plain = id.to_vec();
+    match body.split_first() {
+        Some((1, deflated)) => {
+            flate2::read::DeflateDecoder::new(deflated).read_to_end(&mut plain)?;
+        }
+        Some((_, output)) => plain.extend_from_slice(output),
+        None => {}
+    }
+    Ok(plain)
+}
+
 /// Run the WebSocket event loop. Return `true` for a clean (user-initiated)
 /// close, `false` for an unexpected disconnection that may warrant a retry.
+/// `compressed` says output frames carry the compression flag byte.
+///
+/// Blocks in `poll()` on the socket and the command eventfd between events.
 fn ws_event_loop<S: std::io::Read + std::io::Write>(
     ws: &mut tungstenite::WebSocket<S>,
+    socket_fd: std::os::fd::RawFd,
     cols: usize,
     rows: usize,
-    cmd_rx: &mpsc::Receiver<PtyCommand>,
+    compressed: bool,
+    cmd_rx: &CommandReceiver,
     out_tx: &mpsc::Sender<Vec<u8>>,
 ) -> bool {
-    log::info!("WebSocket connected");
+    log::info!("WebSocket connected (compressed output: {compressed})");
 
     // Send create session request
     let create_msg = format!(r#"{{"type":"create","cols":{cols},"rows":{rows}}}"#);
@@ -646,56 +1099,190 @@ fn ws_event_loop<S: std::io::Read + std::io::Write>(
         return false;
     }
 
+    // Set when a send only partially reached the socket and needs flushing
+    let mut pending_flush = false;
+
     loop {
         // Check for commands from JNI
-        match cmd_rx.try_recv() {
-            Ok(PtyCommand::Input(data)) => {
-                if ws.send(Message::Binary(data.into())).is_err() {
-                    log::error!("WebSocket send failed");
+        loop {
+            let sent = match cmd_rx.try_recv() {
+                Ok(PtyCommand::Input(data)) => ws.send(Message::Binary(data.into())),
+                Ok(PtyCommand::Resize(json)) => ws.send(Message::Text(json.into())),
+                Ok(PtyCommand::Disconnect) => {
+                    let _ = ws.close(None);
+                    return true;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => return true,
+                Err(mpsc::TryRecvError::Empty) => break,
+            };
+            match sent {
+                Ok(()) => {}
+                // Frame is queued in tungstenite's write buffer
+                Err(tungstenite::Error::Io(ref e))
+                    if e.kind() == std::io::ErrorKind::WouldBlock =>
+                {
+                    pending_flush = true;
+                }
+                Err(e) => {
+                    log::error!("WebSocket send failed: {e}");
                     return false;
                 }
             }
-            Ok(PtyCommand::Resize(json)) => {
-                if ws.send(Message::Text(json.into())).is_err() {
+        }
+
+        if pending_flush {
+            match ws.flush() {
+                Ok(()) => pending_flush = false,
+                Err(tungstenite::Error::Io(ref e))
+                    if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                Err(e) => {
+                    log::error!("WebSocket flush failed: {e}");
                     return false;
                 }
             }
-            Ok(PtyCommand::Disconnect) => {
-                let _ = ws.close(None);
-                return true;
-            }
-            Err(mpsc::TryRecvError::Disconnected) => return true,
-            Err(mpsc::TryRecvError::Empty) => {}
         }
 
-        // Read from WebSocket
-        match ws.read() {
-            Ok(Message::Binary(data)) => {
-                let _ = out_tx.send(data.to_vec());
-            }
-            Ok(Message::Text(text)) => {
-                let _ = out_tx.send(text.as_bytes().to_vec());
-            }
-            Ok(Message::Close(_)) => {
-                log::info!("WebSocket closed by server");
-                return false;
-            }
-            Ok(_) => {} // Ping/Pong handled internally
-            Err(tungstenite::Error::Io(ref e))
-                if e.kind() == std::io::ErrorKind::WouldBlock =>
-            {
-                // No data available yet — sleep briefly to avoid busy-loop
-                thread::sleep(std::time::Duration::from_millis(5));
-            }
-            Err(e) => {
-                log::error!("WebSocket error: {e}");
-                return false;
+        // Read everything available; TLS may hold decrypted data the socket
+        // no longer signals, so only poll once a read would block
+        loop {
+            match ws.read() {
+                Ok(Message::Binary(data)) if compressed => match inflate_frame(&data) {
+                    Ok(frame) => {
+                        let _ = out_tx.send(frame);
+                    }
+                    Err(e) => log::warn!("Dropping corrupt compressed frame: {e}"),
+                },
+                Ok(Message::Binary(data)) => {
+                    let _ = out_tx.send(data.to_vec());
+                }
+                Ok(Message::Text(text)) => {
+                    let _ = out_tx.send(text.as_bytes().to_vec());
+                }
+                Ok(Message::Close(_)) => {
+                    log::info!("WebSocket closed by server");
+                    return false;
+                }
+                Ok(_) => {} // Ping/Pong handled internally
+                Err(tungstenite::Error::Io(ref e))
+                    if e.kind() == std::io::ErrorKind::WouldBlock =>
+                {
+                    break;
+                }
+                Err(e) => {
+                    log::error!("WebSocket error: {e}");
+                    return false;
+                }
             }
         }
+
+        let socket_events = if pending_flush {
+            libc::POLLIN | libc::POLLOUT
+        } else {
+            libc::POLLIN
+        };
+        if let Err(e) = poll_fds(&mut [
+            libc::pollfd {
+                fd: socket_fd,
+                events: socket_events,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: cmd_rx.wake.0,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ]) {
+            log::error!("WebSocket poll error: {e}");
+            return false;
+        }
+        cmd_rx.wake.drain();
+    }
+}
+
+/// Block in `poll()` until one of `fds` is ready, retrying on `EINTR`.
+fn poll_fds(fds: &mut [libc::pollfd]) -> std::io::Result<()> {
+    loop {
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } >= 0 {
+            return Ok(());
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
     }
 }
 
 /// Accept any TLS certificate (needed for self-signed dev certs)
+/// CA certificate directories, in order of preference: the updatable
+/// Conscrypt APEX store (Android 14+), the system store, then the usual
+/// Linux location for host builds.
+const CA_CERT_DIRS: &[&str] = &[
+    "/apex/com.android.conscrypt/cacerts",
+    "/system/etc/security/cacerts",
+    "/etc/ssl/certs",
+];
+
+/// Load trusted root certificates from the first populated CA directory.
+fn load_system_roots() -> rustls::RootCertStore {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+
+    let mut roots = rustls::RootCertStore::empty();
+    for dir in CA_CERT_DIRS {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(pem) = std::fs::read(entry.path()) else {
+                continue;
+            };
+            for cert in CertificateDer::pem_slice_iter(&pem).flatten() {
+                let _ = roots.add(cert);
+            }
+        }
+        if !roots.is_empty() {
+            log::info!("Loaded {} CA certificates from {dir}", roots.len());
+            break;
+        }
+    }
+    roots
+}
+
+/// Build the rustls client config for the given trust mode.
+fn tls_client_config(
+    trust: TlsTrust,
+) -> Result<std::sync::Arc<rustls::ClientConfig>, String> {
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let config = match trust {
+        TlsTrust::Verify => {
+            let roots = load_system_roots();
+            if roots.is_empty() {
+                return Err("No system CA certificates found".to_string());
+            }
+            rustls::ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth()
+        }
+        TlsTrust::Pinned(fingerprint) => rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(std::sync::Arc::new(PinnedCert {
+                fingerprint,
+                provider: std::sync::Arc::new(rustls::crypto::ring::default_provider()),
+            }))
+            .with_no_client_auth(),
+        TlsTrust::Insecure => {
+            log::warn!("TLS certificate verification is DISABLED for this connection");
+            rustls::ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(std::sync::Arc::new(AcceptAnyCert))
+                .with_no_client_auth()
+        }
+    };
+    Ok(std::sync::Arc::new(config))
+}
+
+/// Certificate verifier that accepts everything. Only reachable through
+/// `connectInsecure`; see [`TlsTrust::Insecure`].
 #[derive(Debug)]
 struct AcceptAnyCert;
 
@@ -736,6 +1323,93 @@ impl rustls::client::danger::ServerCertVerifier for AcceptAnyCert {
     }
 }
 
+/// Parse a SHA-256 fingerprint from hex, ignoring case and `:`/space separators
+/// (so `openssl x509 -fingerprint -sha256` output can be pasted as is).
+fn parse_fingerprint(hex: &str) -> Option<[u8; 32]> {
+    let digits: Vec<u8> = hex
+        .bytes()
+        .filter(|b| !matches!(b, b':' | b' '))
+        .map(|b| (b as char).to_digit(16).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+    if digits.len() != 64 {
+        return None;
+    }
+
+    let mut fingerprint = [0u8; 32];
+    for (byte, pair) in fingerprint.iter_mut().zip(digits.chunks(2)) {
+        *byte = (pair[0] << 4) | pair[1];
+    }
+    Some(fingerprint)
+}
+
+/// Whether the DER-encoded certificate's SHA-256 digest equals `fingerprint`.
+fn fingerprint_matches(fingerprint: &[u8; 32], cert_der: &[u8]) -> bool {
+    ring::digest::digest(&ring::digest::SHA256, cert_der).as_ref() == fingerprint
+}
+
+/// Certificate verifier for [`TlsTrust::Pinned`]. The chain and hostname are
+/// not checked; instead the leaf must match the pinned fingerprint, and the
+/// handshake signatures are verified so the server must hold its private key.
+#[derive(Debug)]
+struct PinnedCert {
+    fingerprint: [u8; 32],
+    provider: std::sync::Arc<rustls::crypto::CryptoProvider>,
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        if fingerprint_matches(&self.fingerprint, end_entity.as_ref()) {
+            Ok(rustls::client::danger::ServerCertVerified::assertion())
+        } else {
+            log::error!("Server certificate does not match the pinned fingerprint");
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
 /// Word-wrap text to fit within `cols` columns.
 fn wrap_text(text: &str, cols: usize) -> Vec<String> {
     if cols == 0 {
@@ -765,8 +1439,6 @@ fn wrap_text(text: &str, cols: usize) -> Vec<String> {
 
 /// Create local shell directories under `files_dir`.
 fn ensure_local_dirs(files_dir: &str) {
-    use std::ffi::CString;
-
     let dirs = [
         format!("{files_dir}/home"),
         format!("{files_dir}/usr"),
@@ -785,142 +1457,112 @@ fn ensure_local_dirs(files_dir: &str) {
     }
 }
 
-/// Spawn a local PTY shell process.
+/// Spawn a local PTY shell process in `cwd` (or `$HOME`), returning its PID
+/// alongside the channels.
 fn spawn_local_pty(
     files_dir: &str,
     native_lib_dir: &str,
+    cwd: Option<&str>,
     cols: usize,
     rows: usize,
-) -> (mpsc::Sender<PtyCommand>, mpsc::Receiver<Vec<u8>>) {
+) -> (CommandSender, mpsc::Receiver<Vec<u8>>, Option<i32>) {
     use nix::pty::openpty;
-    use nix::unistd::{dup2, execve, fork, setsid, ForkResult};
-    use std::ffi::CString;
-    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use nix::unistd::{fork, ForkResult};
+    use std::os::fd::FromRawFd;
 
     let home = format!("{files_dir}/home");
     let prefix = format!("{files_dir}/usr");
 
     ensure_local_dirs(files_dir);
 
-    let (cmd_tx, cmd_rx) = mpsc::channel::<PtyCommand>();
+    let (cmd_tx, cmd_rx) = command_channel();
     let (out_tx, out_rx) = mpsc::channel::<Vec<u8>>();
 
     let pty = openpty(None, None).expect("openpty failed");
     let master_fd = pty.master;
     let slave_fd = pty.slave;
 
+    // The master must never survive into the shell, even if the child
+    // execs before reaching its own cleanup
+    set_cloexec(master_fd.as_raw_fd());
+
     // Set initial terminal size
     set_winsize(master_fd.as_raw_fd(), cols as u16, rows as u16);
 
-    // Clone strings for the child process (pre-fork)
-    let home_c = home.clone();
-    let prefix_c = prefix.clone();
-    let native_lib_dir_c = native_lib_dir.to_string();
+    // Everything the child needs is laid out before forking: the child of a
+    // multithreaded process must not allocate
+    let start_dir = CString::new(cwd.unwrap_or(&home)).ok();
+    // Fall back to $HOME if the requested directory has disappeared since
+    // it was chosen
+    let home_dir = CString::new(home.as_str()).ok();
+    let dirs: Vec<&CStr> = [&start_dir, &home_dir]
+        .into_iter()
+        .flatten()
+        .map(|dir| dir.as_c_str())
+        .collect();
+
+    let make_env = |path_val: String| -> Vec<String> {
+        vec![
+            format!("HOME={home}"),
+            path_val,
+            format!("PREFIX={prefix}"),
+            format!("TMPDIR={prefix}/tmp"),
+            "TERM=xterm-256color".to_string(),
+            "COLORTERM=truecolor".to_string(),
+            "LANG=en_US.UTF-8".to_string(),
+            format!("TERMINFO={prefix}/share/terminfo"),
+            format!("ENV={home}/.profile"),
+        ]
+    };
+    // Bootstrap shells get their own bin dir first
+    let bootstrap_env = make_env(format!("PATH={prefix}/bin:/system/bin"));
+    // The system shell gets /system/bin first so system commands aren't
+    // shadowed
+    let fallback_env = make_env(format!("PATH=/system/bin:{prefix}/bin"));
+
+    // Busybox from the native lib dir comes first: it is always executable,
+    // unlike the bootstrap shells on app data dirs mounted noexec
+    let shells: Vec<ExecImage> = [
+        (
+            format!("{native_lib_dir}/libbusybox.so"),
+            "-ash",
+            &bootstrap_env,
+        ),
+        (format!("{prefix}/bin/bash"), "-bash", &bootstrap_env),
+        (format!("{prefix}/bin/ash"), "-ash", &bootstrap_env),
+        ("/system/bin/sh".to_string(), "sh", &fallback_env),
+    ]
+    .into_iter()
+    .filter_map(|(path, arg0, env)| ExecImage::new(&path, &[arg0], env))
+    .collect();
+
+    // Register for SIGCHLD before forking so an early exit still wakes the thread
+    let sigchld = SigchldWatch::new();
+    let mut child_pid = None;
 
     match unsafe { fork() } {
-        #[allow(unreachable_code)]
         Ok(ForkResult::Child) => {
-            // Child process: set up slave as controlling terminal
             drop(master_fd);
+            exec_on_pty(slave_fd, &dirs, &shells);
 
-            setsid().expect("setsid failed");
-
-            // Set slave as controlling terminal
+            const MSG: &[u8] = b"fatal: no usable shell found\n";
             unsafe {
-                libc::ioctl(slave_fd.as_raw_fd(), libc::TIOCSCTTY, 0);
-            }
-
-            dup2(slave_fd.as_raw_fd(), 0).expect("dup2 stdin failed");
-            dup2(slave_fd.as_raw_fd(), 1).expect("dup2 stdout failed");
-            dup2(slave_fd.as_raw_fd(), 2).expect("dup2 stderr failed");
-
-            if slave_fd.as_raw_fd() > 2 {
-                drop(slave_fd);
+                libc::write(2, MSG.as_ptr().cast(), MSG.len());
+                libc::_exit(127);
             }
+        }
+        Ok(ForkResult::Parent { child }) => {
+            drop(slave_fd);
+            child_pid = Some(child.as_raw());
 
-            // chdir to $HOME
-            if let Ok(c_home) = CString::new(home_c.as_str()) {
-                unsafe {
-                    libc::chdir(c_home.as_ptr());
-                }
-            }
-
-            // Build env with bootstrap path first
-            let make_env = |path_val: &str| -> Vec<CString> {
-                [
-                    format!("HOME={home_c}"),
-                    path_val.to_string(),
-                    format!("PREFIX={prefix_c}"),
-                    format!("TMPDIR={prefix_c}/tmp"),
-                    "TERM=xterm-256color".to_string(),
-                    "COLORTERM=truecolor".to_string(),
-                    "LANG=en_US.UTF-8".to_string(),
-                    format!("TERMINFO={prefix_c}/share/terminfo"),
-                    format!("ENV={home_c}/.profile"),
-                ]
-                .iter()
-                .filter_map(|s| CString::new(s.as_str()).ok())
-                .collect()
-            };
-
-            // Try busybox from native lib dir first (always executable,
-            // not affected by noexec restrictions on app data dirs)
-            let bootstrap_path = format!("PATH={prefix_c}/bin:/system/bin");
-            let bootstrap_env = make_env(&bootstrap_path);
-            let bootstrap_refs: Vec<&CString> = bootstrap_env.iter().collect();
-
-            {
-                let busybox_path = format!("{native_lib_dir_c}/libbusybox.so");
-                if std::path::Path::new(&busybox_path).exists() {
-                    if let Ok(shell) = CString::new(busybox_path.as_str()) {
-                        let argv0 = CString::new("-ash").unwrap();
-                        let argv = [argv0];
-                        let _ = execve(&shell, &argv, &bootstrap_refs);
-                    }
-                }
-            }
-
-            // Try bootstrap shells from prefix (may fail on noexec mounts)
-            for (path, arg0) in [
-                (format!("{prefix_c}/bin/bash"), "-bash"),
-                (format!("{prefix_c}/bin/ash"), "-ash"),
-            ] {
-                if !std::path::Path::new(&path).exists() {
-                    continue;
-                }
-                if let Ok(shell) = CString::new(path.as_str()) {
-                    let argv0 = CString::new(arg0).unwrap();
-                    let argv = [argv0];
-                    let _ = execve(&shell, &argv, &bootstrap_refs);
-                }
-            }
-
-            // Bootstrap shells failed (noexec); fall back to system shell
-            // with /system/bin first so system commands aren't shadowed
-            let fallback_path = format!("PATH=/system/bin:{prefix_c}/bin");
-            let fallback_env = make_env(&fallback_path);
-            let fallback_refs: Vec<&CString> = fallback_env.iter().collect();
-
-            let sys_shell = CString::new("/system/bin/sh").unwrap();
-            let sys_argv0 = CString::new("sh").unwrap();
-            let sys_argv = [sys_argv0];
-            let _ = execve(&sys_shell, &sys_argv, &fallback_refs);
-
-            // All candidates failed
-            eprintln!("fatal: no usable shell found");
-            unsafe { libc::_exit(127) };
-        }
-        Ok(ForkResult::Parent { child }) => {
-            drop(slave_fd);
-
-            // Set master to non-blocking
-            unsafe {
-                let flags = libc::fcntl(master_fd.as_raw_fd(), libc::F_GETFL);
-                libc::fcntl(
-                    master_fd.as_raw_fd(),
-                    libc::F_SETFL,
-                    flags | libc::O_NONBLOCK,
-                );
+            // Set master to non-blocking
+            unsafe {
+                let flags = libc::fcntl(master_fd.as_raw_fd(), libc::F_GETFL);
+                libc::fcntl(
+                    master_fd.as_raw_fd(),
+                    libc::F_SETFL,
+                    flags | libc::O_NONBLOCK,
+                );
             }
 
             let master_raw = master_fd.as_raw_fd();
@@ -931,7 +1573,7 @@ fn spawn_local_pty(
                 .name("pty-local".into())
                 .spawn(move || {
                     let master = unsafe { OwnedFd::from_raw_fd(master_raw) };
-                    pty_thread_main(master, child, &cmd_rx, &out_tx);
+                    pty_thread_main(master, child, sigchld, &cmd_rx, &out_tx);
                 })
                 .expect("Failed to spawn PTY thread");
         }
@@ -940,7 +1582,7 @@ fn spawn_local_pty(
         }
     }
 
-    (cmd_tx, out_rx)
+    (cmd_tx, out_rx, child_pid)
 }
 
 /// Spawn a local PTY running through proot with the Arch Linux rootfs.
@@ -951,117 +1593,81 @@ fn spawn_proot_pty(
     native_lib_dir: &str,
     cols: usize,
     rows: usize,
-) -> (mpsc::Sender<PtyCommand>, mpsc::Receiver<Vec<u8>>) {
+) -> (CommandSender, mpsc::Receiver<Vec<u8>>) {
     use nix::pty::openpty;
-    use nix::unistd::{dup2, execve, fork, setsid, ForkResult};
-    use std::ffi::CString;
-    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use nix::unistd::{fork, ForkResult};
+    use std::os::fd::FromRawFd;
 
     ensure_local_dirs(files_dir);
 
-    let (cmd_tx, cmd_rx) = mpsc::channel::<PtyCommand>();
+    let (cmd_tx, cmd_rx) = command_channel();
     let (out_tx, out_rx) = mpsc::channel::<Vec<u8>>();
 
     let pty = openpty(None, None).expect("openpty failed");
     let master_fd = pty.master;
     let slave_fd = pty.slave;
 
-    set_winsize(master_fd.as_raw_fd(), cols as u16, rows as u16);
+    // The master must never survive into the shell, even if the child
+    // execs before reaching its own cleanup
+    set_cloexec(master_fd.as_raw_fd());
 
-    let proot_path = proot_path.to_string();
-    let rootfs_path = rootfs_path.to_string();
-    let files_dir = files_dir.to_string();
-    let native_lib_dir = native_lib_dir.to_string();
+    set_winsize(master_fd.as_raw_fd(), cols as u16, rows as u16);
 
     log::info!("spawn_proot_pty: proot={proot_path} rootfs={rootfs_path}");
 
+    // Create libtalloc.so.2 symlink so the dynamic linker can find it
+    // (Termux's proot links against libtalloc.so.2 but we ship libtalloc.so)
+    let lib_dir = format!("{files_dir}/usr/lib");
+    let _ = std::fs::create_dir_all(&lib_dir);
+    let symlink_path = format!("{lib_dir}/libtalloc.so.2");
+    let target_path = format!("{native_lib_dir}/libtalloc.so");
+    let _ = std::fs::remove_file(&symlink_path);
+    let _ = std::os::unix::fs::symlink(&target_path, &symlink_path);
+
+    let rootfs_arg = format!("--rootfs={rootfs_path}");
+    let argv = [
+        "proot",
+        &rootfs_arg,
+        "--bind=/dev",
+        "--bind=/proc",
+        "--bind=/sys",
+        "--bind=/sdcard",
+        "-0",
+        "-w",
+        "/root",
+        "/usr/bin/bash",
+        "-l",
+    ];
+    let env = [
+        "HOME=/root".to_string(),
+        "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin".to_string(),
+        "TERM=xterm-256color".to_string(),
+        "COLORTERM=truecolor".to_string(),
+        "LANG=en_US.UTF-8".to_string(),
+        format!("COLUMNS={cols}"),
+        format!("LINES={rows}"),
+        format!("PROOT_TMP_DIR={files_dir}/usr/tmp"),
+        format!("PROOT_LOADER={native_lib_dir}/libproot-loader.so"),
+        format!("LD_LIBRARY_PATH={lib_dir}:{native_lib_dir}"),
+    ];
+    // Built before forking: the child of a multithreaded process must not
+    // allocate
+    let proot: Vec<ExecImage> = ExecImage::new(proot_path, &argv, &env)
+        .into_iter()
+        .collect();
+
+    // Register for SIGCHLD before forking so an early exit still wakes the thread
+    let sigchld = SigchldWatch::new();
+
     match unsafe { fork() } {
-        #[allow(unreachable_code)]
         Ok(ForkResult::Child) => {
             drop(master_fd);
+            exec_on_pty(slave_fd, &[], &proot);
 
-            setsid().expect("setsid failed");
-
+            const MSG: &[u8] = b"fatal: could not exec proot\n";
             unsafe {
-                libc::ioctl(slave_fd.as_raw_fd(), libc::TIOCSCTTY, 0);
-            }
-
-            dup2(slave_fd.as_raw_fd(), 0).expect("dup2 stdin failed");
-            dup2(slave_fd.as_raw_fd(), 1).expect("dup2 stdout failed");
-            dup2(slave_fd.as_raw_fd(), 2).expect("dup2 stderr failed");
-
-            let slave_raw = slave_fd.as_raw_fd();
-            if slave_raw > 2 {
-                drop(slave_fd);
-            }
-
-            // Close all inherited FDs > 2 (Android graphics FDs, etc.)
-            unsafe {
-                for fd in 3..256 {
-                    if fd != slave_raw {
-                        libc::close(fd);
-                    }
-                }
-            }
-
-            // Create libtalloc.so.2 symlink so the dynamic linker can find it
-            // (Termux's proot links against libtalloc.so.2 but we ship libtalloc.so)
-            let lib_dir = format!("{files_dir}/usr/lib");
-            let _ = std::fs::create_dir_all(&lib_dir);
-            let symlink_path = format!("{lib_dir}/libtalloc.so.2");
-            let target_path = format!("{native_lib_dir}/libtalloc.so");
-            let _ = std::fs::remove_file(&symlink_path);
-            let _ = std::os::unix::fs::symlink(&target_path, &symlink_path);
-
-            let proot = CString::new(proot_path.as_str()).unwrap();
-            let rootfs_arg = format!("--rootfs={rootfs_path}");
-
-            let argv_strs = [
-                "proot",
-                &rootfs_arg,
-                "--bind=/dev",
-                "--bind=/proc",
-                "--bind=/sys",
-                "--bind=/sdcard",
-                "-0",
-                "-w",
-                "/root",
-                "/usr/bin/bash",
-                "-l",
-            ];
-            let argv: Vec<CString> = argv_strs
-                .iter()
-                .filter_map(|s| CString::new(*s).ok())
-                .collect();
-            let argv_refs: Vec<&CString> = argv.iter().collect();
-
-            let tmp_dir = format!("{files_dir}/usr/tmp");
-            let loader_path = format!("{native_lib_dir}/libproot-loader.so");
-            let env_vars: Vec<CString> = [
-                "HOME=/root".to_string(),
-                "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"
-                    .to_string(),
-                "TERM=xterm-256color".to_string(),
-                "COLORTERM=truecolor".to_string(),
-                "LANG=en_US.UTF-8".to_string(),
-                format!("COLUMNS={cols}"),
-                format!("LINES={rows}"),
-                format!("PROOT_TMP_DIR={tmp_dir}"),
-                format!("PROOT_LOADER={loader_path}"),
-                format!("LD_LIBRARY_PATH={lib_dir}:{native_lib_dir}"),
-            ]
-            .iter()
-            .filter_map(|s| CString::new(s.as_str()).ok())
-            .collect();
-
-            let env_refs: Vec<&CString> = env_vars.iter().collect();
-            match execve(&proot, &argv_refs, &env_refs) {
-                Ok(_) => unreachable!(),
-                Err(e) => {
-                    let msg = format!("execve failed: {e}\n");
-                    let _ = nix::unistd::write(std::io::stderr(), msg.as_bytes());
-                    unsafe { libc::_exit(1) };
-                }
+                libc::write(2, MSG.as_ptr().cast(), MSG.len());
+                libc::_exit(1);
             }
         }
         Ok(ForkResult::Parent { child }) => {
@@ -1083,7 +1689,7 @@ fn spawn_proot_pty(
                 .name("pty-proot".into())
                 .spawn(move || {
                     let master = unsafe { OwnedFd::from_raw_fd(master_raw) };
-                    pty_thread_main(master, child, &cmd_rx, &out_tx);
+                    pty_thread_main(master, child, sigchld, &cmd_rx, &out_tx);
                 })
                 .expect("Failed to spawn proot PTY thread");
         }
@@ -1095,6 +1701,190 @@ fn spawn_proot_pty(
     (cmd_tx, out_rx)
 }
 
+/// A program with its argument and environment lists, laid out before
+/// `fork` so the child can exec it without allocating.
+struct ExecImage {
+    path: CString,
+    /// Null-terminated pointers into `strings`
+    argv: Vec<*const libc::c_char>,
+    env: Vec<*const libc::c_char>,
+    /// Keeps the pointed-to arguments and variables alive
+    _strings: Vec<CString>,
+}
+
+impl ExecImage {
+    /// None when any string holds a NUL byte.
+    fn new<A: AsRef<str>, E: AsRef<str>>(
+        path: &str,
+        argv: &[A],
+        env: &[E],
+    ) -> Option<Self> {
+        let path = CString::new(path).ok()?;
+        let strings: Vec<CString> = argv
+            .iter()
+            .map(AsRef::as_ref)
+            .chain(env.iter().map(AsRef::as_ref))
+            .map(|s| CString::new(s).ok())
+            .collect::<Option<_>>()?;
+        let (argv_strings, env_strings) = strings.split_at(argv.len());
+        let pointers = |strings: &[CString]| {
+            strings
+                .iter()
+                .map(|s| s.as_ptr())
+                .chain(std::iter::once(std::ptr::null()))
+                .collect()
+        };
+        Some(Self {
+            path,
+            argv: pointers(argv_strings),
+            env: pointers(env_strings),
+            _strings: strings,
+        })
+    }
+
+    /// Replace the process image. Returns only when `execve` fails.
+    fn exec(&self) {
+        unsafe {
+            libc::execve(self.path.as_ptr(), self.argv.as_ptr(), self.env.as_ptr());
+        }
+    }
+}
+
+/// Child side of a PTY spawn: make `slave` the controlling terminal and
+/// stdio, enter the first of `dirs` that exists, then exec the first of
+/// `images` that starts. Returns only when none did.
+///
+/// Runs between `fork` and `exec` of a multithreaded process, so it only
+/// makes raw syscalls over data the parent prepared.
+fn exec_on_pty(slave: OwnedFd, dirs: &[&CStr], images: &[ExecImage]) {
+    let fd = slave.as_raw_fd();
+    unsafe {
+        libc::setsid();
+        libc::ioctl(fd, libc::TIOCSCTTY, 0);
+        for stdio in 0..=2 {
+            if libc::dup2(fd, stdio) < 0 {
+                libc::_exit(127);
+            }
+        }
+    }
+    if fd > 2 {
+        drop(slave);
+    } else {
+        std::mem::forget(slave);
+    }
+
+    // Close all inherited FDs > 2 (Android graphics FDs, etc.)
+    close_inherited_fds();
+
+    for dir in dirs {
+        if unsafe { libc::chdir(dir.as_ptr()) } == 0 {
+            break;
+        }
+    }
+    for image in images {
+        image.exec();
+    }
+}
+
+/// Mark a file descriptor close-on-exec.
+fn set_cloexec(fd: i32) {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags >= 0 {
+            libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+        }
+    }
+}
+
+/// Close every file descriptor above stderr in a freshly forked child.
+///
+/// Prefers `close_range(2)`; kernels without it fall back to walking
+/// `/proc/self/fd`, so there is no fixed upper bound on what gets closed.
+/// Runs between `fork` and `exec` of a multithreaded process, so nothing
+/// here may allocate: another thread may have held the allocator lock.
+fn close_inherited_fds() {
+    let res = unsafe { libc::syscall(libc::SYS_close_range, 3u32, u32::MAX, 0u32) };
+    if res == 0 {
+        return;
+    }
+    if close_listed_fds() {
+        return;
+    }
+
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    let max = if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == 0 {
+        limit.rlim_cur.min(i32::MAX as libc::rlim_t) as i32
+    } else {
+        1024
+    };
+    for fd in 3..max {
+        unsafe {
+            libc::close(fd);
+        }
+    }
+}
+
+/// Close the descriptors above stderr listed in `/proc/self/fd`, reading
+/// the directory with raw `getdents64` into a stack buffer. False when the
+/// directory can't be read.
+fn close_listed_fds() -> bool {
+    let dir = unsafe {
+        libc::open(
+            c"/proc/self/fd".as_ptr(),
+            libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC,
+        )
+    };
+    if dir < 0 {
+        return false;
+    }
+
+    let mut buf = [0u8; 1024];
+    loop {
+        let len = unsafe {
+            libc::syscall(libc::SYS_getdents64, dir, buf.as_mut_ptr(), buf.len())
+        };
+        if len <= 0 {
+            break;
+        }
+        // Each `linux_dirent64` is d_ino (8), d_off (8), d_reclen (2),
+        // d_type (1), then the NUL-terminated name
+        let mut offset = 0;
+        while offset < len as usize {
+            let reclen =
+                u16::from_ne_bytes([buf[offset + 16], buf[offset + 17]]) as usize;
+            let name = &buf[offset + 19..offset + reclen];
+            let mut fd: i32 = 0;
+            let mut digits = 0;
+            for &byte in name.iter().take_while(|&&byte| byte != 0) {
+                if !byte.is_ascii_digit() {
+                    digits = 0;
+                    break;
+                }
+                fd = fd.saturating_mul(10).saturating_add((byte - b'0') as i32);
+                digits += 1;
+            }
+            // Closing while listing is fine: the kernel walks the fd table
+            // by number from the directory offset
+            if digits > 0 && fd > 2 && fd != dir {
+                unsafe {
+                    libc::close(fd);
+                }
+            }
+            offset += reclen;
+        }
+    }
+
+    unsafe {
+        libc::close(dir);
+    }
+    true
+}
+
+/// Clamp a requested PTY dimension into `1..=MAX_GRID_DIMENSION`.
+fn clamp_dimension(value: u64) -> u16 {
+    value.clamp(1, MAX_GRID_DIMENSION as u64) as u16
+}
+
 /// Set terminal window size via ioctl.
 fn set_winsize(fd: i32, cols: u16, rows: u16) {
     let ws = libc::winsize {
@@ -1108,16 +1898,158 @@ fn set_winsize(fd: i32, cols: u16, rows: u16) {
     }
 }
 
+/// Maximum number of PTY threads that can wait on SIGCHLD at the same time.
+const MAX_CHILD_WATCHERS: usize = 64;
+
+/// Eventfds signalled by the SIGCHLD handler, one per live PTY thread
+/// (-1 = free slot).
+static CHILD_WATCHERS: [AtomicI32; MAX_CHILD_WATCHERS] =
+    [const { AtomicI32::new(-1) }; MAX_CHILD_WATCHERS];
+
+/// SIGCHLD handlers currently writing to `CHILD_WATCHERS` fds. A watcher
+/// waits for this to drop to zero before closing its eventfd, so a handler
+/// that loaded the fd just before it was released never writes to a closed
+/// (or reused) descriptor.
+static SIGCHLD_HANDLERS_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// SIGCHLD disposition that was in place before ours, chained from the handler.
+static PREV_SIGCHLD_HANDLER: AtomicUsize = AtomicUsize::new(libc::SIG_DFL);
+static PREV_SIGCHLD_FLAGS: AtomicI32 = AtomicI32::new(0);
+
+static SIGCHLD_INSTALLED: Once = Once::new();
+
+extern "C" fn on_sigchld(
+    signo: libc::c_int,
+    info: *mut libc::siginfo_t,
+    ctx: *mut libc::c_void,
+) {
+    // Only async-signal-safe calls in here: lock-free atomics, write(2) and
+    // errno save/restore so the interrupted code doesn't see a clobbered value
+    let saved_errno = unsafe { *errno_location() };
+
+    SIGCHLD_HANDLERS_RUNNING.fetch_add(1, Ordering::SeqCst);
+    for slot in &CHILD_WATCHERS {
+        let fd = slot.load(Ordering::SeqCst);
+        if fd >= 0 {
+            let one: u64 = 1;
+            unsafe {
+                libc::write(fd, (&raw const one).cast(), std::mem::size_of::<u64>());
+            }
+        }
+    }
+    SIGCHLD_HANDLERS_RUNNING.fetch_sub(1, Ordering::SeqCst);
+
+    let prev = PREV_SIGCHLD_HANDLER.load(Ordering::Acquire);
+    if prev != libc::SIG_DFL && prev != libc::SIG_IGN {
+        unsafe {
+            if PREV_SIGCHLD_FLAGS.load(Ordering::Acquire) & libc::SA_SIGINFO != 0 {
+                let f: extern "C" fn(
+                    libc::c_int,
+                    *mut libc::siginfo_t,
+                    *mut libc::c_void,
+                ) = std::mem::transmute(prev);
+                f(signo, info, ctx);
+            } else {
+                let f: extern "C" fn(libc::c_int) = std::mem::transmute(prev);
+                f(signo);
+            }
+        }
+    }
+
+    unsafe { *errno_location() = saved_errno };
+}
+
+#[cfg(target_os = "android")]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno()
+}
+
+#[cfg(not(target_os = "android"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+/// Install the process-wide SIGCHLD handler once, chaining any existing one.
+fn install_sigchld_handler() {
+    SIGCHLD_INSTALLED.call_once(|| unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_sigchld as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART | libc::SA_NOCLDSTOP;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        let mut prev: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(libc::SIGCHLD, &action, &mut prev) != 0 {
+            log::error!(
+                "Failed to install SIGCHLD handler: {}",
+                std::io::Error::last_os_error()
+            );
+            return;
+        }
+        PREV_SIGCHLD_HANDLER.store(prev.sa_sigaction, Ordering::Release);
+        PREV_SIGCHLD_FLAGS.store(prev.sa_flags, Ordering::Release);
+    });
+}
+
+/// An eventfd that becomes readable whenever the process receives SIGCHLD.
+///
+/// Created before `fork()` so a child that exits immediately can't slip
+/// past unnoticed.
+struct SigchldWatch {
+    event: EventFd,
+    slot: usize,
+}
+
+impl SigchldWatch {
+    fn new() -> Option<Self> {
+        install_sigchld_handler();
+
+        let event = match EventFd::new() {
+            Ok(event) => event,
+            Err(e) => {
+                log::error!("eventfd failed: {e}");
+                return None;
+            }
+        };
+
+        for (slot, watcher) in CHILD_WATCHERS.iter().enumerate() {
+            if watcher
+                .compare_exchange(-1, event.0, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                return Some(Self { event, slot });
+            }
+        }
+
+        log::error!("Too many PTY sessions for SIGCHLD watching");
+        None
+    }
+}
+
+impl Drop for SigchldWatch {
+    fn drop(&mut self) {
+        CHILD_WATCHERS[self.slot].swap(-1, Ordering::SeqCst);
+        // A handler that already loaded the fd finishes its write before
+        // `event` is closed; any handler starting later sees the free slot
+        while SIGCHLD_HANDLERS_RUNNING.load(Ordering::SeqCst) != 0 {
+            std::hint::spin_loop();
+        }
+    }
+}
+
 /// PTY thread main loop: shuttle data between master fd and channels.
+///
+/// Blocks in `poll()` on the master fd, the command eventfd and the SIGCHLD
+/// eventfd, so input, output and child exit are all handled as they happen.
 fn pty_thread_main(
     master: std::os::fd::OwnedFd,
     child: nix::unistd::Pid,
-    cmd_rx: &mpsc::Receiver<PtyCommand>,
+    sigchld: Option<SigchldWatch>,
+    cmd_rx: &CommandReceiver,
     out_tx: &mpsc::Sender<Vec<u8>>,
 ) {
     use nix::sys::signal::{kill, Signal};
-    use nix::sys::wait::{waitpid, WaitPidFlag};
-    use std::io::{Read, Write};
+    use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+    use std::io::Write;
     use std::os::fd::{AsRawFd, FromRawFd};
 
     let fd = master.as_raw_fd();
@@ -1125,98 +2057,186 @@ fn pty_thread_main(
     // Prevent double-close: File will close the fd, we must not drop OwnedFd
     std::mem::forget(master);
 
-    let mut buf = [0u8; 4096];
+    let mut buf = ReadBuffer::default();
+    // Check once up front in case the child exited before the watch existed
+    let mut child_signalled = true;
+    let mut reaped = false;
+    // Set once every slave fd is closed. The master then polls as hung up
+    // forever, so only the eventfds are waited on until the child is reaped.
+    let mut hung_up = false;
 
     log::info!("PTY thread started, child pid={child}");
 
-    loop {
+    'outer: loop {
         // Check for commands
-        match cmd_rx.try_recv() {
-            Ok(PtyCommand::Input(data)) => {
-                let _ = file.write_all(&data);
-            }
-            Ok(PtyCommand::Resize(json)) => {
-                if let Ok(msg) = serde_json::from_str::<serde_json::Value>(&json) {
-                    let cols =
-                        msg.get("cols").and_then(|v| v.as_u64()).unwrap_or(80) as u16;
-                    let rows =
-                        msg.get("rows").and_then(|v| v.as_u64()).unwrap_or(24) as u16;
-                    set_winsize(fd, cols, rows);
-                    let _ = kill(child, Signal::SIGWINCH);
+        loop {
+            match cmd_rx.try_recv() {
+                Ok(PtyCommand::Input(data)) => {
+                    let _ = file.write_all(&data);
                 }
+                Ok(PtyCommand::Resize(json)) => {
+                    if let Ok(msg) = serde_json::from_str::<serde_json::Value>(&json) {
+                        let cols = msg.get("cols").and_then(|v| v.as_u64()).unwrap_or(80);
+                        let rows = msg.get("rows").and_then(|v| v.as_u64()).unwrap_or(24);
+                        set_winsize(fd, clamp_dimension(cols), clamp_dimension(rows));
+                        let _ = kill(child, Signal::SIGWINCH);
+                    }
+                }
+                Ok(PtyCommand::Disconnect) => {
+                    let _ = kill(child, Signal::SIGHUP);
+                    break 'outer;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => break 'outer,
+                Err(mpsc::TryRecvError::Empty) => break,
             }
-            Ok(PtyCommand::Disconnect) => {
-                let _ = kill(child, Signal::SIGHUP);
-                break;
-            }
-            Err(mpsc::TryRecvError::Disconnected) => break,
-            Err(mpsc::TryRecvError::Empty) => {}
         }
 
-        // Read from master fd
-        match Read::read(&mut file, &mut buf) {
-            Ok(0) => break, // EOF — shell exited
-            Ok(n) => {
-                if out_tx.send(buf[..n].to_vec()).is_err() {
+        // Check if child has exited
+        if child_signalled {
+            child_signalled = false;
+            match waitpid(child, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::Exited(_, code)) => {
+                    log::error!("Shell process exited with code {code}");
+                    reaped = true;
+                    // Drain any remaining output before exiting
+                    loop {
+                        match buf.read(&mut file) {
+                            Ok([]) | Err(_) => break,
+                            Ok(data) => {
+                                let output = String::from_utf8_lossy(data);
+                                log::error!("Shell final output: {output}");
+                                let _ = out_tx.send(data.to_vec());
+                            }
+                        }
+                    }
+                    break;
+                }
+                Ok(WaitStatus::Signaled(_, sig, _)) => {
+                    log::error!("Shell process killed by signal {sig}");
+                    reaped = true;
                     break;
                 }
+                _ => {}
             }
-            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                thread::sleep(std::time::Duration::from_millis(5));
+        }
+
+        let mut fds = [
+            libc::pollfd {
+                fd: if hung_up { -1 } else { fd },
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: cmd_rx.wake.0,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: sigchld.as_ref().map_or(-1, |w| w.event.0),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        // Without a SIGCHLD eventfd, fall back to checking the child on a timer
+        let timeout_ms = if sigchld.is_some() { -1 } else { 100 };
+        let ready = unsafe {
+            libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms)
+        };
+        if ready < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
             }
-            Err(e) => {
-                log::error!("PTY read error: {e}");
-                break;
+            log::error!("PTY poll error: {err}");
+            break;
+        }
+
+        if fds[1].revents & libc::POLLIN != 0 {
+            cmd_rx.wake.drain();
+        }
+        if fds[2].revents & libc::POLLIN != 0 {
+            if let Some(ref watch) = sigchld {
+                watch.event.drain();
             }
+            child_signalled = true;
+        } else if sigchld.is_none() {
+            child_signalled = true;
         }
 
-        // Check if child has exited
-        match waitpid(child, Some(WaitPidFlag::WNOHANG)) {
-            Ok(nix::sys::wait::WaitStatus::Exited(_, code)) => {
-                log::error!("Shell process exited with code {code}");
-                // Drain any remaining output before exiting
-                loop {
-                    match Read::read(&mut file, &mut buf) {
-                        Ok(0) | Err(_) => break,
-                        Ok(n) => {
-                            let output = String::from_utf8_lossy(&buf[..n]);
-                            log::error!("Shell final output: {output}");
-                            let _ = out_tx.send(buf[..n].to_vec());
-                        }
+        if fds[0].revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) == 0 {
+            continue;
+        }
+
+        // Read from master fd until it would block
+        loop {
+            match buf.read(&mut file) {
+                Ok([]) => break 'outer, // EOF — shell exited
+                Ok(data) => {
+                    if out_tx.send(data.to_vec()).is_err() {
+                        break 'outer;
                     }
                 }
-                break;
-            }
-            Ok(nix::sys::wait::WaitStatus::Signaled(_, sig, _)) => {
-                log::error!("Shell process killed by signal {sig}");
-                break;
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                // EIO means the slave side closed, usually as the child
+                // exited, though it may live on with its stdio redirected
+                Err(ref e) if e.raw_os_error() == Some(libc::EIO) => {
+                    child_signalled = true;
+                    hung_up = true;
+                    break;
+                }
+                Err(e) => {
+                    log::error!("PTY read error: {e}");
+                    break 'outer;
+                }
             }
-            _ => {}
         }
     }
 
+    // Don't leave a zombie behind if we bailed out before reaping
+    if !reaped {
+        let _ = waitpid(child, Some(WaitPidFlag::WNOHANG));
+    }
+
     log::info!("PTY thread exiting");
 }
 
-/// Horizontal padding in density-independent pixels (applied on each side).
-const PADDING_DP: f32 = 2.0;
+/// Space kept clear around the grid on each side
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Padding {
+    left: f32,
+    top: f32,
+    right: f32,
+    bottom: f32,
+}
 
-/// Calculate grid columns and rows from surface dimensions.
-fn calc_grid(
-    width: f32,
-    height: f32,
-    scale: f32,
-    sugarloaf: &mut Sugarloaf,
-    rt_id: &usize,
-) -> (usize, usize) {
-    let dims = sugarloaf.get_rich_text_dimensions(rt_id);
-    log::info!(
-        "calc_grid: surface={width}x{height} scale={scale} cell={}x{}",
-        dims.width,
-        dims.height
-    );
+impl Padding {
+    /// Convert from density-independent to physical pixels.
+    fn scaled(self, scale: f32) -> Self {
+        Self {
+            left: self.left * scale,
+            top: self.top * scale,
+            right: self.right * scale,
+            bottom: self.bottom * scale,
+        }
+    }
+}
+
+/// Padding in density-independent pixels until `setPadding` is called
+const DEFAULT_PADDING: Padding = Padding {
+    left: 2.0,
+    top: 0.0,
+    right: 2.0,
+    bottom: 0.0,
+};
+
+/// Padding set through `setPadding`, in density-independent pixels
+static PADDING: Mutex<Padding> = Mutex::new(DEFAULT_PADDING);
 
+/// Cell size in physical pixels, estimated until the font has loaded.
+fn cell_size(sugarloaf: &mut Sugarloaf, rt_id: &usize, scale: f32) -> (f32, f32) {
     // dims are already in physical pixels (font shaped at scaled_font_size)
+    let dims = sugarloaf.get_rich_text_dimensions(rt_id);
     let cell_w = if dims.width > 0.0 {
         dims.width
     } else {
@@ -1228,17 +2248,83 @@ fn calc_grid(
     } else {
         18.0 * 1.2 * scale
     };
+    (cell_w, cell_h)
+}
 
-    // Subtract horizontal padding from available width
-    let usable_width = (width - 2.0 * PADDING_DP * scale).max(cell_w);
-
+/// Columns and rows of `cell`-sized cells fitting in a `width` x `height`
+/// surface inside `padding` (physical pixels).
+fn grid_size(
+    width: f32,
+    height: f32,
+    cell: (f32, f32),
+    padding: Padding,
+) -> (usize, usize) {
+    let (cell_w, cell_h) = cell;
+    let usable_width = (width - padding.left - padding.right).max(cell_w);
+    let usable_height = (height - padding.top - padding.bottom).max(cell_h);
     let cols = (usable_width / cell_w).floor().max(1.0) as usize;
-    let rows = (height / cell_h).floor().max(1.0) as usize;
+    let rows = (usable_height / cell_h).floor().max(1.0) as usize;
+    (cols, rows)
+}
+
+/// Top-left corner of a `cols` x `rows` grid: inside the padding, centered
+/// in whatever is left when the surface is not a whole number of cells.
+fn grid_origin(
+    width: f32,
+    height: f32,
+    (cols, rows): (usize, usize),
+    (cell_w, cell_h): (f32, f32),
+    padding: Padding,
+) -> (f32, f32) {
+    let leftover_x = width - padding.left - padding.right - cols as f32 * cell_w;
+    let leftover_y = height - padding.top - padding.bottom - rows as f32 * cell_h;
+    (
+        padding.left + (leftover_x / 2.0).max(0.0),
+        padding.top + (leftover_y / 2.0).max(0.0),
+    )
+}
+
+/// Calculate grid columns and rows from surface dimensions.
+fn calc_grid(
+    width: f32,
+    height: f32,
+    scale: f32,
+    sugarloaf: &mut Sugarloaf,
+    rt_id: &usize,
+) -> (usize, usize) {
+    let (cell_w, cell_h) = cell_size(sugarloaf, rt_id, scale);
+    let padding = PADDING.lock().unwrap().scaled(scale);
+    log::info!(
+        "calc_grid: surface={width}x{height} scale={scale} cell={cell_w}x{cell_h} padding={padding:?}"
+    );
+
+    let (cols, rows) = grid_size(width, height, (cell_w, cell_h), padding);
 
-    log::info!("calc_grid: result={cols}x{rows} cell_w={cell_w} cell_h={cell_h}");
+    log::info!("calc_grid: result={cols}x{rows}");
     (cols, rows)
 }
 
+/// Grid size packed for JNI as `cols << 32 | rows`.
+fn pack_grid_size((cols, rows): (usize, usize)) -> jlong {
+    ((cols as jlong) << 32) | rows as jlong
+}
+
+/// Install the Android logger (once) and apply the current `LOG_LEVEL`.
+/// The logger itself is unfiltered; `log::max_level` does the filtering.
+fn init_logger() {
+    android_logger::init_once(android_logger::Config::default().with_tag("OmniTerminal"));
+    let level = log::LevelFilter::iter()
+        .nth(LOG_LEVEL.load(Ordering::Relaxed))
+        .unwrap_or(log::LevelFilter::Info);
+    log::set_max_level(level);
+}
+
+/// Map a JNI log level (0=off, 1=error, 2=warn, 3=info, 4=debug, 5=trace).
+fn level_filter_from_int(level: jint) -> Option<log::LevelFilter> {
+    let index = usize::try_from(level).ok()?;
+    log::LevelFilter::iter().nth(index)
+}
+
 // --- JNI Functions ---
 
 /// Initialize sugarloaf with an Android Surface.
@@ -1251,11 +2337,7 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_init(
     height: jint,
     scale: jfloat,
 ) {
-    android_logger::init_once(
-        android_logger::Config::default()
-            .with_max_level(log::LevelFilter::Info)
-            .with_tag("OmniTerminal"),
-    );
+    init_logger();
     log::info!("Initializing native terminal: {width}x{height} scale={scale}");
 
     let a_native_window = unsafe {
@@ -1357,6 +2439,8 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_init(
         scale,
         dims_confirmed,
         shell_counter,
+        scrollbar: Scrollbar::new(),
+        last_frame: std::time::Instant::now(),
     };
 
     // Resize restored sessions to match the new surface dimensions
@@ -1372,13 +2456,152 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_init(
     *global = Some(mgr);
 }
 
-/// Connect to a WebSocket server URL (creates a new remote session).
+/// Change the log level at runtime: 0=off, 1=error, 2=warn, 3=info, 4=debug,
+/// 5=trace.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_setLogLevel(
+    _env: JNIEnv,
+    _class: JClass,
+    level: jint,
+) {
+    let Some(filter) = level_filter_from_int(level) else {
+        log::warn!("Ignoring invalid log level {level}");
+        return;
+    };
+    LOG_LEVEL.store(filter as usize, Ordering::Relaxed);
+    init_logger();
+    log::info!("Log level set to {filter}");
+}
+
+/// Choose how pastes are treated without bracketed paste (0=sanitize,
+/// 1=warn, 2=raw).
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_setPasteMode(
+    _env: JNIEnv,
+    _class: JClass,
+    mode: jint,
+) {
+    let mode = match mode {
+        0 => PasteMode::Sanitize,
+        1 => PasteMode::Warn,
+        2 => PasteMode::Raw,
+        _ => {
+            log::warn!("Ignoring invalid paste mode {mode}");
+            return;
+        }
+    };
+    *PASTE_MODE.lock().unwrap() = mode;
+}
+
+/// Ask servers to compress output on connections made from now on. Worth
+/// enabling on metered (cellular) networks; small frames stay uncompressed.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_setCompression(
+    _env: JNIEnv,
+    _class: JClass,
+    enabled: jboolean,
+) {
+    COMPRESSION.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// Show or hide the scrollbar overlay drawn while scrolled back.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_setScrollbar(
+    _env: JNIEnv,
+    _class: JClass,
+    enabled: jboolean,
+) {
+    SCROLLBAR.store(enabled != 0, Ordering::Relaxed);
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(session) = mgr.as_mut().and_then(|m| m.active_session_mut()) {
+        session.dirty = true;
+    }
+}
+
+/// Set how many lines a wheel notch scrolls, clamped to 0.5-20, and
+/// whether scrolling is reversed for natural scrolling. Swipes move
+/// `lines_per_notch / 3` lines per line of finger travel.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_setScrollConfig(
+    _env: JNIEnv,
+    _class: JClass,
+    lines_per_notch: jfloat,
+    inverted: jboolean,
+) {
+    *SCROLL_CONFIG.lock().unwrap() = ScrollConfig::new(lines_per_notch, inverted != 0);
+}
+
+/// Choose whether scrolling a full-screen application that takes no mouse
+/// reports sends arrow keys, or does nothing.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_setAlternateScroll(
+    _env: JNIEnv,
+    _class: JClass,
+    enabled: jboolean,
+) {
+    ALTERNATE_SCROLL.store(enabled != 0, Ordering::Relaxed);
+}
+
+/// Enable or disable keyboard and paste input to the active session, for a
+/// display-only viewer. Output keeps rendering, and selection and mouse
+/// reporting still work.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_setInputEnabled(
+    _env: JNIEnv,
+    _class: JClass,
+    enabled: jboolean,
+) {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(session) = mgr.as_mut().and_then(|m| m.active_session_mut()) {
+        session.input_enabled = enabled != 0;
+    }
+}
+
+/// Connect to a WebSocket server URL (creates a new remote session).
+/// `wss://` servers must present a certificate trusted by the system CA store.
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_connect(
+    env: JNIEnv,
+    _class: JClass,
+    url: JString,
+) {
+    connect_remote(env, url, TlsTrust::Verify);
+}
+
+/// Connect to a WebSocket server URL without verifying its TLS certificate.
+/// This allows man-in-the-middle attacks; use only for development servers
+/// on a trusted network.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_connectInsecure(
+    env: JNIEnv,
+    _class: JClass,
+    url: JString,
+) {
+    connect_remote(env, url, TlsTrust::Insecure);
+}
+
+/// Connect to a WebSocket server URL, trusting only a `wss://` certificate
+/// whose SHA-256 fingerprint matches `sha256_hex`. Suited to self-signed
+/// development servers; an invalid fingerprint aborts the connection.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_connectPinned(
     mut env: JNIEnv,
     _class: JClass,
     url: JString,
+    sha256_hex: JString,
 ) {
+    let Ok(hex) = env.get_string(&sha256_hex) else {
+        return;
+    };
+    let hex: String = hex.into();
+    let Some(fingerprint) = parse_fingerprint(&hex) else {
+        log::error!("Invalid SHA-256 fingerprint: {hex}");
+        return;
+    };
+    connect_remote(env, url, TlsTrust::Pinned(fingerprint));
+}
+
+fn connect_remote(mut env: JNIEnv, url: JString, trust: TlsTrust) {
     let Ok(url_str) = env.get_string(&url) else {
         return;
     };
@@ -1386,7 +2609,7 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_connect(
 
     let mut mgr = TERMINAL_MANAGER.lock().unwrap();
     if let Some(ref mut m) = *mgr {
-        m.create_remote_session(&url_str);
+        m.create_remote_session(&url_str, trust);
         m.render_content();
     }
 }
@@ -1411,7 +2634,35 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_connectLocal(
 
     let mut mgr = TERMINAL_MANAGER.lock().unwrap();
     if let Some(ref mut m) = *mgr {
-        m.create_local_session(&files_dir_str, &native_lib_str);
+        m.create_local_session(&files_dir_str, &native_lib_str, None);
+        m.render_content();
+    }
+}
+
+/// Open a new local shell in the active session's current directory, like a
+/// desktop "new tab here". Falls back to `$HOME` when the directory is
+/// unknown or no longer exists.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_connectLocalInheritCwd(
+    mut env: JNIEnv,
+    _class: JClass,
+    files_dir: JString,
+    native_lib_dir: JString,
+) {
+    let Ok(files_dir_jstr) = env.get_string(&files_dir) else {
+        return;
+    };
+    let files_dir_str: String = files_dir_jstr.into();
+
+    let Ok(native_lib_jstr) = env.get_string(&native_lib_dir) else {
+        return;
+    };
+    let native_lib_str: String = native_lib_jstr.into();
+
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(ref mut m) = *mgr {
+        let cwd = m.active_session().and_then(Session::inheritable_cwd);
+        m.create_local_session(&files_dir_str, &native_lib_str, cwd.as_deref());
         m.render_content();
     }
 }
@@ -1481,25 +2732,7 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_resize(
         m.surface_width = width as f32;
         m.surface_height = height as f32;
         m.scale = scale;
-
-        let (cols, rows) = calc_grid(
-            width as f32,
-            height as f32,
-            scale,
-            &mut m.sugarloaf,
-            &m.rt_id,
-        );
-        if cols != m.total_cols || rows != m.total_rows {
-            m.total_cols = cols;
-            m.total_rows = rows;
-            for session in &mut m.sessions {
-                session.grid.resize(cols, rows);
-                session.send_resize(cols, rows);
-            }
-        }
-        if let Some(session) = m.sessions.get_mut(m.active) {
-            session.dirty = true;
-        }
+        m.regrid();
         m.render_content();
     }
 }
@@ -1521,12 +2754,74 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_sendKey(
 
     let mut mgr = TERMINAL_MANAGER.lock().unwrap();
     if let Some(ref mut m) = *mgr {
-        if let Some(session) = m.active_session() {
-            session.send_input(input.as_bytes());
+        if let Some(session) = m.active_session_mut() {
+            if session.scroll_mode {
+                for c in input.chars() {
+                    session.scroll_mode_key(NavKey::Char(c));
+                }
+                return;
+            }
+            session.send_typed(input.as_bytes());
         }
-        // Snap to bottom on user input
+    }
+}
+
+/// Paste text into the active session, sanitized according to the paste
+/// mode. Returns false without sending when the paste needs confirmation
+/// (warn mode, line breaks, no bracketed paste) and `confirmed` is false;
+/// the caller should ask the user and call again with `confirmed` set.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_sendPaste(
+    mut env: JNIEnv,
+    _class: JClass,
+    text: JString,
+    confirmed: jboolean,
+) -> jboolean {
+    let Ok(input) = env.get_string(&text) else {
+        return 1;
+    };
+    let input: String = input.into();
+    if input.is_empty() {
+        return 1;
+    }
+    let mode = *PASTE_MODE.lock().unwrap();
+
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    let Some(session) = mgr.as_mut().and_then(|m| m.active_session_mut()) else {
+        return 1;
+    };
+    session.paste(&input, mode, confirmed != 0).into()
+}
+
+/// Send a single character typed with modifiers held to the active session.
+/// `modifiers` is a bitmask: 1=Shift, 2=Alt, 4=Ctrl. Encoded as CSI u when
+/// the application requested it, otherwise as control bytes / ESC prefix.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_sendModifiedKey(
+    mut env: JNIEnv,
+    _class: JClass,
+    text: JString,
+    modifiers: jint,
+) {
+    let Ok(input) = env.get_string(&text) else {
+        return;
+    };
+    let input: String = input.into();
+    let mut chars = input.chars();
+    let (Some(ch), None) = (chars.next(), chars.next()) else {
+        return;
+    };
+    let mods = KeyModifiers {
+        shift: modifiers & 1 != 0,
+        alt: modifiers & 2 != 0,
+        ctrl: modifiers & 4 != 0,
+    };
+
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(ref mut m) = *mgr {
         if let Some(session) = m.active_session_mut() {
-            session.grid.scroll_to_bottom();
+            let bytes = encode_key(Key::Char(ch), mods, session.grid.keyboard_protocol());
+            session.send_typed(&bytes);
         }
     }
 }
@@ -1539,27 +2834,214 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_sendSpecialKe
     key_code: jint,
 ) {
     let bytes: &[u8] = match key_code {
-        1 => b"\r",      // Enter
-        2 => &[0x7f],    // Backspace
-        3 => b"\t",      // Tab
-        4 => &[0x1b],    // Escape
-        10 => b"\x1b[A", // Arrow Up
-        11 => b"\x1b[B", // Arrow Down
-        12 => b"\x1b[D", // Arrow Left
-        13 => b"\x1b[C", // Arrow Right
+        1 => b"\r",       // Enter
+        2 => &[0x7f],     // Backspace
+        3 => b"\t",       // Tab
+        4 => &[0x1b],     // Escape
+        10 => b"\x1b[A",  // Arrow Up
+        11 => b"\x1b[B",  // Arrow Down
+        12 => b"\x1b[D",  // Arrow Left
+        13 => b"\x1b[C",  // Arrow Right
+        14 => b"\x1b[5~", // Page Up
+        15 => b"\x1b[6~", // Page Down
+        16 => b"\x1b[H",  // Home
+        17 => b"\x1b[F",  // End
         _ => return,
     };
 
     let mut mgr = TERMINAL_MANAGER.lock().unwrap();
     if let Some(ref mut m) = *mgr {
+        if let Some(session) = m.active_session_mut() {
+            if session.scroll_mode {
+                let key = match key_code {
+                    4 => NavKey::Escape,
+                    10 => NavKey::Up,
+                    11 => NavKey::Down,
+                    14 => NavKey::PageUp,
+                    15 => NavKey::PageDown,
+                    16 => NavKey::Home,
+                    17 => NavKey::End,
+                    _ => return,
+                };
+                session.scroll_mode_key(key);
+                return;
+            }
+            session.send_typed(bytes);
+        }
+    }
+}
+
+/// `KeyEvent` meta state bits for the modifiers a hardware key is sent with
+const META_SHIFT_ON: i32 = 0x1;
+const META_ALT_ON: i32 = 0x2;
+const META_CTRL_ON: i32 = 0x1000;
+const META_META_ON: i32 = 0x10000;
+
+/// Character a hardware key types, from `KeyEvent.getUnicodeChar`
+fn hardware_char(unicode_char: i32) -> Option<char> {
+    char::from_u32(u32::try_from(unicode_char).ok()?).filter(|c| !c.is_control())
+}
+
+/// Bytes a hardware keyboard key sends, from Android's key code, meta state
+/// and the character the key types without Ctrl, Alt or Meta (0 for none).
+/// Meta (the Search or Windows key) acts as Alt. None for keys left to the
+/// system, such as volume or a modifier alone.
+fn hardware_key_bytes(
+    key_code: i32,
+    meta_state: i32,
+    unicode_char: i32,
+    protocol: KeyboardProtocol,
+) -> Option<Vec<u8>> {
+    let mods = KeyModifiers {
+        shift: meta_state & META_SHIFT_ON != 0,
+        alt: meta_state & (META_ALT_ON | META_META_ON) != 0,
+        ctrl: meta_state & META_CTRL_ON != 0,
+    };
+    let function = match key_code {
+        19 => FunctionKey::Up,
+        20 => FunctionKey::Down,
+        21 => FunctionKey::Left,
+        22 => FunctionKey::Right,
+        92 => FunctionKey::PageUp,
+        93 => FunctionKey::PageDown,
+        112 => FunctionKey::Delete, // Forward delete
+        122 => FunctionKey::Home,
+        123 => FunctionKey::End,
+        124 => FunctionKey::Insert,
+        131..=142 => FunctionKey::F((key_code - 130) as u8),
+        _ => {
+            let key = match key_code {
+                66 | 160 => Key::Enter, // Enter, numpad Enter
+                67 => Key::Backspace,
+                61 => Key::Tab,
+                111 => Key::Escape,
+                // A to Z, when the layout types nothing for the letter
+                29..=54 if unicode_char == 0 => {
+                    Key::Char((b'a' + (key_code - 29) as u8) as char)
+                }
+                _ => Key::Char(hardware_char(unicode_char)?),
+            };
+            return Some(encode_key(key, mods, protocol));
+        }
+    };
+    Some(encode_function_key(function, mods))
+}
+
+/// Scroll mode key for a hardware keyboard key
+fn hardware_nav_key(key_code: i32, unicode_char: i32) -> Option<NavKey> {
+    Some(match key_code {
+        19 => NavKey::Up,
+        20 => NavKey::Down,
+        92 => NavKey::PageUp,
+        93 => NavKey::PageDown,
+        122 => NavKey::Home,
+        123 => NavKey::End,
+        111 => NavKey::Escape,
+        _ => NavKey::Char(hardware_char(unicode_char)?),
+    })
+}
+
+/// Send a key pressed on a hardware keyboard to the active session, with
+/// Ctrl, Alt and Meta applied and function keys carrying xterm modifiers.
+/// `unicode_char` is the character the key types without Ctrl, Alt or Meta,
+/// or 0. Returns false for keys the terminal does not use, which the view
+/// should pass on.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_sendHardwareKey(
+    _env: JNIEnv,
+    _class: JClass,
+    key_code: jint,
+    meta_state: jint,
+    unicode_char: jint,
+) -> jboolean {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    let Some(session) = mgr.as_mut().and_then(|m| m.active_session_mut()) else {
+        return 0;
+    };
+    if session.scroll_mode {
+        let Some(key) = hardware_nav_key(key_code, unicode_char) else {
+            return 0;
+        };
+        session.scroll_mode_key(key);
+        return 1;
+    }
+    let protocol = session.grid.keyboard_protocol();
+    match hardware_key_bytes(key_code, meta_state, unicode_char, protocol) {
+        Some(bytes) => {
+            session.send_typed(&bytes);
+            1
+        }
+        None => 0,
+    }
+}
+
+/// Enter scroll mode on the active session: keys navigate scrollback
+/// (see `scroll_mode_action` for the key map) until Escape or `q`.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_enterScrollMode(
+    _env: JNIEnv,
+    _class: JClass,
+) {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(ref mut m) = *mgr {
+        if let Some(session) = m.active_session_mut() {
+            session.scroll_mode = true;
+        }
+    }
+}
+
+/// Leave scroll mode, snapping back to live output.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_exitScrollMode(
+    _env: JNIEnv,
+    _class: JClass,
+) {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(ref mut m) = *mgr {
+        if let Some(session) = m.active_session_mut() {
+            session.exit_scroll_mode();
+        }
+    }
+}
+
+/// Whether the active session is in scroll mode.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_isScrollMode(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jboolean {
+    let mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(ref m) = *mgr {
         if let Some(session) = m.active_session() {
-            session.send_input(bytes);
+            return session.scroll_mode as jboolean;
         }
-        // Snap to bottom on user input
+    }
+    0
+}
+
+/// Search the active session's scrollback upwards from the current match,
+/// selecting and scrolling to the next one. Returns whether it was found.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_searchScrollback(
+    mut env: JNIEnv,
+    _class: JClass,
+    query: JString,
+) -> jboolean {
+    let Ok(query) = env.get_string(&query) else {
+        return 0;
+    };
+    let query: String = query.into();
+
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(ref mut m) = *mgr {
         if let Some(session) = m.active_session_mut() {
-            session.grid.scroll_to_bottom();
+            let found = session.grid.search_backward(&query);
+            session.last_search = Some(query);
+            session.dirty = true;
+            return found as jboolean;
         }
     }
+    0
 }
 
 /// Set the font size to an exact value (in CSS px).
@@ -1621,6 +3103,9 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_setFontAction
 ///
 /// Positive `lines` = scroll up (into history / wheel up).
 /// Negative `lines` = scroll down (toward live / wheel down).
+///
+/// `lines` is finger travel; the scroll config scales and may reverse it,
+/// with the default moving one line per line of travel.
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_scroll(
     _env: JNIEnv,
@@ -1629,9 +3114,15 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_scroll(
     col: jint,
     row: jint,
 ) {
+    let config = *SCROLL_CONFIG.lock().unwrap();
     let mut mgr = TERMINAL_MANAGER.lock().unwrap();
     if let Some(ref mut m) = *mgr {
         if let Some(session) = m.active_session_mut() {
+            let notches = lines as f32 / DEFAULT_LINES_PER_NOTCH;
+            let lines = config.lines(notches, &mut session.scroll_carry);
+            if lines == 0 {
+                return;
+            }
             if session.grid.mouse_mode() != MouseMode::None {
                 // Forward as mouse wheel events to the application
                 // SGR: button 64 = wheel up, button 65 = wheel down
@@ -1650,6 +3141,12 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_scroll(
                     session.send_input(&data);
                 }
                 session.dirty = true;
+            } else if session.grid.alternate_screen() {
+                // Full-screen applications scroll themselves, so the
+                // history under them stays put
+                if ALTERNATE_SCROLL.load(Ordering::Relaxed) {
+                    session.send_typed(&alternate_scroll_keys(lines));
+                }
             } else {
                 session.grid.scroll_display(lines);
                 session.dirty = true;
@@ -1658,6 +3155,29 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_scroll(
     }
 }
 
+/// Arrow key presses standing in for scrolling `lines` (positive = up) in a
+/// full-screen application without mouse reporting, like xterm's
+/// alternateScroll, so pagers and editors still scroll
+fn alternate_scroll_keys(lines: i32) -> Vec<u8> {
+    let arrow: &[u8] = if lines > 0 { b"\x1b[A" } else { b"\x1b[B" };
+    arrow.repeat(lines.unsigned_abs() as usize)
+}
+
+/// Whether the active session is on the alternate screen, as full-screen
+/// applications such as vim and less are. Scrolling does not move the
+/// history then.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_isAlternateScreen(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jboolean {
+    let mgr = TERMINAL_MANAGER.lock().unwrap();
+    mgr.as_ref()
+        .and_then(|m| m.active_session())
+        .is_some_and(|session| session.grid.alternate_screen())
+        .into()
+}
+
 /// Get the current scroll offset (0 = at bottom/live).
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getScrollOffset(
@@ -1673,7 +3193,8 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getScrollOffs
     0
 }
 
-/// Get the maximum scroll offset (total scrollback lines).
+/// Get the maximum scroll offset (total scrollback rows, in the same
+/// physical-row unit as `getScrollOffset`).
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getScrollMax(
     _env: JNIEnv,
@@ -1688,28 +3209,109 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getScrollMax(
     0
 }
 
-/// Switch to the session at the given index.
+/// Get the number of logical scrollback lines, counting wrapped rows once.
 #[unsafe(no_mangle)]
-pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_switchSession(
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getLogicalScrollMax(
     _env: JNIEnv,
     _class: JClass,
-    index: jint,
-) {
-    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
-    if let Some(ref mut m) = *mgr {
-        let idx = index as usize;
-        if idx < m.sessions.len() {
-            m.active = idx;
-            if let Some(session) = m.sessions.get_mut(idx) {
-                session.dirty = true;
-            }
+) -> jint {
+    let mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(ref m) = *mgr {
+        if let Some(session) = m.active_session() {
+            return session.grid.logical_scrollback_len() as jint;
         }
     }
+    0
 }
 
-/// Close the session at the given index. Returns the number of remaining sessions.
+/// Scroll the active session back to the prompt above the viewport, marked
+/// by shell integration. Returns false at the first prompt.
 #[unsafe(no_mangle)]
-pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_closeSession(
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_scrollToPreviousPrompt(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jboolean {
+    jump_to_prompt(TerminalGrid::previous_prompt)
+}
+
+/// Scroll the active session forward to the next prompt. Returns false at
+/// the last prompt.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_scrollToNextPrompt(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jboolean {
+    jump_to_prompt(TerminalGrid::next_prompt)
+}
+
+/// Move the active session's viewport with `jump`, marking it for redraw
+/// when it moved.
+fn jump_to_prompt(jump: fn(&mut TerminalGrid) -> bool) -> jboolean {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    let Some(session) = mgr.as_mut().and_then(|m| m.active_session_mut()) else {
+        return 0;
+    };
+    let moved = jump(&mut session.grid);
+    session.dirty |= moved;
+    moved as jboolean
+}
+
+/// Get the output of the last command finished in the active session, as
+/// marked by shell integration, or null when there is none.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getLastCommandOutput<
+    'a,
+>(
+    env: JNIEnv<'a>,
+    _class: JClass<'a>,
+) -> JString<'a> {
+    let mgr = TERMINAL_MANAGER.lock().unwrap();
+    let output = mgr
+        .as_ref()
+        .and_then(|m| m.active_session())
+        .and_then(|s| s.grid.last_command_output());
+    drop(mgr);
+    output
+        .and_then(|text| env.new_string(&text).ok())
+        .unwrap_or_else(|| JObject::null().into())
+}
+
+/// Get the exit status of the active session's last finished command, as
+/// reported by shell integration, or -1 when unknown.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getLastExitCode(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jint {
+    let mgr = TERMINAL_MANAGER.lock().unwrap();
+    mgr.as_ref()
+        .and_then(|m| m.active_session())
+        .and_then(|s| s.grid.last_exit_code())
+        .unwrap_or(-1)
+}
+
+/// Switch to the session at the given index.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_switchSession(
+    _env: JNIEnv,
+    _class: JClass,
+    index: jint,
+) {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(ref mut m) = *mgr {
+        let idx = index as usize;
+        if idx < m.sessions.len() {
+            m.active = idx;
+            if let Some(session) = m.sessions.get_mut(idx) {
+                session.dirty = true;
+            }
+        }
+    }
+}
+
+/// Close the session at the given index. Returns the number of remaining sessions.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_closeSession(
     _env: JNIEnv,
     _class: JClass,
     index: jint,
@@ -1777,21 +3379,43 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getSessionLab
     _class: JClass<'a>,
     index: jint,
 ) -> JString<'a> {
-    let mgr = TERMINAL_MANAGER.lock().unwrap();
-    let label_owned = if let Some(ref m) = *mgr {
-        m.sessions
+    let label_owned = with_sessions(|sessions| {
+        sessions
             .get(index as usize)
             .map(|s| s.label.clone())
             .unwrap_or_default()
-    } else {
-        String::new()
-    };
-    drop(mgr);
+    })
+    .unwrap_or_default();
 
     env.new_string(&label_owned)
         .unwrap_or_else(|_| JObject::null().into())
 }
 
+/// Take the next pending bell, returning the index of the session that rang
+/// it, or -1 if none. Also drains session output, so bells are seen while
+/// the app is in the background and nothing is rendering. Call it
+/// periodically to raise notifications for commands that finish with a bell.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_takeBellEvent(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jint {
+    let now = std::time::Instant::now();
+    with_sessions(|sessions| {
+        // Nothing is drawn here, so there is no frame to keep responsive
+        for session in sessions.iter_mut() {
+            session.drain_output(usize::MAX);
+        }
+        // Bells in later sessions stay pending for the next call
+        sessions
+            .iter_mut()
+            .position(|session| session.take_bell_event(now))
+            .map(|idx| idx as jint)
+    })
+    .flatten()
+    .unwrap_or(-1)
+}
+
 /// Check whether the session at the given index is still alive (process has not exited).
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_isSessionAlive(
@@ -1824,6 +3448,82 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_selectionBegi
     }
 }
 
+/// Begin a rectangular (block) selection at the given grid coordinates.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_selectionBeginBlock(
+    _env: JNIEnv,
+    _class: JClass,
+    col: jint,
+    row: jint,
+) {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(ref mut m) = *mgr {
+        if let Some(session) = m.active_session_mut() {
+            session
+                .grid
+                .selection_begin_block(col as usize, row as usize);
+        }
+    }
+}
+
+/// Select the word at the given grid coordinates (double-tap).
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_selectWord(
+    _env: JNIEnv,
+    _class: JClass,
+    col: jint,
+    row: jint,
+) {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(ref mut m) = *mgr {
+        if let Some(session) = m.active_session_mut() {
+            session.grid.select_word(col as usize, row as usize);
+        }
+    }
+}
+
+/// Set the characters, besides whitespace, that end a word for double-tap
+/// selection, for every session.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_setWordSeparators(
+    mut env: JNIEnv,
+    _class: JClass,
+    separators: JString,
+) {
+    let Ok(separators) = env.get_string(&separators) else {
+        return;
+    };
+    let separators: String = separators.into();
+    with_sessions(|sessions| {
+        for session in sessions {
+            session.grid.set_word_separators(&separators);
+        }
+    });
+    *WORD_SEPARATORS.lock().unwrap() = Some(separators);
+}
+
+/// Set the cursor color for every session as `#rrggbb` or `rgb:r/g/b`.
+/// An empty or unparseable string goes back to drawing the cursor by
+/// inversion.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_setCursorColor(
+    mut env: JNIEnv,
+    _class: JClass,
+    color: JString,
+) {
+    let Ok(color) = env.get_string(&color) else {
+        return;
+    };
+    let color = parse_color(&String::from(color));
+    with_sessions(|sessions| {
+        for session in sessions {
+            session.grid.set_cursor_color(color);
+            session.dirty = true;
+        }
+    });
+    *CURSOR_COLOR.lock().unwrap() = color;
+}
+
 /// Set the terminal background color (r, g, b as 0.0-1.0).
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_setBackgroundColor(
@@ -1863,6 +3563,22 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_selectionUpda
     }
 }
 
+/// Clear the active session like the `clear` command. `scrollback` drops
+/// the history as well as the screen; `redraw` asks the shell to reprint
+/// its prompt.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_clearActiveSession(
+    _env: JNIEnv,
+    _class: JClass,
+    scrollback: jboolean,
+    redraw: jboolean,
+) {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(session) = mgr.as_mut().and_then(|m| m.active_session_mut()) {
+        session.clear(scrollback != 0, redraw != 0);
+    }
+}
+
 /// Clear the current text selection.
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_selectionClear(
@@ -1877,25 +3593,179 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_selectionClea
     }
 }
 
-/// Get the currently selected text.
+/// Get the active selection as `[startCol, startRow, endCol, endRow, block]`
+/// in viewport coordinates, normalized so start comes first, or null when
+/// nothing is selected. Rows are negative or past the last row when the
+/// selection extends off screen; `block` is 1 for a rectangular selection.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getSelectionRange<
+    'a,
+>(
+    env: JNIEnv<'a>,
+    _class: JClass<'a>,
+) -> JIntArray<'a> {
+    let mgr = TERMINAL_MANAGER.lock().unwrap();
+    let range = mgr
+        .as_ref()
+        .and_then(|m| m.active_session())
+        .and_then(|s| s.grid.selection_range());
+    drop(mgr);
+
+    let Some(range) = range else {
+        return JObject::null().into();
+    };
+    let values = [
+        range.start.0 as jint,
+        range.start.1 as jint,
+        range.end.0 as jint,
+        range.end.1 as jint,
+        range.block as jint,
+    ];
+    let Ok(array) = env.new_int_array(values.len() as jint) else {
+        return JObject::null().into();
+    };
+    if env.set_int_array_region(&array, 0, &values).is_err() {
+        return JObject::null().into();
+    }
+    array
+}
+
+/// Clipboard named by a JNI `target`: 0 for the clipboard, 1 for the
+/// primary selection.
+fn clipboard_type(target: jint) -> ClipboardType {
+    match target {
+        1 => ClipboardType::Selection,
+        _ => ClipboardType::Clipboard,
+    }
+}
+
+/// Get the currently selected text, copying it to `target` (0 clipboard,
+/// 1 primary selection).
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getSelectedText<'a>(
     env: JNIEnv<'a>,
     _class: JClass<'a>,
+    target: jint,
 ) -> JString<'a> {
     let mgr = TERMINAL_MANAGER.lock().unwrap();
-    let text = if let Some(ref m) = *mgr {
-        m.active_session()
-            .map(|s| s.grid.selected_text())
-            .unwrap_or_default()
-    } else {
-        String::new()
-    };
+    let text = mgr
+        .as_ref()
+        .and_then(|m| m.active_session())
+        .map(|s| {
+            CLIPBOARDS
+                .lock()
+                .unwrap()
+                .copy_selection(&s.grid, clipboard_type(target))
+        })
+        .unwrap_or_default();
+    drop(mgr);
+    env.new_string(&text)
+        .unwrap_or_else(|_| JObject::null().into())
+}
+
+/// Get the text last copied to `target` (0 clipboard, 1 primary
+/// selection), for pasting the primary selection on a middle click.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getClipboardText<
+    'a,
+>(
+    env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    target: jint,
+) -> JString<'a> {
+    let text = CLIPBOARDS
+        .lock()
+        .unwrap()
+        .get(clipboard_type(target))
+        .to_string();
+    env.new_string(&text)
+        .unwrap_or_else(|_| JObject::null().into())
+}
+
+/// Get the selection as text with SGR escapes, or the visible screen when
+/// nothing is selected, for copying with formatting.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getSelectedAnsi<'a>(
+    env: JNIEnv<'a>,
+    _class: JClass<'a>,
+) -> JString<'a> {
+    let mgr = TERMINAL_MANAGER.lock().unwrap();
+    let text = mgr
+        .as_ref()
+        .and_then(|m| m.active_session())
+        .map(|s| match s.grid.selection_range() {
+            Some(_) => s.grid.selected_ansi(),
+            None => s.grid.visible_ansi(),
+        })
+        .unwrap_or_default();
     drop(mgr);
     env.new_string(&text)
         .unwrap_or_else(|_| JObject::null().into())
 }
 
+/// Get the active session's working directory as reported by the shell
+/// (OSC 7), or an empty string when unknown.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getCwd<'a>(
+    env: JNIEnv<'a>,
+    _class: JClass<'a>,
+) -> JString<'a> {
+    let mgr = TERMINAL_MANAGER.lock().unwrap();
+    let cwd = mgr
+        .as_ref()
+        .and_then(|m| m.active_session())
+        .and_then(|s| s.grid.cwd().map(str::to_string))
+        .unwrap_or_default();
+    drop(mgr);
+    env.new_string(&cwd)
+        .unwrap_or_else(|_| JObject::null().into())
+}
+
+/// Describe the cell at viewport (col, row) of the active session as JSON,
+/// for context menus and hit testing: `text`, `fg` and `bg` (`#rrggbb`, bg
+/// null when unset), `bold`, `italic`, `underline`, `inverse`, `hyperlink`
+/// (null without one) and `selected`. Returns null outside the grid.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_cellInfoAt<'a>(
+    env: JNIEnv<'a>,
+    _class: JClass<'a>,
+    col: jint,
+    row: jint,
+) -> JString<'a> {
+    let (Ok(col), Ok(row)) = (usize::try_from(col), usize::try_from(row)) else {
+        return JObject::null().into();
+    };
+    let mgr = TERMINAL_MANAGER.lock().unwrap();
+    let info = mgr
+        .as_ref()
+        .and_then(|m| m.active_session())
+        .and_then(|s| s.grid.cell_info_at(col, row));
+    drop(mgr);
+    let Some(info) = info else {
+        return JObject::null().into();
+    };
+    env.new_string(cell_info_json(&info).to_string())
+        .unwrap_or_else(|_| JObject::null().into())
+}
+
+fn cell_info_json(info: &CellInfo) -> serde_json::Value {
+    let hex = |color: [f32; 4]| {
+        let [r, g, b, _] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+        format!("#{r:02x}{g:02x}{b:02x}")
+    };
+    serde_json::json!({
+        "text": info.text,
+        "fg": hex(info.fg),
+        "bg": info.bg.map(hex),
+        "bold": info.bold,
+        "italic": info.italic,
+        "underline": info.underline,
+        "inverse": info.inverse,
+        "hyperlink": info.hyperlink,
+        "selected": info.selected,
+    })
+}
+
 /// Get cell width in physical pixels.
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getCellWidth(
@@ -1924,26 +3794,87 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getCellHeight
     0.0
 }
 
-/// Get horizontal pixel offset where the grid starts (accounts for centering).
+/// Grid a surface of `width` x `height` physical pixels would get, fitted
+/// exactly as `resize` does, packed as `cols << 32 | rows`. 0 before init.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_recommendedGridSize(
+    _env: JNIEnv,
+    _class: JClass,
+    width: jint,
+    height: jint,
+) -> jlong {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(ref mut m) = *mgr {
+        let size = calc_grid(
+            width as f32,
+            height as f32,
+            m.scale,
+            &mut m.sugarloaf,
+            &m.rt_id,
+        );
+        return pack_grid_size(size);
+    }
+    0
+}
+
+/// Whether the font has loaded and cell dimensions are measured rather
+/// than estimated.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_areDimsConfirmed(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jboolean {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    if let Some(ref mut m) = *mgr {
+        let dims = m.sugarloaf.get_rich_text_dimensions(&m.rt_id);
+        return (dims.width > 0.0) as jboolean;
+    }
+    0
+}
+
+/// Get horizontal pixel offset where the grid starts (accounts for padding
+/// and centering).
 #[unsafe(no_mangle)]
 pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getGridOffsetX(
     _env: JNIEnv,
     _class: JClass,
 ) -> jfloat {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    mgr.as_mut().map_or(0.0, |m| m.grid_origin().0)
+}
+
+/// Get vertical pixel offset where the grid starts (accounts for padding
+/// and centering).
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_getGridOffsetY(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jfloat {
+    let mut mgr = TERMINAL_MANAGER.lock().unwrap();
+    mgr.as_mut().map_or(0.0, |m| m.grid_origin().1)
+}
+
+/// Set the space kept clear around the grid, in density-independent
+/// pixels, and refit the grid to what is left.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_setPadding(
+    _env: JNIEnv,
+    _class: JClass,
+    left: jfloat,
+    top: jfloat,
+    right: jfloat,
+    bottom: jfloat,
+) {
+    *PADDING.lock().unwrap() = Padding {
+        left: left.max(0.0),
+        top: top.max(0.0),
+        right: right.max(0.0),
+        bottom: bottom.max(0.0),
+    };
     let mut mgr = TERMINAL_MANAGER.lock().unwrap();
     if let Some(ref mut m) = *mgr {
-        let pad_px = PADDING_DP * m.scale;
-        let dims = m.sugarloaf.get_rich_text_dimensions(&m.rt_id);
-        let cell_w = if dims.width > 0.0 {
-            dims.width
-        } else {
-            18.0 * 0.6 * m.scale
-        };
-        let text_width = m.total_cols as f32 * cell_w;
-        let leftover = m.surface_width - text_width - 2.0 * pad_px;
-        return pad_px + (leftover / 2.0_f32).max(0.0);
+        m.regrid();
     }
-    0.0
 }
 
 /// Get the cursor column of the active session.
@@ -2012,3 +3943,516 @@ pub extern "system" fn Java_dev_omnidotdev_terminal_NativeTerminal_destroy(
     }
     *mgr = None;
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_level_ints_map_to_filters() {
+        assert_eq!(level_filter_from_int(0), Some(log::LevelFilter::Off));
+        assert_eq!(level_filter_from_int(3), Some(log::LevelFilter::Info));
+        assert_eq!(level_filter_from_int(5), Some(log::LevelFilter::Trace));
+        assert_eq!(level_filter_from_int(6), None);
+        assert_eq!(level_filter_from_int(-1), None);
+    }
+
+    #[test]
+    fn sigchld_watch_wakes_on_child_exit() {
+        let watch = SigchldWatch::new().expect("eventfd");
+
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            unsafe { libc::_exit(0) };
+        }
+        assert!(pid > 0, "fork failed");
+
+        let mut fds = [libc::pollfd {
+            fd: watch.event.0,
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        // The signal itself may interrupt the first poll
+        let ready = loop {
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), 1, 5000) };
+            if ready >= 0
+                || std::io::Error::last_os_error().kind()
+                    != std::io::ErrorKind::Interrupted
+            {
+                break ready;
+            }
+        };
+        assert_eq!(ready, 1);
+        watch.event.drain();
+
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    }
+
+    #[test]
+    fn forked_child_inherits_no_extra_fds() {
+        use std::os::fd::IntoRawFd;
+
+        let low = std::fs::File::open("/dev/null").unwrap().into_raw_fd();
+        // Beyond the old hardcoded 3..256 range
+        let high = unsafe { libc::dup2(low, 300) };
+        assert_eq!(high, 300);
+        let cloexec = std::fs::File::open("/dev/null").unwrap().into_raw_fd();
+        set_cloexec(cloexec);
+
+        // Both with `close_range` and through the fallback for older kernels
+        let close_listed = || {
+            close_listed_fds();
+        };
+        for close in [&close_inherited_fds as &dyn Fn(), &close_listed] {
+            match unsafe { libc::fork() } {
+                0 => {
+                    close();
+                    let leaked = [low, high, cloexec]
+                        .iter()
+                        .any(|fd| unsafe { libc::fcntl(*fd, libc::F_GETFD) } != -1);
+                    let stdio_ok =
+                        (0..=2).all(|fd| unsafe { libc::fcntl(fd, libc::F_GETFD) } != -1);
+                    unsafe { libc::_exit(if !leaked && stdio_ok { 0 } else { 1 }) };
+                }
+                pid => {
+                    assert!(pid > 0, "fork failed");
+                    let mut status = 0;
+                    unsafe { libc::waitpid(pid, &mut status, 0) };
+                    assert!(libc::WIFEXITED(status));
+                    assert_eq!(libc::WEXITSTATUS(status), 0);
+                }
+            }
+        }
+        assert_ne!(
+            unsafe { libc::fcntl(cloexec, libc::F_GETFD) } & libc::FD_CLOEXEC,
+            0
+        );
+        unsafe {
+            libc::close(low);
+            libc::close(high);
+            libc::close(cloexec);
+        }
+    }
+
+    #[test]
+    fn pty_child_falls_back_to_the_next_shell_and_dir() {
+        use std::io::Read;
+
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let dirs = [c"/nonexistent", c"/tmp"];
+        let script = r#"echo "$GREETING $PWD""#;
+        let images: Vec<ExecImage> = [
+            ExecImage::new("/nonexistent/sh", &["sh"], &["GREETING=hi"]),
+            ExecImage::new("/bin/sh", &["sh", "-c", script], &["GREETING=hi"]),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            exec_on_pty(pty.slave, &dirs, &images);
+            unsafe { libc::_exit(127) };
+        }
+        assert!(pid > 0, "fork failed");
+        drop(pty.slave);
+
+        // Reading the master fails with EIO once the child has gone
+        let mut output = Vec::new();
+        let _ = std::fs::File::from(pty.master).read_to_end(&mut output);
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert_eq!(libc::WEXITSTATUS(status), 0);
+        assert_eq!(String::from_utf8_lossy(&output).trim_end(), "hi /tmp");
+    }
+
+    /// Run `argv` on a fresh PTY driven by `pty_thread_main`, returning the
+    /// command sender, the output receiver and the PTY thread.
+    fn spawn_pty_thread(
+        argv: &[&std::ffi::CStr],
+    ) -> (
+        CommandSender,
+        mpsc::Receiver<Vec<u8>>,
+        thread::JoinHandle<()>,
+    ) {
+        use nix::pty::openpty;
+        use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+        let pty = openpty(None, None).expect("openpty failed");
+        let master_raw = pty.master.as_raw_fd();
+        set_cloexec(master_raw);
+        let sigchld = SigchldWatch::new();
+        let mut args: Vec<_> = argv.iter().map(|arg| arg.as_ptr()).collect();
+        args.push(std::ptr::null());
+
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            unsafe {
+                libc::setsid();
+                libc::ioctl(pty.slave.as_raw_fd(), libc::TIOCSCTTY, 0);
+                libc::dup2(pty.slave.as_raw_fd(), 0);
+                libc::dup2(pty.slave.as_raw_fd(), 1);
+                libc::dup2(pty.slave.as_raw_fd(), 2);
+                libc::execv(args[0], args.as_ptr());
+                libc::_exit(127);
+            }
+        }
+        assert!(pid > 0, "fork failed");
+        drop(pty.slave);
+        unsafe {
+            let flags = libc::fcntl(master_raw, libc::F_GETFL);
+            libc::fcntl(master_raw, libc::F_SETFL, flags | libc::O_NONBLOCK);
+        }
+        let master = pty.master;
+        std::mem::forget(master);
+
+        let (cmd_tx, cmd_rx) = command_channel();
+        let (out_tx, out_rx) = mpsc::channel::<Vec<u8>>();
+        let worker = thread::spawn(move || {
+            let master = unsafe { OwnedFd::from_raw_fd(master_raw) };
+            pty_thread_main(
+                master,
+                nix::unistd::Pid::from_raw(pid),
+                sigchld,
+                &cmd_rx,
+                &out_tx,
+            );
+        });
+        (cmd_tx, out_rx, worker)
+    }
+
+    /// Echo-latency micro-benchmark: send one byte through a PTY running
+    /// `cat` and time the round trip. Run with
+    /// `cargo test -p omni-terminal-android -- --ignored --nocapture echo_round_trip`.
+    #[test]
+    #[ignore]
+    fn echo_round_trip_latency() {
+        const ROUNDS: u32 = 200;
+
+        let (cmd_tx, out_rx, worker) = spawn_pty_thread(&[c"/bin/cat"]);
+
+        let mut total = std::time::Duration::ZERO;
+        for _ in 0..ROUNDS {
+            let start = std::time::Instant::now();
+            cmd_tx.send(PtyCommand::Input(b"x".to_vec())).unwrap();
+            // The line discipline echoes the byte immediately
+            let echoed = out_rx
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .expect("no echo from PTY");
+            assert!(!echoed.is_empty());
+            total += start.elapsed();
+        }
+
+        println!(
+            "echo round trip: {:?} avg over {ROUNDS} rounds",
+            total / ROUNDS
+        );
+
+        cmd_tx.send(PtyCommand::Disconnect).unwrap();
+        worker.join().unwrap();
+    }
+
+    /// Bulk-output throughput benchmark: `cat` a 64 MB file through the PTY
+    /// thread into a session and time it until parsed. Run with
+    /// `cargo test -p omni-terminal-android -- --ignored --nocapture cat_throughput`.
+    #[test]
+    #[ignore]
+    fn cat_throughput() {
+        const SIZE: usize = 64 * 1024 * 1024;
+
+        let path = std::env::temp_dir().join(format!("omni-cat-{}", std::process::id()));
+        let line = b"the quick brown fox jumps over the lazy dog 0123456789\n";
+        let data: Vec<u8> = line.iter().copied().cycle().take(SIZE).collect();
+        std::fs::write(&path, &data).unwrap();
+        let path_arg = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+
+        let start = std::time::Instant::now();
+        let (_cmd_tx, out_rx, worker) = spawn_pty_thread(&[c"/bin/cat", &path_arg]);
+        let mut session = Session::new(120, 40, "bench".to_string());
+        let (mut bytes, mut chunks) = (0, 0);
+        // The channel closes once the PTY thread sees `cat` exit
+        while let Ok(chunk) = out_rx.recv_timeout(std::time::Duration::from_secs(10)) {
+            bytes += chunk.len();
+            chunks += 1;
+            session.output.push(&chunk);
+            while session
+                .output
+                .advance(&mut session.grid, OUTPUT_BYTES_PER_FRAME)
+            {}
+        }
+        let elapsed = start.elapsed();
+        worker.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The line discipline turns each LF into CR LF
+        assert!(bytes >= SIZE, "only {bytes} bytes arrived");
+        println!(
+            "cat {} MB: {elapsed:?}, {:.0} MB/s, {chunks} reads of {} bytes avg",
+            SIZE >> 20,
+            bytes as f64 / elapsed.as_secs_f64() / 1e6,
+            bytes / chunks.max(1),
+        );
+    }
+
+    #[test]
+    fn fingerprint_parsing_accepts_common_formats() {
+        let expected: [u8; 32] = std::array::from_fn(|i| i as u8 * 7);
+        let plain: String = expected.iter().map(|b| format!("{b:02x}")).collect();
+        let openssl = expected
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>()
+            .join(":");
+
+        assert_eq!(parse_fingerprint(&plain), Some(expected));
+        assert_eq!(parse_fingerprint(&openssl), Some(expected));
+        assert_eq!(parse_fingerprint(&plain[..62]), None);
+        assert_eq!(parse_fingerprint(&format!("{plain}00")), None);
+        assert_eq!(parse_fingerprint(&plain.replace('0', "g")), None);
+    }
+
+    #[test]
+    fn fingerprint_comparison_matches_only_the_pinned_cert() {
+        let cert = b"not really DER, but any bytes hash the same way";
+        let pin = ring::digest::digest(&ring::digest::SHA256, cert);
+        let pin: [u8; 32] = pin.as_ref().try_into().unwrap();
+
+        assert!(fingerprint_matches(&pin, cert));
+        assert!(!fingerprint_matches(&pin, b"a different certificate"));
+
+        let mut wrong = pin;
+        wrong[31] ^= 1;
+        assert!(!fingerprint_matches(&wrong, cert));
+    }
+
+    #[test]
+    fn disabled_input_is_dropped_while_output_renders() {
+        let (cmd_tx, cmd_rx) = command_channel();
+        let (out_tx, out_rx) = mpsc::channel::<Vec<u8>>();
+        let mut session = Session::new(20, 4, "viewer".to_string());
+        session.ws_tx = Some(cmd_tx);
+        session.ws_rx = Some(out_rx);
+        session.local_mode = true;
+
+        session.input_enabled = false;
+        assert!(!session.send_typed(b"ls\r"));
+        assert!(cmd_rx.rx.try_recv().is_err());
+
+        // Output still renders, and query replies still reach the PTY
+        out_tx.send(b"hello\x1b[18t".to_vec()).unwrap();
+        session.drain_output(OUTPUT_BYTES_PER_FRAME);
+        assert!(session.dirty);
+        assert_eq!(session.grid.cells[0][0].c, 'h');
+        assert!(matches!(
+            cmd_rx.rx.try_recv(),
+            Ok(PtyCommand::Input(reply)) if reply == b"\x1b[8;4;20t"
+        ));
+
+        session.input_enabled = true;
+        assert!(session.send_typed(b"ls\r"));
+        assert!(matches!(
+            cmd_rx.rx.try_recv(),
+            Ok(PtyCommand::Input(input)) if input == b"ls\r"
+        ));
+    }
+
+    #[test]
+    fn paste_is_bracketed_only_when_requested() {
+        let (cmd_tx, cmd_rx) = command_channel();
+        let mut session = Session::new(20, 4, "paste".to_string());
+        session.ws_tx = Some(cmd_tx);
+        session.local_mode = true;
+        let sent = || match cmd_rx.rx.try_recv() {
+            Ok(PtyCommand::Input(data)) => Some(data),
+            _ => None,
+        };
+
+        // Unbracketed: lines are joined and control bytes dropped
+        assert!(session.paste("ls\n\x07pwd", PasteMode::Sanitize, false));
+        assert_eq!(sent().as_deref(), Some(&b"ls pwd"[..]));
+        assert!(!session.paste("a\nb", PasteMode::Warn, false));
+        assert_eq!(sent(), None);
+
+        session.grid.feed(b"\x1b[?2004h");
+        assert!(session.paste("ls\npwd", PasteMode::Warn, false));
+        assert_eq!(sent().as_deref(), Some(&b"\x1b[200~ls\rpwd\x1b[201~"[..]));
+    }
+
+    #[test]
+    fn clearing_a_session_can_keep_or_drop_scrollback() {
+        let (cmd_tx, cmd_rx) = command_channel();
+        let mut session = Session::new(20, 4, "clear".to_string());
+        session.ws_tx = Some(cmd_tx);
+        session.local_mode = true;
+        for i in 0..10 {
+            session.grid.feed(format!("line{i}\r\n").as_bytes());
+        }
+        let history = session.grid.scrollback_len();
+
+        session.dirty = false;
+        session.clear(false, false);
+        assert!(session.dirty);
+        assert_eq!(session.grid.scrollback_len(), history);
+        assert!(cmd_rx.rx.try_recv().is_err());
+
+        session.clear(true, true);
+        assert_eq!(session.grid.scrollback_len(), 0);
+        assert!(session
+            .grid
+            .cells
+            .iter()
+            .flatten()
+            .all(|cell| cell.c == ' '));
+        assert_eq!((session.grid.cursor_col, session.grid.cursor_row), (0, 0));
+        assert!(matches!(
+            cmd_rx.rx.try_recv(),
+            Ok(PtyCommand::Input(input)) if input == b"\x0c"
+        ));
+    }
+
+    #[test]
+    fn cell_info_serializes_for_jni() {
+        let mut grid = TerminalGrid::new(10, 2);
+        grid.feed(b"\x1b[1;44m\x1b]8;;https://example.com\x07a\x1b]8;;\x07\x1b[0mb");
+        let json = cell_info_json(&grid.cell_info_at(0, 0).unwrap());
+        assert_eq!(json["text"], "a");
+        assert_eq!(json["fg"], "#ffffff");
+        assert!(json["bg"].as_str().is_some_and(|bg| bg.starts_with('#')));
+        assert_eq!(json["bold"], true);
+        assert_eq!(json["hyperlink"], "https://example.com");
+        assert_eq!(json["selected"], false);
+
+        let json = cell_info_json(&grid.cell_info_at(1, 0).unwrap());
+        assert!(json["bg"].is_null() && json["hyperlink"].is_null());
+    }
+
+    #[test]
+    fn padding_shrinks_the_grid() {
+        let cell = (10.0, 20.0);
+        let none = Padding {
+            left: 0.0,
+            top: 0.0,
+            right: 0.0,
+            bottom: 0.0,
+        };
+        assert_eq!(grid_size(805.0, 610.0, cell, none), (80, 30));
+
+        let padding = Padding {
+            left: 10.0,
+            top: 40.0,
+            right: 10.0,
+            bottom: 20.0,
+        };
+        assert_eq!(grid_size(805.0, 610.0, cell, padding), (78, 27));
+
+        // Padding wider than the surface still leaves one cell
+        let huge = Padding {
+            left: 1000.0,
+            ..padding
+        };
+        assert_eq!(grid_size(805.0, 610.0, cell, huge).0, 1);
+    }
+
+    #[test]
+    fn recommended_grid_size_packs_what_resize_fits() {
+        let padding = DEFAULT_PADDING.scaled(2.5);
+        let size = grid_size(1080.0, 2200.0, (21.0, 44.0), padding);
+        let packed = pack_grid_size(size);
+        // Unpacked as the Kotlin side does
+        let (cols, rows) = ((packed >> 32) as i32, packed as i32);
+        assert_eq!((cols as usize, rows as usize), size);
+
+        // The same surface resized to that grid fills it
+        let mut grid = TerminalGrid::new(80, 24);
+        grid.resize(size.0, size.1);
+        assert_eq!((grid.cols, grid.rows), size);
+    }
+
+    #[test]
+    fn hardware_keys_encode_with_modifiers() {
+        const SHIFT: i32 = META_SHIFT_ON;
+        const ALT: i32 = META_ALT_ON;
+        const CTRL: i32 = META_CTRL_ON;
+        // (key code, meta state, character typed, bytes)
+        let table: &[(i32, i32, i32, &[u8])] = &[
+            (29, 0, 'a' as i32, b"a"),
+            (29, SHIFT, 'A' as i32, b"A"),
+            (31, CTRL, 'c' as i32, &[0x03]),
+            (31, CTRL, 0, &[0x03]),
+            (31, CTRL | ALT, 'c' as i32, &[0x1b, 0x03]),
+            (30, ALT, 'b' as i32, b"\x1bb"),
+            (30, META_META_ON, 'b' as i32, b"\x1bb"),
+            (33, ALT, 'é' as i32, "\x1bé".as_bytes()),
+            (67, 0, 0, &[0x7f]),
+            (67, ALT, 0, b"\x1b\x7f"),
+            (66, 0, 0, b"\r"),
+            (160, 0, 0, b"\r"),
+            (61, 0, 0, b"\t"),
+            (111, 0, 0, &[0x1b]),
+            (19, 0, 0, b"\x1b[A"),
+            (22, CTRL, 0, b"\x1b[1;5C"),
+            (21, SHIFT | ALT, 0, b"\x1b[1;4D"),
+            (122, 0, 0, b"\x1b[H"),
+            (123, SHIFT, 0, b"\x1b[1;2F"),
+            (92, 0, 0, b"\x1b[5~"),
+            (112, CTRL, 0, b"\x1b[3;5~"),
+            (131, 0, 0, b"\x1bOP"),
+            (135, 0, 0, b"\x1b[15~"),
+            (142, SHIFT, 0, b"\x1b[24;2~"),
+        ];
+        for &(key_code, meta, ch, bytes) in table {
+            assert_eq!(
+                hardware_key_bytes(key_code, meta, ch, KeyboardProtocol::Legacy)
+                    .as_deref(),
+                Some(bytes),
+                "key {key_code} meta {meta:#x}"
+            );
+        }
+
+        // Volume and a lone Ctrl are left to the system
+        assert_eq!(hardware_key_bytes(24, 0, 0, KeyboardProtocol::Legacy), None);
+        assert_eq!(
+            hardware_key_bytes(113, CTRL, 0, KeyboardProtocol::Legacy),
+            None
+        );
+        assert_eq!(
+            hardware_key_bytes(31, CTRL | SHIFT, 'C' as i32, KeyboardProtocol::CsiU),
+            Some(b"\x1b[99;6u".to_vec())
+        );
+    }
+
+    #[test]
+    fn alternate_scroll_sends_one_arrow_per_line() {
+        assert_eq!(alternate_scroll_keys(3), b"\x1b[A\x1b[A\x1b[A");
+        assert_eq!(alternate_scroll_keys(-2), b"\x1b[B\x1b[B");
+        assert!(alternate_scroll_keys(0).is_empty());
+    }
+
+    #[test]
+    fn swipes_track_the_finger_by_default() {
+        let mut carry = 0.0;
+        for lines in [1, 2, -1, 5, -3] {
+            let notches = lines as f32 / DEFAULT_LINES_PER_NOTCH;
+            assert_eq!(ScrollConfig::DEFAULT.lines(notches, &mut carry), lines);
+        }
+        let fast = ScrollConfig::new(6.0, false);
+        assert_eq!(fast.lines(2.0 / DEFAULT_LINES_PER_NOTCH, &mut carry), 4);
+    }
+
+    #[test]
+    fn grid_is_centered_inside_the_padding() {
+        let padding = Padding {
+            left: 10.0,
+            top: 40.0,
+            right: 10.0,
+            bottom: 20.0,
+        };
+        // 5px left over across, 10px down
+        assert_eq!(
+            grid_origin(805.0, 610.0, (78, 27), (10.0, 20.0), padding),
+            (12.5, 45.0)
+        );
+        assert_eq!(DEFAULT_PADDING.scaled(2.0).left, 4.0);
+    }
+}
@@ -9,17 +9,62 @@ object NativeTerminal {
 
     external fun init(surface: Surface, width: Int, height: Int, scale: Float)
     external fun connect(url: String)
+
+    // Skips TLS certificate verification, exposing the session to
+    // man-in-the-middle attacks. Development servers on trusted networks only
+    external fun connectInsecure(url: String)
+
+    // Trusts only a server certificate whose SHA-256 fingerprint matches
+    // (hex, colons optional), e.g. a self-signed development server
+    external fun connectPinned(url: String, sha256Hex: String)
     external fun connectLocal(filesDir: String, nativeLibDir: String)
+
+    // Ask servers to deflate output on later connections (saves mobile data)
+    external fun setCompression(enabled: Boolean)
+
+    // New local shell in the active session's directory ($HOME if unknown)
+    external fun connectLocalInheritCwd(filesDir: String, nativeLibDir: String)
     external fun connectLocalProot(filesDir: String, rootfsPath: String, prootPath: String, nativeLibDir: String)
     external fun render()
     external fun resize(width: Int, height: Int, scale: Float)
     external fun destroySurface()
     external fun destroy()
 
+    // Logging: 0=off, 1=error, 2=warn, 3=info (default), 4=debug, 5=trace
+    external fun setLogLevel(level: Int)
+
     // Input
     external fun sendKey(text: String)
     external fun sendSpecialKey(keyCode: Int)
 
+    // Single character with MOD_* bits held (CSI u when the app requests it)
+    external fun sendModifiedKey(text: String, modifiers: Int)
+
+    // Hardware keyboard key: KeyEvent key code and meta state, with the
+    // character the key types without Ctrl, Alt or Meta (0 for none).
+    // Returns false for keys the terminal does not use
+    external fun sendHardwareKey(keyCode: Int, metaState: Int, unicodeChar: Int): Boolean
+
+    // Paste clipboard text, sanitized per the paste mode (PASTE_*). Returns
+    // false without sending when a multi-line paste needs confirming; call
+    // again with confirmed = true once the user agrees
+    external fun sendPaste(text: String, confirmed: Boolean): Boolean
+    external fun setPasteMode(mode: Int)
+
+    // Display-only viewer: drop keyboard and paste input to the active
+    // session while output keeps rendering. Selection and mouse reporting
+    // still work
+    external fun setInputEnabled(enabled: Boolean)
+
+    // Scroll mode: keys navigate scrollback instead of reaching the shell.
+    // Arrows or j/k move a line, PageUp/PageDown or b/f a screen, Home/End
+    // or g/G to the top/bottom, / searches upwards (the UI prompts and calls
+    // searchScrollback), n repeats the search, Escape or q exits
+    external fun enterScrollMode()
+    external fun exitScrollMode()
+    external fun isScrollMode(): Boolean
+    external fun searchScrollback(query: String): Boolean
+
     // Font size
     external fun setFontSize(size: Float)
     external fun getFontSize(): Float
@@ -30,12 +75,44 @@ object NativeTerminal {
     // Background color
     external fun setBackgroundColor(r: Float, g: Float, b: Float)
 
-    // Scroll by lines or forward mouse wheel to app (positive=up, negative=down)
+    // Cursor color as "#rrggbb"; empty draws the cursor by inversion
+    external fun setCursorColor(color: String)
+
+    // Scroll by lines or forward mouse wheel to app (positive=up, negative=down).
+    // History does not move on the alternate screen
     external fun scroll(lines: Int, col: Int, row: Int)
 
+    // Lines per wheel notch (0.5-20, default 3; swipes move a third of that
+    // per line of travel) and reversed (natural) scrolling
+    external fun setScrollConfig(linesPerNotch: Float, inverted: Boolean)
+
+    // Whether a full-screen app (vim, less) is on the alternate screen
+    external fun isAlternateScreen(): Boolean
+
+    // Scrolling a full-screen app without mouse support sends arrow keys
+    external fun setAlternateScroll(enabled: Boolean)
+
     // Scroll position queries
     external fun getScrollOffset(): Int
     external fun getScrollMax(): Int
+    // Scrollback in logical lines (wrapped rows counted once), for sizing
+    external fun getLogicalScrollMax(): Int
+    // Scrollbar overlay shown while scrolled back, fading out at the bottom
+    external fun setScrollbar(enabled: Boolean)
+
+    // Jump between prompts marked by shell integration (OSC 133); false
+    // once at the first or last one
+    external fun scrollToPreviousPrompt(): Boolean
+    external fun scrollToNextPrompt(): Boolean
+    // Output of the last finished command, null without shell integration
+    external fun getLastCommandOutput(): String?
+    // Exit status of the last finished command, -1 when unknown
+    external fun getLastExitCode(): Int
+
+    // Blank the active session's screen and home the cursor, like `clear`.
+    // `scrollback` drops the history too; `redraw` sends Ctrl+L so the
+    // shell reprints its prompt
+    external fun clearActiveSession(scrollback: Boolean, redraw: Boolean)
 
     // Session management
     external fun switchSession(index: Int)
@@ -49,13 +126,84 @@ object NativeTerminal {
     external fun selectionBegin(col: Int, row: Int)
     external fun selectionUpdate(col: Int, row: Int)
     external fun selectionClear()
-    external fun getSelectedText(): String
+    // Selected text, also kept as the contents of `target`
+    // (CLIPBOARD or SELECTION)
+    external fun getSelectedText(target: Int): String
+
+    // Text last copied to `target`; Android has no primary selection, so
+    // SELECTION is only held natively
+    external fun getClipboardText(target: Int): String
+
+    // Selection (or the whole screen when nothing is selected) with colors
+    // and styles as SGR escapes
+    external fun getSelectedAnsi(): String
+
+    // Select the word under a cell; words end at whitespace and the
+    // separators given to setWordSeparators (by default not / . or -, so
+    // paths and URLs select whole)
+    external fun selectWord(col: Int, row: Int)
+    external fun setWordSeparators(separators: String)
+
+    // Rectangular selection; update and clear as for a normal one
+    external fun selectionBeginBlock(col: Int, row: Int)
+
+    // [startCol, startRow, endCol, endRow, block] in viewport cells, or null
+    // when nothing is selected. Rows outside the screen mean the selection
+    // continues off screen
+    external fun getSelectionRange(): IntArray?
+
+    // Index of a session that rang the bell since the last call (debounced),
+    // or -1. Also keeps background sessions drained while nothing renders
+    external fun takeBellEvent(): Int
+
+    // Working directory reported by the shell (OSC 7); empty when unknown
+    external fun getCwd(): String
+
+    // What is under a tap, as JSON: text, fg, bg, bold, italic, underline,
+    // inverse, hyperlink and selected. Null outside the grid
+    external fun cellInfoAt(col: Int, row: Int): String?
+
     external fun getCellWidth(): Float
     external fun getCellHeight(): Float
+
+    // Grid a surface of this many pixels gets, fitted to the cell size and
+    // padding as on resize: cols in the high 32 bits, rows in the low
+    external fun recommendedGridSize(widthPx: Int, heightPx: Int): Long
+
+    // False while the font is still loading and cell sizes are estimates
+    external fun areDimsConfirmed(): Boolean
+
     external fun getGridOffsetX(): Float
+    external fun getGridOffsetY(): Float
+
+    // Space kept clear around the grid on each side, in dp. The grid is
+    // refitted to the rest and centered in any leftover space
+    external fun setPadding(left: Float, top: Float, right: Float, bottom: Float)
     external fun getCursorCol(): Int
     external fun getCursorRow(): Int
 
+    // Log levels
+    const val LOG_OFF = 0
+    const val LOG_ERROR = 1
+    const val LOG_WARN = 2
+    const val LOG_INFO = 3
+    const val LOG_DEBUG = 4
+    const val LOG_TRACE = 5
+
+    // Paste modes, applied when the app has not enabled bracketed paste
+    const val PASTE_SANITIZE = 0
+    const val PASTE_WARN = 1
+    const val PASTE_RAW = 2
+
+    // Copy targets for getSelectedText and getClipboardText
+    const val CLIPBOARD = 0
+    const val SELECTION = 1
+
+    // Modifier bits for sendModifiedKey
+    const val MOD_SHIFT = 1
+    const val MOD_ALT = 2
+    const val MOD_CTRL = 4
+
     // Special key codes
     const val KEY_ENTER = 1
     const val KEY_BACKSPACE = 2
@@ -65,4 +213,8 @@ object NativeTerminal {
     const val KEY_ARROW_DOWN = 11
     const val KEY_ARROW_LEFT = 12
     const val KEY_ARROW_RIGHT = 13
+    const val KEY_PAGE_UP = 14
+    const val KEY_PAGE_DOWN = 15
+    const val KEY_HOME = 16
+    const val KEY_END = 17
 }
@@ -3,6 +3,7 @@ package dev.omnidotdev.terminal
 import android.annotation.SuppressLint
 import android.app.AlertDialog
 import android.content.Intent
+import android.net.ConnectivityManager
 import android.os.Build
 import android.os.Bundle
 import android.util.DisplayMetrics
@@ -11,7 +12,6 @@ import android.view.Gravity
 import android.view.MotionEvent
 import android.view.ScaleGestureDetector
 import android.view.SurfaceHolder
-import android.view.View
 import android.view.ViewGroup.LayoutParams
 import android.widget.EditText
 import android.widget.FrameLayout
@@ -34,7 +34,6 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
     private lateinit var toolbar: LinearLayout
     private lateinit var tabBar: LinearLayout
     private lateinit var tabContainer: LinearLayout
-    private lateinit var scrollIndicator: View
     private lateinit var scaleDetector: ScaleGestureDetector
     private lateinit var gestureDetector: GestureDetector
     private var initialized = false
@@ -45,7 +44,6 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
         override fun run() {
             if (initialized) {
                 NativeTerminal.render()
-                updateScrollIndicator()
                 reapExitedSessions()
                 renderHandler.postDelayed(this, 16) // ~60fps
             }
@@ -86,6 +84,8 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
         surfaceView = TerminalSurfaceView(this)
         surfaceView.contentDescription = getString(R.string.cd_terminal_surface)
         surfaceView.holder.addCallback(this)
+        surfaceView.onPaste = { text -> pasteText(text) }
+        surfaceView.onScrollSearch = { showScrollSearchDialog() }
         container.addView(surfaceView, LinearLayout.LayoutParams(
             LayoutParams.MATCH_PARENT,
             0,
@@ -104,16 +104,6 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
             LayoutParams.MATCH_PARENT,
         ))
 
-        scrollIndicator = View(this).apply {
-            setBackgroundColor(0x66FFFFFF.toInt())
-            visibility = View.GONE
-        }
-        root.addView(scrollIndicator, FrameLayout.LayoutParams(
-            4,
-            40,
-            Gravity.END,
-        ))
-
         setContentView(root)
 
         // Handle system bars, display cutout, and keyboard insets
@@ -152,7 +142,7 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
 
     private fun createTabBar(): LinearLayout {
         val density = resources.displayMetrics.density
-        val hPad = (2 * density).toInt() // Match terminal PADDING_DP
+        val hPad = (2 * density).toInt() // Match the terminal's default padding
         val vPad = (6 * density).toInt()
         val bar = LinearLayout(this).apply {
             orientation = LinearLayout.HORIZONTAL
@@ -277,7 +267,7 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
             }.start()
             return
         }
-        connectLocalOrProot()
+        connectLocalOrProot(inheritCwd = true)
         refreshTabBar()
         startTerminalService()
     }
@@ -302,6 +292,7 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
                 if (raw.isNotEmpty()) {
                     val wsUrl = ConnectActivity.normalizeWsUrl(raw)
                     prefs.edit().putString(ConnectActivity.PREF_SERVER_URL, raw).apply()
+                    updateCompression()
                     NativeTerminal.connect(wsUrl)
                     refreshTabBar()
                     startTerminalService()
@@ -311,7 +302,8 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
             .show()
     }
 
-    private fun connectLocalOrProot() {
+    // inheritCwd starts a plain local shell in the active session's directory
+    private fun connectLocalOrProot(inheritCwd: Boolean = false) {
         val nativeLibDir = applicationInfo.nativeLibraryDir
         if (ProotEnvironment.isInstalled(this)) {
             NativeTerminal.connectLocalProot(
@@ -320,6 +312,8 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
                 "$nativeLibDir/libproot.so",
                 nativeLibDir,
             )
+        } else if (inheritCwd) {
+            NativeTerminal.connectLocalInheritCwd(filesDir.absolutePath, nativeLibDir)
         } else {
             NativeTerminal.connectLocal(filesDir.absolutePath, nativeLibDir)
         }
@@ -435,7 +429,7 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
 
         val keys = listOf(
             Key("ESC", 0, 0, { NativeTerminal.sendSpecialKey(NativeTerminal.KEY_ESCAPE) }),
-            Key("/", 0, 1, { NativeTerminal.sendKey("/") }),
+            Key("/", 0, 1, { surfaceView.sendText("/") }),
             Key("|", 0, 2, { NativeTerminal.sendKey("|") }),
             Key("-", 0, 3, { NativeTerminal.sendKey("-") }),
             Key("~", 0, 4, { NativeTerminal.sendKey("~") }),
@@ -540,10 +534,18 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
         surfaceView.setOnTouchListener { _, event ->
             scaleDetector.onTouchEvent(event)
             gestureDetector.onTouchEvent(event)
+            // Middle click pastes the primary selection
+            if (event.actionMasked == MotionEvent.ACTION_BUTTON_PRESS &&
+                event.actionButton == MotionEvent.BUTTON_TERTIARY
+            ) {
+                val text = NativeTerminal.getClipboardText(NativeTerminal.SELECTION)
+                if (text.isNotEmpty()) pasteText(text)
+            }
             if (event.action == MotionEvent.ACTION_UP) {
                 if (selecting) {
                     selecting = false
-                    val text = NativeTerminal.getSelectedText()
+                    NativeTerminal.getSelectedText(NativeTerminal.SELECTION)
+                    val text = NativeTerminal.getSelectedText(NativeTerminal.CLIPBOARD)
                     if (text.isNotEmpty()) {
                         val clipboard = getSystemService(android.content.Context.CLIPBOARD_SERVICE) as android.content.ClipboardManager
                         clipboard.setPrimaryClip(android.content.ClipData.newPlainText("terminal", text))
@@ -580,6 +582,11 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
             val theme = TerminalPreferences.getTheme(this)
             applyTheme(theme)
 
+            NativeTerminal.setPasteMode(TerminalPreferences.getPasteMode(this))
+            NativeTerminal.setScrollbar(TerminalPreferences.getShowScrollbar(this))
+            NativeTerminal.setAlternateScroll(TerminalPreferences.getAlternateScroll(this))
+            applyScrollConfig()
+
             // Check if sessions were restored from a previous surface
             val restored = NativeTerminal.getSessionCount() > 0
 
@@ -591,11 +598,13 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
                 } else {
                     serverUrl = intent.getStringExtra(ConnectActivity.EXTRA_SERVER_URL)
                     if (serverUrl != null) {
+                        updateCompression()
                         NativeTerminal.connect(serverUrl!!)
                     }
                 }
             }
 
+            switchToRequestedSession()
             refreshTabBar()
             startTerminalService()
 
@@ -616,6 +625,34 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
         }
     }
 
+    override fun onResume() {
+        super.onResume()
+        TerminalService.appVisible = true
+    }
+
+    override fun onPause() {
+        TerminalService.appVisible = false
+        super.onPause()
+    }
+
+    override fun onNewIntent(intent: Intent) {
+        super.onNewIntent(intent)
+        setIntent(intent)
+        if (initialized) {
+            switchToRequestedSession()
+            refreshTabBar()
+        }
+    }
+
+    // Bell notifications open the tab that rang
+    private fun switchToRequestedSession() {
+        val index = intent.getIntExtra(TerminalService.EXTRA_SESSION_INDEX, -1)
+        if (index in 0 until NativeTerminal.getSessionCount()) {
+            NativeTerminal.switchSession(index)
+        }
+        intent.removeExtra(TerminalService.EXTRA_SESSION_INDEX)
+    }
+
     override fun onDestroy() {
         renderHandler.removeCallbacks(renderRunnable)
         if (isFinishing) {
@@ -628,32 +665,19 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
         super.onDestroy()
     }
 
-    private fun updateScrollIndicator() {
-        val offset = NativeTerminal.getScrollOffset()
-        val max = NativeTerminal.getScrollMax()
-        if (offset == 0 || max == 0) {
-            scrollIndicator.visibility = View.GONE
-            return
-        }
-        scrollIndicator.visibility = View.VISIBLE
-
-        val surfaceHeight = surfaceView.height
-        val indicatorHeight = (surfaceHeight * surfaceView.height / (surfaceHeight + max * 20)).coerceAtLeast(20)
-        val position = ((max - offset).toFloat() / max * (surfaceHeight - indicatorHeight)).toInt()
-
-        val params = scrollIndicator.layoutParams as FrameLayout.LayoutParams
-        params.height = indicatorHeight
-        params.topMargin = position + tabBar.height
-        params.gravity = Gravity.END
-        scrollIndicator.layoutParams = params
+    // Compress server output on metered (typically cellular) networks
+    private fun updateCompression() {
+        val connectivity = getSystemService(ConnectivityManager::class.java)
+        NativeTerminal.setCompression(connectivity?.isActiveNetworkMetered ?: false)
     }
 
     private fun pixelToCell(x: Float, y: Float): Pair<Int, Int> {
         val cellW = NativeTerminal.getCellWidth()
         val cellH = NativeTerminal.getCellHeight()
         val offsetX = NativeTerminal.getGridOffsetX()
+        val offsetY = NativeTerminal.getGridOffsetY()
         val col = if (cellW > 0) ((x - offsetX) / cellW).toInt().coerceAtLeast(0) else 0
-        val row = if (cellH > 0) (y / cellH).toInt().coerceAtLeast(0) else 0
+        val row = if (cellH > 0) ((y - offsetY) / cellH).toInt().coerceAtLeast(0) else 0
         return Pair(col, row)
     }
 
@@ -665,6 +689,47 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
         items.add(getString(R.string.theme))
         actions.add { showThemeDialog() }
 
+        // Paste from clipboard
+        items.add(getString(R.string.paste))
+        actions.add { pasteClipboard() }
+
+        items.add(getString(R.string.paste_mode))
+        actions.add { showPasteModeDialog() }
+
+        val showScrollbar = TerminalPreferences.getShowScrollbar(this)
+        items.add(getString(if (showScrollbar) R.string.scrollbar_hide else R.string.scrollbar_show))
+        actions.add {
+            TerminalPreferences.setShowScrollbar(this, !showScrollbar)
+            NativeTerminal.setScrollbar(!showScrollbar)
+        }
+
+        items.add(getString(R.string.scroll_speed))
+        actions.add { showScrollSpeedDialog() }
+
+        val invertScroll = TerminalPreferences.getInvertScroll(this)
+        items.add(getString(if (invertScroll) R.string.invert_scroll_off else R.string.invert_scroll_on))
+        actions.add {
+            TerminalPreferences.setInvertScroll(this, !invertScroll)
+            applyScrollConfig()
+        }
+
+        val alternateScroll = TerminalPreferences.getAlternateScroll(this)
+        items.add(
+            getString(
+                if (alternateScroll) R.string.alternate_scroll_off else R.string.alternate_scroll_on
+            )
+        )
+        actions.add {
+            TerminalPreferences.setAlternateScroll(this, !alternateScroll)
+            NativeTerminal.setAlternateScroll(!alternateScroll)
+        }
+
+        items.add(getString(R.string.scroll_mode))
+        actions.add { enterScrollMode() }
+
+        items.add(getString(R.string.copy_ansi))
+        actions.add { copyScreenAnsi() }
+
         // Arch Linux install/remove
         if (ProotEnvironment.isInstalled(this)) {
             items.add(getString(R.string.arch_remove))
@@ -694,6 +759,97 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
             .show()
     }
 
+    private fun pasteClipboard() {
+        val clipboard = getSystemService(android.content.Context.CLIPBOARD_SERVICE) as android.content.ClipboardManager
+        val text = clipboard.primaryClip?.getItemAt(0)?.coerceToText(this)?.toString()
+        if (!text.isNullOrEmpty()) pasteText(text)
+    }
+
+    // Multi-line pastes in warn mode are only sent once confirmed
+    private fun pasteText(text: String) {
+        if (NativeTerminal.sendPaste(text, false)) return
+        AlertDialog.Builder(this)
+            .setTitle(R.string.paste_confirm_title)
+            .setMessage(R.string.paste_confirm_body)
+            .setPositiveButton(R.string.paste) { _, _ -> NativeTerminal.sendPaste(text, true) }
+            .setNegativeButton(android.R.string.cancel, null)
+            .show()
+    }
+
+    private fun copyScreenAnsi() {
+        val text = NativeTerminal.getSelectedAnsi()
+        if (text.isEmpty()) return
+        val clipboard = getSystemService(android.content.Context.CLIPBOARD_SERVICE) as android.content.ClipboardManager
+        clipboard.setPrimaryClip(android.content.ClipData.newPlainText("terminal", text))
+        Snackbar.make(root, "Copied", Snackbar.LENGTH_SHORT).show()
+    }
+
+    private fun enterScrollMode() {
+        NativeTerminal.enterScrollMode()
+        Snackbar.make(surfaceView, R.string.scroll_mode_hint, Snackbar.LENGTH_LONG)
+            .setAction(R.string.scroll_mode_exit) { NativeTerminal.exitScrollMode() }
+            .show()
+    }
+
+    private fun showScrollSearchDialog() {
+        val input = EditText(this).apply {
+            isSingleLine = true
+            setPadding(48, 24, 48, 24)
+        }
+        AlertDialog.Builder(this)
+            .setTitle(R.string.scroll_search)
+            .setView(input)
+            .setPositiveButton(R.string.scroll_search) { _, _ ->
+                val query = input.text?.toString().orEmpty()
+                if (query.isNotEmpty() && !NativeTerminal.searchScrollback(query)) {
+                    Snackbar.make(surfaceView, R.string.scroll_search_not_found, Snackbar.LENGTH_SHORT).show()
+                }
+            }
+            .setNegativeButton(android.R.string.cancel, null)
+            .show()
+    }
+
+    private fun showPasteModeDialog() {
+        val modes = arrayOf(
+            getString(R.string.paste_mode_sanitize),
+            getString(R.string.paste_mode_warn),
+            getString(R.string.paste_mode_raw),
+        )
+        AlertDialog.Builder(this)
+            .setTitle(R.string.paste_mode)
+            .setSingleChoiceItems(modes, TerminalPreferences.getPasteMode(this)) { dialog, which ->
+                TerminalPreferences.setPasteMode(this, which)
+                NativeTerminal.setPasteMode(which)
+                dialog.dismiss()
+            }
+            .show()
+    }
+
+    private fun showScrollSpeedDialog() {
+        val speeds = arrayOf(
+            getString(R.string.scroll_speed_slow),
+            getString(R.string.scroll_speed_normal),
+            getString(R.string.scroll_speed_fast),
+        )
+        val lines = floatArrayOf(1.5f, TerminalPreferences.DEFAULT_SCROLL_LINES, 6f)
+        val selected = lines.indexOfFirst { it == TerminalPreferences.getScrollLines(this) }
+        AlertDialog.Builder(this)
+            .setTitle(R.string.scroll_speed)
+            .setSingleChoiceItems(speeds, selected) { dialog, which ->
+                TerminalPreferences.setScrollLines(this, lines[which])
+                applyScrollConfig()
+                dialog.dismiss()
+            }
+            .show()
+    }
+
+    private fun applyScrollConfig() {
+        NativeTerminal.setScrollConfig(
+            TerminalPreferences.getScrollLines(this),
+            TerminalPreferences.getInvertScroll(this),
+        )
+    }
+
     private fun showThemeDialog() {
         val currentTheme = TerminalPreferences.getTheme(this)
 
@@ -720,14 +876,17 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
         when (theme) {
             "dark" -> {
                 NativeTerminal.setBackgroundColor(0.05f, 0.05f, 0.1f)
+                NativeTerminal.setCursorColor("")
                 root.setBackgroundColor(0xFF0D0D1A.toInt())
             }
             "solarized" -> {
                 NativeTerminal.setBackgroundColor(0.0f, 0.169f, 0.212f)
+                NativeTerminal.setCursorColor("#93a1a1")
                 root.setBackgroundColor(0xFF002B36.toInt())
             }
             "light" -> {
                 NativeTerminal.setBackgroundColor(0.99f, 0.96f, 0.89f)
+                NativeTerminal.setCursorColor("#586e75")
                 root.setBackgroundColor(0xFFFDF6E3.toInt())
             }
         }
@@ -757,6 +916,14 @@ class NativeTerminalActivity : AppCompatActivity(), SurfaceHolder.Callback {
             return true
         }
 
+        // Copied and cleared on release, like a long-press selection
+        override fun onDoubleTap(e: MotionEvent): Boolean {
+            selecting = true
+            val (col, row) = pixelToCell(e.x, e.y)
+            NativeTerminal.selectWord(col, row)
+            return true
+        }
+
         override fun onLongPress(e: MotionEvent) {
             selecting = true
             val (col, row) = pixelToCell(e.x, e.y)
@@ -6,9 +6,16 @@ import androidx.preference.PreferenceManager
 object TerminalPreferences {
     private const val KEY_FONT_SIZE = "font_size"
     private const val KEY_THEME = "theme"
+    private const val KEY_PASTE_MODE = "paste_mode"
+    private const val KEY_SHOW_SCROLLBAR = "show_scrollbar"
+    private const val KEY_ALTERNATE_SCROLL = "alternate_scroll"
+    private const val KEY_SCROLL_LINES = "scroll_lines"
+    private const val KEY_INVERT_SCROLL = "invert_scroll"
 
     const val DEFAULT_FONT_SIZE = 18f
     const val DEFAULT_THEME = "dark"
+    const val DEFAULT_PASTE_MODE = NativeTerminal.PASTE_SANITIZE
+    const val DEFAULT_SCROLL_LINES = 3f
 
     fun getFontSize(context: Context): Float {
         val prefs = PreferenceManager.getDefaultSharedPreferences(context)
@@ -29,4 +36,54 @@ object TerminalPreferences {
         val prefs = PreferenceManager.getDefaultSharedPreferences(context)
         prefs.edit().putString(KEY_THEME, theme).apply()
     }
+
+    fun getPasteMode(context: Context): Int {
+        val prefs = PreferenceManager.getDefaultSharedPreferences(context)
+        return prefs.getInt(KEY_PASTE_MODE, DEFAULT_PASTE_MODE)
+    }
+
+    fun setPasteMode(context: Context, mode: Int) {
+        val prefs = PreferenceManager.getDefaultSharedPreferences(context)
+        prefs.edit().putInt(KEY_PASTE_MODE, mode).apply()
+    }
+
+    fun getShowScrollbar(context: Context): Boolean {
+        val prefs = PreferenceManager.getDefaultSharedPreferences(context)
+        return prefs.getBoolean(KEY_SHOW_SCROLLBAR, true)
+    }
+
+    fun setShowScrollbar(context: Context, show: Boolean) {
+        val prefs = PreferenceManager.getDefaultSharedPreferences(context)
+        prefs.edit().putBoolean(KEY_SHOW_SCROLLBAR, show).apply()
+    }
+
+    fun getScrollLines(context: Context): Float {
+        val prefs = PreferenceManager.getDefaultSharedPreferences(context)
+        return prefs.getFloat(KEY_SCROLL_LINES, DEFAULT_SCROLL_LINES)
+    }
+
+    fun setScrollLines(context: Context, lines: Float) {
+        val prefs = PreferenceManager.getDefaultSharedPreferences(context)
+        prefs.edit().putFloat(KEY_SCROLL_LINES, lines).apply()
+    }
+
+    fun getInvertScroll(context: Context): Boolean {
+        val prefs = PreferenceManager.getDefaultSharedPreferences(context)
+        return prefs.getBoolean(KEY_INVERT_SCROLL, false)
+    }
+
+    fun setInvertScroll(context: Context, inverted: Boolean) {
+        val prefs = PreferenceManager.getDefaultSharedPreferences(context)
+        prefs.edit().putBoolean(KEY_INVERT_SCROLL, inverted).apply()
+    }
+
+    fun getAlternateScroll(context: Context): Boolean {
+        val prefs = PreferenceManager.getDefaultSharedPreferences(context)
+        return prefs.getBoolean(KEY_ALTERNATE_SCROLL, true)
+    }
+
+    fun setAlternateScroll(context: Context, enabled: Boolean) {
+        val prefs = PreferenceManager.getDefaultSharedPreferences(context)
+        prefs.edit().putBoolean(KEY_ALTERNATE_SCROLL, enabled).apply()
+    }
 }
@@ -7,17 +7,27 @@ import android.app.PendingIntent
 import android.app.Service
 import android.content.Intent
 import android.os.Build
+import android.os.Handler
 import android.os.IBinder
+import android.os.Looper
 import android.os.PowerManager
 import androidx.core.app.NotificationCompat
 
 class TerminalService : Service() {
     private var wakeLock: PowerManager.WakeLock? = null
+    private val bellHandler = Handler(Looper.getMainLooper())
+    private val bellRunnable = object : Runnable {
+        override fun run() {
+            pollBell()
+            bellHandler.postDelayed(this, BELL_POLL_MS)
+        }
+    }
 
     override fun onCreate() {
         super.onCreate()
         createNotificationChannel()
         acquireWakeLock()
+        bellHandler.post(bellRunnable)
     }
 
     override fun onStartCommand(intent: Intent?, flags: Int, startId: Int): Int {
@@ -29,6 +39,7 @@ class TerminalService : Service() {
     override fun onBind(intent: Intent?): IBinder? = null
 
     override fun onDestroy() {
+        bellHandler.removeCallbacks(bellRunnable)
         wakeLock?.let {
             if (it.isHeld) it.release()
         }
@@ -44,9 +55,44 @@ class TerminalService : Service() {
             ).apply {
                 description = getString(R.string.notification_channel_description)
             }
+            val bellChannel = NotificationChannel(
+                BELL_CHANNEL_ID,
+                getString(R.string.bell_channel_name),
+                NotificationManager.IMPORTANCE_DEFAULT,
+            ).apply {
+                description = getString(R.string.bell_channel_description)
+            }
             val manager = getSystemService(NotificationManager::class.java)
             manager.createNotificationChannel(channel)
+            manager.createNotificationChannel(bellChannel)
+        }
+    }
+
+    // Bells are always consumed so they don't pile up, but only notify while
+    // the terminal is out of sight
+    private fun pollBell() {
+        val index = NativeTerminal.takeBellEvent()
+        if (index < 0 || appVisible) return
+
+        val label = NativeTerminal.getSessionLabel(index)
+        val tapIntent = Intent(this, NativeTerminalActivity::class.java).apply {
+            flags = Intent.FLAG_ACTIVITY_SINGLE_TOP
+            putExtra(EXTRA_SESSION_INDEX, index)
         }
+        val pending = PendingIntent.getActivity(
+            this, index, tapIntent,
+            PendingIntent.FLAG_UPDATE_CURRENT or PendingIntent.FLAG_IMMUTABLE,
+        )
+
+        val notification = NotificationCompat.Builder(this, BELL_CHANNEL_ID)
+            .setContentTitle(getString(R.string.bell_notification_title, label))
+            .setContentText(getString(R.string.bell_notification_text))
+            .setSmallIcon(R.drawable.ic_launcher)
+            .setContentIntent(pending)
+            .setAutoCancel(true)
+            .build()
+        val manager = getSystemService(NotificationManager::class.java)
+        manager.notify(BELL_NOTIFICATION_TAG, index, notification)
     }
 
     private fun buildNotification(sessionCount: Int): Notification {
@@ -91,5 +137,15 @@ class TerminalService : Service() {
         const val CHANNEL_ID = "terminal_sessions"
         const val NOTIFICATION_ID = 1
         const val EXTRA_SESSION_COUNT = "session_count"
+        const val BELL_CHANNEL_ID = "terminal_bell"
+        const val BELL_NOTIFICATION_TAG = "bell"
+        const val BELL_POLL_MS = 1000L
+
+        // Tab to switch to when a bell notification is tapped
+        const val EXTRA_SESSION_INDEX = "session_index"
+
+        // Set by NativeTerminalActivity while it is in the foreground
+        @Volatile
+        var appVisible = false
     }
 }
@@ -19,6 +19,13 @@ class TerminalSurfaceView @JvmOverloads constructor(
     var ctrlDown = false
     var altDown = false
 
+    // Receives committed text that looks like a paste (line breaks or escape
+    // sequences) so it can be sanitized instead of typed
+    var onPaste: ((String) -> Unit)? = null
+
+    // Opens the search prompt when `/` is typed in scroll mode
+    var onScrollSearch: (() -> Unit)? = null
+
     init {
         isFocusable = true
         isFocusableInTouchMode = true
@@ -37,21 +44,21 @@ class TerminalSurfaceView @JvmOverloads constructor(
         return object : BaseInputConnection(this, false) {
             override fun commitText(text: CharSequence?, newCursorPosition: Int): Boolean {
                 text?.toString()?.let { input ->
-                    if (ctrlDown && input.length == 1) {
-                        val ch = input[0].lowercaseChar()
-                        if (ch in 'a'..'z') {
-                            // Send Ctrl+letter as control byte
-                            val ctrlByte = (ch.code - 'a'.code + 1).toChar()
-                            NativeTerminal.sendKey(ctrlByte.toString())
-                            return true
-                        }
+                    if ((ctrlDown || altDown) && input.length == 1) {
+                        // Native side picks control byte / ESC prefix or CSI u
+                        var modifiers = 0
+                        if (ctrlDown) modifiers = modifiers or NativeTerminal.MOD_CTRL
+                        if (altDown) modifiers = modifiers or NativeTerminal.MOD_ALT
+                        if (input[0].isUpperCase()) modifiers = modifiers or NativeTerminal.MOD_SHIFT
+                        NativeTerminal.sendModifiedKey(input, modifiers)
+                        return true
                     }
-                    if (altDown && input.length == 1) {
-                        // Send Alt+key as ESC prefix
-                        NativeTerminal.sendKey("\u001b$input")
+                    val paste = onPaste
+                    if (paste != null && input.any { it == '\n' || it == '\r' || it == '\u001b' }) {
+                        paste(input)
                         return true
                     }
-                    NativeTerminal.sendKey(input)
+                    sendText(input)
                 }
                 return true
             }
@@ -77,10 +84,14 @@ class TerminalSurfaceView @JvmOverloads constructor(
                     KeyEvent.KEYCODE_DPAD_DOWN -> NativeTerminal.sendSpecialKey(NativeTerminal.KEY_ARROW_DOWN)
                     KeyEvent.KEYCODE_DPAD_LEFT -> NativeTerminal.sendSpecialKey(NativeTerminal.KEY_ARROW_LEFT)
                     KeyEvent.KEYCODE_DPAD_RIGHT -> NativeTerminal.sendSpecialKey(NativeTerminal.KEY_ARROW_RIGHT)
+                    KeyEvent.KEYCODE_PAGE_UP -> NativeTerminal.sendSpecialKey(NativeTerminal.KEY_PAGE_UP)
+                    KeyEvent.KEYCODE_PAGE_DOWN -> NativeTerminal.sendSpecialKey(NativeTerminal.KEY_PAGE_DOWN)
+                    KeyEvent.KEYCODE_MOVE_HOME -> NativeTerminal.sendSpecialKey(NativeTerminal.KEY_HOME)
+                    KeyEvent.KEYCODE_MOVE_END -> NativeTerminal.sendSpecialKey(NativeTerminal.KEY_END)
                     else -> {
                         val ch = event.unicodeChar
                         if (ch != 0) {
-                            NativeTerminal.sendKey(ch.toChar().toString())
+                            sendText(ch.toChar().toString())
                         }
                     }
                 }
@@ -90,41 +101,39 @@ class TerminalSurfaceView @JvmOverloads constructor(
     }
 
     override fun onKeyDown(keyCode: Int, event: KeyEvent): Boolean {
-        when (keyCode) {
-            KeyEvent.KEYCODE_ENTER -> {
-                NativeTerminal.sendSpecialKey(NativeTerminal.KEY_ENTER)
-                return true
-            }
-            KeyEvent.KEYCODE_DEL -> {
-                NativeTerminal.sendSpecialKey(NativeTerminal.KEY_BACKSPACE)
-                return true
-            }
-            KeyEvent.KEYCODE_TAB -> {
-                NativeTerminal.sendSpecialKey(NativeTerminal.KEY_TAB)
-                return true
-            }
-            KeyEvent.KEYCODE_ESCAPE -> {
-                NativeTerminal.sendSpecialKey(NativeTerminal.KEY_ESCAPE)
-                return true
-            }
-            KeyEvent.KEYCODE_DPAD_UP -> {
-                NativeTerminal.sendSpecialKey(NativeTerminal.KEY_ARROW_UP)
-                return true
-            }
-            KeyEvent.KEYCODE_DPAD_DOWN -> {
-                NativeTerminal.sendSpecialKey(NativeTerminal.KEY_ARROW_DOWN)
-                return true
-            }
-            KeyEvent.KEYCODE_DPAD_LEFT -> {
-                NativeTerminal.sendSpecialKey(NativeTerminal.KEY_ARROW_LEFT)
-                return true
-            }
-            KeyEvent.KEYCODE_DPAD_RIGHT -> {
-                NativeTerminal.sendSpecialKey(NativeTerminal.KEY_ARROW_RIGHT)
-                return true
+        if (sendHardwareKey(event)) return true
+        return super.onKeyDown(keyCode, event)
+    }
+
+    /// Send a key from a physical keyboard, with its modifiers applied on
+    /// the native side. Right Alt that types a character of its own is
+    /// AltGr, so the character is sent rather than an Alt chord
+    private fun sendHardwareKey(event: KeyEvent): Boolean {
+        val chord = KeyEvent.META_CTRL_MASK or KeyEvent.META_ALT_MASK or KeyEvent.META_META_MASK
+        var meta = event.metaState
+        var ch = event.getUnicodeChar(meta and chord.inv())
+        if ((meta and KeyEvent.META_ALT_RIGHT_ON) != 0) {
+            val altGr = event.getUnicodeChar(
+                meta and (KeyEvent.META_CTRL_MASK or KeyEvent.META_META_MASK).inv()
+            )
+            if (altGr != 0 && altGr != ch) {
+                ch = altGr
+                meta = meta and KeyEvent.META_ALT_MASK.inv()
             }
         }
-        return super.onKeyDown(keyCode, event)
+        if (ch == '/'.code && NativeTerminal.isScrollMode()) {
+            onScrollSearch?.invoke()
+            return true
+        }
+        return NativeTerminal.sendHardwareKey(event.keyCode, meta, ch)
+    }
+
+    fun sendText(input: String) {
+        if (input == "/" && NativeTerminal.isScrollMode()) {
+            onScrollSearch?.invoke()
+            return
+        }
+        NativeTerminal.sendKey(input)
     }
 
     fun showKeyboard() {
@@ -16,8 +16,9 @@ pub enum Command {
 #[cfg(all(unix, feature = "serve"))]
 #[derive(Args, Debug)]
 pub struct ServeCommand {
-    /// Host address to bind to.
-    #[clap(long, default_value = "127.0.0.1")]
+    /// Address of the interface to bind to; 0.0.0.0 or :: listens on every
+    /// interface.
+    #[clap(long, default_value = "127.0.0.1", env = "BIND_ADDR")]
     pub host: std::net::IpAddr,
 
     /// Port to listen on.
@@ -32,9 +33,71 @@ pub struct ServeCommand {
     #[clap(long, env = "TLS_KEY")]
     pub tls_key: Option<std::path::PathBuf>,
 
-    /// Disable TLS (serve plain HTTP).
-    #[clap(long)]
+    /// Disable TLS and serve plain http:// and ws:// (local development only).
+    #[clap(long, env = "INSECURE", value_parser = clap::builder::BoolishValueParser::new())]
     pub no_tls: bool,
+
+    /// Maximum input bytes per second accepted for each session (0 disables the
+    /// limit).
+    #[clap(long, value_name = "BYTES", default_value = "1048576")]
+    pub input_rate_limit: u32,
+
+    /// Largest PTY read buffer, grown from 4 KB while output keeps filling it.
+    #[clap(long, value_name = "BYTES", default_value = "65536")]
+    pub max_read_buffer: usize,
+
+    /// Seconds between sweeps for detached sessions whose grace period has run
+    /// out.
+    #[clap(
+        long,
+        value_name = "SECS",
+        default_value = "10",
+        env = "REAP_INTERVAL_SECS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub reap_interval_secs: u64,
+
+    /// Seconds a detached session waits for its client to reconnect before its
+    /// process is killed.
+    #[clap(
+        long,
+        value_name = "SECS",
+        default_value = "60",
+        env = "SESSION_GRACE_SECS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub session_grace_secs: u64,
+
+    /// Extra host names browsers may connect through, besides localhost and
+    /// this machine's addresses; `*` allows any.
+    #[clap(
+        long,
+        value_name = "HOSTS",
+        env = "ALLOWED_ORIGINS",
+        value_delimiter = ','
+    )]
+    pub allowed_origins: Vec<String>,
+
+    /// Listen on a Unix domain socket (`unix:/path/to.sock`) instead of TCP,
+    /// behind a reverse proxy that terminates TLS; add its public host name to
+    /// ALLOWED_ORIGINS.
+    #[clap(long, value_name = "unix:PATH", env = "LISTEN", value_parser = parse_listen)]
+    pub listen: Option<PathBuf>,
+
+    /// File recording live sessions so reconnecting clients learn they expired
+    /// after a restart.
+    #[clap(long, value_name = "PATH", value_hint = ValueHint::FilePath)]
+    pub state_file: Option<PathBuf>,
+}
+
+/// Parse a `--listen` address, the socket path after a `unix:` prefix.
+#[cfg(all(unix, feature = "serve"))]
+fn parse_listen(value: &str) -> Result<PathBuf, String> {
+    value
+        .strip_prefix("unix:")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .ok_or_else(|| format!("expected unix:/path/to.sock, got '{value}'"))
 }
 
 #[derive(Parser, Default, Debug)]
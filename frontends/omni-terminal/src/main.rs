@@ -161,6 +161,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 tls_cert: serve_cmd.tls_cert,
                 tls_key: serve_cmd.tls_key,
                 no_tls: serve_cmd.no_tls,
+                input_rate_limit: serve_cmd.input_rate_limit,
+                max_read_buffer: serve_cmd.max_read_buffer,
+                reap_interval: std::time::Duration::from_secs(
+                    serve_cmd.reap_interval_secs,
+                ),
+                session_grace: std::time::Duration::from_secs(
+                    serve_cmd.session_grace_secs,
+                ),
+                allowed_origins: serve_cmd.allowed_origins,
+                unix_socket: serve_cmd.listen,
+                state_file: serve_cmd.state_file,
             })
             .await
         });
@@ -1,4 +1,6 @@
 mod server;
 mod session;
+mod state;
+mod title;
 
 pub use server::{run, ServeArgs};
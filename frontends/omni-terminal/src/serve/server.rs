@@ -1,22 +1,28 @@
 use axum::{
     extract::{
         ws::{Message, WebSocket},
-        State, WebSocketUpgrade,
+        Query, State, WebSocketUpgrade,
     },
     response::IntoResponse,
     routing::get,
     Router,
 };
+use flate2::{write::DeflateEncoder, Compression};
 use futures::{SinkExt, StreamExt};
 use include_dir::{include_dir, Dir};
 use std::collections::HashMap;
+use std::io::Write;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
 use tokio_rustls::TlsAcceptor;
 
-use super::session::{SessionId, SessionManager};
+use super::session::{
+    AttachError, AttachId, AttachMode, OnExit, SessionId, SessionManager, SessionMetrics,
+    MAX_DIMENSION,
+};
 
 static WASM_FRONTEND: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/../wasm");
 
@@ -27,40 +33,127 @@ pub struct ServeArgs {
     pub tls_cert: Option<PathBuf>,
     pub tls_key: Option<PathBuf>,
     pub no_tls: bool,
+    /// Input bytes per second allowed into each session; 0 disables limiting
+    pub input_rate_limit: u32,
+    /// Largest buffer each session's PTY reads grow to under bulk output
+    pub max_read_buffer: usize,
+    /// How often detached sessions are checked against `session_grace`
+    pub reap_interval: Duration,
+    /// How long a detached session survives before it is reaped
+    pub session_grace: Duration,
+    /// Host names accepted in `Host` and `Origin` besides localhost and the
+    /// machine's own addresses; `*` accepts any
+    pub allowed_origins: Vec<String>,
+    /// Serve over this Unix domain socket instead of TCP, leaving TLS to a
+    /// reverse proxy
+    pub unix_socket: Option<PathBuf>,
+    /// Where to persist session metadata across restarts
+    pub state_file: Option<PathBuf>,
 }
 
+/// Header set on the upgrade response when the server accepted a client's
+/// `?compress=deflate` request
+const COMPRESSION_HEADER: &str = "x-omni-compression";
+
+/// Output smaller than this is sent uncompressed: interactive echo is a few
+/// bytes, where deflate saves nothing and only costs time
+const COMPRESSION_THRESHOLD: usize = 256;
+
+/// Largest control message parsed; control messages are small JSON objects,
+/// so anything bigger is refused before it reaches the JSON parser
+const MAX_CONTROL_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Largest WebSocket message or frame accepted, bounding what a client can
+/// make the server buffer. Binary input frames carry whole pastes, so this
+/// sits well above the control message limit
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// How long to wait for connected clients to receive the shutdown notice
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
 #[derive(Clone)]
 struct AppState {
     session_manager: SessionManager,
+    /// Flips to `true` once the server starts shutting down
+    shutdown: watch::Receiver<bool>,
+    /// Host names WebSocket upgrades may name in `Host` and `Origin`
+    allowed_hosts: Arc<Vec<String>>,
+    /// When the server started, for the uptime `/healthz` reports
+    started: Instant,
 }
 
 /// Run the Omni Terminal web server
 pub async fn run(args: ServeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let shutdown_tx = Arc::new(shutdown_tx);
+    let mut session_manager = SessionManager::new(args.input_rate_limit)
+        .with_max_read_buffer(args.max_read_buffer);
+    if let Some(path) = args.state_file {
+        session_manager = session_manager.with_state_file(path);
+    }
     let state = AppState {
-        session_manager: SessionManager::default(),
+        session_manager: session_manager.clone(),
+        shutdown: shutdown_rx.clone(),
+        allowed_hosts: Arc::new(allowed_hosts(
+            args.allowed_origins,
+            local_ip_addresses(),
+            // Named in the certificate too, so hostname URLs work
+            local_hostname().filter(|_| !args.host.is_loopback()),
+        )),
+        started: Instant::now(),
     };
 
-    // Spawn reaper task to clean up stale disconnected sessions
-    let reaper_manager = state.session_manager.clone();
+    let signal_tx = Arc::clone(&shutdown_tx);
     tokio::spawn(async move {
-        let mut interval = tokio::time::interval(std::time::Duration::from_secs(10));
-        loop {
-            interval.tick().await;
-            reaper_manager.reap_stale_sessions(std::time::Duration::from_secs(60));
-        }
+        shutdown_signal().await;
+        tracing::info!("Shutdown signal received, closing connections");
+        signal_tx.send_replace(true);
     });
+    let graceful = {
+        let mut shutdown = shutdown_rx;
+        async move {
+            let _ = shutdown.changed().await;
+        }
+    };
 
-    let app = Router::new()
-        .route("/ws", get(ws_handler))
-        .fallback(static_handler)
-        .with_state(state);
+    spawn_reaper(
+        state.session_manager.clone(),
+        args.reap_interval,
+        args.session_grace,
+    );
+
+    let app = router(state);
 
     let addr = SocketAddr::from((args.host, args.port));
+    if addr.ip().is_unspecified() && args.unix_socket.is_none() {
+        tracing::warn!("Listening on every network interface ({addr})");
+    }
 
-    if args.no_tls {
+    if let Some(path) = args.unix_socket {
+        let listener = UnixSocketListener::bind(&path)?;
+        tracing::info!(
+            "Omni Terminal web server listening on unix:{}",
+            path.display()
+        );
+        axum::serve(listener, app)
+            .with_graceful_shutdown(graceful)
+            .await?;
+    } else if args.no_tls {
+        tracing::warn!(
+            "TLS is DISABLED: terminal input and output travel unencrypted over ws://. \
+             Use this only for local development."
+        );
+        if !addr.ip().is_loopback() {
+            tracing::warn!(
+                "Serving without TLS on non-loopback address {addr}; anyone on the \
+                 network can read and drive these terminals"
+            );
+        }
         let listener = tokio::net::TcpListener::bind(addr).await?;
         tracing::info!("Omni Terminal web server listening on http://{addr}");
-        axum::serve(listener, app).await?;
+        axum::serve(listener, app)
+            .with_graceful_shutdown(graceful)
+            .await?;
     } else {
         let (cert_pem, key_pem) = match (args.tls_cert, args.tls_key) {
             (Some(cert_path), Some(key_path)) => {
@@ -68,17 +161,9 @@ pub async fn run(args: ServeArgs) -> Result<(), Box<dyn std::error::Error>> {
                 (std::fs::read(&cert_path)?, std::fs::read(&key_path)?)
             }
             _ => {
-                let mut sans: Vec<String> = vec!["localhost".into()];
-                for ip in local_ip_addresses() {
-                    sans.push(ip.to_string());
-                }
-                tracing::info!("generating self-signed TLS certificate for {sans:?}");
-                let generated = rcgen::generate_simple_self_signed(sans)?;
-
-                (
-                    generated.cert.pem().into_bytes(),
-                    generated.key_pair.serialize_pem().into_bytes(),
-                )
+                let sans =
+                    certificate_sans(args.host, local_ip_addresses(), local_hostname());
+                self_signed_certificate(&certificate_cache_dir(), sans)?
             }
         };
 
@@ -100,34 +185,196 @@ pub async fn run(args: ServeArgs) -> Result<(), Box<dyn std::error::Error>> {
         };
 
         tracing::info!("Omni Terminal web server listening on https://{addr}");
-        axum::serve(tls_listener, app).await?;
+        axum::serve(tls_listener, app)
+            .with_graceful_shutdown(graceful)
+            .await?;
     }
 
+    // Each connected socket holds a receiver until it has sent its notice
+    if tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, shutdown_tx.closed())
+        .await
+        .is_err()
+    {
+        tracing::warn!("Timed out waiting for clients to disconnect");
+    }
+
+    let terminated = session_manager.close_all();
+    tracing::info!("Server shut down, terminated {terminated} session(s)");
+
     Ok(())
 }
 
-async fn static_handler(uri: axum::http::Uri) -> impl axum::response::IntoResponse {
+/// Spawn the task that closes sessions left detached for longer than `grace`,
+/// checking every `interval`
+fn spawn_reaper(
+    manager: SessionManager,
+    interval: Duration,
+    grace: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            manager.reap_stale_sessions(grace);
+        }
+    })
+}
+
+/// Resolve once SIGTERM or SIGINT is received
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm =
+        signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+}
+
+fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/ws", get(ws_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/healthz", get(healthz_handler))
+        .fallback(static_handler)
+        .with_state(state)
+}
+
+/// Liveness and readiness probe: uptime and live session count as JSON
+async fn healthz_handler(State(state): State<AppState>) -> impl IntoResponse {
+    axum::Json(serde_json::json!({
+        "status": "ok",
+        "uptime_secs": state.started.elapsed().as_secs(),
+        "sessions": state.session_manager.session_count(),
+    }))
+}
+
+/// Session metrics in the Prometheus text exposition format
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        render_metrics(&state.session_manager.metrics()),
+    )
+}
+
+fn render_metrics(metrics: &SessionMetrics) -> String {
+    let families = [
+        (
+            "omni_terminal_sessions",
+            "gauge",
+            "Live sessions by client attachment state",
+            vec![
+                ("{state=\"active\"}", metrics.active_sessions),
+                ("{state=\"detached\"}", metrics.detached_sessions),
+            ],
+        ),
+        (
+            "omni_terminal_input_bytes_total",
+            "counter",
+            "Input bytes written to session PTYs",
+            vec![("", metrics.bytes_in)],
+        ),
+        (
+            "omni_terminal_output_bytes_total",
+            "counter",
+            "Output bytes read from session PTYs",
+            vec![("", metrics.bytes_out)],
+        ),
+        (
+            "omni_terminal_sessions_created_total",
+            "counter",
+            "Sessions created",
+            vec![("", metrics.sessions_created)],
+        ),
+        (
+            "omni_terminal_sessions_closed_total",
+            "counter",
+            "Sessions closed, including reaped ones",
+            vec![("", metrics.sessions_closed)],
+        ),
+        (
+            "omni_terminal_sessions_reaped_total",
+            "counter",
+            "Detached sessions closed by the reaper",
+            vec![("", metrics.sessions_reaped)],
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, kind, help, samples) in families {
+        out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n"));
+        for (labels, value) in samples {
+            out.push_str(&format!("{name}{labels} {value}\n"));
+        }
+    }
+    out
+}
+
+async fn static_handler(
+    uri: axum::http::Uri,
+    headers: axum::http::HeaderMap,
+) -> axum::response::Response {
     let path = uri.path().trim_start_matches('/');
     let path = if path.is_empty() { "index.html" } else { path };
 
     match WASM_FRONTEND.get_file(path) {
-        Some(file) => {
-            let mime = mime_from_path(path);
-            (
-                axum::http::StatusCode::OK,
-                [(axum::http::header::CONTENT_TYPE, mime)],
-                file.contents(),
-            )
-                .into_response()
-        }
+        Some(file) => embedded_file_response(
+            path,
+            file.contents(),
+            headers.get(axum::http::header::IF_NONE_MATCH),
+        ),
         None => axum::http::StatusCode::NOT_FOUND.into_response(),
     }
 }
 
+/// Build the response for an embedded frontend file. Browsers revalidate
+/// every request against the ETag, since asset names are not content-hashed.
+/// COOP/COEP make the page cross-origin isolated so WASM threads
+/// (`SharedArrayBuffer`) remain an option.
+fn embedded_file_response(
+    path: &str,
+    contents: &'static [u8],
+    if_none_match: Option<&axum::http::HeaderValue>,
+) -> axum::response::Response {
+    use axum::http::{header, StatusCode};
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::hash::DefaultHasher::new();
+    contents.hash(&mut hasher);
+    let etag = format!("\"{:016x}\"", hasher.finish());
+
+    let headers = [
+        (header::CONTENT_TYPE, mime_from_path(path)),
+        (header::CACHE_CONTROL, "no-cache"),
+        (
+            header::HeaderName::from_static("cross-origin-opener-policy"),
+            "same-origin",
+        ),
+        (
+            header::HeaderName::from_static("cross-origin-embedder-policy"),
+            "require-corp",
+        ),
+    ];
+
+    let not_modified = if_none_match
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)], headers)
+            .into_response();
+    }
+
+    (StatusCode::OK, [(header::ETAG, etag)], headers, contents).into_response()
+}
+
 fn mime_from_path(path: &str) -> &'static str {
     match path.rsplit('.').next() {
         Some("html") => "text/html; charset=utf-8",
-        Some("js") => "application/javascript",
+        Some("js") => "text/javascript; charset=utf-8",
         Some("wasm") => "application/wasm",
         Some("css") => "text/css",
         Some("json") => "application/json",
@@ -137,16 +384,103 @@ fn mime_from_path(path: &str) -> &'static str {
     }
 }
 
+/// A connection's attachment to one session
+struct SessionTask {
+    attach_id: AttachId,
+    /// Attached as a viewer, so input from this connection is dropped
+    read_only: bool,
+    /// Forwards the session's output to the socket
+    forwarder: tokio::task::JoinHandle<()>,
+}
+
+/// Sessions a connection is attached to
+type SessionTasks = HashMap<SessionId, SessionTask>;
+
+/// Host names accepted by default, to which `extra` configured names are
+/// added: localhost, the machine's `local` addresses and its `hostname`
+/// when reachable
+fn allowed_hosts(
+    extra: Vec<String>,
+    local: Vec<IpAddr>,
+    hostname: Option<String>,
+) -> Vec<String> {
+    let mut hosts = vec!["localhost".to_string()];
+    hosts.extend(local.iter().map(IpAddr::to_string));
+    hosts.extend(hostname.map(|name| name.to_ascii_lowercase()));
+    hosts.extend(extra.iter().map(|host| host.trim().to_ascii_lowercase()));
+    hosts
+}
+
+/// Host part of an authority such as `example.com:3000` or `[::1]:3000`
+fn authority_host(authority: &str) -> &str {
+    if let Some(rest) = authority.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    authority.split(':').next().unwrap_or(authority)
+}
+
+/// Whether an upgrade request names an allowed host in `Host`, and in
+/// `Origin` when a browser sent one. A page on another site that resolves
+/// its own name to this machine (DNS rebinding) carries that name in both,
+/// so it cannot drive the terminals.
+fn origin_allowed(headers: &axum::http::HeaderMap, allowed: &[String]) -> bool {
+    if allowed.iter().any(|host| host == "*") {
+        return true;
+    }
+    let is_allowed = |authority: &str| {
+        let host = authority_host(authority).to_ascii_lowercase();
+        allowed.contains(&host)
+    };
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+    let host_ok = header(axum::http::header::HOST).is_some_and(is_allowed);
+    let origin_ok = match header(axum::http::header::ORIGIN) {
+        Some(origin) => origin
+            .split_once("://")
+            .is_some_and(|(_, authority)| is_allowed(authority)),
+        // Native clients send no Origin
+        None => true,
+    };
+    host_ok && origin_ok
+}
+
 async fn ws_handler(
     ws: WebSocketUpgrade,
+    headers: axum::http::HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+) -> axum::response::Response {
+    if !origin_allowed(&headers, &state.allowed_hosts) {
+        tracing::warn!(
+            "Rejected WebSocket upgrade from host {:?}, origin {:?}",
+            headers.get(axum::http::header::HOST),
+            headers.get(axum::http::header::ORIGIN)
+        );
+        return axum::http::StatusCode::FORBIDDEN.into_response();
+    }
+
+    // The WebSocket stack has no permessage-deflate, so clients on metered
+    // links opt into deflating PTY output frame by frame instead
+    let compress = query
+        .get("compress")
+        .is_some_and(|value| value == "deflate");
+    let mut response = ws
+        .max_message_size(MAX_MESSAGE_SIZE)
+        .max_frame_size(MAX_MESSAGE_SIZE)
+        .on_upgrade(move |socket| handle_socket(socket, state, compress));
+    if compress {
+        response.headers_mut().insert(
+            COMPRESSION_HEADER,
+            axum::http::HeaderValue::from_static("deflate"),
+        );
+    }
+    response
 }
 
-async fn handle_socket(socket: WebSocket, state: AppState) {
+async fn handle_socket(socket: WebSocket, state: AppState, compress: bool) {
     let (mut ws_sender, mut ws_receiver) = socket.split();
     let manager = state.session_manager;
+    let mut shutdown = state.shutdown;
 
     // Merged output channel: all sessions' PTY output flows through here
     let (merged_tx, mut merged_rx) = mpsc::unbounded_channel::<(SessionId, Vec<u8>)>();
@@ -155,15 +489,21 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
     let (exit_tx, mut exit_rx) = mpsc::unbounded_channel::<SessionId>();
 
     // Track active sessions and their forwarding tasks
-    let mut session_tasks: HashMap<SessionId, tokio::task::JoinHandle<()>> =
-        HashMap::new();
+    let mut session_tasks: SessionTasks = HashMap::new();
 
     loop {
         tokio::select! {
+            // Server shutting down: notify the client and close the socket
+            _ = shutdown.changed() => {
+                let msg = serde_json::json!({ "type": "server_shutdown" });
+                let _ = ws_sender.send(Message::Text(msg.to_string().into())).await;
+                let _ = ws_sender.send(Message::Close(None)).await;
+                break;
+            }
+
             // Forward merged PTY output to WebSocket
             Some((session_id, data)) = merged_rx.recv() => {
-                let mut frame = session_id.as_bytes().to_vec();
-                frame.extend_from_slice(&data);
+                let frame = output_frame(&session_id, &data, compress);
                 if ws_sender.send(Message::Binary(frame.into())).await.is_err() {
                     break;
                 }
@@ -171,10 +511,41 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
 
             // Session exited: PTY output ended (shell exited)
             Some(session_id) = exit_rx.recv() => {
-                session_tasks.remove(&session_id);
+                // Output also ends when another client takes the session over
+                if !manager.has_exited(&session_id) {
+                    session_tasks.remove(&session_id);
+                    let msg = serde_json::json!({
+                        "type": "detached",
+                        "session_id": session_id.to_string(),
+                        "reason": "takeover",
+                    });
+                    if ws_sender.send(Message::Text(msg.to_string().into())).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+
+                let code = manager.exit_code(&session_id);
+                // Held sessions keep their buffer, and this connection its
+                // attachment, until the client closes them; a disconnect then
+                // leaves them to the reaper. Another client watching the
+                // session may have closed it already.
+                let msg_type = match manager.on_exit(&session_id) {
+                    Some(OnExit::Hold) => "exited",
+                    Some(OnExit::Close) => {
+                        session_tasks.remove(&session_id);
+                        manager.close_session(&session_id);
+                        "closed"
+                    }
+                    None => {
+                        session_tasks.remove(&session_id);
+                        "closed"
+                    }
+                };
                 let msg = serde_json::json!({
-                    "type": "exited",
+                    "type": msg_type,
                     "session_id": session_id.to_string(),
+                    "code": code,
                 });
                 if ws_sender.send(Message::Text(msg.to_string().into())).await.is_err() {
                     break;
@@ -184,6 +555,16 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
             // Handle incoming WebSocket messages
             msg = ws_receiver.next() => {
                 match msg {
+                    Some(Ok(Message::Text(text))) if text.len() > MAX_CONTROL_MESSAGE_SIZE => {
+                        tracing::warn!("Rejected {} byte control message", text.len());
+                        let e = ControlError::from(format!(
+                            "Control message too large: {} bytes, limit {MAX_CONTROL_MESSAGE_SIZE}",
+                            text.len()
+                        ));
+                        let _ = ws_sender.send(Message::Text(
+                            e.to_json().to_string().into()
+                        )).await;
+                    }
                     Some(Ok(Message::Text(text))) => {
                         match handle_control_message(
                             &text,
@@ -192,6 +573,7 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                             &exit_tx,
                             &mut session_tasks,
                             &mut ws_sender,
+                            compress,
                         ).await {
                             Ok(should_continue) => {
                                 if !should_continue {
@@ -200,10 +582,7 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                             }
                             Err(e) => {
                                 let _ = ws_sender.send(Message::Text(
-                                    serde_json::json!({
-                                        "type": "error",
-                                        "message": e
-                                    }).to_string().into()
+                                    e.to_json().to_string().into()
                                 )).await;
                             }
                         }
@@ -213,8 +592,29 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                         if data.len() > 16 {
                             let session_id = SessionId::from_slice(&data[..16]);
                             if let Ok(sid) = session_id {
-                                if let Err(e) = manager.write_to_session(&sid, &data[16..]) {
-                                    tracing::error!("Write error: {e}");
+                                // Only sessions this connection may type into
+                                if let Some(task) =
+                                    session_tasks.get(&sid).filter(|task| !task.read_only)
+                                {
+                                    match manager.write_to_session(
+                                        &sid,
+                                        task.attach_id,
+                                        &data[16..],
+                                    ) {
+                                        // Warn rather than wait on a process
+                                        // that has stopped reading its input
+                                        Ok(true) => {
+                                            let msg = serde_json::json!({
+                                                "type": "input_stalled",
+                                                "session_id": sid.to_string(),
+                                            });
+                                            let _ = ws_sender
+                                                .send(Message::Text(msg.to_string().into()))
+                                                .await;
+                                        }
+                                        Ok(false) => {}
+                                        Err(e) => tracing::error!("Write error: {e}"),
+                                    }
                                 }
                             }
                         }
@@ -227,11 +627,42 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
     }
 
     // Detach all sessions on disconnect, keeping PTYs alive for reconnection
-    for (session_id, handle) in session_tasks {
-        handle.abort();
+    for (session_id, task) in session_tasks {
+        task.forwarder.abort();
         tracing::info!("WebSocket disconnected, detaching session {session_id}");
-        manager.detach_session(&session_id);
+        manager.detach_session(&session_id, task.attach_id);
+    }
+}
+
+/// Binary frame carrying PTY output for `session_id`: the 16-byte id, then
+/// the output. On connections that asked for compression a flag byte sits
+/// between the two, 1 when the output is deflated and 0 when it is not.
+fn output_frame(session_id: &SessionId, data: &[u8], compress: bool) -> Vec<u8> {
+    let mut frame = session_id.as_bytes().to_vec();
+    if !compress {
+        frame.extend_from_slice(data);
+        return frame;
     }
+    if data.len() >= COMPRESSION_THRESHOLD {
+        match deflate(data) {
+            Ok(deflated) if deflated.len() < data.len() => {
+                frame.push(1);
+                frame.extend_from_slice(&deflated);
+                return frame;
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to compress output: {e}"),
+        }
+    }
+    frame.push(0);
+    frame.extend_from_slice(data);
+    frame
+}
+
+fn deflate(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    encoder.write_all(data)?;
+    encoder.finish()
 }
 
 /// Forward a single session's PTY output into the merged channel.
@@ -252,14 +683,64 @@ fn spawn_output_forwarder(
     })
 }
 
+/// Error reply to a control message
+#[derive(Debug, PartialEq)]
+struct ControlError {
+    message: String,
+    /// Machine-readable reason, for failures the client reacts to
+    code: Option<&'static str>,
+    session_id: Option<SessionId>,
+}
+
+impl ControlError {
+    fn attach(error: AttachError, session_id: Option<SessionId>) -> Self {
+        Self {
+            message: error.to_string(),
+            code: Some(error.code()),
+            session_id,
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut json = serde_json::json!({
+            "type": "error",
+            "message": self.message,
+        });
+        if let Some(code) = self.code {
+            json["code"] = code.into();
+        }
+        if let Some(session_id) = self.session_id {
+            json["session_id"] = session_id.to_string().into();
+        }
+        json
+    }
+}
+
+impl From<String> for ControlError {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            code: None,
+            session_id: None,
+        }
+    }
+}
+
+impl From<&str> for ControlError {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
 async fn handle_control_message(
     text: &str,
     manager: &SessionManager,
     merged_tx: &mpsc::UnboundedSender<(SessionId, Vec<u8>)>,
     exit_tx: &mpsc::UnboundedSender<SessionId>,
-    session_tasks: &mut HashMap<SessionId, tokio::task::JoinHandle<()>>,
+    session_tasks: &mut SessionTasks,
     ws_sender: &mut (impl SinkExt<Message, Error = axum::Error> + Unpin),
-) -> Result<bool, String> {
+    compress: bool,
+) -> Result<bool, ControlError> {
     let msg: serde_json::Value =
         serde_json::from_str(text).map_err(|e| format!("Invalid JSON: {e}"))?;
 
@@ -270,22 +751,48 @@ async fn handle_control_message(
 
     match msg_type {
         "create" => {
-            let cols = msg.get("cols").and_then(|v| v.as_u64()).unwrap_or(80) as u16;
-            let rows = msg.get("rows").and_then(|v| v.as_u64()).unwrap_or(24) as u16;
+            let cols = parse_dimension(&msg, "cols", 80)?;
+            let rows = parse_dimension(&msg, "rows", 24)?;
+            let on_exit = match msg.get("on_exit").and_then(|v| v.as_str()) {
+                Some(value) => value.parse()?,
+                None => OnExit::default(),
+            };
+            let command = parse_command(&msg)?;
+            let env = parse_env(&msg)?;
+            let cwd = match parse_cwd(&msg)? {
+                Some(CwdRequest::Path(path)) => Some(path),
+                // Start where another session currently is, e.g. a new tab
+                // opened from an existing one
+                Some(CwdRequest::From(id)) => manager.session_cwd(&id),
+                None => None,
+            };
 
-            let (session_id, rx) = manager.create_session(cols, rows)?;
+            let (session_id, attachment) =
+                manager.create_session(cols, rows, on_exit, command, cwd, env)?;
 
-            let handle = spawn_output_forwarder(
+            let forwarder = spawn_output_forwarder(
                 session_id,
-                rx,
+                attachment.rx,
                 merged_tx.clone(),
                 exit_tx.clone(),
             );
-            session_tasks.insert(session_id, handle);
+            session_tasks.insert(
+                session_id,
+                SessionTask {
+                    attach_id: attachment.id,
+                    read_only: !attachment.writer,
+                    forwarder,
+                },
+            );
 
+            let (cols, rows) = manager.session_size(&session_id).unwrap_or((cols, rows));
             let response = serde_json::json!({
                 "type": "created",
                 "session_id": session_id.to_string(),
+                "writer": attachment.writer,
+                "mode": granted_mode(attachment.writer),
+                "cols": cols,
+                "rows": rows,
             });
 
             let _ = ws_sender
@@ -301,40 +808,104 @@ async fn handle_control_message(
                 .ok_or("Missing session_id")?;
             let session_id: SessionId =
                 session_id_str.parse().map_err(|_| "Invalid session_id")?;
-            let cols = msg.get("cols").and_then(|v| v.as_u64()).unwrap_or(80) as u16;
-            let rows = msg.get("rows").and_then(|v| v.as_u64()).unwrap_or(24) as u16;
+            let cols = parse_dimension(&msg, "cols", 80)?;
+            let rows = parse_dimension(&msg, "rows", 24)?;
 
-            manager.resize_session(&session_id, cols, rows)?;
+            // Viewers follow the writer's size rather than imposing their own
+            let is_writer = session_tasks
+                .get(&session_id)
+                .is_some_and(|task| manager.is_writer(&session_id, task.attach_id));
+            if is_writer {
+                manager.resize_session(&session_id, cols, rows)?;
+            }
             Ok(true)
         }
-        "attach" => {
-            let session_id_str = msg
+        // Every session is listed: the server has a single user, so any
+        // connection may reattach to any session
+        "list" | "list_sessions" => {
+            let sessions: Vec<_> = manager
+                .list_sessions()
+                .into_iter()
+                .map(|session| {
+                    serde_json::json!({
+                        "id": session.id.to_string(),
+                        "cols": session.cols,
+                        "rows": session.rows,
+                        "title": session.title,
+                        "attached": session.attached,
+                    })
+                })
+                .collect();
+            let response =
+                serde_json::json!({ "type": "sessions", "sessions": sessions });
+            let _ = ws_sender
+                .send(Message::Text(response.to_string().into()))
+                .await;
+            Ok(true)
+        }
+        "signal" => {
+            let session_id: SessionId = msg
                 .get("session_id")
                 .and_then(|v| v.as_str())
-                .ok_or("Missing session_id")?;
-            let session_id: SessionId =
-                session_id_str.parse().map_err(|_| "Invalid session_id")?;
+                .ok_or("Missing session_id")?
+                .parse()
+                .map_err(|_| "Invalid session_id")?;
+            let signal = parse_signal(&msg)?;
+
+            // Viewers may watch but not interrupt
+            let is_writer = session_tasks
+                .get(&session_id)
+                .is_some_and(|task| manager.is_writer(&session_id, task.attach_id));
+            if !is_writer {
+                return Err("Only the client in control may signal a session".into());
+            }
+            manager.signal_session(&session_id, signal)?;
+            Ok(true)
+        }
+        "attach" => {
+            let (session_id, mode) =
+                parse_attach(&msg).map_err(|e| ControlError::attach(e, None))?;
+            let attachment = manager
+                .attach_session(&session_id, mode)
+                .map_err(|e| ControlError::attach(e, Some(session_id)))?;
 
-            let (rx, buffered) = manager.attach_session(&session_id)?;
+            // Replace an earlier attachment from this connection
+            if let Some(old) = session_tasks.remove(&session_id) {
+                old.forwarder.abort();
+                manager.detach_session(&session_id, old.attach_id);
+            }
 
-            let handle = spawn_output_forwarder(
+            let forwarder = spawn_output_forwarder(
                 session_id,
-                rx,
+                attachment.rx,
                 merged_tx.clone(),
                 exit_tx.clone(),
             );
-            session_tasks.insert(session_id, handle);
+            session_tasks.insert(
+                session_id,
+                SessionTask {
+                    attach_id: attachment.id,
+                    read_only: !attachment.writer,
+                    forwarder,
+                },
+            );
 
             // Send buffered output first
-            if !buffered.is_empty() {
-                let mut frame = session_id.as_bytes().to_vec();
-                frame.extend_from_slice(&buffered);
+            if !attachment.buffered.is_empty() {
+                let frame = output_frame(&session_id, &attachment.buffered, compress);
                 let _ = ws_sender.send(Message::Binary(frame.into())).await;
             }
 
+            // The session keeps its size, so the client lays out the replayed
+            // output the way it was produced
+            let (cols, rows) = manager.session_size(&session_id).unwrap_or_default();
             let response = serde_json::json!({
                 "type": "attached",
                 "session_id": session_id.to_string(),
+                "writer": attachment.writer,
+                "mode": granted_mode(attachment.writer),
+                "cols": cols,
+                "rows": rows,
             });
             let _ = ws_sender
                 .send(Message::Text(response.to_string().into()))
@@ -351,14 +922,160 @@ async fn handle_control_message(
                 session_id_str.parse().map_err(|_| "Invalid session_id")?;
 
             // Abort the forwarding task for this session
-            if let Some(handle) = session_tasks.remove(&session_id) {
-                handle.abort();
-            }
+            let Some(task) = session_tasks.remove(&session_id) else {
+                return Ok(true);
+            };
+            task.forwarder.abort();
 
-            manager.close_session(&session_id);
+            // Viewers only stop watching; the writer ends the session
+            if manager.is_writer(&session_id, task.attach_id) {
+                manager.close_session(&session_id);
+            } else {
+                manager.detach_session(&session_id, task.attach_id);
+            }
             Ok(true)
         }
-        _ => Err(format!("Unknown message type: {msg_type}")),
+        _ => Err(format!("Unknown message type: {msg_type}").into()),
+    }
+}
+
+/// Read the session id and attach mode from an `attach` message. `mode` is
+/// `rw` to take control or `ro` to watch; the older `read_only` flag still
+/// works when it is absent, and `takeover` wins over both
+fn parse_attach(msg: &serde_json::Value) -> Result<(SessionId, AttachMode), AttachError> {
+    let session_id = msg
+        .get("session_id")
+        .and_then(|v| v.as_str())
+        .and_then(|v| v.parse().ok())
+        .ok_or(AttachError::InvalidId)?;
+
+    let requested = match msg.get("mode").filter(|v| !v.is_null()) {
+        Some(value) => match value.as_str() {
+            Some("rw") => Some(AttachMode::Control),
+            Some("ro") => Some(AttachMode::ReadOnly),
+            _ => return Err(AttachError::InvalidMode),
+        },
+        None => None,
+    };
+    let flag = |name: &str| msg.get(name).and_then(|v| v.as_bool()) == Some(true);
+    let mode = if flag("takeover") {
+        AttachMode::Takeover
+    } else if let Some(mode) = requested {
+        mode
+    } else if flag("read_only") {
+        AttachMode::ReadOnly
+    } else {
+        AttachMode::Control
+    };
+    Ok((session_id, mode))
+}
+
+/// Read the `signal` of a signal message: `INT`, `TSTP` or `QUIT`, the
+/// signals Ctrl+C, Ctrl+Z and Ctrl+\ send
+fn parse_signal(msg: &serde_json::Value) -> Result<libc::c_int, String> {
+    match msg.get("signal").and_then(|v| v.as_str()) {
+        Some("INT") => Ok(libc::SIGINT),
+        Some("TSTP") => Ok(libc::SIGTSTP),
+        Some("QUIT") => Ok(libc::SIGQUIT),
+        _ => Err("Invalid 'signal': expected \"INT\", \"TSTP\" or \"QUIT\"".to_string()),
+    }
+}
+
+/// `mode` reported back to a client: `rw` when it holds control
+fn granted_mode(writer: bool) -> &'static str {
+    if writer {
+        "rw"
+    } else {
+        "ro"
+    }
+}
+
+/// Read a `cols`/`rows` field from a control message, falling back to
+/// `default` when absent. Non-positive values are rejected and oversized
+/// ones clamped to `MAX_DIMENSION`
+fn parse_dimension(
+    msg: &serde_json::Value,
+    field: &str,
+    default: u16,
+) -> Result<u16, String> {
+    match msg.get(field) {
+        None | Some(serde_json::Value::Null) => Ok(default),
+        Some(value) => match value.as_u64() {
+            Some(n) if n > 0 => Ok(n.min(u64::from(MAX_DIMENSION)) as u16),
+            _ => Err(format!("Invalid '{field}': expected a positive integer")),
+        },
+    }
+}
+
+/// Read the optional `command` argv array from a create message
+fn parse_command(msg: &serde_json::Value) -> Result<Option<Vec<String>>, String> {
+    let Some(value) = msg.get("command").filter(|v| !v.is_null()) else {
+        return Ok(None);
+    };
+    let invalid =
+        || "Invalid 'command': expected a non-empty array of strings".to_string();
+
+    let argv = value
+        .as_array()
+        .ok_or_else(invalid)?
+        .iter()
+        .map(|arg| arg.as_str().map(str::to_string).ok_or_else(invalid))
+        .collect::<Result<Vec<_>, _>>()?;
+    if argv.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Some(argv))
+}
+
+/// Read the optional `env` object of a create message, mapping variable
+/// names to string values. These override the server's defaults.
+fn parse_env(msg: &serde_json::Value) -> Result<Vec<(String, String)>, String> {
+    let Some(value) = msg.get("env").filter(|v| !v.is_null()) else {
+        return Ok(Vec::new());
+    };
+    let invalid = || "Invalid 'env': expected an object of string values".to_string();
+
+    value
+        .as_object()
+        .ok_or_else(invalid)?
+        .iter()
+        .map(|(key, value)| {
+            if key.is_empty() || key.contains(['=', '\0']) {
+                return Err(format!("Invalid 'env' variable name '{key}'"));
+            }
+            let value = value.as_str().filter(|v| !v.contains('\0'));
+            Ok((key.clone(), value.ok_or_else(invalid)?.to_string()))
+        })
+        .collect()
+}
+
+/// Working directory requested for a new session
+#[derive(Debug, PartialEq)]
+enum CwdRequest {
+    Path(PathBuf),
+    /// Inherit the current directory of an existing session
+    From(SessionId),
+}
+
+/// Read the optional working directory of a create message: an absolute
+/// `cwd` path, or `cwd_from` naming a session whose directory to inherit.
+/// An explicit `cwd` wins when both are present.
+fn parse_cwd(msg: &serde_json::Value) -> Result<Option<CwdRequest>, String> {
+    if let Some(value) = msg.get("cwd").filter(|v| !v.is_null()) {
+        let path = value
+            .as_str()
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .ok_or("Invalid 'cwd': expected an absolute path")?;
+        return Ok(Some(CwdRequest::Path(path)));
+    }
+    match msg.get("cwd_from").filter(|v| !v.is_null()) {
+        Some(value) => value
+            .as_str()
+            .and_then(|id| id.parse().ok())
+            .map(|id| Some(CwdRequest::From(id)))
+            .ok_or_else(|| "Invalid 'cwd_from': expected a session id".to_string()),
+        None => Ok(None),
     }
 }
 
@@ -390,6 +1107,148 @@ impl axum::serve::Listener for TlsListener {
     }
 }
 
+/// Unix domain socket listener for serving behind a reverse proxy on the same
+/// machine. A socket left by an earlier run is replaced on bind, and the
+/// socket file is removed again when the listener is dropped
+struct UnixSocketListener {
+    inner: tokio::net::UnixListener,
+    path: PathBuf,
+}
+
+impl UnixSocketListener {
+    fn bind(path: &Path) -> std::io::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+
+        // Only ever remove a stale socket, never a file that happens to be
+        // at the configured path
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if metadata.file_type().is_socket() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(Self {
+            inner: tokio::net::UnixListener::bind(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl axum::serve::Listener for UnixSocketListener {
+    type Io = tokio::net::UnixStream;
+    type Addr = tokio::net::unix::SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            match self.inner.accept().await {
+                Ok(conn) => return conn,
+                Err(e) => tracing::error!("Unix socket accept failed: {e}"),
+            }
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        self.inner.local_addr()
+    }
+}
+
+impl Drop for UnixSocketListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Names a self-signed certificate is issued for: localhost and the bound
+/// address, or every local address when bound to all interfaces, plus the
+/// machine's host name unless only loopback is reachable
+fn certificate_sans(
+    host: IpAddr,
+    local: Vec<IpAddr>,
+    hostname: Option<String>,
+) -> Vec<String> {
+    let mut sans = vec!["localhost".to_string()];
+    if let Some(hostname) = hostname.filter(|_| !host.is_loopback()) {
+        sans.push(hostname);
+    }
+    if host.is_unspecified() {
+        sans.extend(local.iter().map(IpAddr::to_string));
+    } else {
+        sans.push(host.to_string());
+    }
+    sans
+}
+
+/// Where the generated self-signed certificate is kept between runs
+fn certificate_cache_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("omni")
+        .join("terminal")
+        .join("tls")
+}
+
+/// Certificate and key PEM for `sans`, reusing the ones cached in `dir` so
+/// the fingerprint clients pinned survives restarts. A new pair is only
+/// generated when none is cached or the names it covers have changed
+fn self_signed_certificate(
+    dir: &Path,
+    mut sans: Vec<String>,
+) -> Result<(Vec<u8>, Vec<u8>), Box<dyn std::error::Error>> {
+    let (cert_path, key_path, sans_path) =
+        (dir.join("cert.pem"), dir.join("key.pem"), dir.join("sans"));
+    sans.sort();
+    sans.dedup();
+    let cached_sans = std::fs::read_to_string(&sans_path).ok().map(|text| {
+        let mut cached: Vec<String> = text.lines().map(str::to_string).collect();
+        cached.sort();
+        cached
+    });
+    if cached_sans.as_ref() == Some(&sans) {
+        if let (Ok(cert), Ok(key)) = (std::fs::read(&cert_path), std::fs::read(&key_path))
+        {
+            tracing::info!("using cached self-signed TLS certificate for {sans:?}");
+            return Ok((cert, key));
+        }
+    }
+
+    tracing::info!("generating self-signed TLS certificate for {sans:?}");
+    let generated = rcgen::generate_simple_self_signed(sans.clone())?;
+    let cert = generated.cert.pem().into_bytes();
+    let key = generated.key_pair.serialize_pem().into_bytes();
+
+    let cache = || -> std::io::Result<()> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        std::fs::create_dir_all(dir)?;
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&key_path)?
+            .write_all(&key)?;
+        std::fs::write(&cert_path, &cert)?;
+        // Written last, so an interrupted write regenerates next time
+        std::fs::write(&sans_path, sans.join("\n"))
+    };
+    if let Err(e) = cache() {
+        tracing::warn!("Failed to cache TLS certificate in {}: {e}", dir.display());
+    }
+    Ok((cert, key))
+}
+
+/// This machine's host name, as `gethostname` reports it
+fn local_hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    std::str::from_utf8(&buf[..len])
+        .ok()
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
 /// Enumerate all local network interface IP addresses via `getifaddrs`
 fn local_ip_addresses() -> Vec<IpAddr> {
     let mut addrs = Vec::new();
@@ -422,3 +1281,787 @@ fn local_ip_addresses() -> Vec<IpAddr> {
     }
     addrs
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{BoxFuture, FutureExt};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    #[test]
+    fn upgrades_are_accepted_only_from_allowed_hosts() {
+        use axum::http::{header, HeaderMap, HeaderValue};
+
+        let request = |host: &'static str, origin: Option<&'static str>| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, HeaderValue::from_static(host));
+            if let Some(origin) = origin {
+                headers.insert(header::ORIGIN, HeaderValue::from_static(origin));
+            }
+            headers
+        };
+        let allowed = allowed_hosts(
+            vec![" Term.Example.com".to_string()],
+            vec![
+                Ipv4Addr::new(10, 0, 0, 5).into(),
+                Ipv6Addr::LOCALHOST.into(),
+            ],
+            Some("DevBox".to_string()),
+        );
+
+        for (host, origin) in [
+            ("localhost:3000", Some("https://localhost:3000")),
+            ("10.0.0.5:3000", Some("https://10.0.0.5:3000")),
+            ("[::1]:3000", Some("http://[::1]:3000")),
+            ("term.example.com", Some("https://TERM.example.com")),
+            ("devbox:3000", Some("https://devbox:3000")),
+            // Native clients send no Origin
+            ("10.0.0.5:3000", None),
+        ] {
+            assert!(
+                origin_allowed(&request(host, origin), &allowed),
+                "{host} {origin:?}"
+            );
+        }
+
+        for (host, origin) in [
+            // DNS rebinding: the attacker's name resolves to this machine
+            ("evil.example:3000", Some("http://evil.example:3000")),
+            ("localhost:3000", Some("https://evil.example")),
+            ("evil.example", None),
+            ("localhost", Some("null")),
+        ] {
+            assert!(
+                !origin_allowed(&request(host, origin), &allowed),
+                "{host} {origin:?}"
+            );
+        }
+        assert!(!origin_allowed(&HeaderMap::new(), &allowed));
+
+        let any = allowed_hosts(vec!["*".to_string()], Vec::new(), None);
+        assert!(origin_allowed(&request("evil.example", None), &any));
+    }
+
+    #[test]
+    fn certificate_covers_only_the_bound_interface() {
+        let local: Vec<IpAddr> = vec![
+            Ipv4Addr::LOCALHOST.into(),
+            Ipv4Addr::new(10, 0, 0, 5).into(),
+            Ipv6Addr::LOCALHOST.into(),
+        ];
+        assert_eq!(
+            certificate_sans(Ipv4Addr::new(10, 0, 0, 5).into(), local.clone(), None),
+            ["localhost", "10.0.0.5"]
+        );
+        assert_eq!(
+            certificate_sans(
+                Ipv4Addr::UNSPECIFIED.into(),
+                local.clone(),
+                Some("devbox".to_string())
+            ),
+            ["localhost", "devbox", "127.0.0.1", "10.0.0.5", "::1"]
+        );
+        // The host name cannot be reached through loopback
+        assert_eq!(
+            certificate_sans(
+                Ipv4Addr::LOCALHOST.into(),
+                local,
+                Some("devbox".to_string())
+            ),
+            ["localhost", "127.0.0.1"]
+        );
+    }
+
+    #[test]
+    fn self_signed_certificate_is_reused_until_names_change() {
+        let dir = std::env::temp_dir().join(format!("omni-tls-{}", std::process::id()));
+        let sans = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+
+        let first =
+            self_signed_certificate(&dir, sans(&["localhost", "devbox"])).unwrap();
+        // Same names in another order, as on the next startup
+        let second =
+            self_signed_certificate(&dir, sans(&["devbox", "localhost"])).unwrap();
+        assert_eq!(first, second);
+
+        let renamed =
+            self_signed_certificate(&dir, sans(&["localhost", "laptop"])).unwrap();
+        assert_ne!(renamed.0, first.0);
+        let again =
+            self_signed_certificate(&dir, sans(&["localhost", "laptop"])).unwrap();
+        assert_eq!(again, renamed);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_dimension_defaults_when_absent() {
+        let msg = serde_json::json!({ "type": "create" });
+        assert_eq!(parse_dimension(&msg, "cols", 80), Ok(80));
+    }
+
+    #[test]
+    fn parse_dimension_clamps_oversized_values() {
+        let msg = serde_json::json!({ "cols": 70000 });
+        assert_eq!(parse_dimension(&msg, "cols", 80), Ok(MAX_DIMENSION));
+    }
+
+    #[test]
+    fn parse_dimension_rejects_non_positive_values() {
+        for value in [
+            serde_json::json!(0),
+            serde_json::json!(-5),
+            serde_json::json!(12.5),
+            serde_json::json!("80"),
+        ] {
+            let msg = serde_json::json!({ "rows": value });
+            assert!(parse_dimension(&msg, "rows", 24).is_err());
+        }
+    }
+
+    #[test]
+    fn output_frame_deflates_only_large_output() {
+        use std::io::Read;
+
+        let id = SessionId::new_v4();
+        // Roughly what `cat` of a source file sends
+        let text = include_str!("server.rs").replace('\n', "\r\n");
+        let plain = output_frame(&id, text.as_bytes(), false);
+        let compressed = output_frame(&id, text.as_bytes(), true);
+        assert_eq!(&plain[16..], text.as_bytes());
+        assert_eq!(compressed[16], 1);
+        assert!(
+            compressed.len() < plain.len() / 2,
+            "{} bytes compressed vs {} plain",
+            compressed.len(),
+            plain.len()
+        );
+
+        let mut inflated = Vec::new();
+        flate2::read::DeflateDecoder::new(&compressed[17..])
+            .read_to_end(&mut inflated)
+            .unwrap();
+        assert_eq!(inflated, text.as_bytes());
+
+        // Interactive echo is sent as is, behind the flag byte
+        let echo = output_frame(&id, b"ls\r\n", true);
+        assert_eq!(&echo[..16], id.as_bytes());
+        assert_eq!(&echo[16..], b"\0ls\r\n");
+    }
+
+    #[test]
+    fn parse_command_accepts_argv() {
+        let msg = serde_json::json!({ "command": ["make", "-j4"] });
+        assert_eq!(
+            parse_command(&msg),
+            Ok(Some(vec!["make".to_string(), "-j4".to_string()]))
+        );
+        assert_eq!(parse_command(&serde_json::json!({})), Ok(None));
+    }
+
+    #[test]
+    fn parse_command_rejects_malformed_argv() {
+        for value in [
+            serde_json::json!([]),
+            serde_json::json!("make"),
+            serde_json::json!(["make", 4]),
+        ] {
+            let msg = serde_json::json!({ "command": value });
+            assert!(parse_command(&msg).is_err());
+        }
+    }
+
+    #[test]
+    fn parse_env_reads_string_variables() {
+        let msg = serde_json::json!({ "env": { "TERM": "xterm" } });
+        assert_eq!(
+            parse_env(&msg),
+            Ok(vec![("TERM".to_string(), "xterm".to_string())])
+        );
+        assert_eq!(parse_env(&serde_json::json!({})), Ok(vec![]));
+
+        for value in [
+            serde_json::json!(["TERM=xterm"]),
+            serde_json::json!({ "TERM": 1 }),
+            serde_json::json!({ "A=B": "c" }),
+        ] {
+            let msg = serde_json::json!({ "env": value });
+            assert!(parse_env(&msg).is_err());
+        }
+    }
+
+    #[test]
+    fn parse_cwd_accepts_path_or_session() {
+        let msg = serde_json::json!({ "cwd": "/home/user/my project" });
+        assert_eq!(
+            parse_cwd(&msg),
+            Ok(Some(CwdRequest::Path(PathBuf::from(
+                "/home/user/my project"
+            ))))
+        );
+
+        let id = SessionId::new_v4();
+        let msg = serde_json::json!({ "cwd_from": id.to_string() });
+        assert_eq!(parse_cwd(&msg), Ok(Some(CwdRequest::From(id))));
+
+        assert_eq!(parse_cwd(&serde_json::json!({})), Ok(None));
+    }
+
+    #[test]
+    fn parse_cwd_rejects_relative_paths_and_bad_ids() {
+        assert!(parse_cwd(&serde_json::json!({ "cwd": "src" })).is_err());
+        assert!(parse_cwd(&serde_json::json!({ "cwd": 5 })).is_err());
+        assert!(parse_cwd(&serde_json::json!({ "cwd_from": "nope" })).is_err());
+    }
+
+    #[test]
+    fn render_metrics_uses_prometheus_text_format() {
+        let metrics = SessionMetrics {
+            active_sessions: 2,
+            detached_sessions: 1,
+            bytes_in: 10,
+            ..SessionMetrics::default()
+        };
+        let text = render_metrics(&metrics);
+
+        assert!(text.contains("# TYPE omni_terminal_sessions gauge\n"));
+        assert!(text.contains("omni_terminal_sessions{state=\"active\"} 2\n"));
+        assert!(text.contains("omni_terminal_sessions{state=\"detached\"} 1\n"));
+        assert!(text.contains("omni_terminal_input_bytes_total 10\n"));
+        assert!(text.contains("omni_terminal_sessions_reaped_total 0\n"));
+    }
+
+    #[tokio::test]
+    async fn healthz_reports_uptime_and_sessions() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (addr, _) = spawn_test_server(SessionManager::new(0)).await;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("content-type: application/json"));
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["sessions"], 0);
+        assert!(body["uptime_secs"].is_u64());
+    }
+
+    /// Host name of the machine the test server runs on
+    const TEST_HOSTNAME: &str = "devbox";
+
+    /// Server state accepting WebSocket upgrades addressed to localhost or
+    /// `TEST_HOSTNAME`. The sender must be kept alive, or connections see
+    /// the server shut down
+    fn test_state(session_manager: SessionManager) -> (AppState, watch::Sender<bool>) {
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let hostname = Some(TEST_HOSTNAME.to_string());
+        let state = AppState {
+            session_manager,
+            shutdown,
+            allowed_hosts: Arc::new(allowed_hosts(Vec::new(), Vec::new(), hostname)),
+            started: Instant::now(),
+        };
+        (state, shutdown_tx)
+    }
+
+    /// Send a control message and return the next control message the
+    /// server replies with, skipping output frames
+    async fn control<S>(ws: &mut S, msg: serde_json::Value) -> serde_json::Value
+    where
+        S: futures::Stream<Item = tokio_tungstenite::tungstenite::Result<WsMessage>>
+            + futures::Sink<WsMessage, Error = tokio_tungstenite::tungstenite::Error>
+            + Unpin,
+    {
+        ws.send(WsMessage::text(msg.to_string())).await.unwrap();
+        loop {
+            if let WsMessage::Text(text) = ws.next().await.unwrap().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    type TestSocket = tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>;
+
+    /// Serve `manager` on a loopback port, returning its address and a
+    /// function opening WebSocket connections to it as localhost
+    async fn spawn_test_server(
+        manager: SessionManager,
+    ) -> (SocketAddr, impl Fn() -> BoxFuture<'static, TestSocket>) {
+        let (state, shutdown_tx) = test_state(manager);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // Connections see the server shut down once this is dropped
+            let _shutdown_tx = shutdown_tx;
+            axum::serve(listener, router(state)).await
+        });
+        let connect = move || {
+            async move {
+                let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+                let (ws, _) =
+                    tokio_tungstenite::client_async("ws://localhost/ws", stream)
+                        .await
+                        .unwrap();
+                ws
+            }
+            .boxed()
+        };
+        (addr, connect)
+    }
+
+    #[tokio::test]
+    async fn attach_reports_the_session_geometry() {
+        let manager = SessionManager::new(0);
+        let (_, connect) = spawn_test_server(manager.clone()).await;
+
+        let mut owner = connect().await;
+        let create = serde_json::json!({
+            "type": "create",
+            "cols": 100,
+            "rows": 30,
+            "command": ["sleep", "10"],
+        });
+        let created = control(&mut owner, create).await;
+        assert_eq!(
+            (&created["cols"], &created["rows"]),
+            (&100.into(), &30.into())
+        );
+        let id = created["session_id"].clone();
+        let session_id: SessionId = id.as_str().unwrap().parse().unwrap();
+
+        let resize = serde_json::json!({
+            "type": "resize",
+            "session_id": id,
+            "cols": 120,
+            "rows": 40,
+        });
+        owner
+            .send(WsMessage::text(resize.to_string()))
+            .await
+            .unwrap();
+        while manager.session_size(&session_id) != Some((120, 40)) {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        // A viewer is told the controller's size, and cannot change it
+        let mut viewer = connect().await;
+        let attach =
+            serde_json::json!({ "type": "attach", "session_id": id, "read_only": true });
+        let attached = control(&mut viewer, attach.clone()).await;
+        assert_eq!(
+            (&attached["cols"], &attached["rows"]),
+            (&120.into(), &40.into())
+        );
+        let resize = serde_json::json!({
+            "type": "resize",
+            "session_id": id,
+            "cols": 80,
+            "rows": 24,
+        });
+        viewer
+            .send(WsMessage::text(resize.to_string()))
+            .await
+            .unwrap();
+        let reattached = control(&mut viewer, attach).await;
+        assert_eq!(reattached["writer"], false);
+        assert_eq!(
+            (&reattached["cols"], &reattached["rows"]),
+            (&120.into(), &40.into())
+        );
+
+        manager.close_all();
+    }
+
+    #[tokio::test]
+    async fn list_reports_attached_and_detached_sessions() {
+        let manager = SessionManager::new(0);
+        let (_, connect) = spawn_test_server(manager.clone()).await;
+        let create = |cols: u16| {
+            serde_json::json!({
+                "type": "create",
+                "cols": cols,
+                "rows": 24,
+                "command": ["sleep", "10"],
+            })
+        };
+
+        let mut kept = connect().await;
+        let attached = control(&mut kept, create(100)).await;
+        let mut dropped = connect().await;
+        let detached = control(&mut dropped, create(120)).await;
+        dropped.close(None).await.unwrap();
+        while manager.metrics().detached_sessions != 1 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        // A client that lost its session ids discovers both
+        let mut fresh = connect().await;
+        let listed = control(&mut fresh, serde_json::json!({ "type": "list" })).await;
+        assert_eq!(listed["type"], "sessions");
+        let mut sessions = listed["sessions"].as_array().unwrap().clone();
+        sessions.sort_by_key(|session| session["cols"].as_u64());
+        assert_eq!(
+            sessions,
+            [
+                serde_json::json!({
+                    "id": attached["session_id"],
+                    "cols": 100,
+                    "rows": 24,
+                    "title": null,
+                    "attached": true,
+                }),
+                serde_json::json!({
+                    "id": detached["session_id"],
+                    "cols": 120,
+                    "rows": 24,
+                    "title": null,
+                    "attached": false,
+                }),
+            ]
+        );
+
+        manager.close_all();
+    }
+
+    /// Ids of the sessions the server lists
+    async fn listed_ids(ws: &mut TestSocket) -> Vec<serde_json::Value> {
+        let listed = control(ws, serde_json::json!({ "type": "list" })).await;
+        let sessions = listed["sessions"].as_array().unwrap();
+        sessions
+            .iter()
+            .map(|session| session["id"].clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn held_sessions_stay_until_closed() {
+        let manager = SessionManager::new(0);
+        let (_, connect) = spawn_test_server(manager.clone()).await;
+
+        let mut owner = connect().await;
+        let create = serde_json::json!({
+            "type": "create",
+            "on_exit": "hold",
+            "command": ["true"],
+        });
+        let created = control(&mut owner, create).await;
+        let id = created["session_id"].clone();
+        let exited = owner.next().await.unwrap().unwrap();
+        let exited: serde_json::Value =
+            serde_json::from_str(exited.to_text().unwrap()).unwrap();
+        assert_eq!(
+            (&exited["type"], &exited["session_id"]),
+            (&"exited".into(), &id)
+        );
+
+        // The exited session can still be reattached to read its output
+        let mut other = connect().await;
+        let attach =
+            serde_json::json!({ "type": "attach", "session_id": id, "read_only": true });
+        assert_eq!(control(&mut other, attach).await["type"], "attached");
+        assert_eq!(listed_ids(&mut connect().await).await, [id.clone()]);
+
+        let close = serde_json::json!({ "type": "close", "session_id": id });
+        owner
+            .send(WsMessage::text(close.to_string()))
+            .await
+            .unwrap();
+        while manager.session_count() != 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(listed_ids(&mut connect().await).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sessions_closing_on_exit_are_removed() {
+        let manager = SessionManager::new(0);
+        let (_, connect) = spawn_test_server(manager.clone()).await;
+
+        let mut owner = connect().await;
+        let create = serde_json::json!({
+            "type": "create",
+            "on_exit": "close",
+            "command": ["true"],
+        });
+        let created = control(&mut owner, create).await;
+        let closed = owner.next().await.unwrap().unwrap();
+        let closed: serde_json::Value =
+            serde_json::from_str(closed.to_text().unwrap()).unwrap();
+        assert_eq!(
+            (&closed["type"], &closed["session_id"]),
+            (&"closed".into(), &created["session_id"])
+        );
+        assert!(listed_ids(&mut owner).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn browsers_may_connect_by_host_name() {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let (addr, _) = spawn_test_server(SessionManager::new(0)).await;
+        let open = |name: &str| {
+            let authority = format!("{name}:{}", addr.port());
+            let mut request = format!("ws://{authority}/ws")
+                .into_client_request()
+                .unwrap();
+            let origin = format!("https://{authority}").parse().unwrap();
+            request.headers_mut().insert("origin", origin);
+            async move {
+                let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+                tokio_tungstenite::client_async(request, stream).await
+            }
+        };
+
+        let (mut ws, _) = open(TEST_HOSTNAME).await.unwrap();
+        let listed = control(&mut ws, serde_json::json!({ "type": "list" })).await;
+        assert_eq!(listed["type"], "sessions");
+        match open("elsewhere").await {
+            Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), axum::http::StatusCode::FORBIDDEN)
+            }
+            other => panic!("unexpected upgrade result: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_messages_are_refused() {
+        let (_, connect) = spawn_test_server(SessionManager::new(0)).await;
+        let mut ws = connect().await;
+
+        // Too large to parse, but the connection carries on
+        let padding = "x".repeat(MAX_CONTROL_MESSAGE_SIZE);
+        let bloated = serde_json::json!({ "type": "list", "padding": padding });
+        let refused = control(&mut ws, bloated).await;
+        assert_eq!(refused["type"], "error");
+        assert!(refused["message"].as_str().unwrap().contains("too large"));
+        let listed = control(&mut ws, serde_json::json!({ "type": "list" })).await;
+        assert_eq!(listed["type"], "sessions");
+
+        // Past the message limit the server drops the connection
+        let huge = WsMessage::binary(vec![0; MAX_MESSAGE_SIZE + 1]);
+        let _ = ws.send(huge).await;
+        let closed = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match ws.next().await {
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        })
+        .await;
+        assert!(closed.is_ok(), "connection stayed open");
+    }
+
+    #[tokio::test]
+    async fn unix_socket_serves_create_and_attach() {
+        let dir = std::env::temp_dir().join(format!("omni-serve-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("serve.sock");
+        let listener = UnixSocketListener::bind(&path).unwrap();
+        let manager = SessionManager::new(0);
+        let (state, _shutdown_tx) = test_state(manager.clone());
+        tokio::spawn(async move { axum::serve(listener, router(state)).await });
+
+        let connect = || async {
+            let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+            let (ws, _) = tokio_tungstenite::client_async("ws://localhost/ws", stream)
+                .await
+                .unwrap();
+            ws
+        };
+
+        let mut owner = connect().await;
+        let create = serde_json::json!({ "type": "create", "command": ["sleep", "10"] });
+        let created = control(&mut owner, create).await;
+        assert_eq!(created["type"], "created");
+        assert_eq!(created["writer"], true);
+
+        let mut viewer = connect().await;
+        let attach = serde_json::json!({
+            "type": "attach",
+            "session_id": created["session_id"],
+            "read_only": true,
+        });
+        let attached = control(&mut viewer, attach).await;
+        assert_eq!(attached["type"], "attached");
+        assert_eq!(attached["session_id"], created["session_id"]);
+        assert_eq!(attached["writer"], false);
+
+        manager.close_all();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn detached_sessions_are_reaped_after_the_grace_period() {
+        let manager = SessionManager::new(0);
+        let (id, attachment) = manager
+            .create_session(80, 24, OnExit::Hold, None, None, Vec::new())
+            .unwrap();
+        manager.detach_session(&id, attachment.id);
+        let reaper = spawn_reaper(
+            manager.clone(),
+            Duration::from_millis(10),
+            Duration::from_millis(200),
+        );
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(manager.session_count(), 1, "reaped within the grace period");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(manager.session_count(), 0);
+        reaper.abort();
+    }
+
+    #[tokio::test]
+    async fn static_handler_serves_index_with_headers() {
+        use axum::http::{header, HeaderMap, StatusCode, Uri};
+
+        let response = static_handler(Uri::from_static("/"), HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers[header::CONTENT_TYPE], "text/html; charset=utf-8");
+        assert_eq!(headers[header::CACHE_CONTROL], "no-cache");
+        assert_eq!(headers["cross-origin-opener-policy"], "same-origin");
+        assert_eq!(headers["cross-origin-embedder-policy"], "require-corp");
+        let etag = headers[header::ETAG].clone();
+
+        // Revalidating with the current ETag skips the body
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(header::IF_NONE_MATCH, etag);
+        let response = static_handler(Uri::from_static("/"), request_headers).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response =
+            static_handler(Uri::from_static("/missing.wasm"), HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn embedded_wasm_and_js_get_streaming_compatible_types() {
+        use axum::http::header;
+
+        let wasm =
+            embedded_file_response("wasm/omni_terminal_wasm_bg.wasm", b"\0asm", None);
+        assert_eq!(wasm.headers()[header::CONTENT_TYPE], "application/wasm");
+
+        let js = embedded_file_response("wasm/omni_terminal_wasm.js", b"", None);
+        assert_eq!(
+            js.headers()[header::CONTENT_TYPE],
+            "text/javascript; charset=utf-8"
+        );
+        assert_ne!(wasm.headers()[header::ETAG], js.headers()[header::ETAG]);
+    }
+
+    #[test]
+    fn parse_attach_rejects_missing_and_malformed_ids() {
+        for msg in [
+            serde_json::json!({ "type": "attach" }),
+            serde_json::json!({ "type": "attach", "session_id": "not-a-uuid" }),
+            serde_json::json!({ "type": "attach", "session_id": 42 }),
+        ] {
+            assert_eq!(parse_attach(&msg), Err(AttachError::InvalidId));
+        }
+
+        let id = SessionId::new_v4();
+        let msg = serde_json::json!({ "session_id": id.to_string(), "read_only": true });
+        assert_eq!(parse_attach(&msg), Ok((id, AttachMode::ReadOnly)));
+    }
+
+    #[test]
+    fn parse_attach_reads_the_requested_mode() {
+        let id = SessionId::new_v4();
+        let attach = |mode: serde_json::Value| {
+            parse_attach(
+                &serde_json::json!({ "session_id": id.to_string(), "mode": mode }),
+            )
+        };
+        assert_eq!(attach("rw".into()), Ok((id, AttachMode::Control)));
+        assert_eq!(attach("ro".into()), Ok((id, AttachMode::ReadOnly)));
+        assert_eq!(
+            attach(serde_json::Value::Null),
+            Ok((id, AttachMode::Control))
+        );
+        assert_eq!(attach("write".into()), Err(AttachError::InvalidMode));
+        assert_eq!(attach(true.into()), Err(AttachError::InvalidMode));
+    }
+
+    #[tokio::test]
+    async fn read_only_attachments_cannot_type() {
+        let manager = SessionManager::new(0);
+        let (_, connect) = spawn_test_server(manager.clone()).await;
+
+        let mut owner = connect().await;
+        let create = serde_json::json!({ "type": "create", "command": ["cat"] });
+        let created = control(&mut owner, create).await;
+        assert_eq!(created["mode"], "rw");
+        let id = created["session_id"].clone();
+        let session_id: SessionId = id.as_str().unwrap().parse().unwrap();
+
+        let mut viewer = connect().await;
+        let attached = control(
+            &mut viewer,
+            serde_json::json!({ "type": "attach", "session_id": id, "mode": "ro" }),
+        )
+        .await;
+        assert_eq!(attached["mode"], "ro");
+        // Only one client may hold control
+        let denied = control(
+            &mut viewer,
+            serde_json::json!({ "type": "attach", "session_id": id, "mode": "rw" }),
+        )
+        .await;
+        assert_eq!(denied["code"], "in_use");
+
+        let input = |text: &str| {
+            let mut frame = session_id.as_bytes().to_vec();
+            frame.extend_from_slice(text.as_bytes());
+            WsMessage::binary(frame)
+        };
+        viewer.send(input("viewer\n")).await.unwrap();
+        owner.send(input("owner\n")).await.unwrap();
+
+        let mut echoed = Vec::new();
+        while !String::from_utf8_lossy(&echoed).contains("owner") {
+            if let WsMessage::Binary(frame) = owner.next().await.unwrap().unwrap() {
+                echoed.extend_from_slice(&frame[16..]);
+            }
+        }
+        assert!(!String::from_utf8_lossy(&echoed).contains("viewer"));
+
+        manager.close_all();
+    }
+
+    #[test]
+    fn parse_signal_accepts_only_job_control_signals() {
+        let signal = |name: serde_json::Value| {
+            parse_signal(&serde_json::json!({ "signal": name }))
+        };
+        assert_eq!(signal("INT".into()), Ok(libc::SIGINT));
+        assert_eq!(signal("TSTP".into()), Ok(libc::SIGTSTP));
+        assert_eq!(signal("QUIT".into()), Ok(libc::SIGQUIT));
+        for name in ["KILL", "int", "SIGINT", ""] {
+            assert!(signal(name.into()).is_err(), "{name}");
+        }
+        assert!(parse_signal(&serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn attach_errors_carry_a_code() {
+        let id = SessionId::new_v4();
+        let json = ControlError::attach(AttachError::Expired, Some(id)).to_json();
+        assert_eq!(json["type"], "error");
+        assert_eq!(json["code"], "expired");
+        assert_eq!(json["session_id"], id.to_string());
+
+        let json = ControlError::from("Unknown message type: x").to_json();
+        assert!(json.get("code").is_none());
+    }
+}
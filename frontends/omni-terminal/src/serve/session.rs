@@ -1,37 +1,183 @@
-use dashmap::DashMap;
-use std::io::{Read, Write};
+use dashmap::{DashMap, DashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
-use teletypewriter::create_pty_with_spawn;
+use teletypewriter::create_pty_with_spawn_and_env;
+use terminal_emulator::{ReadBuffer, DEFAULT_MAX_READ_BUFFER, MAX_GRID_DIMENSION};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
 use tokio::sync::mpsc;
 use uuid::Uuid;
 
+use super::state::{self, SessionRecord};
+use super::title::TitleScanner;
+
 const MAX_BUFFER_SIZE: usize = 1024 * 1024; // 1 MB
 
+/// Default per-session input rate limit, generous enough for pastes
+pub const DEFAULT_INPUT_RATE_LIMIT: u32 = 1024 * 1024; // 1 MB/s
+
+/// Input queued for a process that is not reading it, beyond which further
+/// input is dropped rather than held
+const MAX_PENDING_INPUT: usize = 64 * 1024;
+
+/// Largest terminal dimension (cols or rows) a client may request, the
+/// largest grid the emulator accepts
+pub const MAX_DIMENSION: u16 = MAX_GRID_DIMENSION as u16;
+
+/// Clamp a requested dimension into `1..=MAX_DIMENSION`
+fn clamp_dimension(value: u16) -> u16 {
+    value.clamp(1, MAX_DIMENSION)
+}
+
 pub type SessionId = Uuid;
 
+/// Identifies one client's attachment to a session
+pub type AttachId = u64;
+
+/// What happens to a session once its process exits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnExit {
+    /// Remove the session as soon as the process exits
+    Close,
+    /// Keep the session and its buffered output until the client closes it
+    #[default]
+    Hold,
+}
+
+impl std::str::FromStr for OnExit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "close" => Ok(Self::Close),
+            "hold" => Ok(Self::Hold),
+            _ => Err(format!(
+                "Invalid on_exit '{s}': expected \"close\" or \"hold\""
+            )),
+        }
+    }
+}
+
+/// How a client attaches to an existing session
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AttachMode {
+    /// Take control as the writer; fails while another client holds it
+    #[default]
+    Control,
+    /// Watch output without sending input
+    ReadOnly,
+    /// Detach every other client, take control and replay the history.
+    /// For reconnecting while a stale connection still holds the session.
+    Takeover,
+}
+
+/// Why a client could not attach to a session
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttachError {
+    /// No session with this id was ever served
+    NotFound,
+    /// The session was reaped, or belonged to a previous run of the server
+    Expired,
+    /// The session id is missing or malformed
+    InvalidId,
+    /// The requested `mode` is neither `rw` nor `ro`
+    InvalidMode,
+    /// Another client holds control of the session
+    InUse,
+}
+
+impl AttachError {
+    /// Machine-readable code sent to clients alongside the message
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::NotFound => "not_found",
+            Self::Expired => "expired",
+            Self::InvalidId => "invalid_id",
+            Self::InvalidMode => "invalid_mode",
+            Self::InUse => "in_use",
+        }
+    }
+}
+
+impl std::fmt::Display for AttachError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::NotFound => "Session not found",
+            Self::Expired => "Session expired",
+            Self::InvalidId => "Invalid session_id",
+            Self::InvalidMode => "Invalid 'mode': expected \"rw\" or \"ro\"",
+            Self::InUse => {
+                "Session is attached to another client; attach with \"takeover\" \
+                 or \"mode\": \"ro\""
+            }
+        })
+    }
+}
+
+/// One client's attachment to a session, as handed out by
+/// `SessionManager::create_session` and `attach_session`
+pub struct Attachment {
+    pub id: AttachId,
+    pub rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    /// Output produced while no client was attached, or the whole retained
+    /// history on takeover
+    pub buffered: Vec<u8>,
+    /// Whether this client may send input; other attachments only watch
+    pub writer: bool,
+}
+
 pub struct SessionOutput {
+    /// Most recent output, up to `MAX_BUFFER_SIZE`
     buffer: Vec<u8>,
-    sender: Option<mpsc::UnboundedSender<Vec<u8>>>,
+    /// Trailing bytes of `buffer` no client has received yet
+    unsent: usize,
+    /// Every attached client receives the same output
+    senders: Vec<(AttachId, mpsc::UnboundedSender<Vec<u8>>)>,
+    next_attach_id: AttachId,
+    /// Attachment allowed to write input
+    writer: Option<AttachId>,
+    /// Set once the PTY has hit EOF and the child has been reaped
+    exited: bool,
+    exit_code: Option<i32>,
+    /// Last window title the program set, for listing sessions
+    title: Option<String>,
+    title_scanner: TitleScanner,
 }
 
 impl SessionOutput {
-    fn new(sender: mpsc::UnboundedSender<Vec<u8>>) -> Self {
+    fn new() -> Self {
         Self {
             buffer: Vec::new(),
-            sender: Some(sender),
+            unsent: 0,
+            senders: Vec::new(),
+            next_attach_id: 0,
+            writer: None,
+            exited: false,
+            exit_code: None,
+            title: None,
+            title_scanner: TitleScanner::new(),
         }
     }
 
     pub fn write(&mut self, data: &[u8]) {
-        if let Some(ref sender) = self.sender {
-            if sender.send(data.to_vec()).is_err() {
-                self.sender = None;
-                self.buffer_data(data);
-            }
-        } else {
-            self.buffer_data(data);
+        if let Some(title) = self.title_scanner.scan(data) {
+            self.title = Some(title).filter(|title| !title.is_empty());
+        }
+        self.senders
+            .retain(|(_, sender)| sender.send(data.to_vec()).is_ok());
+        if self
+            .writer
+            .is_some_and(|writer| !self.senders.iter().any(|(id, _)| *id == writer))
+        {
+            self.writer = None;
         }
+        if self.senders.is_empty() {
+            self.unsent += data.len();
+        }
+        self.buffer_data(data);
     }
 
     fn buffer_data(&mut self, data: &[u8]) {
@@ -40,60 +186,380 @@ impl SessionOutput {
             let excess = self.buffer.len() - MAX_BUFFER_SIZE;
             self.buffer.drain(..excess);
         }
+        self.unsent = self.unsent.min(self.buffer.len());
+    }
+
+    /// Whether a connected client currently holds control
+    fn has_live_writer(&self) -> bool {
+        self.writer.is_some_and(|writer| {
+            self.senders
+                .iter()
+                .any(|(id, sender)| *id == writer && !sender.is_closed())
+        })
+    }
+
+    /// Attach a new client in `mode`. Once the process has exited the sender
+    /// is dropped immediately so the receiver observes end-of-output right
+    /// after the buffer.
+    fn attach(&mut self, mode: AttachMode) -> Result<Attachment, AttachError> {
+        if mode == AttachMode::Control && self.has_live_writer() {
+            return Err(AttachError::InUse);
+        }
+
+        let buffered = if mode == AttachMode::Takeover {
+            // Dropping the senders ends the other clients' output streams
+            self.senders.clear();
+            self.buffer.clone()
+        } else {
+            self.buffer[self.buffer.len() - self.unsent..].to_vec()
+        };
+        self.unsent = 0;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let id = self.next_attach_id;
+        self.next_attach_id += 1;
+        if !self.exited {
+            self.senders.push((id, tx));
+        }
+        if mode != AttachMode::ReadOnly {
+            self.writer = Some(id);
+        }
+        Ok(Attachment {
+            id,
+            rx,
+            buffered,
+            writer: self.writer == Some(id),
+        })
     }
 
-    pub fn attach(&mut self, sender: mpsc::UnboundedSender<Vec<u8>>) -> Vec<u8> {
-        self.sender = Some(sender);
-        std::mem::take(&mut self.buffer)
+    /// Drop one client, returning true when it was the last one attached
+    fn detach(&mut self, id: AttachId) -> bool {
+        let before = self.senders.len();
+        self.senders.retain(|(sender_id, _)| *sender_id != id);
+        if self.writer == Some(id) {
+            self.writer = None;
+        }
+        self.senders.len() < before && self.senders.is_empty()
     }
+}
+
+/// Input on its way to a session's PTY. A task does the non-blocking
+/// writes, so a process that stops reading its input stalls only its own
+/// queue and never the connection feeding it.
+struct PtyInput {
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    /// Bytes handed to the writer task and not yet written
+    pending: Arc<AtomicUsize>,
+    /// Set while input is dropped because the queue is full
+    stalled: bool,
+    writer_handle: tokio::task::JoinHandle<()>,
+}
 
-    pub fn detach(&mut self) {
-        self.sender = None;
+impl PtyInput {
+    /// Start writing to `pty`, which must be in non-blocking mode
+    fn spawn(pty: std::fs::File) -> std::io::Result<Self> {
+        let pty = AsyncFd::with_interest(pty, Interest::WRITABLE)?;
+        let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let written = Arc::clone(&pending);
+        let writer_handle = tokio::spawn(async move {
+            while let Some(data) = rx.recv().await {
+                let mut rest = &data[..];
+                while !rest.is_empty() {
+                    let result = match pty.writable().await {
+                        Ok(mut guard) => guard.try_io(|pty| pty.get_ref().write(rest)),
+                        Err(e) => Ok(Err(e)),
+                    };
+                    match result {
+                        Ok(Ok(n)) => {
+                            rest = &rest[n..];
+                            written.fetch_sub(n, Ordering::Relaxed);
+                        }
+                        Ok(Err(e)) => {
+                            tracing::error!("PTY write error: {e}");
+                            return;
+                        }
+                        // Readiness was stale; wait for the next wakeup
+                        Err(_) => {}
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            tx,
+            pending,
+            stalled: false,
+            writer_handle,
+        })
+    }
+
+    /// Queue `data` for the PTY, or drop it while the queue is full. Returns
+    /// true when the queue has just filled up. A write larger than the
+    /// limit still goes through when nothing is pending, so pastes work.
+    fn send(&mut self, data: &[u8]) -> bool {
+        let pending = self.pending.load(Ordering::Relaxed);
+        if pending > 0 && pending + data.len() > MAX_PENDING_INPUT {
+            let newly = !self.stalled;
+            self.stalled = true;
+            return newly;
+        }
+        self.stalled = false;
+        self.pending.fetch_add(data.len(), Ordering::Relaxed);
+        // Fails only once the writer has given up on a closed PTY
+        let _ = self.tx.send(data.to_vec());
+        false
+    }
+}
+
+impl Drop for PtyInput {
+    fn drop(&mut self) {
+        self.writer_handle.abort();
+    }
+}
+
+/// Token bucket capping the rate of input written to a session's PTY.
+/// Holds up to one second's worth of tokens so short bursts pass through.
+struct TokenBucket {
+    /// Refill rate in bytes per second; 0 disables limiting
+    rate: u32,
+    tokens: f64,
+    last_refill: Instant,
+    throttling: bool,
+}
+
+impl TokenBucket {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate,
+            tokens: f64::from(rate),
+            last_refill: Instant::now(),
+            throttling: false,
+        }
+    }
+
+    /// Take up to `len` bytes worth of tokens at `now`, returning how many
+    /// bytes may be written
+    fn take(&mut self, len: usize, now: Instant) -> usize {
+        if self.rate == 0 {
+            return len;
+        }
+
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let rate = f64::from(self.rate);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(rate);
+        self.last_refill = now;
+
+        let allowed = (self.tokens as usize).min(len);
+        self.tokens -= allowed as f64;
+        allowed
     }
 }
 
 pub struct Session {
+    /// The PTY, for resizing; input goes through `input`
     pub pty_writer: std::fs::File,
+    input: PtyInput,
     pub child_pid: i32,
     pub cols: u16,
     pub rows: u16,
+    /// Program and arguments the session was spawned with
+    program: String,
+    args: Vec<String>,
+    pub on_exit: OnExit,
     pub output: Arc<Mutex<SessionOutput>>,
     pub disconnected_at: Option<Instant>,
+    input_limiter: TokenBucket,
     reader_handle: Option<tokio::task::JoinHandle<()>>,
 }
 
+impl Session {
+    fn record(&self, id: SessionId) -> SessionRecord {
+        SessionRecord {
+            id,
+            cols: self.cols,
+            rows: self.rows,
+            program: self.program.clone(),
+            args: self.args.clone(),
+            cwd: self.cwd(),
+        }
+    }
+
+    /// Current working directory of the session's process
+    fn cwd(&self) -> Option<PathBuf> {
+        std::fs::read_link(format!("/proc/{}/cwd", self.child_pid)).ok()
+    }
+
+    fn is_writer(&self, attach_id: AttachId) -> bool {
+        self.output.lock().unwrap().writer == Some(attach_id)
+    }
+}
+
 impl Drop for Session {
     fn drop(&mut self) {
         if let Some(handle) = self.reader_handle.take() {
             handle.abort();
         }
-        teletypewriter::kill_pid(self.child_pid);
+        // An exited child has already been reaped, so its pid may be reused
+        let exited = self.output.lock().is_ok_and(|output| output.exited);
+        if !exited {
+            teletypewriter::kill_pid(self.child_pid);
+        }
     }
 }
 
+/// A live session as described to clients choosing one to attach to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionInfo {
+    pub id: SessionId,
+    pub cols: u16,
+    pub rows: u16,
+    /// Window title last set by the session's program
+    pub title: Option<String>,
+    /// Whether any client is attached; detached sessions await reconnection
+    pub attached: bool,
+}
+
+/// Lifetime counters shared by all sessions of a `SessionManager`
+#[derive(Default)]
+struct SessionCounters {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    sessions_created: AtomicU64,
+    sessions_closed: AtomicU64,
+    sessions_reaped: AtomicU64,
+}
+
+/// Point-in-time view of session activity, served on `/metrics`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SessionMetrics {
+    /// Sessions with a client attached
+    pub active_sessions: u64,
+    /// Sessions kept alive without a client
+    pub detached_sessions: u64,
+    /// Input bytes written to PTYs
+    pub bytes_in: u64,
+    /// Output bytes read from PTYs
+    pub bytes_out: u64,
+    pub sessions_created: u64,
+    pub sessions_closed: u64,
+    /// Detached sessions closed by the reaper
+    pub sessions_reaped: u64,
+}
+
 #[derive(Clone)]
 pub struct SessionManager {
     pub sessions: Arc<DashMap<SessionId, Session>>,
+    /// Input bytes per second allowed into each session; 0 disables limiting
+    input_rate_limit: u32,
+    /// Largest buffer a session's PTY reader grows to under bulk output
+    max_read_buffer: usize,
+    counters: Arc<SessionCounters>,
+    /// File mirroring live session metadata, when persistence is enabled
+    state_file: Option<Arc<PathBuf>>,
+    /// Sessions reaped or served by a previous run of the server
+    expired: Arc<DashSet<SessionId>>,
 }
 
 impl Default for SessionManager {
     fn default() -> Self {
+        Self::new(DEFAULT_INPUT_RATE_LIMIT)
+    }
+}
+
+impl SessionManager {
+    pub fn new(input_rate_limit: u32) -> Self {
         Self {
             sessions: Arc::new(DashMap::new()),
+            input_rate_limit,
+            max_read_buffer: DEFAULT_MAX_READ_BUFFER,
+            counters: Arc::new(SessionCounters::default()),
+            state_file: None,
+            expired: Arc::new(DashSet::new()),
+        }
+    }
+
+    /// Let PTY reads grow up to `bytes` while output keeps filling them.
+    pub fn with_max_read_buffer(mut self, bytes: usize) -> Self {
+        self.max_read_buffer = bytes;
+        self
+    }
+
+    /// Persist session metadata to `path`. Sessions recorded there by a
+    /// previous run cannot be revived, but attaching to them reports that
+    /// they expired rather than that they never existed.
+    pub fn with_state_file(mut self, path: PathBuf) -> Self {
+        let previous = state::load(&path);
+        if !previous.is_empty() {
+            tracing::info!(
+                "{} session(s) from the previous run have expired",
+                previous.len()
+            );
+        }
+        for record in previous {
+            self.expired.insert(record.id);
+        }
+        self.state_file = Some(Arc::new(path));
+        self.persist();
+        self
+    }
+
+    /// Write the live sessions to the state file, if one is configured
+    fn persist(&self) {
+        let Some(path) = self.state_file.as_deref() else {
+            return;
+        };
+        let records: Vec<SessionRecord> = self
+            .sessions
+            .iter()
+            .map(|entry| entry.value().record(*entry.key()))
+            .collect();
+        if let Err(e) = state::save(path, &records) {
+            tracing::warn!("Failed to save session state {}: {e}", path.display());
         }
     }
-}
 
-impl SessionManager {
     pub fn create_session(
         &self,
         cols: u16,
         rows: u16,
-    ) -> Result<(SessionId, mpsc::UnboundedReceiver<Vec<u8>>), String> {
-        let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+        on_exit: OnExit,
+        command: Option<Vec<String>>,
+        cwd: Option<PathBuf>,
+        env: Vec<(String, String)>,
+    ) -> Result<(SessionId, Attachment), String> {
+        let cols = clamp_dimension(cols);
+        let rows = clamp_dimension(rows);
+
+        // Run a one-shot command when given, otherwise an interactive shell
+        let (program, args) = match command {
+            Some(argv) => {
+                let mut argv = argv.into_iter();
+                let program = argv.next().ok_or("Command must not be empty")?;
+                let resolved = resolve_program(&program)
+                    .ok_or_else(|| format!("Command not found: {program}"))?;
+                (resolved.to_string_lossy().into_owned(), argv.collect())
+            }
+            None => (
+                std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string()),
+                vec![],
+            ),
+        };
+
+        // A directory that has since been removed falls back to the default
+        // rather than failing the whole session
+        let cwd = cwd.filter(|dir| {
+            let exists = dir.is_dir();
+            if !exists {
+                tracing::warn!("Ignoring missing working directory {}", dir.display());
+            }
+            exists
+        });
+        let cwd = cwd.map(|dir| dir.to_string_lossy().into_owned());
 
-        let pty = create_pty_with_spawn(&shell, vec![], &None, cols, rows)
-            .map_err(|e| format!("Failed to create PTY: {e}"))?;
+        let env = session_env(env);
+        let pty =
+            create_pty_with_spawn_and_env(&program, args.clone(), &env, &cwd, cols, rows)
+                .map_err(|e| format!("Failed to create PTY: {e}"))?;
 
         let session_id = Uuid::new_v4();
         let child_pid = *pty.child.pid as i32;
@@ -109,11 +575,11 @@ impl SessionManager {
             if wfd < 0 || rfd < 0 {
                 return Err("Failed to dup PTY fd".to_string());
             }
-            // Set both to blocking mode (PTY may default to non-blocking)
-            let flags = libc::fcntl(rfd, libc::F_GETFL);
-            libc::fcntl(rfd, libc::F_SETFL, flags & !libc::O_NONBLOCK);
+            // Both share one open file description and so one set of flags.
+            // Non-blocking keeps a process that stops reading its input from
+            // stalling the writer; the reader waits in `poll` instead.
             let flags = libc::fcntl(wfd, libc::F_GETFL);
-            libc::fcntl(wfd, libc::F_SETFL, flags & !libc::O_NONBLOCK);
+            libc::fcntl(wfd, libc::F_SETFL, flags | libc::O_NONBLOCK);
             // Close original fd now that it has been duplicated
             libc::close(pty_fd);
             (wfd, rfd)
@@ -123,23 +589,38 @@ impl SessionManager {
             use std::os::unix::io::FromRawFd;
             std::fs::File::from_raw_fd(write_fd)
         };
+        let input = pty_writer
+            .try_clone()
+            .and_then(PtyInput::spawn)
+            .map_err(|e| format!("Failed to set up PTY input: {e}"))?;
 
-        let (tx, output_rx) = mpsc::unbounded_channel();
-        let output = Arc::new(Mutex::new(SessionOutput::new(tx)));
+        // The creating client is the first attachment and the writer
+        let mut output = SessionOutput::new();
+        let attachment = output
+            .attach(AttachMode::Control)
+            .map_err(|e| e.to_string())?;
+        let output = Arc::new(Mutex::new(output));
 
         // Spawn PTY reader task with pre-dup'd fd
         let output_clone = Arc::clone(&output);
+        let counters = Arc::clone(&self.counters);
+        let mut buf = ReadBuffer::new(self.max_read_buffer);
         let reader_handle = tokio::task::spawn_blocking(move || {
             let mut reader = unsafe {
                 use std::os::unix::io::FromRawFd;
                 std::fs::File::from_raw_fd(read_fd)
             };
-            let mut buf = [0u8; 4096];
             loop {
-                match reader.read(&mut buf) {
-                    Ok(0) => break,
-                    Ok(n) => {
-                        output_clone.lock().unwrap().write(&buf[..n]);
+                match buf.read(&mut reader) {
+                    Ok([]) => break,
+                    Ok(data) => {
+                        counters
+                            .bytes_out
+                            .fetch_add(data.len() as u64, Ordering::Relaxed);
+                        output_clone.lock().unwrap().write(data);
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        wait_readable(read_fd);
                     }
                     Err(e) => {
                         // EIO means PTY closed (child exited)
@@ -151,36 +632,85 @@ impl SessionManager {
                     }
                 }
             }
-            // Drop the sender so the output forwarder detects end-of-output
-            output_clone.lock().unwrap().sender = None;
+            let exit_code = wait_for_exit(child_pid);
+            tracing::info!("Session process {child_pid} exited with {exit_code:?}");
+
+            // Record the exit status before dropping the senders so the
+            // output forwarders can report it on end-of-output
+            let mut output = output_clone.lock().unwrap();
+            output.exited = true;
+            output.exit_code = exit_code;
+            output.senders.clear();
         });
 
         let session = Session {
             pty_writer,
+            input,
             child_pid,
             cols,
             rows,
+            program,
+            args,
+            on_exit,
             output,
             disconnected_at: None,
+            input_limiter: TokenBucket::new(self.input_rate_limit),
             reader_handle: Some(reader_handle),
         };
 
         self.sessions.insert(session_id, session);
+        self.persist();
+        self.counters
+            .sessions_created
+            .fetch_add(1, Ordering::Relaxed);
         tracing::info!("Created session {session_id} (pid {child_pid})");
 
-        Ok((session_id, output_rx))
+        Ok((session_id, attachment))
     }
 
+    /// Write input from attachment `attach_id`. Input from read-only viewers
+    /// is dropped, as is input to a process that has stopped reading it.
+    /// Never blocks; returns true when the session's input has just backed
+    /// up, so the client can be warned.
     pub fn write_to_session(
         &self,
         session_id: &SessionId,
+        attach_id: AttachId,
         data: &[u8],
-    ) -> Result<(), String> {
+    ) -> Result<bool, String> {
         if let Some(mut session) = self.sessions.get_mut(session_id) {
-            session
-                .pty_writer
-                .write_all(data)
-                .map_err(|e| format!("PTY write error: {e}"))
+            if !session.is_writer(attach_id) {
+                tracing::debug!(
+                    "Dropped {} bytes from read-only viewer of session {session_id}",
+                    data.len()
+                );
+                return Ok(false);
+            }
+
+            // Drop input beyond the session's rate limit
+            let allowed = session.input_limiter.take(data.len(), Instant::now());
+            let throttled = allowed < data.len();
+            if throttled && !session.input_limiter.throttling {
+                tracing::warn!(
+                    "Throttling input to session {session_id}: dropped {} bytes",
+                    data.len() - allowed
+                );
+            } else if !throttled && session.input_limiter.throttling {
+                tracing::info!("Input to session {session_id} no longer throttled");
+            }
+            session.input_limiter.throttling = throttled;
+            self.counters
+                .bytes_in
+                .fetch_add(allowed as u64, Ordering::Relaxed);
+
+            let stalled = session.input.send(&data[..allowed]);
+            if stalled {
+                tracing::warn!(
+                    "Session {session_id} is not reading its input; dropping input \
+                     until it catches up"
+                );
+            }
+            Ok(stalled)
         } else {
             Err(format!("Session {session_id} not found"))
         }
@@ -192,6 +722,8 @@ impl SessionManager {
         cols: u16,
         rows: u16,
     ) -> Result<(), String> {
+        let cols = clamp_dimension(cols);
+        let rows = clamp_dimension(rows);
         if let Some(mut session) = self.sessions.get_mut(session_id) {
             session.cols = cols;
             session.rows = rows;
@@ -213,28 +745,81 @@ impl SessionManager {
         }
     }
 
+    /// Send `signal` to the job in the session's foreground, as Ctrl+C or
+    /// Ctrl+Z would, but without relying on the terminal's line discipline
+    /// turning those keys into signals. Falls back to the session's own
+    /// process when the foreground is unknown.
+    pub fn signal_session(
+        &self,
+        session_id: &SessionId,
+        signal: libc::c_int,
+    ) -> Result<(), String> {
+        use std::os::unix::io::AsRawFd;
+
+        let session = self
+            .sessions
+            .get(session_id)
+            .ok_or_else(|| format!("Session {session_id} not found"))?;
+        // An exited child has already been reaped, so its pid may be reused
+        if session.output.lock().unwrap().exited {
+            return Err(format!("Session {session_id} has exited"));
+        }
+        let result = unsafe {
+            let pgrp = libc::tcgetpgrp(session.pty_writer.as_raw_fd());
+            if pgrp > 0 {
+                libc::kill(-pgrp, signal)
+            } else {
+                libc::kill(session.child_pid, signal)
+            }
+        };
+        if result != 0 {
+            return Err(format!(
+                "Failed to signal session {session_id}: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Attach another client to a session. A successful attach clears
+    /// `disconnected_at`, so a takeover also rescues a session the reaper
+    /// was about to close; the stale client's later disconnect does not
+    /// restart the reaper's clock because its attachment is already gone.
     pub fn attach_session(
         &self,
         session_id: &SessionId,
-    ) -> Result<(mpsc::UnboundedReceiver<Vec<u8>>, Vec<u8>), String> {
+        mode: AttachMode,
+    ) -> Result<Attachment, AttachError> {
         if let Some(mut session) = self.sessions.get_mut(session_id) {
-            let (tx, rx) = mpsc::unbounded_channel();
-            let buffered = session.output.lock().unwrap().attach(tx);
+            let attachment = session.output.lock().unwrap().attach(mode)?;
             session.disconnected_at = None;
-            Ok((rx, buffered))
+            Ok(attachment)
+        } else if self.expired.contains(session_id) {
+            Err(AttachError::Expired)
         } else {
-            Err(format!("Session {session_id} not found"))
+            Err(AttachError::NotFound)
         }
     }
 
-    pub fn detach_session(&self, session_id: &SessionId) {
+    /// Detach one client. The PTY is kept alive for reconnection once the
+    /// last client has gone.
+    pub fn detach_session(&self, session_id: &SessionId, attach_id: AttachId) {
         if let Some(mut session) = self.sessions.get_mut(session_id) {
-            session.output.lock().unwrap().detach();
-            session.disconnected_at = Some(Instant::now());
-            tracing::info!("Session {session_id} detached, PTY kept alive");
+            let last = session.output.lock().unwrap().detach(attach_id);
+            if last {
+                session.disconnected_at = Some(Instant::now());
+                tracing::info!("Session {session_id} detached, PTY kept alive");
+            }
         }
     }
 
+    /// Whether attachment `attach_id` holds control of the session
+    pub fn is_writer(&self, session_id: &SessionId, attach_id: AttachId) -> bool {
+        self.sessions
+            .get(session_id)
+            .is_some_and(|session| session.is_writer(attach_id))
+    }
+
     pub fn reap_stale_sessions(&self, max_disconnect_duration: std::time::Duration) {
         let now = Instant::now();
         let stale: Vec<SessionId> = self
@@ -252,13 +837,694 @@ impl SessionManager {
 
         for session_id in stale {
             self.close_session(&session_id);
+            // Reconnecting clients learn the session is gone for good
+            self.expired.insert(session_id);
+            self.counters
+                .sessions_reaped
+                .fetch_add(1, Ordering::Relaxed);
             tracing::info!("Reaped stale session {session_id}");
         }
     }
 
+    /// Current working directory of the session's process, used to start new
+    /// sessions where an existing one is
+    pub fn session_cwd(&self, session_id: &SessionId) -> Option<PathBuf> {
+        self.sessions.get(session_id)?.cwd()
+    }
+
+    /// Current terminal size of the session as (cols, rows), which a client
+    /// attaching alongside others adopts rather than imposing its own
+    pub fn session_size(&self, session_id: &SessionId) -> Option<(u16, u16)> {
+        let session = self.sessions.get(session_id)?;
+        Some((session.cols, session.rows))
+    }
+
+    /// Exit code of the session's process, once it has exited
+    pub fn exit_code(&self, session_id: &SessionId) -> Option<i32> {
+        let session = self.sessions.get(session_id)?;
+        let output = session.output.lock().unwrap();
+        output.exit_code
+    }
+
+    /// Whether the session's process has exited. Unknown sessions count as
+    /// exited.
+    pub fn has_exited(&self, session_id: &SessionId) -> bool {
+        self.sessions
+            .get(session_id)
+            .is_none_or(|session| session.output.lock().unwrap().exited)
+    }
+
+    /// Exit behavior the session was created with
+    pub fn on_exit(&self, session_id: &SessionId) -> Option<OnExit> {
+        self.sessions.get(session_id).map(|session| session.on_exit)
+    }
+
     pub fn close_session(&self, session_id: &SessionId) {
-        if let Some((_, session)) = self.sessions.remove(session_id) {
-            tracing::info!("Closed session {session_id} (pid {})", session.child_pid);
+        if self.remove_session(session_id) {
+            self.persist();
+        }
+    }
+
+    fn remove_session(&self, session_id: &SessionId) -> bool {
+        let Some((_, session)) = self.sessions.remove(session_id) else {
+            return false;
+        };
+        self.counters
+            .sessions_closed
+            .fetch_add(1, Ordering::Relaxed);
+        tracing::info!("Closed session {session_id} (pid {})", session.child_pid);
+        true
+    }
+
+    /// Every live session, attached or not
+    pub fn list_sessions(&self) -> Vec<SessionInfo> {
+        self.sessions
+            .iter()
+            .map(|entry| {
+                let session = entry.value();
+                SessionInfo {
+                    id: *entry.key(),
+                    cols: session.cols,
+                    rows: session.rows,
+                    title: session.output.lock().unwrap().title.clone(),
+                    attached: session.disconnected_at.is_none(),
+                }
+            })
+            .collect()
+    }
+
+    /// Number of live sessions, attached or not
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Snapshot session counts and lifetime counters
+    pub fn metrics(&self) -> SessionMetrics {
+        let detached_sessions = self
+            .sessions
+            .iter()
+            .filter(|entry| entry.value().disconnected_at.is_some())
+            .count() as u64;
+        let total_sessions = self.sessions.len() as u64;
+
+        SessionMetrics {
+            active_sessions: total_sessions - detached_sessions,
+            detached_sessions,
+            bytes_in: self.counters.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.counters.bytes_out.load(Ordering::Relaxed),
+            sessions_created: self.counters.sessions_created.load(Ordering::Relaxed),
+            sessions_closed: self.counters.sessions_closed.load(Ordering::Relaxed),
+            sessions_reaped: self.counters.sessions_reaped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Close every session, killing their processes. Returns how many were
+    /// closed. The state file keeps their records so the next run can tell
+    /// reconnecting clients their sessions expired.
+    pub fn close_all(&self) -> usize {
+        let session_ids: Vec<SessionId> =
+            self.sessions.iter().map(|entry| *entry.key()).collect();
+        for session_id in &session_ids {
+            self.remove_session(session_id);
+        }
+        session_ids.len()
+    }
+}
+
+/// Environment for a session's process: a terminal type and locale the web
+/// frontend supports, whatever the server was started with, followed by the
+/// client's `overrides`. The server's `LANG` is kept when it is already UTF-8.
+fn session_env(overrides: Vec<(String, String)>) -> Vec<(String, String)> {
+    let lang = std::env::var("LANG")
+        .ok()
+        .filter(|lang| {
+            let lower = lang.to_ascii_lowercase();
+            lower.ends_with(".utf-8") || lower.ends_with(".utf8")
+        })
+        .unwrap_or_else(|| "C.UTF-8".to_string());
+    let mut env = vec![
+        ("TERM".to_string(), "xterm-256color".to_string()),
+        ("COLORTERM".to_string(), "truecolor".to_string()),
+        ("LANG".to_string(), lang),
+    ];
+    env.extend(overrides);
+    env
+}
+
+/// Resolve `program` to an executable, searching `PATH` unless it contains a `/`
+fn resolve_program(program: &str) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let is_executable = |path: &Path| {
+        path.metadata()
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    };
+
+    if program.is_empty() {
+        return None;
+    }
+    if program.contains('/') {
+        let path = PathBuf::from(program);
+        return is_executable(&path).then_some(path);
+    }
+
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(program))
+        .find(|path| is_executable(path))
+}
+
+/// Block until `fd` has input to read, or has hung up
+fn wait_readable(fd: i32) {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe {
+        libc::poll(&mut pollfd, 1, -1);
+    }
+}
+
+/// Block until `pid` exits and return its exit code. Death by signal is
+/// reported as `128 + signo`, matching shell convention.
+fn wait_for_exit(pid: i32) -> Option<i32> {
+    let mut status: libc::c_int = 0;
+    loop {
+        let res = unsafe { libc::waitpid(pid, &mut status, 0) };
+        if res == pid {
+            break;
+        }
+        if res < 0
+            && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+        {
+            continue;
         }
+        return None;
+    }
+
+    if libc::WIFEXITED(status) {
+        Some(libc::WEXITSTATUS(status))
+    } else if libc::WIFSIGNALED(status) {
+        Some(128 + libc::WTERMSIG(status))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn resize_clamps_absurd_dimensions() {
+        use std::os::unix::io::AsRawFd;
+
+        let manager = SessionManager::default();
+        let (session_id, _attachment) = manager
+            .create_session(80, 24, OnExit::Hold, None, None, Vec::new())
+            .unwrap();
+
+        manager.resize_session(&session_id, u16::MAX, 0).unwrap();
+
+        {
+            let session = manager.sessions.get(&session_id).unwrap();
+            assert_eq!((session.cols, session.rows), (MAX_DIMENSION, 1));
+
+            let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+            let res = unsafe {
+                libc::ioctl(session.pty_writer.as_raw_fd(), libc::TIOCGWINSZ, &mut ws)
+            };
+            assert_eq!(res, 0);
+            assert_eq!((ws.ws_col, ws.ws_row), (MAX_DIMENSION, 1));
+        }
+
+        manager.close_session(&session_id);
+    }
+
+    #[tokio::test]
+    async fn exit_code_is_recorded_when_shell_exits() {
+        let manager = SessionManager::default();
+        let (session_id, mut attachment) = manager
+            .create_session(80, 24, OnExit::Hold, None, None, Vec::new())
+            .unwrap();
+
+        manager
+            .write_to_session(&session_id, attachment.id, b"exit 3\n")
+            .unwrap();
+
+        // Output ends once the shell has exited and been reaped
+        let drained = tokio::time::timeout(Duration::from_secs(10), async {
+            while attachment.rx.recv().await.is_some() {}
+        })
+        .await;
+        assert!(drained.is_ok(), "shell did not exit");
+        assert_eq!(manager.exit_code(&session_id), Some(3));
+
+        // Reattaching an exited session yields an already-closed receiver
+        let mut attachment = manager
+            .attach_session(&session_id, AttachMode::Control)
+            .unwrap();
+        assert!(attachment.rx.recv().await.is_none());
+
+        manager.close_session(&session_id);
+    }
+
+    #[tokio::test]
+    async fn sessions_are_listed_with_their_title() {
+        let manager = SessionManager::default();
+        let command = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "printf '\\033]2;make test\\007done'; sleep 30".to_string(),
+        ];
+        let (titled, mut attachment) = manager
+            .create_session(80, 24, OnExit::Hold, Some(command), None, Vec::new())
+            .unwrap();
+        let (untitled, _attachment) = manager
+            .create_session(80, 24, OnExit::Hold, None, None, Vec::new())
+            .unwrap();
+
+        // The title sequence still reaches the client untouched
+        let output = tokio::time::timeout(Duration::from_secs(10), async {
+            let mut output = Vec::new();
+            while !output.ends_with(b"done") {
+                output.extend(attachment.rx.recv().await.unwrap());
+            }
+            output
+        })
+        .await
+        .unwrap();
+        assert_eq!(output, b"\x1b]2;make test\x07done");
+
+        let mut sessions = manager.list_sessions();
+        sessions.sort_by_key(|session| session.id != titled);
+        assert_eq!(
+            sessions,
+            [
+                SessionInfo {
+                    id: titled,
+                    cols: 80,
+                    rows: 24,
+                    title: Some("make test".to_string()),
+                    attached: true,
+                },
+                SessionInfo {
+                    id: untitled,
+                    cols: 80,
+                    rows: 24,
+                    title: None,
+                    attached: true,
+                },
+            ]
+        );
+
+        manager.close_all();
+    }
+
+    #[tokio::test]
+    async fn signals_reach_the_foreground_process() {
+        let manager = SessionManager::default();
+        let command = vec!["sleep".to_string(), "30".to_string()];
+        let (session_id, mut attachment) = manager
+            .create_session(80, 24, OnExit::Hold, Some(command), None, Vec::new())
+            .unwrap();
+
+        manager.signal_session(&session_id, libc::SIGINT).unwrap();
+        let drained = tokio::time::timeout(Duration::from_secs(10), async {
+            while attachment.rx.recv().await.is_some() {}
+        })
+        .await;
+        assert!(drained.is_ok(), "sleep was not interrupted");
+        assert_eq!(manager.exit_code(&session_id), Some(128 + libc::SIGINT));
+        assert!(manager.signal_session(&session_id, libc::SIGINT).is_err());
+
+        manager.close_session(&session_id);
+    }
+
+    #[test]
+    fn on_exit_parses_known_values() {
+        assert_eq!("close".parse::<OnExit>(), Ok(OnExit::Close));
+        assert_eq!("hold".parse::<OnExit>(), Ok(OnExit::Hold));
+        assert!("detach".parse::<OnExit>().is_err());
+        assert_eq!(OnExit::default(), OnExit::Hold);
+    }
+
+    #[tokio::test]
+    async fn command_runs_instead_of_shell() {
+        let manager = SessionManager::default();
+        let command = vec!["sh".to_string(), "-c".to_string(), "exit 7".to_string()];
+        let (session_id, mut attachment) = manager
+            .create_session(80, 24, OnExit::Hold, Some(command), None, Vec::new())
+            .unwrap();
+
+        let drained = tokio::time::timeout(Duration::from_secs(10), async {
+            while attachment.rx.recv().await.is_some() {}
+        })
+        .await;
+        assert!(drained.is_ok(), "command did not exit");
+        assert_eq!(manager.exit_code(&session_id), Some(7));
+
+        manager.close_session(&session_id);
+    }
+
+    #[tokio::test]
+    async fn session_starts_in_requested_directory() {
+        let manager = SessionManager::default();
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        let command = vec!["sleep".to_string(), "5".to_string()];
+        let (session_id, _attachment) = manager
+            .create_session(
+                80,
+                24,
+                OnExit::Hold,
+                Some(command.clone()),
+                Some(dir.clone()),
+                Vec::new(),
+            )
+            .unwrap();
+        assert_eq!(manager.session_cwd(&session_id), Some(dir));
+        manager.close_session(&session_id);
+
+        // A directory that no longer exists is ignored rather than fatal
+        let missing = PathBuf::from("/omni-terminal-no-such-dir");
+        let (session_id, _attachment) = manager
+            .create_session(
+                80,
+                24,
+                OnExit::Hold,
+                Some(command),
+                Some(missing),
+                Vec::new(),
+            )
+            .unwrap();
+        assert!(manager.session_cwd(&session_id).is_some());
+        manager.close_session(&session_id);
+    }
+
+    #[tokio::test]
+    async fn child_sees_terminal_environment() {
+        let manager = SessionManager::default();
+        let command = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "echo \"$TERM $COLORTERM $FOO\"".to_string(),
+        ];
+        let overrides = vec![("FOO".to_string(), "bar".to_string())];
+        let (session_id, mut attachment) = manager
+            .create_session(80, 24, OnExit::Hold, Some(command), None, overrides)
+            .unwrap();
+
+        let output = tokio::time::timeout(Duration::from_secs(10), async {
+            let mut out = Vec::new();
+            while let Some(data) = attachment.rx.recv().await {
+                out.extend_from_slice(&data);
+            }
+            out
+        })
+        .await
+        .expect("command did not exit");
+        let output = String::from_utf8_lossy(&output);
+        assert!(
+            output.contains("xterm-256color truecolor bar"),
+            "{output:?}"
+        );
+
+        // The client may replace the defaults
+        let overrides = vec![("TERM".to_string(), "dumb".to_string())];
+        let env = session_env(overrides);
+        let term = env.iter().rev().find(|(key, _)| key == "TERM");
+        assert_eq!(term.map(|(_, value)| value.as_str()), Some("dumb"));
+
+        manager.close_session(&session_id);
+    }
+
+    #[test]
+    fn command_is_validated() {
+        let manager = SessionManager::default();
+        let empty =
+            manager.create_session(80, 24, OnExit::Hold, Some(vec![]), None, Vec::new());
+        assert!(empty.is_err());
+
+        let missing = manager.create_session(
+            80,
+            24,
+            OnExit::Hold,
+            Some(vec!["omni-terminal-no-such-binary".to_string()]),
+            None,
+            Vec::new(),
+        );
+        assert!(missing.is_err());
+        assert!(manager.sessions.is_empty());
+    }
+
+    #[test]
+    fn token_bucket_caps_burst_throughput() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000);
+        bucket.last_refill = start;
+
+        // A burst passes up to one second's worth, then is cut off
+        assert_eq!(bucket.take(5000, start), 1000);
+        assert_eq!(bucket.take(5000, start), 0);
+
+        // Tokens refill at the configured rate, capped at one second's worth
+        assert_eq!(bucket.take(5000, start + Duration::from_millis(500)), 500);
+        assert_eq!(bucket.take(5000, start + Duration::from_secs(10)), 1000);
+
+        // Sustained flooding over five seconds is held to the rate
+        let mut total = 0;
+        for ms in (0..5000).step_by(10) {
+            let now = start + Duration::from_secs(10) + Duration::from_millis(ms);
+            total += bucket.take(4096, now);
+        }
+        assert!(total <= 5000, "wrote {total} bytes in 5s at 1000 B/s");
+    }
+
+    #[test]
+    fn token_bucket_zero_rate_is_unlimited() {
+        let mut bucket = TokenBucket::new(0);
+        assert_eq!(bucket.take(1 << 20, Instant::now()), 1 << 20);
+    }
+
+    #[tokio::test]
+    async fn close_all_terminates_every_session() {
+        let manager = SessionManager::default();
+        for _ in 0..2 {
+            manager
+                .create_session(80, 24, OnExit::Hold, None, None, Vec::new())
+                .unwrap();
+        }
+
+        assert_eq!(manager.close_all(), 2);
+        assert!(manager.sessions.is_empty());
+        assert_eq!(manager.close_all(), 0);
+    }
+
+    #[tokio::test]
+    async fn metrics_track_session_lifecycle() {
+        let manager = SessionManager::default();
+        assert_eq!(manager.metrics(), SessionMetrics::default());
+
+        let (first, attachment) = manager
+            .create_session(80, 24, OnExit::Hold, None, None, Vec::new())
+            .unwrap();
+        let (second, second_attachment) = manager
+            .create_session(80, 24, OnExit::Hold, None, None, Vec::new())
+            .unwrap();
+        manager
+            .write_to_session(&first, attachment.id, b"true\n")
+            .unwrap();
+        manager.detach_session(&second, second_attachment.id);
+
+        let metrics = manager.metrics();
+        assert_eq!(metrics.active_sessions, 1);
+        assert_eq!(metrics.detached_sessions, 1);
+        assert_eq!(metrics.sessions_created, 2);
+        assert_eq!(metrics.bytes_in, 5);
+
+        manager.reap_stale_sessions(Duration::ZERO);
+        manager.close_session(&first);
+
+        let metrics = manager.metrics();
+        assert_eq!((metrics.active_sessions, metrics.detached_sessions), (0, 0));
+        assert_eq!(metrics.sessions_closed, 2);
+        assert_eq!(metrics.sessions_reaped, 1);
+    }
+
+    #[tokio::test]
+    async fn input_to_a_process_that_stops_reading_is_dropped() {
+        let manager = SessionManager::new(0);
+        let command = vec!["sleep".to_string(), "10".to_string()];
+        let (session_id, attachment) = manager
+            .create_session(80, 24, OnExit::Hold, Some(command), None, Vec::new())
+            .unwrap();
+
+        // Complete lines pile up unread in the PTY until its buffers are full,
+        // then in the queue; no write waits for the process
+        let line = [b"x".repeat(1023), b"\n".to_vec()].concat();
+        let mut stalls = Vec::new();
+        for _ in 0..1024 {
+            stalls.push(
+                manager
+                    .write_to_session(&session_id, attachment.id, &line)
+                    .unwrap(),
+            );
+            // Let the writer hand the PTY whatever it still accepts
+            tokio::task::yield_now().await;
+        }
+        let first = stalls.iter().position(|&stalled| stalled).unwrap();
+        assert!(first * line.len() > MAX_PENDING_INPUT);
+        assert_eq!(stalls.iter().filter(|&&stalled| stalled).count(), 1);
+
+        manager.close_session(&session_id);
+    }
+
+    #[tokio::test]
+    async fn viewers_receive_the_same_output_as_the_writer() {
+        let manager = SessionManager::default();
+        let command = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "read line; echo \"got $line\"".to_string(),
+        ];
+        let (session_id, writer) = manager
+            .create_session(80, 24, OnExit::Hold, Some(command), None, Vec::new())
+            .unwrap();
+        let viewer = manager
+            .attach_session(&session_id, AttachMode::ReadOnly)
+            .unwrap();
+        assert!(writer.writer);
+        assert!(!viewer.writer);
+
+        // Viewer input is dropped; only the writer reaches the PTY
+        manager
+            .write_to_session(&session_id, viewer.id, b"viewer\n")
+            .unwrap();
+        manager
+            .write_to_session(&session_id, writer.id, b"hello\n")
+            .unwrap();
+
+        let collect = |mut rx: mpsc::UnboundedReceiver<Vec<u8>>| async move {
+            let mut out = Vec::new();
+            while let Some(data) = rx.recv().await {
+                out.extend_from_slice(&data);
+            }
+            out
+        };
+        let outputs = tokio::time::timeout(
+            Duration::from_secs(10),
+            futures::future::join(collect(writer.rx), collect(viewer.rx)),
+        )
+        .await;
+        let (writer_out, viewer_out) = outputs.expect("command did not exit");
+
+        assert_eq!(writer_out, viewer_out);
+        let text = String::from_utf8_lossy(&writer_out);
+        assert!(text.contains("got hello"), "unexpected output: {text:?}");
+        assert!(
+            !text.contains("viewer"),
+            "viewer input was written: {text:?}"
+        );
+
+        manager.close_session(&session_id);
+    }
+
+    #[tokio::test]
+    async fn attach_requires_takeover_while_a_writer_is_live() {
+        let manager = SessionManager::default();
+        let command = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "echo ready; sleep 30".to_string(),
+        ];
+        let (session_id, mut stale) = manager
+            .create_session(80, 24, OnExit::Hold, Some(command), None, Vec::new())
+            .unwrap();
+        let mut seen = Vec::new();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !String::from_utf8_lossy(&seen).contains("ready") {
+                seen.extend(stale.rx.recv().await.unwrap());
+            }
+        })
+        .await
+        .expect("no output");
+
+        let in_use = manager.attach_session(&session_id, AttachMode::Control);
+        assert_eq!(in_use.err(), Some(AttachError::InUse));
+
+        // Takeover ends the stale stream and replays what it had seen
+        let fresh = manager
+            .attach_session(&session_id, AttachMode::Takeover)
+            .unwrap();
+        assert!(fresh.writer);
+        assert!(String::from_utf8_lossy(&fresh.buffered).contains("ready"));
+        assert!(stale.rx.recv().await.is_none());
+        assert!(!manager.has_exited(&session_id));
+
+        // The stale client's disconnect leaves the session attached
+        manager.detach_session(&session_id, stale.id);
+        assert_eq!(manager.metrics().detached_sessions, 0);
+        assert!(manager.is_writer(&session_id, fresh.id));
+
+        // Once the writer leaves, a plain attach takes control again
+        manager.detach_session(&session_id, fresh.id);
+        assert_eq!(manager.metrics().detached_sessions, 1);
+        let next = manager
+            .attach_session(&session_id, AttachMode::Control)
+            .unwrap();
+        assert!(next.writer);
+        assert!(next.buffered.is_empty());
+
+        manager.close_session(&session_id);
+    }
+
+    #[tokio::test]
+    async fn restarted_server_reports_previous_sessions_as_expired() {
+        let path =
+            std::env::temp_dir().join(format!("omni-terminal-{}.json", Uuid::new_v4()));
+
+        let manager = SessionManager::default().with_state_file(path.clone());
+        let (session_id, _attachment) = manager
+            .create_session(80, 24, OnExit::Hold, None, None, Vec::new())
+            .unwrap();
+        let records = state::load(&path);
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            (records[0].id, records[0].cols, records[0].rows),
+            (session_id, 80, 24)
+        );
+
+        // Shutdown keeps the records for the next run
+        manager.close_all();
+        let restarted = SessionManager::default().with_state_file(path.clone());
+        let expired = restarted.attach_session(&session_id, AttachMode::Control);
+        assert_eq!(expired.err(), Some(AttachError::Expired));
+        let unknown = restarted.attach_session(&Uuid::new_v4(), AttachMode::Control);
+        assert_eq!(unknown.err(), Some(AttachError::NotFound));
+        assert!(state::load(&path).is_empty());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn reaped_sessions_are_expired_and_closed_ones_not_found() {
+        let manager = SessionManager::default();
+        let (reaped, reaped_attachment) = manager
+            .create_session(80, 24, OnExit::Hold, None, None, Vec::new())
+            .unwrap();
+        let (closed, _attachment) = manager
+            .create_session(80, 24, OnExit::Hold, None, None, Vec::new())
+            .unwrap();
+
+        manager.detach_session(&reaped, reaped_attachment.id);
+        manager.reap_stale_sessions(Duration::ZERO);
+        manager.close_session(&closed);
+
+        let attach = |id| manager.attach_session(id, AttachMode::Control).err();
+        assert_eq!(attach(&reaped), Some(AttachError::Expired));
+        assert_eq!(attach(&closed), Some(AttachError::NotFound));
+        assert_eq!(AttachError::Expired.code(), "expired");
     }
 }
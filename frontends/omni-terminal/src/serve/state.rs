@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::session::SessionId;
+
+/// Metadata kept on disk for each live session so a restarted server knows
+/// which session ids it used to serve
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub id: SessionId,
+    pub cols: u16,
+    pub rows: u16,
+    pub program: String,
+    pub args: Vec<String>,
+    /// Working directory of the session's process when last saved
+    pub cwd: Option<PathBuf>,
+}
+
+/// Read the records saved by a previous run. A missing file means no
+/// previous sessions; an unreadable one is logged and ignored.
+pub fn load(path: &Path) -> Vec<SessionRecord> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            tracing::warn!("Failed to read session state {}: {e}", path.display());
+            return Vec::new();
+        }
+    };
+    serde_json::from_slice(&contents).unwrap_or_else(|e| {
+        tracing::warn!("Ignoring malformed session state {}: {e}", path.display());
+        Vec::new()
+    })
+}
+
+/// Replace the saved records, writing to a temporary file first so a crash
+/// mid-write never leaves a truncated state file behind.
+pub fn save(path: &Path, records: &[SessionRecord]) -> std::io::Result<()> {
+    let json = serde_json::to_vec_pretty(records).map_err(std::io::Error::other)?;
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, path)
+}
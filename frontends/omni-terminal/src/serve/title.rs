@@ -0,0 +1,141 @@
+/// Longest title kept; the rest of a longer one is ignored
+const MAX_TITLE_LEN: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum State {
+    #[default]
+    Ground,
+    /// After ESC
+    Escape,
+    /// Reading the OSC number, which is kept once it selects the title
+    Param { title: bool, digits: u8 },
+    /// In the string of an OSC 0 or 2
+    Title,
+    /// In the string of any other OSC
+    Ignore,
+    /// After ESC inside an OSC string, which is ST when `\` follows
+    StringEscape { title: bool },
+}
+
+/// Picks window titles set with OSC 0 and OSC 2 out of PTY output without
+/// running a full terminal parser. Sequences may be split across chunks.
+#[derive(Debug, Default)]
+pub struct TitleScanner {
+    state: State,
+    pending: Vec<u8>,
+}
+
+impl TitleScanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scan the next chunk of output, returning the last title it completes
+    pub fn scan(&mut self, data: &[u8]) -> Option<String> {
+        let mut title = None;
+        for &byte in data {
+            self.state = match (self.state, byte) {
+                (State::Ground, 0x1b) => State::Escape,
+                (State::Ground, _) => State::Ground,
+                (State::Escape, b']') => State::Param {
+                    title: true,
+                    digits: 0,
+                },
+                (State::Escape, 0x1b) => State::Escape,
+                (State::Escape, _) => State::Ground,
+                (State::Param { title, digits }, b'0'..=b'9') => {
+                    // Only a lone 0 or 2 names the title
+                    State::Param {
+                        title: title && digits == 0 && (byte == b'0' || byte == b'2'),
+                        digits: digits.saturating_add(1),
+                    }
+                }
+                (
+                    State::Param {
+                        title: true,
+                        digits: 1,
+                    },
+                    b';',
+                ) => {
+                    self.pending.clear();
+                    State::Title
+                }
+                (State::Param { .. }, 0x07) => State::Ground,
+                (State::Param { .. }, 0x1b) => State::StringEscape { title: false },
+                (State::Param { .. }, _) => State::Ignore,
+                (State::Title, 0x07) => {
+                    title = Some(self.take());
+                    State::Ground
+                }
+                (State::Title, 0x1b) => State::StringEscape { title: true },
+                (State::Ignore, 0x07) => State::Ground,
+                (State::Ignore, 0x1b) => State::StringEscape { title: false },
+                // CAN and SUB abort the sequence
+                (State::Title | State::Ignore, 0x18 | 0x1a) => State::Ground,
+                (State::Title, _) => {
+                    if self.pending.len() < MAX_TITLE_LEN {
+                        self.pending.push(byte);
+                    }
+                    State::Title
+                }
+                (State::Ignore, _) => State::Ignore,
+                (State::StringEscape { title: true }, b'\\') => {
+                    title = Some(self.take());
+                    State::Ground
+                }
+                (State::StringEscape { .. }, b'\\') => State::Ground,
+                // Any other escape ends the string unterminated and starts anew
+                (State::StringEscape { .. }, b']') => State::Param {
+                    title: true,
+                    digits: 0,
+                },
+                (State::StringEscape { .. }, _) => State::Ground,
+            };
+        }
+        title
+    }
+
+    fn take(&mut self) -> String {
+        let title = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        title
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_are_found_across_chunks() {
+        let mut scanner = TitleScanner::new();
+        assert_eq!(
+            scanner.scan(b"$ \x1b]2;vim main.rs\x07ok"),
+            Some("vim main.rs".into())
+        );
+        assert_eq!(scanner.scan(b"\x1b]0;ho"), None);
+        assert_eq!(scanner.scan(b"me\x1b"), None);
+        assert_eq!(scanner.scan(b"\\"), Some("home".into()));
+        assert_eq!(
+            scanner.scan("\x1b]2;first\x07\x1b]2;größe\x07".as_bytes()),
+            Some("größe".into())
+        );
+    }
+
+    #[test]
+    fn other_sequences_are_skipped() {
+        let mut scanner = TitleScanner::new();
+        // Icon name, hyperlinks, cwd reports, CSI and aborted titles
+        for data in [
+            &b"\x1b]1;icon\x07"[..],
+            b"\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\",
+            b"\x1b]7;file://host/tmp\x07",
+            b"\x1b]22;x\x07\x1b]20;x\x07",
+            b"\x1b[2;3H\x1b]2;cut\x18",
+        ] {
+            assert_eq!(scanner.scan(data), None, "{data:?}");
+        }
+        let long = format!("\x1b]2;{}\x07", "x".repeat(5000));
+        assert_eq!(scanner.scan(long.as_bytes()).unwrap().len(), MAX_TITLE_LEN);
+    }
+}
@@ -1,11 +1,17 @@
 #![cfg(target_arch = "wasm32")]
 
-use terminal_emulator::{render_grid, MouseMode, TerminalGrid};
+use terminal_emulator::{
+    encode_input, encode_key, parse_color, prepare_paste, prompt_marker_quads,
+    render_grid, scroll_mode_action, scrollbar_quad, scrollbar_thumb, web_char_key,
+    ClipboardType, Clipboards, Key, KeyModifiers, KeyboardProtocol, MouseMode, NavKey,
+    OutputQueue, PasteMode, ScrollAction, ScrollConfig, Scrollbar, TerminalGrid, Theme,
+    OUTPUT_BYTES_PER_FRAME,
+};
 
 use raw_window_handle::{
     RawDisplayHandle, RawWindowHandle, WebDisplayHandle, WebWindowHandle,
 };
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use sugarloaf::layout::RootStyle;
 use sugarloaf::{
@@ -17,6 +23,246 @@ use web_sys::{HtmlCanvasElement, HtmlDivElement, HtmlElement, HtmlTextAreaElemen
 /// Height of the tab bar in CSS pixels
 const TAB_BAR_HEIGHT: u32 = 36;
 
+/// Tab bar background while the theme keeps the default background
+const TAB_BAR_BACKGROUND: &str = "#1a1a2e";
+
+thread_local! {
+    /// Paste handling when the app has not enabled bracketed paste
+    static PASTE_MODE: Cell<PasteMode> = Cell::new(PasteMode::default());
+    /// Word separators for double-click selection; `None` keeps the default
+    static WORD_SEPARATORS: RefCell<Option<String>> = const { RefCell::new(None) };
+    /// Cursor color; `None` draws the cursor by inversion
+    static CURSOR_COLOR: Cell<Option<[f32; 4]>> = const { Cell::new(None) };
+    /// Colors the grid is drawn with
+    static THEME: RefCell<Theme> = RefCell::new(Theme::default());
+    /// Wheel scroll speed and direction
+    static SCROLL_CONFIG: Cell<ScrollConfig> = const { Cell::new(ScrollConfig::DEFAULT) };
+    /// Draw a scrollbar overlay while scrolled back
+    static SHOW_SCROLLBAR: Cell<bool> = const { Cell::new(true) };
+    /// Copied text per clipboard, holding the primary selection that
+    /// browsers lack for middle-click pastes
+    static CLIPBOARDS: RefCell<Clipboards> = const { RefCell::new(Clipboards::new()) };
+    /// Tabs and connection of the terminal made by `create_terminal`, for
+    /// exports that act on the active tab
+    static TERMINAL: RefCell<Option<(Rc<RefCell<TabManager>>, Rc<RefCell<WsState>>)>> =
+        const { RefCell::new(None) };
+}
+
+/// Choose how pastes are treated when the running program has not enabled
+/// bracketed paste: "sanitize" (default), "warn" or "raw"
+#[wasm_bindgen]
+pub fn set_paste_mode(mode: &str) -> Result<(), JsValue> {
+    let mode: PasteMode = mode.parse().map_err(|e: String| JsValue::from_str(&e))?;
+    PASTE_MODE.with(|cell| cell.set(mode));
+    Ok(())
+}
+
+/// Set the characters, besides whitespace, that end a word when
+/// double-clicking to select
+#[wasm_bindgen]
+pub fn set_word_separators(separators: &str) {
+    WORD_SEPARATORS.with(|cell| *cell.borrow_mut() = Some(separators.to_string()));
+}
+
+/// Set the cursor color as `#rrggbb` or `rgb:r/g/b`, or an empty string to
+/// draw the cursor by inversion
+#[wasm_bindgen]
+pub fn set_cursor_color(color: &str) -> Result<(), JsValue> {
+    let color = match color {
+        "" => None,
+        spec => Some(
+            parse_color(spec)
+                .ok_or_else(|| JsValue::from_str(&format!("Invalid color '{spec}'")))?,
+        ),
+    };
+    CURSOR_COLOR.with(|cell| cell.set(color));
+    Ok(())
+}
+
+/// Initialize a terminal like `create_terminal`, drawn with the theme in
+/// `options` (see `set_theme`). `linesPerNotch` and `invertScroll` set the
+/// wheel scrolling (see `set_scroll`).
+#[wasm_bindgen]
+pub fn create_terminal_with_options(
+    container_id: String,
+    ws_url: String,
+    font_size: f32,
+    options: JsValue,
+) {
+    create_terminal(container_id, ws_url, font_size);
+    let field = |name: &str| js_sys::Reflect::get(&options, &name.into()).ok();
+    let lines_per_notch = field("linesPerNotch").and_then(|v| v.as_f64());
+    let inverted = field("invertScroll").and_then(|v| v.as_bool());
+    if lines_per_notch.is_some() || inverted.is_some() {
+        let current = SCROLL_CONFIG.with(Cell::get);
+        set_scroll(
+            lines_per_notch.map_or(current.lines_per_notch(), |lines| lines as f32),
+            inverted.unwrap_or(current.inverted()),
+        );
+    }
+    set_theme(options);
+}
+
+/// Set how many lines a wheel notch scrolls (clamped to 0.5-20, default 3)
+/// and whether scrolling is reversed, as natural scrolling users prefer
+#[wasm_bindgen]
+pub fn set_scroll(lines_per_notch: f32, inverted: bool) {
+    SCROLL_CONFIG.with(|cell| cell.set(ScrollConfig::new(lines_per_notch, inverted)));
+}
+
+/// Set the theme from an object with `foreground`, `background` and
+/// `palette` (the 16 ANSI colors, in order) as `#rrggbb` or `rgb:r/g/b`.
+/// Missing or invalid colors keep their defaults.
+#[wasm_bindgen]
+pub fn set_theme(options: JsValue) {
+    let field = |name: &str| js_sys::Reflect::get(&options, &name.into()).ok();
+    let string = |name: &str| field(name).and_then(|v| v.as_string());
+    let palette: Vec<String> = field("palette")
+        .filter(js_sys::Array::is_array)
+        .map(|v| {
+            js_sys::Array::from(&v)
+                .iter()
+                .map(|color| color.as_string().unwrap_or_default())
+                .collect()
+        })
+        .unwrap_or_default();
+    let (theme, rejected) = Theme::parse(
+        string("foreground").as_deref(),
+        string("background").as_deref(),
+        &palette,
+    );
+    for spec in rejected {
+        log::warn!("Invalid color {spec:?}, keeping the default");
+    }
+
+    if let Some(tab_bar) = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id("tab-bar"))
+    {
+        let tab_bar: HtmlElement = tab_bar.unchecked_into();
+        let _ = tab_bar
+            .style()
+            .set_property("background", &tab_bar_background(&theme));
+    }
+    if let Some((tabs, _)) = TERMINAL.with(|terminal| terminal.borrow().clone()) {
+        for tab in &mut tabs.borrow_mut().tabs {
+            tab.grid.set_theme(theme.clone());
+        }
+    }
+    THEME.with(|cell| *cell.borrow_mut() = theme);
+}
+
+/// CSS color for the tab bar: the theme's background once it sets one
+fn tab_bar_background(theme: &Theme) -> String {
+    if theme.background == Theme::default().background {
+        return TAB_BAR_BACKGROUND.to_string();
+    }
+    let [r, g, b, _] = theme.background.map(|c| (c * 255.0).round() as u8);
+    format!("rgb({r}, {g}, {b})")
+}
+
+/// Sugarloaf clear color for `color`
+fn wgpu_color([r, g, b, a]: [f32; 4]) -> wgpu::Color {
+    wgpu::Color {
+        r: r as f64,
+        g: g as f64,
+        b: b as f64,
+        a: a as f64,
+    }
+}
+
+/// Show or hide the scrollbar overlay drawn while scrolled back
+#[wasm_bindgen]
+pub fn set_scrollbar(enabled: bool) {
+    SHOW_SCROLLBAR.with(|cell| cell.set(enabled));
+}
+
+/// Output of the last command finished in the active tab, as marked by
+/// shell integration (OSC 133), for "copy last output"
+#[wasm_bindgen]
+pub fn last_command_output() -> Option<String> {
+    let (tabs, _) = TERMINAL.with(|terminal| terminal.borrow().clone())?;
+    let tabs_ref = tabs.borrow();
+    tabs_ref.active_tab().grid.last_command_output()
+}
+
+/// Exit status of the last command finished in the active tab, as reported
+/// by shell integration (OSC 133), or undefined when unknown
+#[wasm_bindgen]
+pub fn last_exit_code() -> Option<i32> {
+    let (tabs, _) = TERMINAL.with(|terminal| terminal.borrow().clone())?;
+    let tabs_ref = tabs.borrow();
+    tabs_ref.active_tab().grid.last_exit_code()
+}
+
+/// Clear the active tab like the `clear` command: blank the screen and home
+/// the cursor. `scrollback` drops the history too; `redraw` sends Ctrl+L so
+/// the shell reprints its prompt.
+#[wasm_bindgen]
+pub fn clear_active_session(scrollback: bool, redraw: bool) {
+    let Some((tabs, ws_state)) = TERMINAL.with(|terminal| terminal.borrow().clone())
+    else {
+        return;
+    };
+    let sid = {
+        let mut tabs_ref = tabs.borrow_mut();
+        let active = tabs_ref.active_tab_mut();
+        active.grid.clear(scrollback);
+        active.session_id
+    };
+    if let (true, Some(sid)) = (redraw, sid) {
+        ws_send_binary(&ws_state, &sid, b"\x0c");
+    }
+}
+
+/// Connection and session of the active tab, with whether its application
+/// enabled bracketed paste
+fn active_session() -> Option<(Rc<RefCell<WsState>>, [u8; 16], bool)> {
+    let (tabs, ws_state) = TERMINAL.with(|terminal| terminal.borrow().clone())?;
+    let tabs_ref = tabs.borrow();
+    let active = tabs_ref.active_tab();
+    let sid = active.session_id?;
+    Some((ws_state, sid, active.grid.bracketed_paste()))
+}
+
+/// Type `text` into the active tab, as an embedder injecting a command does.
+/// Line breaks press Enter; several lines go as one bracketed paste when the
+/// application enabled it.
+#[wasm_bindgen]
+pub fn send_input(text: &str) {
+    if let Some((ws_state, sid, bracketed)) = active_session() {
+        ws_send_binary(&ws_state, &sid, &encode_input(text, bracketed));
+    }
+}
+
+/// Send `data` to the active tab's session unchanged
+#[wasm_bindgen]
+pub fn send_bytes(data: &[u8]) {
+    if let Some((ws_state, sid, _)) = active_session() {
+        ws_send_binary(&ws_state, &sid, data);
+    }
+}
+
+/// Send `signal` (`INT`, `TSTP` or `QUIT`) to the job running in the active
+/// tab, so a button can interrupt it whatever the terminal's modes are.
+#[wasm_bindgen]
+pub fn send_signal(signal: &str) {
+    if !matches!(signal, "INT" | "TSTP" | "QUIT") {
+        log::warn!("Unknown signal {signal:?}");
+        return;
+    }
+    let Some((ws_state, sid, _)) = active_session() else {
+        return;
+    };
+    let msg = format!(
+        r#"{{"type":"signal","session_id":"{}","signal":"{signal}"}}"#,
+        uuid::Uuid::from_bytes(sid)
+    );
+    if let Some(ref ws) = ws_state.borrow().ws {
+        let _ = ws.send_with_str(&msg);
+    }
+}
+
 /// Detect iOS/iPadOS Safari where WebGPU has device-loss issues
 fn is_ios_safari() -> bool {
     let window = match web_sys::window() {
@@ -127,34 +373,51 @@ struct MouseState {
     buttons_down: u8,
 }
 
-/// Single terminal tab with its own session, grid, and parser
+/// Single terminal tab with its own session and grid
 struct Tab {
     session_id: Option<[u8; 16]>,
     grid: TerminalGrid,
-    parser: copa::Parser,
+    /// PTY output received but not yet parsed into `grid`
+    output: OutputQueue,
     title: String,
     awaiting_restart: bool,
+    /// Session whose process exited, which the server holds for reattach
+    /// until the tab is dismissed or closed
+    held_session: Option<[u8; 16]>,
+    /// Exit code of the tab's last process, shown until it is restarted
+    exit_code: Option<i32>,
+    /// Keys navigate scrollback instead of reaching the PTY
+    scroll_mode: bool,
+    /// Query repeated by `n` in scroll mode
+    last_search: Option<String>,
 }
 
 /// Manage multiple terminal tabs
 struct TabManager {
     tabs: Vec<Tab>,
     active: usize,
+    /// Cell size in device pixels, for pixel size reports
+    cell_size: (f32, f32),
 }
 
 impl TabManager {
     /// Create a new TabManager with one initial tab
-    fn new(cols: usize, rows: usize) -> Self {
+    fn new(cols: usize, rows: usize, cell_size: (f32, f32)) -> Self {
         let tab = Tab {
             session_id: None,
             grid: TerminalGrid::new(cols, rows),
-            parser: copa::Parser::new(),
+            output: OutputQueue::new(),
             title: "Tab 1".to_string(),
             awaiting_restart: false,
+            held_session: None,
+            exit_code: None,
+            scroll_mode: false,
+            last_search: None,
         };
         Self {
             tabs: vec![tab],
             active: 0,
+            cell_size,
         }
     }
 
@@ -172,15 +435,20 @@ impl TabManager {
         let tab = Tab {
             session_id: None,
             grid: TerminalGrid::new(cols, rows),
-            parser: copa::Parser::new(),
+            output: OutputQueue::new(),
             title: format!("Tab {}", idx + 1),
             awaiting_restart: false,
+            held_session: None,
+            exit_code: None,
+            scroll_mode: false,
+            last_search: None,
         };
         self.tabs.push(tab);
         idx
     }
 
-    /// Close tab at index, returning its session_id for cleanup.
+    /// Close tab at index, returning its session_id (or the exited one still
+    /// held) for cleanup.
     /// Returns None if this is the last tab (refuses to close).
     fn close_tab(&mut self, idx: usize) -> Option<[u8; 16]> {
         if self.tabs.len() <= 1 {
@@ -196,7 +464,7 @@ impl TabManager {
         } else if self.active > idx {
             self.active -= 1;
         }
-        tab.session_id
+        tab.session_id.or(tab.held_session)
     }
 
     fn switch_to(&mut self, idx: usize) {
@@ -207,14 +475,35 @@ impl TabManager {
         }
     }
 
-    /// Route PTY output to the tab with the matching session_id
+    /// Queue PTY output for the tab with the matching session_id. It is
+    /// parsed by `advance_output` from the render loop.
     fn route_output(&mut self, session_id: &[u8; 16], data: &[u8]) {
+        if let Some(tab) = self
+            .tabs
+            .iter_mut()
+            .find(|tab| tab.session_id.as_ref() == Some(session_id))
+        {
+            tab.output.push(data);
+        }
+    }
+
+    /// Parse up to `budget` bytes of queued output per tab, returning the
+    /// replies the output asked for (size reports, etc.) by session
+    fn advance_output(&mut self, budget: usize) -> Vec<([u8; 16], Vec<u8>)> {
+        let (cell_width, cell_height) = self.cell_size;
+        let mut replies = Vec::new();
         for tab in &mut self.tabs {
-            if tab.session_id.as_ref() == Some(session_id) {
-                tab.parser.advance(&mut tab.grid, data);
-                return;
+            if tab.output.is_empty() {
+                continue;
+            }
+            tab.grid.set_cell_size(cell_width, cell_height);
+            tab.output.advance(&mut tab.grid, budget);
+            let writes = std::mem::take(&mut tab.grid.pending_writes);
+            if let (Some(sid), false) = (tab.session_id, writes.is_empty()) {
+                replies.push((sid, writes));
             }
         }
+        replies
     }
 
     fn tab_count(&self) -> usize {
@@ -281,7 +570,8 @@ fn create_tab_bar(container: &HtmlElement) {
         .set_attribute(
             "style",
             &format!(
-                "display: flex; background: #1a1a2e; border-bottom: 1px solid #333; height: {}px; align-items: center; padding: 6px 0; gap: 4px; user-select: none; flex-shrink: 0;",
+                "display: flex; background: {}; border-bottom: 1px solid #333; height: {}px; align-items: center; padding: 6px 0; gap: 4px; user-select: none; flex-shrink: 0;",
+                THEME.with(|theme| tab_bar_background(&theme.borrow())),
                 TAB_BAR_HEIGHT
             ),
         )
@@ -310,7 +600,13 @@ fn rebuild_tab_bar(tabs: &Rc<RefCell<TabManager>>, ws_state: &Rc<RefCell<WsState
     let active = tabs_ref.active;
 
     for i in 0..tab_count {
-        let title = &tabs_ref.tabs[i].title;
+        let tab = &tabs_ref.tabs[i];
+        // Prefer the title set by the running program (OSC 0/2)
+        let base = tab.grid.title().unwrap_or(&tab.title);
+        let title = match tab.exit_code {
+            Some(code) => format!("{base} [exit {code}]"),
+            None => base.to_string(),
+        };
         let is_active = i == active;
 
         // Tab button container
@@ -331,7 +627,7 @@ fn rebuild_tab_bar(tabs: &Rc<RefCell<TabManager>>, ws_state: &Rc<RefCell<WsState
         // Tab label span
         let label: web_sys::HtmlSpanElement =
             document.create_element("span").unwrap().unchecked_into();
-        label.set_text_content(Some(title));
+        label.set_text_content(Some(&title));
 
         // Click on label/tab to switch
         {
@@ -472,13 +768,18 @@ fn connect_ws(
         let on_open = Closure::<dyn FnMut()>::new(move || {
             ws_state.borrow_mut().backoff_ms = 0; // Reset backoff on successful connect
 
-            let tabs_ref = tabs.borrow();
+            let mut tabs_ref = tabs.borrow_mut();
             let state = ws_state.borrow();
 
-            for tab in &tabs_ref.tabs {
+            for tab in &mut tabs_ref.tabs {
                 if let Some(sid) = tab.session_id {
+                    // The old connection may not have timed out on the server
+                    // yet, so take the session over. The server replays its
+                    // retained output, which redraws a fresh grid.
+                    tab.grid = TerminalGrid::new(tab.grid.cols, tab.grid.rows);
+                    tab.output.clear();
                     let attach_msg = format!(
-                        r#"{{"type":"attach","session_id":"{}"}}"#,
+                        r#"{{"type":"attach","session_id":"{}","takeover":true}}"#,
                         uuid::Uuid::from_bytes(sid)
                     );
                     if let Some(ref ws) = state.ws {
@@ -537,59 +838,201 @@ fn connect_ws(
                             }
                         }
 
-                        // Reattached -- tab already has the correct session_id
+                        // Reattached -- tab already has the correct session_id.
+                        // Adopt the session's size so the replayed output lays
+                        // out as it was produced; the next window resize
+                        // reasserts this client's own size.
                         if msg_type.as_deref() == Some("attached") {
                             if let Some(sid) =
                                 js_sys::Reflect::get(&msg, &"session_id".into())
                                     .ok()
                                     .and_then(|v| v.as_string())
                             {
+                                let dimension = |name: &str| {
+                                    js_sys::Reflect::get(&msg, &name.into())
+                                        .ok()
+                                        .and_then(|v| v.as_f64())
+                                        .filter(|&v| v >= 1.0)
+                                        .map(|v| v as usize)
+                                };
+                                if let (Ok(uuid), Some(cols), Some(rows)) = (
+                                    uuid::Uuid::parse_str(&sid),
+                                    dimension("cols"),
+                                    dimension("rows"),
+                                ) {
+                                    let session_bytes = *uuid.as_bytes();
+                                    let mut tabs_ref = tabs.borrow_mut();
+                                    if let Some(tab) =
+                                        tabs_ref.tabs.iter_mut().find(|t| {
+                                            t.session_id.as_ref() == Some(&session_bytes)
+                                        })
+                                    {
+                                        if (cols, rows) != (tab.grid.cols, tab.grid.rows)
+                                        {
+                                            tab.grid.resize(cols, rows);
+                                        }
+                                    }
+                                }
                                 log::info!("Session reattached: {sid}");
                             }
                         }
 
-                        // Attach failed -- clear stale session_id and create fresh
-                        if msg_type.as_deref() == Some("error") {
+                        // Another client took the session over -- stop sending to it
+                        if msg_type.as_deref() == Some("detached") {
+                            if let Some(sid) =
+                                js_sys::Reflect::get(&msg, &"session_id".into())
+                                    .ok()
+                                    .and_then(|v| v.as_string())
+                                    .and_then(|v| uuid::Uuid::parse_str(&v).ok())
+                            {
+                                let session_bytes = *sid.as_bytes();
+                                let mut tabs_ref = tabs.borrow_mut();
+                                let action = if tabs_ref.tab_count() > 1 {
+                                    "close"
+                                } else {
+                                    "restart"
+                                };
+                                if let Some(tab) = tabs_ref.tabs.iter_mut().find(|t| {
+                                    t.session_id.as_ref() == Some(&session_bytes)
+                                }) {
+                                    tab.session_id = None;
+                                    tab.awaiting_restart = true;
+                                    let prompt = format!(
+                                        "\r\n[Session taken over by another client. Press Enter to {action}.]"
+                                    );
+                                    tab.grid.feed(prompt.as_bytes());
+                                }
+                                log::info!("Session taken over: {sid}");
+                            }
+                        }
+
+                        // The session's process has stopped reading input, so
+                        // keystrokes are being dropped until it catches up
+                        if msg_type.as_deref() == Some("input_stalled") {
+                            if let Some(sid) =
+                                js_sys::Reflect::get(&msg, &"session_id".into())
+                                    .ok()
+                                    .and_then(|v| v.as_string())
+                            {
+                                log::warn!("Session {sid} is not reading input");
+                            }
+                        }
+
+                        // Server going away -- the socket closes next and reconnects
+                        if msg_type.as_deref() == Some("server_shutdown") {
                             let mut tabs_ref = tabs.borrow_mut();
-                            let active = tabs_ref.active_tab_mut();
-                            active.session_id = None;
-                            let cols = active.grid.cols;
-                            let rows = active.grid.rows;
-                            drop(tabs_ref);
+                            for tab in &mut tabs_ref.tabs {
+                                tab.grid.feed(b"\r\n[Server shutting down]\r\n");
+                            }
+                            log::info!("Server shutting down");
+                        }
 
-                            let create_msg = format!(
-                                r#"{{"type":"create","cols":{},"rows":{}}}"#,
-                                cols, rows
-                            );
-                            let state = ws_state.borrow();
-                            if let Some(ref ws) = state.ws {
-                                let _ = ws.send_with_str(&create_msg);
+                        // Attach failed -- a session that is gone for good is
+                        // replaced with a fresh one; anything else is reported
+                        if msg_type.as_deref() == Some("error") {
+                            let field = |name: &str| {
+                                js_sys::Reflect::get(&msg, &name.into())
+                                    .ok()
+                                    .and_then(|v| v.as_string())
+                            };
+                            let message = field("message").unwrap_or_default();
+                            match field("code").as_deref() {
+                                Some("expired") | Some("not_found") => {
+                                    let session_bytes = field("session_id")
+                                        .and_then(|v| uuid::Uuid::parse_str(&v).ok())
+                                        .map(|v| *v.as_bytes());
+                                    let mut tabs_ref = tabs.borrow_mut();
+                                    let idx = tabs_ref
+                                        .tabs
+                                        .iter()
+                                        .position(|t| {
+                                            session_bytes.is_some()
+                                                && t.session_id == session_bytes
+                                        })
+                                        .unwrap_or(tabs_ref.active);
+                                    let tab = &mut tabs_ref.tabs[idx];
+                                    tab.session_id = None;
+                                    tab.grid.feed(
+                                        format!("[{message}, starting a new one]\r\n")
+                                            .as_bytes(),
+                                    );
+                                    let cols = tab.grid.cols;
+                                    let rows = tab.grid.rows;
+                                    drop(tabs_ref);
+
+                                    let create_msg = format!(
+                                        r#"{{"type":"create","cols":{},"rows":{}}}"#,
+                                        cols, rows
+                                    );
+                                    let state = ws_state.borrow();
+                                    if let Some(ref ws) = state.ws {
+                                        let _ = ws.send_with_str(&create_msg);
+                                    }
+                                    log::info!(
+                                        "Attach failed ({message}), creating new session"
+                                    );
+                                }
+                                Some("invalid_id") => {
+                                    log::error!("Bug: attach sent an invalid session id: {message}");
+                                }
+                                _ => log::warn!("Server error: {message}"),
                             }
-                            log::info!("Attach failed, creating new session");
                         }
 
-                        // Session exited -- show restart prompt
-                        if msg_type.as_deref() == Some("exited") {
+                        // Session exited -- a "closed" session tears its tab down,
+                        // an "exited" (held) one keeps its output behind a prompt
+                        if matches!(msg_type.as_deref(), Some("exited") | Some("closed"))
+                        {
                             if let Some(sid) =
                                 js_sys::Reflect::get(&msg, &"session_id".into())
                                     .ok()
                                     .and_then(|v| v.as_string())
                             {
                                 if let Ok(uuid) = uuid::Uuid::parse_str(&sid) {
+                                    let closed = msg_type.as_deref() == Some("closed");
+                                    let code = js_sys::Reflect::get(&msg, &"code".into())
+                                        .ok()
+                                        .and_then(|v| v.as_f64())
+                                        .map(|v| v as i32);
                                     let session_bytes = *uuid.as_bytes();
                                     let mut tabs_ref = tabs.borrow_mut();
-                                    if let Some(tab) =
-                                        tabs_ref.tabs.iter_mut().find(|t| {
-                                            t.session_id.as_ref() == Some(&session_bytes)
-                                        })
-                                    {
-                                        tab.session_id = None;
-                                        tab.awaiting_restart = true;
-                                        let prompt =
-                                            b"\r\n[Process exited. Press Enter to restart.]";
-                                        tab.parser.advance(&mut tab.grid, prompt);
+                                    let tab_count = tabs_ref.tab_count();
+                                    let idx = tabs_ref.tabs.iter().position(|t| {
+                                        t.session_id.as_ref() == Some(&session_bytes)
+                                    });
+                                    if let Some(idx) = idx {
+                                        if closed && tab_count > 1 {
+                                            tabs_ref.close_tab(idx);
+                                            let active = tabs_ref.active;
+                                            tabs_ref.switch_to(active);
+                                        } else {
+                                            let tab = &mut tabs_ref.tabs[idx];
+                                            if !closed {
+                                                tab.held_session = tab.session_id;
+                                            }
+                                            tab.session_id = None;
+                                            tab.awaiting_restart = true;
+                                            tab.exit_code = code;
+                                            let action = if tab_count > 1 {
+                                                "close"
+                                            } else {
+                                                "restart"
+                                            };
+                                            let prompt = match code {
+                                                Some(code) => format!(
+                                                    "\r\n[Process exited with code {code}. Press Enter to {action}.]"
+                                                ),
+                                                None => format!(
+                                                    "\r\n[Process exited. Press Enter to {action}.]"
+                                                ),
+                                            };
+                                            tab.grid.feed(prompt.as_bytes());
+                                        }
                                     }
-                                    log::info!("Session exited: {sid}");
+                                    drop(tabs_ref);
+
+                                    rebuild_tab_bar(&tabs, &ws_state);
+                                    log::info!("Session exited: {sid} (code {code:?})");
                                 }
                             }
                         }
@@ -603,8 +1046,15 @@ fn connect_ws(
                     let data = array.to_vec();
                     if data.len() > 16 {
                         let sid: [u8; 16] = data[..16].try_into().unwrap();
-                        let pty_output = &data[16..];
-                        tabs.borrow_mut().route_output(&sid, pty_output);
+                        let mut tabs_ref = tabs.borrow_mut();
+                        tabs_ref.route_output(&sid, &data[16..]);
+                        // No frames run while the page is hidden, so parse
+                        // here to keep answering queries and bound the backlog
+                        if page_hidden() {
+                            for (sid, writes) in tabs_ref.advance_output(usize::MAX) {
+                                ws_send_binary(&ws_state, &sid, &writes);
+                            }
+                        }
                     }
                 }
             },
@@ -674,6 +1124,85 @@ fn schedule_reconnect(
     log::info!("Reconnecting in {delay}ms");
 }
 
+/// Dismiss the active tab after its process exited: close it, or start a
+/// fresh session in place when it is the last tab. Either way the server
+/// stops holding the exited session.
+fn dismiss_exited_tab(tabs: &Rc<RefCell<TabManager>>, ws_state: &Rc<RefCell<WsState>>) {
+    let mut tabs_ref = tabs.borrow_mut();
+    if let Some(sid) = tabs_ref.active_tab_mut().held_session.take() {
+        let close_msg = format!(
+            r#"{{"type":"close","session_id":"{}"}}"#,
+            uuid::Uuid::from_bytes(sid)
+        );
+        let state = ws_state.borrow();
+        if let Some(ref ws) = state.ws {
+            if ws.ready_state() == web_sys::WebSocket::OPEN {
+                let _ = ws.send_with_str(&close_msg);
+            }
+        }
+    }
+    if tabs_ref.tab_count() > 1 {
+        let active = tabs_ref.active;
+        tabs_ref.close_tab(active);
+        let active = tabs_ref.active;
+        tabs_ref.switch_to(active);
+        drop(tabs_ref);
+        rebuild_tab_bar(tabs, ws_state);
+        return;
+    }
+
+    let active = tabs_ref.active_tab_mut();
+    active.awaiting_restart = false;
+    active.exit_code = None;
+    let cols = active.grid.cols;
+    let rows = active.grid.rows;
+    active.grid = TerminalGrid::new(cols, rows);
+    active.output.clear();
+    drop(tabs_ref);
+    rebuild_tab_bar(tabs, ws_state);
+
+    let create_msg = format!(r#"{{"type":"create","cols":{},"rows":{}}}"#, cols, rows);
+    let state = ws_state.borrow();
+    if let Some(ref ws) = state.ws {
+        if ws.ready_state() == web_sys::WebSocket::OPEN {
+            let _ = ws.send_with_str(&create_msg);
+        }
+    }
+}
+
+/// Build the create message for a tab opened from `from`, starting in the
+/// directory the shell last reported (OSC 7), or else wherever the server
+/// sees `from`'s process
+fn new_tab_create_msg(from: &Tab, cols: usize, rows: usize) -> String {
+    let cwd = match (from.grid.cwd(), from.session_id) {
+        (Some(cwd), _) => format!(r#","cwd":{}"#, json_string(cwd)),
+        (None, Some(sid)) => {
+            format!(r#","cwd_from":"{}""#, uuid::Uuid::from_bytes(sid))
+        }
+        (None, None) => String::new(),
+    };
+    format!(
+        r#"{{"type":"create","cols":{},"rows":{}{}}}"#,
+        cols, rows, cwd
+    )
+}
+
+/// Quote `s` as a JSON string literal
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
 /// Send bytes over the WebSocket with session UUID prefix
 fn ws_send_binary(ws_state: &RefCell<WsState>, session_id: &[u8; 16], payload: &[u8]) {
     let state = ws_state.borrow();
@@ -690,6 +1219,64 @@ fn ws_send_binary(ws_state: &RefCell<WsState>, session_id: &[u8; 16], payload: &
     let _ = ws.send_with_array_buffer_view(&array);
 }
 
+/// Send `text` to the active tab as a paste, framed with bracketed paste
+/// when the application enabled it and handled per the paste mode otherwise
+fn send_paste(tabs: &RefCell<TabManager>, ws_state: &RefCell<WsState>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+
+    let (sid, bracketed) = {
+        let tabs_ref = tabs.borrow();
+        let active = tabs_ref.active_tab();
+        (active.session_id, active.grid.bracketed_paste())
+    };
+    let Some(sid) = sid else {
+        return;
+    };
+
+    // Without bracketed paste a line break runs whatever precedes it
+    let mode = PASTE_MODE.with(Cell::get);
+    let paste = prepare_paste(text, bracketed, mode);
+    if paste.multiline && !bracketed {
+        match mode {
+            PasteMode::Warn => {
+                let confirmed = web_sys::window()
+                    .and_then(|w| {
+                        w.confirm_with_message(
+                            "The pasted text contains line breaks and may \
+                             run commands. Paste anyway?",
+                        )
+                        .ok()
+                    })
+                    .unwrap_or(false);
+                if !confirmed {
+                    return;
+                }
+            }
+            PasteMode::Sanitize => {
+                log::warn!("Pasted text had line breaks; joined into one line")
+            }
+            PasteMode::Raw => {
+                log::warn!("Pasting multi-line text without bracketed paste")
+            }
+        }
+    }
+    ws_send_binary(ws_state, &sid, &paste.bytes);
+}
+
+/// Copy the grid's selection to `ty`. Browsers have no primary selection,
+/// so it is kept in memory; as on the desktop, both targets also fill the
+/// system clipboard.
+fn copy_selection(grid: &TerminalGrid, ty: ClipboardType) {
+    let text =
+        CLIPBOARDS.with(|clipboards| clipboards.borrow_mut().copy_selection(grid, ty));
+    if !text.is_empty() {
+        let clipboard = web_sys::window().unwrap().navigator().clipboard();
+        let _ = clipboard.write_text(&text);
+    }
+}
+
 /// Initialize a terminal inside the given container element
 #[wasm_bindgen]
 pub fn create_terminal(container_id: String, ws_url: String, font_size: f32) {
@@ -800,14 +1387,15 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
 
     log::info!("Terminal dimensions: {cols}x{rows} (cell: {cell_width}x{cell_height})");
 
-    let tabs = Rc::new(RefCell::new(TabManager::new(cols, rows)));
+    let tabs = Rc::new(RefCell::new(TabManager::new(
+        cols,
+        rows,
+        (cell_width, cell_height),
+    )));
 
-    sugarloaf.set_background_color(Some(wgpu::Color {
-        r: 0.05,
-        g: 0.05,
-        b: 0.1,
-        a: 1.0,
-    }));
+    sugarloaf.set_background_color(Some(wgpu_color(
+        THEME.with(|theme| theme.borrow().background),
+    )));
 
     // WebSocket connection with auto-reconnect
     let ws_url = Rc::new(ws_url);
@@ -815,6 +1403,8 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
         ws: None,
         backoff_ms: 0,
     }));
+    TERMINAL
+        .with(|terminal| *terminal.borrow_mut() = Some((tabs.clone(), ws_state.clone())));
     connect_ws(&ws_state, &tabs, &ws_url);
 
     // Build the initial tab bar
@@ -846,30 +1436,11 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
                     return;
                 }
 
-                // Restart session on Enter when process has exited
+                // Dismiss the tab on Enter when process has exited
                 if tabs_restart.borrow().active_tab().awaiting_restart {
                     if event.key() == "Enter" {
                         event.prevent_default();
-                        let (cols, rows) = {
-                            let mut tabs_ref = tabs_restart.borrow_mut();
-                            let active = tabs_ref.active_tab_mut();
-                            active.awaiting_restart = false;
-                            let cols = active.grid.cols;
-                            let rows = active.grid.rows;
-                            active.grid = TerminalGrid::new(cols, rows);
-                            active.parser = copa::Parser::new();
-                            (cols, rows)
-                        };
-                        let create_msg = format!(
-                            r#"{{"type":"create","cols":{},"rows":{}}}"#,
-                            cols, rows
-                        );
-                        let state = ws_state_restart.borrow();
-                        if let Some(ref ws) = state.ws {
-                            if ws.ready_state() == web_sys::WebSocket::OPEN {
-                                let _ = ws.send_with_str(&create_msg);
-                            }
-                        }
+                        dismiss_exited_tab(&tabs_restart, &ws_state_restart);
                     }
                     return;
                 }
@@ -877,17 +1448,16 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
                 // Ctrl+T: create new tab
                 if event.ctrl_key() && event.key() == "t" {
                     event.prevent_default();
-                    let (cols, rows) = {
+                    let (cols, rows, create_msg) = {
                         let tabs_ref = tabs_shortcut.borrow();
                         let active = tabs_ref.active_tab();
-                        (active.grid.cols, active.grid.rows)
+                        let (cols, rows) = (active.grid.cols, active.grid.rows);
+                        (cols, rows, new_tab_create_msg(active, cols, rows))
                     };
                     let new_idx = tabs_shortcut.borrow_mut().add_tab(cols, rows);
                     tabs_shortcut.borrow_mut().switch_to(new_idx);
 
                     // Send create message for the new tab
-                    let create_msg =
-                        format!(r#"{{"type":"create","cols":{},"rows":{}}}"#, cols, rows);
                     let state = ws_state_shortcut.borrow();
                     if let Some(ref ws) = state.ws {
                         if ws.ready_state() == web_sys::WebSocket::OPEN {
@@ -921,6 +1491,62 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
                     return;
                 }
 
+                // Ctrl+Shift+C: copy the selection to the clipboard
+                if event.ctrl_key() && event.shift_key() && event.key() == "C" {
+                    event.prevent_default();
+                    let tabs_ref = tabs_key.borrow();
+                    copy_selection(&tabs_ref.active_tab().grid, ClipboardType::Clipboard);
+                    return;
+                }
+
+                // Ctrl+Shift+X: copy the selection, or the screen, with
+                // colors as SGR escapes
+                if event.ctrl_key() && event.shift_key() && event.key() == "X" {
+                    event.prevent_default();
+                    let tabs_ref = tabs_key.borrow();
+                    let grid = &tabs_ref.active_tab().grid;
+                    let text = match grid.selection_range() {
+                        Some(_) => grid.selected_ansi(),
+                        None => grid.visible_ansi(),
+                    };
+                    if !text.is_empty() {
+                        let clipboard =
+                            web_sys::window().unwrap().navigator().clipboard();
+                        let _ = clipboard.write_text(&text);
+                    }
+                    return;
+                }
+
+                // Ctrl+Shift+Up/Down: jump between prompts marked by shell
+                // integration
+                if event.ctrl_key()
+                    && event.shift_key()
+                    && matches!(event.key().as_str(), "ArrowUp" | "ArrowDown")
+                {
+                    event.prevent_default();
+                    let mut tabs_ref = tabs_key.borrow_mut();
+                    let grid = &mut tabs_ref.active_tab_mut().grid;
+                    if event.key() == "ArrowUp" {
+                        grid.previous_prompt();
+                    } else {
+                        grid.next_prompt();
+                    }
+                    return;
+                }
+
+                // Ctrl+Shift+Space: enter scroll mode
+                if event.ctrl_key() && event.shift_key() && event.key() == " " {
+                    event.prevent_default();
+                    tabs_key.borrow_mut().active_tab_mut().scroll_mode = true;
+                    return;
+                }
+
+                if tabs_key.borrow().active_tab().scroll_mode {
+                    event.prevent_default();
+                    scroll_mode_keydown(&tabs_key, &event);
+                    return;
+                }
+
                 // Let Ctrl+V through so the browser paste event fires
                 if event.ctrl_key() && event.key() == "v" {
                     return;
@@ -934,7 +1560,8 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
                     .grid
                     .selection_clear();
 
-                let bytes = key_event_to_bytes(&event);
+                let protocol = tabs_key.borrow().active_tab().grid.keyboard_protocol();
+                let bytes = key_event_to_bytes(&event, protocol);
                 if bytes.is_empty() {
                     return;
                 }
@@ -995,24 +1622,7 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
                 let Ok(text) = data.get_data("text/plain") else {
                     return;
                 };
-                if text.is_empty() {
-                    return;
-                }
-
-                // Bracketed paste: \x1b[200~ + text + \x1b[201~
-                let mut payload = Vec::new();
-                payload.extend_from_slice(b"\x1b[200~");
-                payload.extend_from_slice(text.as_bytes());
-                payload.extend_from_slice(b"\x1b[201~");
-
-                let sid = {
-                    let tabs_ref = tabs_paste.borrow();
-                    tabs_ref.active_tab().session_id
-                };
-                let Some(sid) = sid else {
-                    return;
-                };
-                ws_send_binary(&ws_state_paste, &sid, &payload);
+                send_paste(&tabs_paste, &ws_state_paste, &text);
             },
         );
         textarea_target
@@ -1176,7 +1786,39 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
                     // Start text selection when mouse mode is off
                     let mode = active.grid.mouse_mode();
                     if mode == MouseMode::None {
-                        active.grid.selection_begin(col, row);
+                        // Middle click pastes the primary selection
+                        if event.button() == 1 {
+                            drop(tabs_ref);
+                            event.prevent_default();
+                            let text = CLIPBOARDS.with(|clipboards| {
+                                clipboards
+                                    .borrow()
+                                    .get(ClipboardType::Selection)
+                                    .to_string()
+                            });
+                            send_paste(&tabs, &ws_state, &text);
+                            return;
+                        }
+
+                        // Double-click selects the word under the pointer
+                        // and copies it to the primary selection
+                        if event.detail() == 2 {
+                            WORD_SEPARATORS.with(|cell| {
+                                if let Some(separators) = cell.borrow().as_deref() {
+                                    active.grid.set_word_separators(separators);
+                                }
+                            });
+                            active.grid.select_word(col, row);
+                            copy_selection(&active.grid, ClipboardType::Selection);
+                            return;
+                        }
+
+                        // Alt+drag selects a rectangle
+                        if event.alt_key() {
+                            active.grid.selection_begin_block(col, row);
+                        } else {
+                            active.grid.selection_begin(col, row);
+                        }
                         *selecting.borrow_mut() = true;
                         drop(tabs_ref);
                         return;
@@ -1222,20 +1864,13 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
 
                     mouse_state.borrow_mut().buttons_down &= !(1 << button);
 
-                    // Finish text selection and copy to clipboard
+                    // Finish text selection and copy to the primary selection
                     if *selecting.borrow() {
                         *selecting.borrow_mut() = false;
                         let mut tabs_ref = tabs.borrow_mut();
                         let active = tabs_ref.active_tab_mut();
                         active.grid.selection_update(col, row);
-                        let text = active.grid.selected_text();
-                        drop(tabs_ref);
-
-                        if !text.is_empty() {
-                            let clipboard =
-                                web_sys::window().unwrap().navigator().clipboard();
-                            let _ = clipboard.write_text(&text);
-                        }
+                        copy_selection(&active.grid, ClipboardType::Selection);
                         return;
                     }
 
@@ -1343,6 +1978,7 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
             let ws_state = ws_state.clone();
             let cw = cell_width;
             let ch = cell_height;
+            let mut carry = 0.0;
             let on_wheel = Closure::<dyn FnMut(web_sys::WheelEvent)>::new(
                 move |event: web_sys::WheelEvent| {
                     let mouse_event: &web_sys::MouseEvent = event.as_ref();
@@ -1353,10 +1989,14 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
                         ch,
                     );
 
+                    // One notch per event, up when the wheel moves away
+                    let config = SCROLL_CONFIG.with(Cell::get);
+                    let notch = if event.delta_y() < 0.0 { 1.0 } else { -1.0 };
+
                     // When mouse mode is off, scroll the viewport instead
                     let mode = tabs.borrow().active_tab().grid.mouse_mode();
                     if mode == MouseMode::None {
-                        let lines = if event.delta_y() < 0.0 { 3 } else { -3 };
+                        let lines = config.lines(notch, &mut carry);
                         tabs.borrow_mut()
                             .active_tab_mut()
                             .grid
@@ -1365,7 +2005,10 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
                         return;
                     }
 
-                    let button: u8 = if event.delta_y() < 0.0 { 64 } else { 65 };
+                    // Applications pick their own speed; only the direction
+                    // follows the config
+                    let up = (notch > 0.0) != config.inverted();
+                    let button: u8 = if up { 64 } else { 65 };
                     let mods = mouse_modifiers(mouse_event);
 
                     let mut tabs_ref = tabs.borrow_mut();
@@ -1426,29 +2069,10 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
                         return;
                     }
 
-                    // Restart session on Enter when process has exited
+                    // Dismiss the tab on Enter when process has exited
                     if tabs.borrow().active_tab().awaiting_restart {
                         if text.contains('\n') || text.contains('\r') {
-                            let (cols, rows) = {
-                                let mut tabs_ref = tabs.borrow_mut();
-                                let active = tabs_ref.active_tab_mut();
-                                active.awaiting_restart = false;
-                                let cols = active.grid.cols;
-                                let rows = active.grid.rows;
-                                active.grid = TerminalGrid::new(cols, rows);
-                                active.parser = copa::Parser::new();
-                                (cols, rows)
-                            };
-                            let create_msg = format!(
-                                r#"{{"type":"create","cols":{},"rows":{}}}"#,
-                                cols, rows
-                            );
-                            let state = ws_state.borrow();
-                            if let Some(ref ws) = state.ws {
-                                if ws.ready_state() == web_sys::WebSocket::OPEN {
-                                    let _ = ws.send_with_str(&create_msg);
-                                }
-                            }
+                            dismiss_exited_tab(&tabs, &ws_state);
                         }
                         return;
                     }
@@ -1592,38 +2216,149 @@ async fn async_main(container_id: String, ws_url: String, font_size: f32) {
     }
 
     // Render loop
-    render_loop(sugarloaf, tabs, rt_id);
+    render_loop(sugarloaf, tabs, ws_state, rt_id, dpr);
+}
+
+/// Whether the page is hidden, when browsers stop or throttle animation
+/// frames
+fn page_hidden() -> bool {
+    web_sys::window()
+        .and_then(|window| window.document())
+        .is_some_and(|document| document.hidden())
+}
+
+/// Animation frames driving `render_loop`. They stop while the page is
+/// hidden and start again once it is visible.
+struct FrameLoop {
+    frame: RefCell<Option<Closure<dyn FnMut()>>>,
+    /// A frame is scheduled
+    running: Cell<bool>,
+}
+
+impl FrameLoop {
+    fn schedule(&self) {
+        if let Some(frame) = self.frame.borrow().as_ref() {
+            request_animation_frame(frame);
+            self.running.set(true);
+        }
+    }
 }
 
 fn render_loop(
     sugarloaf: Rc<RefCell<Sugarloaf<'static>>>,
     tabs: Rc<RefCell<TabManager>>,
+    ws_state: Rc<RefCell<WsState>>,
     rt_id: usize,
+    scale: f32,
 ) {
-    let f: Rc<RefCell<Option<Closure<dyn FnMut()>>>> = Rc::new(RefCell::new(None));
-    let g = f.clone();
+    let frames = Rc::new(FrameLoop {
+        frame: RefCell::new(None),
+        running: Cell::new(false),
+    });
+
+    // Resume with a full redraw of whatever changed while hidden
+    {
+        let frames = frames.clone();
+        let tabs = tabs.clone();
+        let on_visibility = Closure::<dyn FnMut()>::new(move || {
+            if page_hidden() || frames.running.get() {
+                return;
+            }
+            for tab in &mut tabs.borrow_mut().tabs {
+                tab.grid.dirty = true;
+            }
+            frames.schedule();
+        });
+        if let Some(document) = web_sys::window().and_then(|window| window.document()) {
+            let _ = document.add_event_listener_with_callback(
+                "visibilitychange",
+                on_visibility.as_ref().unchecked_ref(),
+            );
+        }
+        on_visibility.forget();
+    }
+
+    let next = frames.clone();
+    let mut scrollbar = Scrollbar::new();
+    let mut last_frame = js_sys::Date::now();
+
+    *frames.frame.borrow_mut() = Some(Closure::new(move || {
+        // Parse a bounded amount of output per frame so a large burst does
+        // not stall rendering
+        let replies = tabs.borrow_mut().advance_output(OUTPUT_BYTES_PER_FRAME);
+        for (sid, writes) in replies {
+            ws_send_binary(&ws_state, &sid, &writes);
+        }
 
-    *g.borrow_mut() = Some(Closure::new(move || {
         {
             let mut tabs_ref = tabs.borrow_mut();
             let active = tabs_ref.active_tab_mut();
-            if active.grid.dirty {
+            active.grid.set_cursor_color(CURSOR_COLOR.with(Cell::get));
+            active
+                .grid
+                .set_theme(THEME.with(|theme| theme.borrow().clone()));
+
+            // Keep frames coming while the scrollbar fades out
+            let now = js_sys::Date::now();
+            let dt = ((now - last_frame) / 1000.0) as f32;
+            last_frame = now;
+            let scrollbar_opacity = if SHOW_SCROLLBAR.with(Cell::get) {
+                scrollbar.update(active.grid.display_offset > 0, dt)
+            } else {
+                0.0
+            };
+            if scrollbar.fading() {
+                active.grid.dirty = true;
+            }
+
+            // Hold frames while the application is in a synchronized update
+            if active.grid.dirty && !active.grid.synchronized_output() {
                 let mut sugarloaf = sugarloaf.borrow_mut();
+                sugarloaf.set_background_color(Some(wgpu_color(
+                    active.grid.theme().background,
+                )));
                 render_grid(&mut sugarloaf, &active.grid, rt_id);
-                sugarloaf.set_objects(vec![Object::RichText(RichText {
+                let mut objects = vec![Object::RichText(RichText {
                     id: rt_id,
                     position: [0.0, 0.0],
                     lines: None,
-                })]);
+                })];
+                let dims = sugarloaf.get_rich_text_dimensions(&rt_id);
+                let area = [
+                    0.0,
+                    0.0,
+                    active.grid.cols as f32 * dims.width,
+                    active.grid.rows as f32 * dims.height,
+                ];
+                objects.extend(
+                    prompt_marker_quads(&active.grid, area, scale)
+                        .into_iter()
+                        .map(Object::Quad),
+                );
+                let thumb = scrollbar_thumb(&active.grid);
+                if let Some(thumb) = thumb.filter(|_| scrollbar_opacity > 0.0) {
+                    objects.push(Object::Quad(scrollbar_quad(
+                        thumb,
+                        scrollbar_opacity,
+                        area,
+                        scale,
+                    )));
+                }
+                sugarloaf.set_objects(objects);
                 sugarloaf.render();
                 active.grid.dirty = false;
             }
         }
 
-        request_animation_frame(f.borrow().as_ref().unwrap());
+        // Hidden pages get no frames until they are visible again
+        if page_hidden() {
+            next.running.set(false);
+        } else {
+            next.schedule();
+        }
     }));
 
-    request_animation_frame(g.borrow().as_ref().unwrap());
+    frames.schedule();
 }
 
 fn request_animation_frame(f: &Closure<dyn FnMut()>) {
@@ -1633,18 +2368,87 @@ fn request_animation_frame(f: &Closure<dyn FnMut()>) {
         .unwrap();
 }
 
-/// Convert a browser keyboard event to terminal input bytes
-fn key_event_to_bytes(event: &web_sys::KeyboardEvent) -> Vec<u8> {
+/// Convert a browser keyboard event to terminal input bytes, using CSI u for
+/// modified keys when the application asked for it
+/// Handle a key in scroll mode. Nothing reaches the PTY; see
+/// `scroll_mode_action` for the key map.
+fn scroll_mode_keydown(tabs: &Rc<RefCell<TabManager>>, event: &web_sys::KeyboardEvent) {
+    let key = match event.key().as_str() {
+        "ArrowUp" => NavKey::Up,
+        "ArrowDown" => NavKey::Down,
+        "PageUp" => NavKey::PageUp,
+        "PageDown" => NavKey::PageDown,
+        "Home" => NavKey::Home,
+        "End" => NavKey::End,
+        "Escape" => NavKey::Escape,
+        key => {
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => NavKey::Char(c),
+                _ => return,
+            }
+        }
+    };
+
+    let action = scroll_mode_action(key);
+    let query = match action {
+        Some(ScrollAction::Search) => {
+            // Prompt without holding the borrow; the dialog is modal
+            let Some(window) = web_sys::window() else {
+                return;
+            };
+            match window.prompt_with_message("Search scrollback") {
+                Ok(Some(query)) if !query.is_empty() => Some(query),
+                _ => return,
+            }
+        }
+        _ => None,
+    };
+
+    let mut tabs_ref = tabs.borrow_mut();
+    let tab = tabs_ref.active_tab_mut();
+    match action {
+        Some(ScrollAction::Search) => {
+            if let Some(query) = query {
+                tab.grid.search_backward(&query);
+                tab.last_search = Some(query);
+            }
+        }
+        Some(ScrollAction::SearchNext) => {
+            if let Some(query) = &tab.last_search {
+                tab.grid.search_backward(query);
+            }
+        }
+        Some(ScrollAction::Exit) => {
+            tab.scroll_mode = false;
+            tab.grid.scroll_to_bottom();
+            tab.grid.selection_clear();
+        }
+        Some(action) => {
+            action.apply(&mut tab.grid);
+        }
+        None => {}
+    }
+    tab.grid.dirty = true;
+}
+
+fn key_event_to_bytes(
+    event: &web_sys::KeyboardEvent,
+    protocol: KeyboardProtocol,
+) -> Vec<u8> {
     let key = event.key();
-    let ctrl = event.ctrl_key();
-    let alt = event.alt_key();
+    let mods = KeyModifiers {
+        shift: event.shift_key(),
+        alt: event.alt_key(),
+        ctrl: event.ctrl_key(),
+    };
 
     // Handle special keys
     match key.as_str() {
-        "Enter" => return b"\r".to_vec(),
-        "Backspace" => return vec![0x7f],
-        "Tab" => return b"\t".to_vec(),
-        "Escape" => return vec![0x1b],
+        "Enter" => return encode_key(Key::Enter, mods, protocol),
+        "Backspace" => return encode_key(Key::Backspace, mods, protocol),
+        "Tab" => return encode_key(Key::Tab, mods, protocol),
+        "Escape" => return encode_key(Key::Escape, mods, protocol),
         "ArrowUp" => return b"\x1b[A".to_vec(),
         "ArrowDown" => return b"\x1b[B".to_vec(),
         "ArrowRight" => return b"\x1b[C".to_vec(),
@@ -1670,29 +2474,24 @@ fn key_event_to_bytes(event: &web_sys::KeyboardEvent) -> Vec<u8> {
         _ => {}
     }
 
-    // Ctrl+key combinations (skip Ctrl+V -- let browser paste event handle it)
-    if ctrl && key.len() == 1 {
-        let ch = key.chars().next().unwrap();
-        if ch.to_ascii_lowercase() == 'v' {
-            return vec![];
-        }
-        if ch.is_ascii_alphabetic() {
-            let ctrl_byte = (ch.to_ascii_lowercase() as u8) - b'a' + 1;
-            return vec![ctrl_byte];
-        }
-    }
-
-    // Alt+key: send ESC prefix
-    if alt && key.len() == 1 {
-        let mut bytes = vec![0x1b];
-        bytes.extend_from_slice(key.as_bytes());
-        return bytes;
-    }
-
-    // Regular character input
-    if key.len() == 1 || key.chars().count() == 1 {
-        return key.as_bytes().to_vec();
+    // Dead keys ("Dead") and other named keys type nothing; the composed
+    // character arrives with the next key
+    let mut chars = key.chars();
+    let (Some(ch), None) = (chars.next(), chars.next()) else {
+        return vec![];
+    };
+    let (key, mods) = web_char_key(
+        ch,
+        &event.code(),
+        mods,
+        event.get_modifier_state("AltGraph"),
+    );
+
+    // Skip Ctrl+V -- let browser paste event handle it
+    if mods.ctrl && matches!(key, Key::Char('v' | 'V')) {
+        return vec![];
     }
 
-    vec![]
+    // Ctrl+letter, Alt ESC prefix or plain text
+    encode_key(key, mods, protocol)
 }
@@ -128,6 +128,23 @@ impl Compositor {
                     shape: UnderlineShape::Regular,
                 })
             }
+            Some(FragmentStyleDecoration::Overline) => {
+                // Along the top of the glyph box, where the cursor starts
+                let overline_thickness = if style.underline_thickness > 0.0 {
+                    style.underline_thickness
+                } else {
+                    1.5
+                };
+
+                Some(RunUnderline {
+                    enabled: true,
+                    offset: -style.ascent,
+                    size: overline_thickness,
+                    color: style.decoration_color.unwrap_or(style.color),
+                    is_doubled: false,
+                    shape: UnderlineShape::Regular,
+                })
+            }
             _ => None,
         }
     }
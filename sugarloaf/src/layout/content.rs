@@ -286,6 +286,7 @@ pub enum FragmentStyleDecoration {
     // offset, size
     Underline(UnderlineInfo),
     Strikethrough,
+    Overline,
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -400,6 +400,21 @@ pub fn create_pty_with_spawn(
     working_directory: &Option<String>,
     columns: u16,
     rows: u16,
+) -> Result<Pty, Error> {
+    create_pty_with_spawn_and_env(shell, args, &[], working_directory, columns, rows)
+}
+
+///
+/// Creates a pseudoterminal using spawn, like [`create_pty_with_spawn`], setting
+/// `env` in the child's environment on top of the inherited one.
+///
+pub fn create_pty_with_spawn_and_env(
+    shell: &str,
+    args: Vec<String>,
+    env: &[(String, String)],
+    working_directory: &Option<String>,
+    columns: u16,
+    rows: u16,
 ) -> Result<Pty, Error> {
     #[cfg(not(any(target_os = "macos", target_os = "freebsd")))]
     let mut is_controling_terminal = true;
@@ -542,6 +557,7 @@ pub fn create_pty_with_spawn(
 
     builder.env("USER", user.user);
     builder.env("HOME", user.home);
+    builder.envs(env.iter().map(|(key, value)| (key, value)));
 
     unsafe {
         builder.pre_exec(move || {
@@ -0,0 +1,74 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::collections::VecDeque;
+use std::hint::black_box;
+use terminal_emulator::{Cell, TerminalGrid};
+
+const LINES: usize = 100_000;
+const COLS: usize = 120;
+const ROWS: usize = 50;
+
+/// A 100k-line file as `cat` would write it
+fn file_contents() -> Vec<u8> {
+    let mut data = Vec::new();
+    for i in 0..LINES {
+        data.extend_from_slice(
+            format!("{i:>8}  the quick brown fox jumps\r\n").as_bytes(),
+        );
+    }
+    data
+}
+
+/// Lines of history the grid keeps
+const HISTORY: usize = 1000;
+
+/// Scroll `LINES` times with rows and history in `Vec`s, as the grid did
+/// before its ring buffers: removing the front row moves every other row
+fn memmove_scroll() -> Vec<Vec<Cell>> {
+    let mut rows = vec![vec![Cell::default(); COLS]; ROWS];
+    let mut history = Vec::new();
+    for _ in 0..LINES {
+        history.push(rows.remove(0));
+        if history.len() > HISTORY {
+            black_box(history.remove(0));
+        }
+        rows.push(vec![Cell::default(); COLS]);
+    }
+    rows
+}
+
+/// The same scrolling with `VecDeque`s, as the grid stores them now
+fn ring_buffer_scroll() -> VecDeque<Vec<Cell>> {
+    let mut rows: VecDeque<_> = vec![vec![Cell::default(); COLS]; ROWS].into();
+    let mut history = VecDeque::new();
+    for _ in 0..LINES {
+        history.push_back(rows.pop_front().unwrap());
+        if history.len() > HISTORY {
+            black_box(history.pop_front());
+        }
+        rows.push_back(vec![Cell::default(); COLS]);
+    }
+    rows
+}
+
+fn bench_scrolling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scroll_100k_lines");
+    group.sample_size(10);
+    let data = file_contents();
+
+    group.bench_function("grid_feed", |b| {
+        b.iter(|| {
+            let mut grid = TerminalGrid::new(COLS, ROWS);
+            grid.feed(black_box(&data));
+            grid
+        });
+    });
+
+    // Row storage alone, without parsing
+    group.bench_function("ring_buffer_rows", |b| b.iter(ring_buffer_scroll));
+    group.bench_function("memmove_rows", |b| b.iter(memmove_scroll));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_scrolling);
+criterion_main!(benches);
@@ -0,0 +1,183 @@
+use crate::grid::{ansi_color, Cell, Underline};
+
+/// Default foreground, which SGR 39 and 0 restore
+const DEFAULT_FG: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// SGR attributes shared by a run of cells
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Style {
+    fg: [f32; 4],
+    bg: Option<[f32; 4]>,
+    bold: bool,
+    italic: bool,
+    underline: Underline,
+    strikethrough: bool,
+    overline: bool,
+    inverse: bool,
+}
+
+impl Style {
+    const DEFAULT: Self = Self {
+        fg: DEFAULT_FG,
+        bg: None,
+        bold: false,
+        italic: false,
+        underline: Underline::None,
+        strikethrough: false,
+        overline: false,
+        inverse: false,
+    };
+
+    fn of(cell: &Cell) -> Self {
+        Self {
+            fg: cell.fg,
+            bg: cell.bg,
+            bold: cell.bold,
+            italic: cell.italic,
+            underline: cell.underline,
+            strikethrough: cell.strikethrough,
+            overline: cell.overline,
+            inverse: cell.inverse,
+        }
+    }
+
+    /// SGR parameters turning `self` into `next`, changed attributes only.
+    fn transition(&self, next: &Self) -> Vec<String> {
+        let mut params = Vec::new();
+        let mut flag = |was: bool, is: bool, on: &str, off: &str| {
+            if was != is {
+                params.push(if is { on } else { off }.to_string());
+            }
+        };
+        flag(self.bold, next.bold, "1", "22");
+        flag(self.italic, next.italic, "3", "23");
+        flag(self.inverse, next.inverse, "7", "27");
+        flag(self.strikethrough, next.strikethrough, "9", "29");
+        flag(self.overline, next.overline, "53", "55");
+        if self.underline != next.underline {
+            params.push(underline_param(next.underline).to_string());
+        }
+        if self.fg != next.fg {
+            params.push(color_param(next.fg, 30));
+        }
+        if self.bg != next.bg {
+            params.push(match next.bg {
+                Some(bg) => color_param(bg, 40),
+                None => "49".to_string(),
+            });
+        }
+        params
+    }
+}
+
+/// SGR parameter selecting `color`, with `base` 30 for foreground or 40 for
+/// background. Palette colors keep their index so they re-parse exactly;
+/// anything else is sent as truecolor.
+fn color_param(color: [f32; 4], base: u16) -> String {
+    if base == 30 && color == DEFAULT_FG {
+        return "39".to_string();
+    }
+    match (0..=255).find(|&idx| ansi_color(idx) == color) {
+        Some(idx @ 0..=7) => (base + idx).to_string(),
+        Some(idx @ 8..=15) => (base + 60 + idx - 8).to_string(),
+        Some(idx) => format!("{};5;{idx}", base + 8),
+        None => {
+            let [r, g, b, _] = color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+            format!("{};2;{r};{g};{b}", base + 8)
+        }
+    }
+}
+
+/// SGR parameter selecting `underline`
+fn underline_param(underline: Underline) -> &'static str {
+    match underline {
+        Underline::None => "24",
+        Underline::Single => "4",
+        Underline::Double => "4:2",
+        Underline::Curly => "4:3",
+        Underline::Dotted => "4:4",
+        Underline::Dashed => "4:5",
+    }
+}
+
+/// Cell that shows nothing, so can be dropped from the end of a line
+fn is_blank(cell: &Cell) -> bool {
+    cell.c == ' '
+        && cell.bg.is_none()
+        && !cell.inverse
+        && cell.underline == Underline::None
+        && !cell.strikethrough
+        && !cell.overline
+}
+
+/// Serialize lines of cells as text with SGR escapes, one line per `\n`.
+/// Escapes are only written where the style changes, and each line that
+/// ends styled is reset so lines can be pasted on their own.
+pub(crate) fn encode_lines<'a>(lines: impl IntoIterator<Item = &'a [Cell]>) -> String {
+    let mut out = String::new();
+    for (i, line) in lines.into_iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let len = line
+            .iter()
+            .rposition(|cell| !is_blank(cell))
+            .map_or(0, |i| i + 1);
+        let mut style = Style::DEFAULT;
+        for cell in line[..len].iter().filter(|cell| !cell.spacer) {
+            let next = Style::of(cell);
+            let params = style.transition(&next);
+            if !params.is_empty() {
+                out.push_str("\x1b[");
+                out.push_str(&params.join(";"));
+                out.push('m');
+                style = next;
+            }
+            cell.push_text(&mut out);
+        }
+        if style != Style::DEFAULT {
+            out.push_str("\x1b[0m");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grid::TerminalGrid;
+
+    fn parse(cols: usize, rows: usize, data: &str) -> TerminalGrid {
+        let mut grid = TerminalGrid::new(cols, rows);
+        grid.feed(data.as_bytes());
+        grid
+    }
+
+    #[test]
+    fn styled_line_round_trips() {
+        let source = parse(
+            40,
+            2,
+            "\x1b[1;31mred\x1b[0m plain \x1b[38;2;10;20;30;48;5;200mtc\
+             \x1b[4;7mx\x1b[22;3;92m it\x1b[m\r\n\x1b[44m  \x1b[0m",
+        );
+        let ansi = source.visible_ansi();
+
+        // Runs of one style share a single escape
+        assert!(
+            ansi.starts_with("\x1b[1;31mred\x1b[22;39m plain "),
+            "{ansi:?}"
+        );
+        assert_eq!(ansi.matches("\x1b[").count(), 8, "{ansi:?}");
+
+        let copy = parse(40, 2, &ansi.replace('\n', "\r\n"));
+        assert_eq!(copy.cells, source.cells);
+    }
+
+    #[test]
+    fn selection_serializes_only_selected_cells() {
+        let mut grid = parse(20, 2, "ab\x1b[32mcd\x1b[0mef");
+        grid.selection_begin(1, 0);
+        grid.selection_update(3, 0);
+        assert_eq!(grid.selected_ansi(), "b\x1b[32mcd\x1b[0m");
+    }
+}
@@ -0,0 +1,61 @@
+/// Character set designated to G0 or G1 with SCS (`ESC (` / `ESC )`)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) enum Charset {
+    #[default]
+    Ascii,
+    /// DEC Special Graphics, used by curses programs for line drawing
+    DecSpecialGraphics,
+}
+
+impl Charset {
+    /// Charset selected by the final byte of an SCS sequence, if supported.
+    pub(crate) fn from_designator(byte: u8) -> Option<Self> {
+        match byte {
+            b'B' => Some(Self::Ascii),
+            b'0' => Some(Self::DecSpecialGraphics),
+            _ => None,
+        }
+    }
+
+    /// Translate a printed character through this charset.
+    pub(crate) fn map(self, c: char) -> char {
+        match self {
+            Self::Ascii => c,
+            Self::DecSpecialGraphics => match c {
+                '_' => ' ',
+                '`' => '◆',
+                'a' => '▒',
+                'b' => '␉',
+                'c' => '␌',
+                'd' => '␍',
+                'e' => '␊',
+                'f' => '°',
+                'g' => '±',
+                'h' => '␤',
+                'i' => '␋',
+                'j' => '┘',
+                'k' => '┐',
+                'l' => '┌',
+                'm' => '└',
+                'n' => '┼',
+                'o' => '⎺',
+                'p' => '⎻',
+                'q' => '─',
+                'r' => '⎼',
+                's' => '⎽',
+                't' => '├',
+                'u' => '┤',
+                'v' => '┴',
+                'w' => '┬',
+                'x' => '│',
+                'y' => '≤',
+                'z' => '≥',
+                '{' => 'π',
+                '|' => '≠',
+                '}' => '£',
+                '~' => '·',
+                c => c,
+            },
+        }
+    }
+}
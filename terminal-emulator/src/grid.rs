@@ -1,15 +1,42 @@
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
 use copa::{Params, Perform};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+use crate::ansi;
+use crate::charset::Charset;
+use crate::keyboard::KeyboardProtocol;
+use crate::scrollback::ScrollbackRow;
+use crate::theme::Theme;
 
 /// Terminal cell with character and style attributes
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Cell {
     pub c: char,
     pub fg: [f32; 4],
     pub bg: Option<[f32; 4]>,
     pub bold: bool,
     pub italic: bool,
-    pub underline: bool,
+    pub underline: Underline,
+    /// Line through the text (SGR 9)
+    pub strikethrough: bool,
+    /// Line above the text (SGR 53)
+    pub overline: bool,
     pub inverse: bool,
+    /// Characters after `c` in the same grapheme cluster: combining marks,
+    /// variation selectors, emoji modifiers and ZWJ sequences
+    pub combining: Option<Box<str>>,
+    /// Right half of the double-width character in the previous cell;
+    /// draws nothing itself
+    pub spacer: bool,
+    /// Target of the OSC 8 hyperlink the cell was printed under
+    pub hyperlink: Option<Arc<str>>,
+    /// Font the renderer matched for `c`, reused on later frames
+    pub(crate) font: FontCache,
 }
 
 impl Default for Cell {
@@ -20,8 +47,89 @@ impl Default for Cell {
             bg: None,
             bold: false,
             italic: false,
-            underline: false,
+            underline: Underline::None,
+            strikethrough: false,
+            overline: false,
             inverse: false,
+            combining: None,
+            spacer: false,
+            hyperlink: None,
+            font: FontCache::default(),
+        }
+    }
+}
+
+impl Cell {
+    /// Append the text the cell shows: its whole grapheme cluster, or
+    /// nothing for a spacer.
+    pub fn push_text(&self, out: &mut String) {
+        if self.spacer {
+            return;
+        }
+        out.push(self.c);
+        if let Some(combining) = &self.combining {
+            out.push_str(combining);
+        }
+    }
+
+    /// Turn the cell into a blank, keeping its style.
+    fn blank(&mut self) {
+        self.c = ' ';
+        self.combining = None;
+        self.spacer = false;
+        self.font.clear();
+    }
+}
+
+/// Font matched for a cell on first render, as `(font_id, is_emoji)`.
+/// Cells are replaced rather than restyled when edited, so a cached match
+/// stays valid until the cell is blanked or the font library changes.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FontCache(OnceCell<(usize, bool)>);
+
+impl FontCache {
+    pub(crate) fn clear(&mut self) {
+        self.0.take();
+    }
+
+    #[cfg_attr(not(feature = "render"), allow(dead_code))]
+    pub(crate) fn get_or_init(
+        &self,
+        resolve: impl FnOnce() -> (usize, bool),
+    ) -> (usize, bool) {
+        *self.0.get_or_init(resolve)
+    }
+}
+
+/// A cache is not part of what the cell shows
+impl PartialEq for FontCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+/// Underline style set by SGR 4, 21 and the `4:n` sub-parameter form
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Underline {
+    #[default]
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+impl Underline {
+    /// Style selected by `4:n`. Unknown styles fall back to a single line.
+    pub(crate) fn from_sgr(style: u16) -> Self {
+        match style {
+            0 => Self::None,
+            2 => Self::Double,
+            3 => Self::Curly,
+            4 => Self::Dotted,
+            5 => Self::Dashed,
+            _ => Self::Single,
         }
     }
 }
@@ -37,17 +145,134 @@ pub enum MouseMode {
 /// Maximum number of lines kept in scrollback history.
 pub const MAX_SCROLLBACK: usize = 1000;
 
+/// Largest accepted grid dimension (cols or rows).
+pub const MAX_GRID_DIMENSION: usize = 1000;
+
+/// Characters besides whitespace that end a word for double-click selection
+/// by default. Path and URL characters such as `/`, `.` and `-` are left out
+/// so a path selects in one go.
+pub const DEFAULT_WORD_SEPARATORS: &str = ",│`|:\"'()[]{}<>";
+
+/// Depth limit for the Kitty keyboard flags stack.
+const KITTY_KEYBOARD_STACK_LIMIT: usize = 16;
+
+/// Depth limit for the title stack (`CSI 22 t`), as in xterm.
+const TITLE_STACK_LIMIT: usize = 10;
+
+/// Longest OSC 8 hyperlink target kept; longer ones are dropped.
+const MAX_HYPERLINK_LEN: usize = 2048;
+
+/// Most shell integration marks kept; the oldest are dropped first.
+const MAX_COMMAND_MARKS: usize = 4 * MAX_SCROLLBACK;
+
+/// Text selection anchored to content rather than the screen, so it stays on
+/// the same lines while the viewport scrolls. Rows are absolute line indices
+/// (scrollback first, then the live screen).
+#[derive(Debug, Clone, Copy)]
+struct Selection {
+    /// (col, line) where the selection began
+    anchor: (usize, usize),
+    /// (col, line) of the moving end
+    end: (usize, usize),
+    /// Rectangular selection instead of running text
+    block: bool,
+}
+
+impl Selection {
+    /// Top-left and bottom-right corners. For a normal selection these are
+    /// the first and last selected cells in reading order.
+    fn bounds(&self) -> ((usize, usize), (usize, usize)) {
+        let (a, b) = (self.anchor, self.end);
+        if self.block {
+            ((a.0.min(b.0), a.1.min(b.1)), (a.0.max(b.0), a.1.max(b.1)))
+        } else if (a.1, a.0) <= (b.1, b.0) {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+}
+
+/// Command boundary reported by shell integration (OSC 133)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CommandMark {
+    /// `A`: a prompt starts
+    Prompt,
+    /// `B`: the prompt ends and the command line starts
+    Command,
+    /// `C`: the command runs and its output starts
+    Output,
+    /// `D`: the command finished
+    Finished,
+}
+
+/// Where a `CommandMark` was received. Lines are counted from the first line
+/// the grid ever held, so marks stay on their content as the screen scrolls
+/// and old history is trimmed.
+#[derive(Debug, Clone, Copy)]
+struct Mark {
+    kind: CommandMark,
+    line: usize,
+    col: usize,
+}
+
+/// What is under a cell of the viewport, for hit testing taps and clicks
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellInfo {
+    /// Grapheme cluster shown in the cell
+    pub text: String,
+    pub fg: [f32; 4],
+    pub bg: Option<[f32; 4]>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+    /// OSC 8 hyperlink target
+    pub hyperlink: Option<String>,
+    pub selected: bool,
+}
+
+/// Normalized selection bounds in viewport coordinates, as (col, row). Rows
+/// fall outside `0..rows` when the selection extends past the visible area.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelectionRange {
+    pub start: (usize, isize),
+    pub end: (usize, isize),
+    /// Rectangular (block) selection rather than running text
+    pub block: bool,
+}
+
 /// Simple terminal grid state driven by ANSI escape sequences
 pub struct TerminalGrid {
     pub cols: usize,
     pub rows: usize,
-    pub cells: Vec<Vec<Cell>>,
+    /// Live screen rows, top first. A ring buffer, so scrolling the whole
+    /// screen moves no other rows.
+    pub cells: VecDeque<Vec<Cell>>,
     pub cursor_row: usize,
     pub cursor_col: usize,
     pub dirty: bool,
+    /// Per live row: text ran past the last column and continued on the next
+    /// row (autowrap), so both rows belong to one logical line
+    wrapped: VecDeque<bool>,
+    /// Escape sequence parser, holding any sequence split across `feed` calls
+    parser: copa::Parser,
 
     // Scrollback history (oldest first)
-    scrollback: Vec<Vec<Cell>>,
+    scrollback: VecDeque<ScrollbackRow>,
+    /// Rows trimmed from the front of scrollback so far, where absolute
+    /// line indices of `marks` start
+    lines_dropped: usize,
+    /// Shell integration marks, oldest first
+    marks: VecDeque<Mark>,
+    /// Exit status of the last finished command (`OSC 133 ; D ; code`),
+    /// cleared when the next command starts
+    last_exit_code: Option<i32>,
+    /// `wrapped` flags of the scrollback rows, in the same order
+    scrollback_wrapped: VecDeque<bool>,
+    /// Store rows entering scrollback in compact form. The live grid is
+    /// never compacted.
+    compact_scrollback: bool,
     /// Viewport offset from the bottom. 0 = viewing live output.
     pub display_offset: usize,
 
@@ -56,12 +281,25 @@ pub struct TerminalGrid {
     cur_bg: Option<[f32; 4]>,
     cur_bold: bool,
     cur_italic: bool,
-    cur_underline: bool,
+    cur_underline: Underline,
+    cur_strikethrough: bool,
+    cur_overline: bool,
     cur_inverse: bool,
+    /// Hyperlink opened by OSC 8, applied to printed cells until closed
+    cur_hyperlink: Option<Arc<str>>,
 
     // Scroll region
     scroll_top: usize,
     scroll_bottom: usize,
+    /// Origin mode (DECOM): absolute row positions count from the top of the
+    /// scroll region and stay inside it
+    origin_mode: bool,
+    /// Per column: a tab stop is set there
+    tab_stops: Vec<bool>,
+    /// Character sets designated to G0 and G1
+    charsets: [Charset; 2],
+    /// Index into `charsets` shifted in with SI (G0) or SO (G1)
+    active_charset: usize,
 
     // Saved cursor position
     saved_cursor_row: usize,
@@ -72,18 +310,59 @@ pub struct TerminalGrid {
     mouse_drag: bool,   // Mode 1002: report drag motion
     mouse_motion: bool, // Mode 1003: report all motion
     mouse_sgr: bool,    // Mode 1006: SGR extended encoding
+    mouse_pixels: bool, // Mode 1016: SGR with pixel coordinates
+
+    // Mode 2004: wrap pastes in `CSI 200~` / `CSI 201~`
+    bracketed_paste: bool,
+
+    // Mode 2026: the application is redrawing; hold frames until it is done
+    synchronized_output: bool,
+
+    // Modes 47, 1047 and 1049: a full-screen application switched to the
+    // alternate screen. Only the switch is tracked; output still goes to
+    // the one buffer.
+    alternate_screen: bool,
+
+    // Keyboard protocol requests
+    modify_other_keys: u8,          // xterm `CSI > 4 ; N m`
+    kitty_keyboard_flags: u16,      // Kitty `CSI > flags u` and friends
+    kitty_keyboard_stack: Vec<u16>, // Flags saved by each push
 
     // Bytes to send back to the PTY (mouse reports, etc.). Drained by lib.rs each frame.
     pub pending_writes: Vec<u8>,
 
     // Selection state
-    pub selection_start: Option<(usize, usize)>, // (col, row) in grid coordinates
-    pub selection_end: Option<(usize, usize)>,
+    selection: Option<Selection>,
+    // Characters besides whitespace that end a word for `select_word`
+    word_separators: String,
+
+    // Working directory reported by the shell via OSC 7
+    cwd: Option<String>,
+
+    // Window title set via OSC 0/2, and titles saved by `CSI 22 t`
+    title: Option<String>,
+    title_stack: Vec<Option<String>>,
+
+    // BEL received since the frontend last checked
+    bell_pending: bool,
+
+    // Cell size in pixels, when the frontend has reported it
+    cell_size: Option<(f32, f32)>,
+
+    // Cursor color set via OSC 12, and the frontend's configured one that
+    // OSC 112 restores. None draws the cursor by inversion.
+    cursor_color: Option<[f32; 4]>,
+    default_cursor_color: Option<[f32; 4]>,
+
+    // Colors configured by the frontend to draw the grid with
+    theme: Theme,
 }
 
 impl TerminalGrid {
     pub fn new(cols: usize, rows: usize) -> Self {
-        let cells = vec![vec![Cell::default(); cols]; rows];
+        let cols = cols.clamp(1, MAX_GRID_DIMENSION);
+        let rows = rows.clamp(1, MAX_GRID_DIMENSION);
+        let cells = vec![vec![Cell::default(); cols]; rows].into();
         Self {
             cols,
             rows,
@@ -91,25 +370,54 @@ impl TerminalGrid {
             cursor_row: 0,
             cursor_col: 0,
             dirty: true,
-            scrollback: Vec::new(),
+            wrapped: vec![false; rows].into(),
+            scrollback: VecDeque::new(),
+            lines_dropped: 0,
+            marks: VecDeque::new(),
+            last_exit_code: None,
+            scrollback_wrapped: VecDeque::new(),
+            compact_scrollback: true,
             display_offset: 0,
             cur_fg: [1.0, 1.0, 1.0, 1.0],
             cur_bg: None,
             cur_bold: false,
             cur_italic: false,
-            cur_underline: false,
+            cur_underline: Underline::None,
+            cur_strikethrough: false,
+            cur_overline: false,
             cur_inverse: false,
+            cur_hyperlink: None,
             scroll_top: 0,
             scroll_bottom: rows - 1,
+            origin_mode: false,
+            tab_stops: (0..cols).map(default_tab_stop).collect(),
+            charsets: [Charset::Ascii; 2],
+            active_charset: 0,
             saved_cursor_row: 0,
             saved_cursor_col: 0,
             mouse_click: false,
             mouse_drag: false,
             mouse_motion: false,
             mouse_sgr: false,
+            mouse_pixels: false,
+            bracketed_paste: false,
+            synchronized_output: false,
+            alternate_screen: false,
+            modify_other_keys: 0,
+            kitty_keyboard_flags: 0,
+            kitty_keyboard_stack: Vec::new(),
             pending_writes: Vec::new(),
-            selection_start: None,
-            selection_end: None,
+            parser: copa::Parser::new(),
+            selection: None,
+            word_separators: DEFAULT_WORD_SEPARATORS.to_string(),
+            cwd: None,
+            title: None,
+            title_stack: Vec::new(),
+            bell_pending: false,
+            cell_size: None,
+            cursor_color: None,
+            default_cursor_color: None,
+            theme: Theme::default(),
         }
     }
 
@@ -125,13 +433,76 @@ impl TerminalGrid {
         }
     }
 
+    /// Whether the application asked for bracketed paste (mode 2004).
+    pub fn bracketed_paste(&self) -> bool {
+        self.bracketed_paste
+    }
+
+    /// Whether a synchronized update (mode 2026) is in progress. Frontends
+    /// skip drawing meanwhile, so a redraw never shows half done.
+    pub fn synchronized_output(&self) -> bool {
+        self.synchronized_output
+    }
+
+    /// Whether a full-screen application switched to the alternate screen
+    /// (modes 47, 1047 and 1049). Such applications scroll themselves, so
+    /// frontends should not scroll the history under them.
+    pub fn alternate_screen(&self) -> bool {
+        self.alternate_screen
+    }
+
+    /// Key encoding the application has asked for. modifyOtherKeys level 1
+    /// only changes chords legacy encoding cannot express at all, which the
+    /// legacy path already approximates, so it maps to `Legacy`.
+    pub fn keyboard_protocol(&self) -> KeyboardProtocol {
+        if self.kitty_keyboard_flags & 1 != 0 || self.modify_other_keys >= 2 {
+            KeyboardProtocol::CsiU
+        } else {
+            KeyboardProtocol::Legacy
+        }
+    }
+
+    /// Parse PTY output into the grid. A sequence or UTF-8 character cut
+    /// off at the end of `bytes` completes on the next call.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        let mut parser = std::mem::take(&mut self.parser);
+        parser.advance(self, bytes);
+        self.parser = parser;
+    }
+
+    /// Parse text into the grid, as `feed`.
+    pub fn feed_str(&mut self, text: &str) {
+        self.feed(text.as_bytes());
+    }
+
+    /// Resize the grid. Dimensions are clamped to `1..=MAX_GRID_DIMENSION`.
+    /// Any selection is cleared when the size changes: its coordinates would
+    /// no longer cover the same text, and copying it would give garbage.
     pub fn resize(&mut self, cols: usize, rows: usize) {
+        let cols = cols.clamp(1, MAX_GRID_DIMENSION);
+        let rows = rows.clamp(1, MAX_GRID_DIMENSION);
+        if (cols, rows) != (self.cols, self.rows) {
+            self.selection = None;
+        }
+        if rows < self.rows {
+            // Rows cut from the bottom take their marks with them
+            self.forget_marks_from(self.scrollback.len() + rows);
+        }
         self.cols = cols;
         self.rows = rows;
         self.cells.resize(rows, vec![Cell::default(); cols]);
+        self.wrapped.resize(rows, false);
         for row in &mut self.cells {
             row.resize(cols, Cell::default());
         }
+        // Frontends resize after a font change, when fonts must be matched
+        // again
+        self.clear_font_cache();
+        let old_cols = self.tab_stops.len();
+        self.tab_stops.truncate(cols);
+        self.tab_stops
+            .extend((old_cols..cols).map(default_tab_stop));
+        self.scroll_top = 0;
         self.scroll_bottom = rows - 1;
         if self.cursor_row >= rows {
             self.cursor_row = rows - 1;
@@ -140,6 +511,18 @@ impl TerminalGrid {
             self.cursor_col = cols - 1;
         }
         self.dirty = true;
+        self.debug_assert_row_widths();
+    }
+
+    /// Forget the fonts the renderer matched for each cell, so the next
+    /// frame looks them up again, e.g. after the font library changed.
+    pub fn clear_font_cache(&mut self) {
+        for cell in self.cells.iter_mut().flatten() {
+            cell.font.clear();
+        }
+        for row in &mut self.scrollback {
+            row.clear_font_cache();
+        }
     }
 
     /// Adjust the viewport by `delta` lines. Positive = scroll up (into history).
@@ -152,22 +535,39 @@ impl TerminalGrid {
 
     /// Return the row to display at screen position `row_idx`, accounting for
     /// `display_offset`. When scrolled back, rows come from scrollback history.
-    pub fn visible_row(&self, row_idx: usize) -> &Vec<Cell> {
-        if self.display_offset == 0 {
-            return &self.cells[row_idx];
-        }
+    /// Compacted scrollback rows are expanded on the fly.
+    pub fn visible_row(&self, row_idx: usize) -> Cow<'_, [Cell]> {
+        self.line(self.absolute_row(row_idx))
+    }
 
-        // Total virtual lines = scrollback + live cells
-        // We want to show `rows` lines ending at (total - display_offset)
-        let total = self.scrollback.len() + self.rows;
-        let end = total - self.display_offset;
-        let start = end.saturating_sub(self.rows);
-        let abs_idx = start + row_idx;
+    /// Cells shown at screen position `row_idx`, left to right. Prefer this
+    /// and `rows_visible` over indexing, so callers keep working however
+    /// rows end up stored.
+    pub fn visible_cells(&self, row_idx: usize) -> impl Iterator<Item = Cow<'_, Cell>> {
+        let cells: Box<dyn Iterator<Item = Cow<'_, Cell>>> =
+            match self.visible_row(row_idx) {
+                Cow::Borrowed(cells) => Box::new(cells.iter().map(Cow::Borrowed)),
+                Cow::Owned(cells) => Box::new(cells.into_iter().map(Cow::Owned)),
+            };
+        cells
+    }
 
-        if abs_idx < self.scrollback.len() {
-            &self.scrollback[abs_idx]
-        } else {
-            &self.cells[abs_idx - self.scrollback.len()]
+    /// Rows of the viewport, top to bottom, as `visible_row` returns them.
+    pub fn rows_visible(&self) -> impl Iterator<Item = Cow<'_, [Cell]>> {
+        (0..self.rows).map(|row| self.visible_row(row))
+    }
+
+    /// Absolute line index (scrollback first, then the live screen) shown at
+    /// screen position `row_idx`.
+    fn absolute_row(&self, row_idx: usize) -> usize {
+        self.scrollback.len().saturating_sub(self.display_offset) + row_idx
+    }
+
+    /// Line at an absolute index, as returned by `absolute_row`.
+    fn line(&self, abs_idx: usize) -> Cow<'_, [Cell]> {
+        match abs_idx.checked_sub(self.scrollback.len()) {
+            Some(idx) => Cow::Borrowed(&self.cells[idx]),
+            None => self.scrollback[abs_idx].cells(),
         }
     }
 
@@ -176,57 +576,435 @@ impl TerminalGrid {
         self.display_offset == 0
     }
 
-    /// Return the number of lines in the scrollback buffer.
+    /// Return the number of physical rows in the scrollback buffer. This is
+    /// the unit of `display_offset` and `scroll_display`.
     pub fn scrollback_len(&self) -> usize {
         self.scrollback.len()
     }
 
-    /// Snap the viewport back to the bottom (live output).
-    pub fn scroll_to_bottom(&mut self) {
-        if self.display_offset != 0 {
-            self.display_offset = 0;
+    /// Return the number of logical lines in the scrollback buffer, counting
+    /// rows joined by autowrap once. Suited to sizing a scrollbar; use
+    /// `scrollback_len` for offsets.
+    pub fn logical_scrollback_len(&self) -> usize {
+        let continuations = self
+            .scrollback_wrapped
+            .iter()
+            .take(self.scrollback.len().saturating_sub(1))
+            .filter(|&&wrapped| wrapped)
+            .count();
+        self.scrollback.len() - continuations
+    }
+
+    /// Return `display_offset` in logical lines: the lines of scrollback
+    /// from the top of the viewport down, counting wrapped rows once.
+    pub fn logical_display_offset(&self) -> usize {
+        let len = self.scrollback.len();
+        let top = len - self.display_offset.min(len);
+        (top..len)
+            .filter(|&row| row == 0 || !self.scrollback_wrapped[row - 1])
+            .count()
+    }
+
+    /// Approximate memory held by scrollback history, in bytes.
+    pub fn scrollback_memory(&self) -> usize {
+        self.scrollback
+            .iter()
+            .map(ScrollbackRow::memory_bytes)
+            .sum()
+    }
+
+    /// Choose whether rows entering scrollback are stored compactly (the
+    /// default). Existing history is left as is.
+    pub fn set_compact_scrollback(&mut self, compact: bool) {
+        self.compact_scrollback = compact;
+    }
+
+    /// Working directory last reported by the shell (OSC 7), if any.
+    pub fn cwd(&self) -> Option<&str> {
+        self.cwd.as_deref()
+    }
+
+    /// Color to draw the cursor block in, or None to draw it by inversion.
+    pub fn cursor_color(&self) -> Option<[f32; 4]> {
+        self.cursor_color
+    }
+
+    /// Configure the cursor color. Programs can still change it with
+    /// OSC 12; OSC 112 goes back to this color. Setting the color already
+    /// configured keeps any OSC 12 override.
+    pub fn set_cursor_color(&mut self, color: Option<[f32; 4]>) {
+        if color == self.default_cursor_color {
+            return;
+        }
+        self.default_cursor_color = color;
+        self.cursor_color = color;
+        self.dirty = true;
+    }
+
+    /// Colors to draw the grid with.
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    /// Configure the colors to draw the grid with, redrawing when they
+    /// change.
+    pub fn set_theme(&mut self, theme: Theme) {
+        if theme != self.theme {
+            self.theme = theme;
             self.dirty = true;
         }
     }
 
+    /// Window title set by the running program (OSC 0/2), if any.
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Record the rendered cell size in pixels, used to answer pixel size
+    /// queries (`CSI 14 t`). Non-positive sizes mean unknown.
+    pub fn set_cell_size(&mut self, width: f32, height: f32) {
+        self.cell_size = (width > 0.0 && height > 0.0).then_some((width, height));
+    }
+
+    /// Whether a bell (BEL) was received since the last call, clearing it.
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell_pending)
+    }
+
+    /// Snap the viewport back to the bottom (live output). Frontends call this
+    /// before sending input, so a selection reaching into scrollback (made
+    /// while scrolled back) is cleared rather than left off screen.
+    pub fn scroll_to_bottom(&mut self) {
+        if self.display_offset == 0 {
+            return;
+        }
+        self.display_offset = 0;
+        let history = self.scrollback.len();
+        if self
+            .selection
+            .is_some_and(|selection| selection.bounds().0 .1 < history)
+        {
+            self.selection = None;
+        }
+        self.dirty = true;
+    }
+
     /// Begin a text selection at the given grid coordinates.
     pub fn selection_begin(&mut self, col: usize, row: usize) {
-        self.selection_start = Some((col, row));
-        self.selection_end = Some((col, row));
+        self.start_selection(col, row, false);
+    }
+
+    /// Begin a rectangular (block) selection at the given grid coordinates.
+    pub fn selection_begin_block(&mut self, col: usize, row: usize) {
+        self.start_selection(col, row, true);
+    }
+
+    fn start_selection(&mut self, col: usize, row: usize, block: bool) {
+        let point = (col, self.absolute_row(row));
+        self.selection = Some(Selection {
+            anchor: point,
+            end: point,
+            block,
+        });
+        self.dirty = true;
+    }
+
+    /// Select the word under (col, row), as on double-click. Words end at
+    /// whitespace and the configured separators; clicking a separator
+    /// selects just that cell.
+    pub fn select_word(&mut self, col: usize, row: usize) {
+        let (start, end) = {
+            let line = self.visible_row(row.min(self.rows - 1));
+            let col = col.min(line.len() - 1);
+            let is_separator = |cell: &Cell| {
+                !cell.spacer
+                    && (cell.c.is_whitespace() || self.word_separators.contains(cell.c))
+            };
+            if is_separator(&line[col]) {
+                (col, col)
+            } else {
+                let start = line[..col]
+                    .iter()
+                    .rposition(is_separator)
+                    .map_or(0, |idx| idx + 1);
+                let end = line[col..]
+                    .iter()
+                    .position(is_separator)
+                    .map_or(line.len(), |idx| col + idx)
+                    - 1;
+                (start, end)
+            }
+        };
+        self.selection_begin(start, row);
+        self.selection_update(end, row);
+    }
+
+    /// Search upwards for `query`, starting just before the current
+    /// selection (or at the bottom of the screen). A match is selected and
+    /// scrolled into view; matches do not span rows. Returns whether one was
+    /// found.
+    pub fn search_backward(&mut self, query: &str) -> bool {
+        let query: Vec<char> = query.chars().collect();
+        if query.is_empty() {
+            return false;
+        }
+        let last_line = self.scrollback.len() + self.rows - 1;
+        let (from_line, before_col) = match self.selection {
+            Some(selection) => {
+                let (start, _) = selection.bounds();
+                (start.1, start.0)
+            }
+            None => (last_line, usize::MAX),
+        };
+
+        for line in (0..=from_line).rev() {
+            let limit = if line == from_line {
+                before_col
+            } else {
+                usize::MAX
+            };
+            let found = {
+                // Spacers are skipped so double-width text matches too
+                let row = self.line(line);
+                let chars: Vec<(usize, char)> = row
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, cell)| !cell.spacer)
+                    .map(|(col, cell)| (col, cell.c))
+                    .collect();
+                chars
+                    .windows(query.len())
+                    .rev()
+                    .filter(|window| window[0].0 < limit)
+                    .find(|window| {
+                        window.iter().map(|&(_, c)| c).eq(query.iter().copied())
+                    })
+                    .map(|window| (window[0].0, window[query.len() - 1].0))
+            };
+            if let Some((start, end)) = found {
+                self.selection = Some(Selection {
+                    anchor: (start, line),
+                    end: (end, line),
+                    block: false,
+                });
+                self.scroll_line_into_view(line);
+                self.dirty = true;
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Move the viewport the least needed to show an absolute line.
+    fn scroll_line_into_view(&mut self, line: usize) {
+        let history = self.scrollback.len();
+        let top = self.absolute_row(0);
+        if line < top {
+            self.display_offset = history - line;
+        } else if line >= top + self.rows {
+            self.display_offset = (history + self.rows - 1).saturating_sub(line);
+        }
+        self.dirty = true;
+    }
+
+    /// Scroll back to the prompt above the top of the viewport, putting it on
+    /// the top row. Prompts are marked by shell integration (OSC 133).
+    /// Returns whether there was one.
+    pub fn previous_prompt(&mut self) -> bool {
+        let top = self.absolute_row(0);
+        let prompt = self.prompt_lines().rev().find(|&line| line < top);
+        prompt.is_some_and(|line| self.scroll_line_to_top(line))
+    }
+
+    /// Scroll forward to the prompt below the top of the viewport, putting
+    /// it on the top row or returning to live output when it is on the
+    /// screen. Returns whether there was one.
+    pub fn next_prompt(&mut self) -> bool {
+        let top = self.absolute_row(0);
+        let prompt = self.prompt_lines().find(|&line| line > top);
+        prompt.is_some_and(|line| self.scroll_line_to_top(line))
+    }
+
+    /// Viewport rows where a prompt marked by shell integration starts, top
+    /// first.
+    pub fn prompt_rows(&self) -> impl Iterator<Item = usize> + '_ {
+        let top = self.absolute_row(0);
+        self.prompt_lines()
+            .filter(move |&line| line >= top && line < top + self.rows)
+            .map(move |line| line - top)
+    }
+
+    /// Text printed by the last finished command, from where its output
+    /// started to where it finished, as marked by shell integration
+    /// (OSC 133). None without marks or once the output has left history.
+    pub fn last_command_output(&self) -> Option<String> {
+        let mut end = None;
+        for mark in self.marks.iter().rev() {
+            match (mark.kind, end) {
+                (CommandMark::Finished, None) => end = Some(mark),
+                (CommandMark::Output, Some(end)) => {
+                    let start = self.mark_position(mark)?;
+                    let mut end = self.mark_position(end)?;
+                    // Output usually ends with a line break, leaving the
+                    // finish mark at the start of the next line
+                    if end.0 == 0 && end.1 > start.1 {
+                        end = (usize::MAX, end.1 - 1);
+                    }
+                    return Some(lines_text(&self.cells_between(start, end, false)));
+                }
+                // A command that never ran, such as an empty line
+                (CommandMark::Prompt, Some(_)) => end = None,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Absolute lines of the marked prompts still in history, oldest first.
+    fn prompt_lines(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.marks
+            .iter()
+            .filter(|mark| mark.kind == CommandMark::Prompt)
+            .filter_map(|mark| self.mark_position(mark))
+            .map(|(_, line)| line)
+    }
+
+    /// (col, absolute line) of a mark, or None once its line is gone.
+    fn mark_position(&self, mark: &Mark) -> Option<(usize, usize)> {
+        let line = mark.line.checked_sub(self.lines_dropped)?;
+        (line < self.scrollback.len() + self.rows).then_some((mark.col, line))
+    }
+
+    /// Move the viewport so an absolute line is its top row, as far as
+    /// history allows. Returns whether the viewport moved.
+    fn scroll_line_to_top(&mut self, line: usize) -> bool {
+        let offset = self.scrollback.len().saturating_sub(line);
+        if offset == self.display_offset {
+            return false;
+        }
+        self.display_offset = offset;
         self.dirty = true;
+        true
+    }
+
+    /// Exit status the shell reported for the last finished command, if
+    /// any. None while a command runs.
+    pub fn last_exit_code(&self) -> Option<i32> {
+        self.last_exit_code
+    }
+
+    /// Record a shell integration mark (`OSC 133 ; kind ; args`) at the
+    /// cursor.
+    fn mark_command(&mut self, kind: &[u8], args: &[&[u8]]) {
+        let kind = match kind {
+            b"A" => CommandMark::Prompt,
+            b"B" => CommandMark::Command,
+            b"C" => {
+                self.last_exit_code = None;
+                CommandMark::Output
+            }
+            b"D" => {
+                self.last_exit_code = args
+                    .first()
+                    .and_then(|code| std::str::from_utf8(code).ok())
+                    .and_then(|code| code.parse().ok());
+                CommandMark::Finished
+            }
+            _ => return,
+        };
+        self.marks.push_back(Mark {
+            kind,
+            line: self.lines_dropped + self.scrollback.len() + self.cursor_row,
+            col: self.cursor_col,
+        });
+        if self.marks.len() > MAX_COMMAND_MARKS {
+            self.marks.pop_front();
+        }
+    }
+
+    /// Drop the marks on absolute `line` and below, whose content is gone.
+    fn forget_marks_from(&mut self, line: usize) {
+        let line = self.lines_dropped + line;
+        while self.marks.back().is_some_and(|mark| mark.line >= line) {
+            self.marks.pop_back();
+        }
+    }
+
+    /// Set the characters besides whitespace that end a word for
+    /// `select_word`.
+    pub fn set_word_separators(&mut self, separators: &str) {
+        self.word_separators = separators.to_string();
     }
 
     /// Update the end of the current selection.
     pub fn selection_update(&mut self, col: usize, row: usize) {
-        self.selection_end = Some((col, row));
+        let line = self.absolute_row(row);
+        if let Some(selection) = &mut self.selection {
+            selection.end = (col, line);
+        }
         self.dirty = true;
     }
 
     /// Clear the selection.
     pub fn selection_clear(&mut self) {
-        self.selection_start = None;
-        self.selection_end = None;
+        self.selection = None;
         self.dirty = true;
     }
 
+    /// Bounds of the current selection relative to the viewport, for drawing
+    /// handles or menus next to it.
+    pub fn selection_range(&self) -> Option<SelectionRange> {
+        let selection = self.selection?;
+        let (start, end) = selection.bounds();
+        let top = self.absolute_row(0) as isize;
+        Some(SelectionRange {
+            start: (start.0, start.1 as isize - top),
+            end: (end.0, end.1 as isize - top),
+            block: selection.block,
+        })
+    }
+
+    /// Describe the cell at viewport (col, row), following `display_offset`
+    /// into scrollback. The right half of a double-width character reports
+    /// the character. None outside the grid.
+    pub fn cell_info_at(&self, col: usize, row: usize) -> Option<CellInfo> {
+        if row >= self.rows {
+            return None;
+        }
+        let line = self.visible_row(row);
+        let mut col = col;
+        while line.get(col)?.spacer && col > 0 {
+            col -= 1;
+        }
+        let cell = &line[col];
+        let mut text = String::new();
+        cell.push_text(&mut text);
+        Some(CellInfo {
+            text,
+            fg: cell.fg,
+            bg: cell.bg,
+            bold: cell.bold,
+            italic: cell.italic,
+            underline: cell.underline != Underline::None,
+            inverse: cell.inverse,
+            hyperlink: cell.hyperlink.as_deref().map(str::to_string),
+            selected: self.is_selected(col, row),
+        })
+    }
+
     /// Return whether the cell at (col, row) is within the current selection.
     pub fn is_selected(&self, col: usize, row: usize) -> bool {
-        let (Some(start), Some(end)) = (self.selection_start, self.selection_end) else {
+        let Some(selection) = self.selection else {
             return false;
         };
-
-        // Normalize so start <= end
-        let (start, end) = if start.1 < end.1 || (start.1 == end.1 && start.0 <= end.0) {
-            (start, end)
-        } else {
-            (end, start)
-        };
+        let (start, end) = selection.bounds();
+        let row = self.absolute_row(row);
 
         if row < start.1 || row > end.1 {
             return false;
         }
-        if start.1 == end.1 {
-            // Single-line selection
+        if selection.block || start.1 == end.1 {
+            // Single-line or rectangular selection
             col >= start.0 && col <= end.0
         } else if row == start.1 {
             col >= start.0
@@ -239,59 +1017,129 @@ impl TerminalGrid {
 
     /// Extract the selected text as a string.
     pub fn selected_text(&self) -> String {
-        let (Some(start), Some(end)) = (self.selection_start, self.selection_end) else {
-            return String::new();
-        };
+        lines_text(&self.selected_cells())
+    }
 
-        let (start, end) = if start.1 < end.1 || (start.1 == end.1 && start.0 <= end.0) {
-            (start, end)
-        } else {
-            (end, start)
+    /// Extract the selection as text with SGR escapes reconstructed from
+    /// each cell's attributes, for copying with formatting.
+    pub fn selected_ansi(&self) -> String {
+        let rows = self.selected_cells();
+        ansi::encode_lines(rows.iter().map(|row| &row[..]))
+    }
+
+    /// Serialize the rows currently on screen as text with SGR escapes.
+    pub fn visible_ansi(&self) -> String {
+        let rows: Vec<_> = self.rows_visible().collect();
+        ansi::encode_lines(rows.iter().map(|row| &row[..]))
+    }
+
+    /// Cells covered by the selection, one entry per selected line.
+    fn selected_cells(&self) -> Vec<Vec<Cell>> {
+        let Some(selection) = self.selection else {
+            return Vec::new();
         };
+        let (start, end) = selection.bounds();
+        self.cells_between(start, (end.0 + 1, end.1), selection.block)
+    }
+
+    /// Cells from (col, line) `start` up to but not including `end`, one
+    /// entry per line. A `block` takes the same columns from every line.
+    fn cells_between(
+        &self,
+        start: (usize, usize),
+        end: (usize, usize),
+        block: bool,
+    ) -> Vec<Vec<Cell>> {
+        let last_line = self.scrollback.len() + self.rows - 1;
 
-        let mut result = String::new();
-        for row_idx in start.1..=end.1 {
-            let row = self.visible_row(row_idx);
-            let col_start = if row_idx == start.1 { start.0 } else { 0 };
-            let col_end = if row_idx == end.1 {
-                end.0 + 1
+        let mut result = Vec::new();
+        for line_idx in start.1..=end.1.min(last_line) {
+            let row = self.line(line_idx);
+            let col_start = if block || line_idx == start.1 {
+                start.0
+            } else {
+                0
+            };
+            let col_end = if block || line_idx == end.1 {
+                end.0
             } else {
                 row.len()
             };
             let col_end = col_end.min(row.len());
-
-            let line: String = row[col_start..col_end]
-                .iter()
-                .map(|c| c.c)
-                .collect::<String>()
-                .trim_end()
-                .to_string();
-            result.push_str(&line);
-            if row_idx < end.1 {
-                result.push('\n');
-            }
+            let col_start = col_start.min(col_end);
+            result.push(row[col_start..col_end].to_vec());
         }
         result
     }
 
+    /// Move the cursor down a line, scrolling the region at its bottom.
+    /// Below the region the cursor stops at the last row without scrolling.
+    fn line_feed(&mut self) {
+        if self.cursor_row == self.scroll_bottom {
+            self.scroll_up();
+        } else if self.cursor_row + 1 < self.rows {
+            self.cursor_row += 1;
+        }
+    }
+
+    /// Move the cursor to absolute `row` (0-based), counted from the top of
+    /// the scroll region in origin mode.
+    fn set_cursor_row(&mut self, row: usize) {
+        self.cursor_row = if self.origin_mode {
+            (self.scroll_top + row).min(self.scroll_bottom)
+        } else {
+            row.min(self.rows - 1)
+        };
+    }
+
     fn scroll_up(&mut self) {
-        let removed = self.cells.remove(self.scroll_top);
+        // Removing the top row and inserting at the bottom touch only the
+        // ends of the ring buffer when the region is the whole screen
+        let mut removed = self.cells.remove(self.scroll_top).unwrap_or_default();
+        let wrapped = self.wrapped.remove(self.scroll_top).unwrap_or_default();
         // Only save to scrollback when the whole screen scrolls (region == full screen)
-        if self.scroll_top == 0 {
-            self.scrollback.push(removed);
+        let blank = if self.scroll_top == 0 && self.scroll_bottom == self.rows - 1 {
+            self.scrollback
+                .push_back(ScrollbackRow::new(removed, self.compact_scrollback));
+            self.scrollback_wrapped.push_back(wrapped);
             if self.scrollback.len() > MAX_SCROLLBACK {
-                self.scrollback.remove(0);
+                self.scrollback.pop_front();
+                self.scrollback_wrapped.pop_front();
+                self.lines_dropped += 1;
+                while self
+                    .marks
+                    .front()
+                    .is_some_and(|mark| mark.line < self.lines_dropped)
+                {
+                    self.marks.pop_front();
+                }
+                // Keep the selection on the same content
+                if let Some(selection) = &mut self.selection {
+                    selection.anchor.1 = selection.anchor.1.saturating_sub(1);
+                    selection.end.1 = selection.end.1.saturating_sub(1);
+                }
             }
-        }
-        self.cells
-            .insert(self.scroll_bottom, vec![Cell::default(); self.cols]);
+            vec![Cell::default(); self.cols]
+        } else {
+            // Recycle the discarded row
+            removed.fill(Cell::default());
+            removed
+        };
+        self.cells.insert(self.scroll_bottom, blank);
+        self.wrapped.insert(self.scroll_bottom, false);
         self.dirty = true;
     }
 
+    /// Shift the scroll region down one line, discarding its bottom line.
+    /// Only rows inside the region move, and scrollback is never touched:
+    /// history holds lines that left the top of the screen, so there is no
+    /// sensible place to put a line pushed off the bottom.
     fn scroll_down(&mut self) {
-        self.cells.remove(self.scroll_bottom);
-        self.cells
-            .insert(self.scroll_top, vec![Cell::default(); self.cols]);
+        let mut removed = self.cells.remove(self.scroll_bottom).unwrap_or_default();
+        removed.fill(Cell::default());
+        self.cells.insert(self.scroll_top, removed);
+        self.wrapped.remove(self.scroll_bottom);
+        self.wrapped.insert(self.scroll_top, false);
         self.dirty = true;
     }
 
@@ -303,49 +1151,166 @@ impl TerminalGrid {
             bold: self.cur_bold,
             italic: self.cur_italic,
             underline: self.cur_underline,
+            strikethrough: self.cur_strikethrough,
+            overline: self.cur_overline,
             inverse: self.cur_inverse,
+            combining: None,
+            spacer: false,
+            hyperlink: self.cur_hyperlink.clone(),
+            font: FontCache::default(),
         }
     }
 
-    fn clear_row(&mut self, row: usize) {
-        if row < self.rows {
-            self.cells[row] = vec![Cell::default(); self.cols];
+    /// Add `c` to the grapheme cluster in the cell before the cursor when
+    /// it continues that cluster, as a combining mark, emoji modifier or
+    /// ZWJ sequence does. Returns whether it was joined.
+    fn join_previous(&mut self, c: char) -> bool {
+        // ASCII never continues a cluster (CR LF is not printed)
+        if c.is_ascii() || self.cursor_col == 0 || self.cursor_row >= self.rows {
+            return false;
+        }
+        let row = &mut self.cells[self.cursor_row];
+        let mut col = self.cursor_col.min(row.len()) - 1;
+        if row[col].spacer && col > 0 {
+            col -= 1;
         }
+        let cell = &mut row[col];
+        let mut cluster = String::new();
+        cell.push_text(&mut cluster);
+        cluster.push(c);
+        if cluster.graphemes(true).nth(1).is_some() {
+            return false;
+        }
+        cell.combining = Some(cluster[cell.c.len_utf8()..].into());
+        true
     }
 
-    fn erase_in_display(&mut self, mode: u16) {
-        match mode {
-            // Clear from cursor to end of screen
-            0 => {
-                // Clear rest of current row
-                for col in self.cursor_col..self.cols {
-                    self.cells[self.cursor_row][col] = Cell::default();
-                }
-                // Clear rows below
-                for row in (self.cursor_row + 1)..self.rows {
-                    self.clear_row(row);
-                }
+    /// Move the cursor `n` characters left, stepping over the right half of
+    /// double-width characters so it lands where a glyph starts.
+    fn cursor_back(&mut self, n: usize) {
+        for _ in 0..n {
+            if self.cursor_col == 0 {
+                break;
             }
-            // Clear from beginning to cursor
-            1 => {
-                for row in 0..self.cursor_row {
-                    self.clear_row(row);
-                }
-                for col in 0..=self.cursor_col.min(self.cols - 1) {
+            self.cursor_col = self.cursor_col.min(self.cols) - 1;
+            let row = self.cursor_row.min(self.rows - 1);
+            if self.cells[row][self.cursor_col].spacer && self.cursor_col > 0 {
+                self.cursor_col -= 1;
+            }
+        }
+    }
+
+    /// Blank the other half of any double-width character that writing
+    /// `width` cells at (row, col) partly overwrites.
+    fn split_wide(&mut self, row: usize, col: usize, width: usize) {
+        let line = &mut self.cells[row];
+        if line[col].spacer && col > 0 {
+            line[col - 1].blank();
+        }
+        if let Some(next) = line.get_mut(col + width) {
+            if next.spacer {
+                next.blank();
+            }
+        }
+    }
+
+    /// Blank any double-width character in `row` left without its spacer,
+    /// and any spacer left without its character, after cells were shifted
+    /// by inserting or deleting characters.
+    fn repair_wide(&mut self, row: usize) {
+        if self.cols < 2 {
+            return;
+        }
+        let line = &mut self.cells[row];
+        for col in 0..line.len() {
+            let paired = if line[col].spacer {
+                col > 0 && !line[col - 1].spacer && line[col - 1].c.width() == Some(2)
+            } else if line[col].c.width() == Some(2) {
+                line.get(col + 1).is_some_and(|next| next.spacer)
+            } else {
+                true
+            };
+            if !paired {
+                line[col].blank();
+            }
+        }
+    }
+
+    /// Every live row is exactly `cols` cells wide; rows of any other width
+    /// would be indexed out of bounds or render misaligned.
+    fn debug_assert_row_widths(&self) {
+        debug_assert_eq!(self.cells.len(), self.rows);
+        debug_assert!(
+            self.cells.iter().all(|row| row.len() == self.cols),
+            "row width differs from cols ({})",
+            self.cols
+        );
+    }
+
+    fn clear_row(&mut self, row: usize) {
+        if row < self.rows {
+            self.cells[row] = vec![Cell::default(); self.cols];
+            self.wrapped[row] = false;
+        }
+    }
+
+    fn erase_in_display(&mut self, mode: u16) {
+        match mode {
+            // Clear from cursor to end of screen
+            0 => {
+                // Clear rest of current row
+                for col in self.cursor_col..self.cols {
                     self.cells[self.cursor_row][col] = Cell::default();
                 }
+                // Clear rows below
+                for row in (self.cursor_row + 1)..self.rows {
+                    self.clear_row(row);
+                }
             }
-            // Clear entire screen
-            2 | 3 => {
+            // Clear from beginning to cursor
+            1 => {
+                for row in 0..self.cursor_row {
+                    self.clear_row(row);
+                }
+                for col in 0..=self.cursor_col.min(self.cols - 1) {
+                    self.cells[self.cursor_row][col] = Cell::default();
+                }
+            }
+            // Clear entire screen, keeping history
+            2 => {
                 for row in 0..self.rows {
                     self.clear_row(row);
                 }
+                self.forget_marks_from(self.scrollback.len());
+            }
+            // Clear entire screen and the scrollback
+            3 => {
+                for row in 0..self.rows {
+                    self.clear_row(row);
+                }
+                self.lines_dropped += self.scrollback.len();
+                self.scrollback.clear();
+                self.scrollback_wrapped.clear();
+                self.marks.clear();
+                self.display_offset = 0;
+                // Selection lines are counted from the start of history
+                self.selection = None;
             }
             _ => {}
         }
         self.dirty = true;
     }
 
+    /// Blank the screen and home the cursor, as the `clear` command does.
+    /// With `scrollback` the history is dropped too; otherwise it is kept,
+    /// like `ED 2`.
+    pub fn clear(&mut self, scrollback: bool) {
+        self.erase_in_display(if scrollback { 3 } else { 2 });
+        self.cursor_row = 0;
+        self.cursor_col = 0;
+        self.selection = None;
+    }
+
     fn erase_in_line(&mut self, mode: u16) {
         match mode {
             // Clear from cursor to end of line
@@ -408,49 +1373,64 @@ pub fn ansi_color(idx: u16) -> [f32; 4] {
 
 impl Perform for TerminalGrid {
     fn print(&mut self, c: char) {
-        if self.cursor_col >= self.cols {
-            self.cursor_col = 0;
-            self.cursor_row += 1;
-            if self.cursor_row > self.scroll_bottom {
-                self.cursor_row = self.scroll_bottom;
-                self.scroll_up();
+        let c = self.charsets[self.active_charset].map(c);
+        if self.join_previous(c) {
+            self.dirty = true;
+            return;
+        }
+        let width = match c.width() {
+            // Zero-width characters with nothing to join are dropped
+            Some(0) | None => return,
+            Some(2) if self.cols > 1 => 2,
+            Some(_) => 1,
+        };
+
+        // A double-width character that does not fit wraps whole
+        if self.cursor_col + width > self.cols {
+            if self.cursor_row < self.rows {
+                self.wrapped[self.cursor_row] = true;
             }
+            self.cursor_col = 0;
+            self.line_feed();
         }
 
         if self.cursor_row < self.rows && self.cursor_col < self.cols {
-            self.cells[self.cursor_row][self.cursor_col] = self.new_cell(c);
-            self.cursor_col += 1;
+            let (row, col) = (self.cursor_row, self.cursor_col);
+            self.split_wide(row, col, width);
+            self.cells[row][col] = self.new_cell(c);
+            if width == 2 {
+                self.cells[row][col + 1] = Cell {
+                    spacer: true,
+                    ..self.new_cell(' ')
+                };
+            }
+            self.cursor_col += width;
         }
         self.dirty = true;
+        self.debug_assert_row_widths();
     }
 
     fn execute(&mut self, byte: u8) {
         match byte {
             // Bell
-            0x07 => {}
+            0x07 => self.bell_pending = true,
             // Backspace
-            0x08 => {
-                if self.cursor_col > 0 {
-                    self.cursor_col -= 1;
-                }
-            }
+            0x08 => self.cursor_back(1),
             // Tab
             0x09 => {
-                let next_tab = (self.cursor_col / 8 + 1) * 8;
-                self.cursor_col = next_tab.min(self.cols - 1);
+                self.cursor_col = (self.cursor_col + 1..self.cols)
+                    .find(|&col| self.tab_stops[col])
+                    .unwrap_or(self.cols - 1);
             }
             // Line feed / Vertical tab / Form feed
-            0x0A..=0x0C => {
-                self.cursor_row += 1;
-                if self.cursor_row > self.scroll_bottom {
-                    self.cursor_row = self.scroll_bottom;
-                    self.scroll_up();
-                }
-            }
+            0x0A..=0x0C => self.line_feed(),
             // Carriage return
             0x0D => {
                 self.cursor_col = 0;
             }
+            // Shift Out / Shift In: switch to G1 / G0
+            0x0E => self.active_charset = 1,
+            0x0F => self.active_charset = 0,
             _ => {}
         }
         self.dirty = true;
@@ -480,15 +1460,15 @@ impl Perform for TerminalGrid {
                 let n = if first == 0 { 1 } else { first as usize };
                 self.cursor_row = (self.cursor_row + n).min(self.rows - 1);
             }
-            // Cursor Forward
-            'C' => {
+            // Cursor Forward (also HPR)
+            'C' | 'a' => {
                 let n = if first == 0 { 1 } else { first as usize };
                 self.cursor_col = (self.cursor_col + n).min(self.cols - 1);
             }
             // Cursor Back
             'D' => {
                 let n = if first == 0 { 1 } else { first as usize };
-                self.cursor_col = self.cursor_col.saturating_sub(n);
+                self.cursor_back(n.min(self.cols));
             }
             // Cursor Next Line
             'E' => {
@@ -502,8 +1482,8 @@ impl Perform for TerminalGrid {
                 self.cursor_row = self.cursor_row.saturating_sub(n);
                 self.cursor_col = 0;
             }
-            // Cursor Horizontal Absolute
-            'G' => {
+            // Cursor Horizontal Absolute (also HPA)
+            'G' | '`' => {
                 let col = if first == 0 { 1 } else { first as usize };
                 self.cursor_col = (col - 1).min(self.cols - 1);
             }
@@ -515,9 +1495,19 @@ impl Perform for TerminalGrid {
                     .and_then(|p| p.first().copied())
                     .unwrap_or(1) as usize;
                 let col = if col == 0 { 1 } else { col };
-                self.cursor_row = (row - 1).min(self.rows - 1);
+                self.set_cursor_row(row - 1);
                 self.cursor_col = (col - 1).min(self.cols - 1);
             }
+            // Line Position Absolute
+            'd' => {
+                let row = if first == 0 { 1 } else { first as usize };
+                self.set_cursor_row(row - 1);
+            }
+            // Line Position Relative
+            'e' => {
+                let n = if first == 0 { 1 } else { first as usize };
+                self.cursor_row = (self.cursor_row + n).min(self.rows - 1);
+            }
             // Erase in Display
             'J' => {
                 self.erase_in_display(first);
@@ -529,37 +1519,38 @@ impl Perform for TerminalGrid {
             // Insert Lines
             'L' => {
                 let n = if first == 0 { 1 } else { first as usize };
+                let n = n.min((self.scroll_bottom + 1).saturating_sub(self.cursor_row));
                 for _ in 0..n {
-                    if self.cursor_row <= self.scroll_bottom {
-                        self.cells.remove(self.scroll_bottom);
-                        self.cells
-                            .insert(self.cursor_row, vec![Cell::default(); self.cols]);
-                    }
+                    self.cells.remove(self.scroll_bottom);
+                    self.cells
+                        .insert(self.cursor_row, vec![Cell::default(); self.cols]);
+                    self.wrapped.remove(self.scroll_bottom);
+                    self.wrapped.insert(self.cursor_row, false);
                 }
                 self.dirty = true;
             }
             // Delete Lines
             'M' => {
                 let n = if first == 0 { 1 } else { first as usize };
+                let n = n.min((self.scroll_bottom + 1).saturating_sub(self.cursor_row));
                 for _ in 0..n {
-                    if self.cursor_row <= self.scroll_bottom {
-                        self.cells.remove(self.cursor_row);
-                        self.cells
-                            .insert(self.scroll_bottom, vec![Cell::default(); self.cols]);
-                    }
+                    self.cells.remove(self.cursor_row);
+                    self.cells
+                        .insert(self.scroll_bottom, vec![Cell::default(); self.cols]);
+                    self.wrapped.remove(self.cursor_row);
+                    self.wrapped.insert(self.scroll_bottom, false);
                 }
                 self.dirty = true;
             }
             // Delete Characters
             'P' => {
                 let n = if first == 0 { 1 } else { first as usize };
-                let row = &mut self.cells[self.cursor_row];
-                for _ in 0..n.min(self.cols - self.cursor_col) {
-                    if self.cursor_col < row.len() {
-                        row.remove(self.cursor_col);
-                        row.push(Cell::default());
-                    }
-                }
+                let col = self.cursor_col.min(self.cols);
+                let n = n.min(self.cols - col);
+                let line = &mut self.cells[self.cursor_row];
+                line.drain(col..col + n);
+                line.resize(self.cols, Cell::default());
+                self.repair_wide(self.cursor_row);
                 self.dirty = true;
             }
             // Scroll Up
@@ -576,16 +1567,145 @@ impl Perform for TerminalGrid {
                     self.scroll_down();
                 }
             }
+            // Cursor Backward Tabulation
+            'Z' => {
+                let n = if first == 0 { 1 } else { first as usize };
+                for _ in 0..n {
+                    self.cursor_col = (0..self.cursor_col)
+                        .rev()
+                        .find(|&col| self.tab_stops[col])
+                        .unwrap_or(0);
+                }
+            }
             // Insert Characters
             '@' => {
                 let n = if first == 0 { 1 } else { first as usize };
-                let row = &mut self.cells[self.cursor_row];
-                for _ in 0..n.min(self.cols - self.cursor_col) {
-                    row.insert(self.cursor_col, Cell::default());
-                    row.truncate(self.cols);
-                }
+                let col = self.cursor_col.min(self.cols);
+                let n = n.min(self.cols - col);
+                let line = &mut self.cells[self.cursor_row];
+                line.truncate(self.cols - n);
+                line.splice(col..col, std::iter::repeat_n(Cell::default(), n));
+                self.repair_wide(self.cursor_row);
                 self.dirty = true;
             }
+            // xterm modifyOtherKeys: `CSI > 4 ; N m` (N omitted resets)
+            'm' if intermediates == [b'>'] => {
+                if first == 4 {
+                    self.modify_other_keys = param_iter
+                        .next()
+                        .and_then(|p| p.first().copied())
+                        .unwrap_or(0)
+                        .min(2) as u8;
+                }
+            }
+            // Other private `m` sequences (e.g. `?` modifier queries) are not
+            // SGR and must not touch attributes
+            'm' if !intermediates.is_empty() => {}
+            // Kitty keyboard protocol: push, pop, set and query flags
+            'u' if intermediates == [b'>'] => {
+                if self.kitty_keyboard_stack.len() == KITTY_KEYBOARD_STACK_LIMIT {
+                    self.kitty_keyboard_stack.remove(0);
+                }
+                self.kitty_keyboard_stack.push(self.kitty_keyboard_flags);
+                self.kitty_keyboard_flags = first;
+            }
+            // Popping more entries than were pushed empties the stack and
+            // clears all flags
+            'u' if intermediates == [b'<'] => {
+                let n = usize::from(first.max(1));
+                let depth = self.kitty_keyboard_stack.len();
+                self.kitty_keyboard_flags = if n > depth {
+                    0
+                } else {
+                    self.kitty_keyboard_stack[depth - n]
+                };
+                self.kitty_keyboard_stack.truncate(depth.saturating_sub(n));
+            }
+            'u' if intermediates == [b'='] => {
+                let mode = param_iter
+                    .next()
+                    .and_then(|p| p.first().copied())
+                    .unwrap_or(1);
+                match mode {
+                    1 => self.kitty_keyboard_flags = first,
+                    2 => self.kitty_keyboard_flags |= first,
+                    3 => self.kitty_keyboard_flags &= !first,
+                    _ => {}
+                }
+            }
+            'u' if intermediates == [b'?'] => {
+                let reply = format!("\x1b[?{}u", self.kitty_keyboard_flags);
+                self.pending_writes.extend_from_slice(reply.as_bytes());
+            }
+            // Window manipulation (XTWINOPS): answer size queries only, since
+            // moving or resizing the window makes no sense here
+            't' if intermediates.is_empty() => match first {
+                // Text area size in characters
+                18 => {
+                    let reply = format!("\x1b[8;{};{}t", self.rows, self.cols);
+                    self.pending_writes.extend_from_slice(reply.as_bytes());
+                }
+                // Save and restore the title. The second parameter selects
+                // icon (1), window (2) or both (0); only the window title
+                // is tracked.
+                22 | 23 => {
+                    let which = param_iter
+                        .next()
+                        .and_then(|p| p.first().copied())
+                        .unwrap_or(0);
+                    if which == 1 {
+                        return;
+                    }
+                    if first == 22 {
+                        if self.title_stack.len() == TITLE_STACK_LIMIT {
+                            self.title_stack.remove(0);
+                        }
+                        self.title_stack.push(self.title.clone());
+                    } else if let Some(title) = self.title_stack.pop() {
+                        if title != self.title {
+                            self.title = title;
+                            self.dirty = true;
+                        }
+                    }
+                }
+                // Text area size in pixels, when the cell size is known
+                14 => {
+                    if let Some((width, height)) = self.cell_size {
+                        let reply = format!(
+                            "\x1b[4;{};{}t",
+                            (height * self.rows as f32).round() as u32,
+                            (width * self.cols as f32).round() as u32
+                        );
+                        self.pending_writes.extend_from_slice(reply.as_bytes());
+                    }
+                }
+                // Cell size in pixels, for applications that scale images
+                // to the text
+                16 => {
+                    if let Some((width, height)) = self.cell_size {
+                        let reply = format!(
+                            "\x1b[6;{};{}t",
+                            height.round() as u32,
+                            width.round() as u32
+                        );
+                        self.pending_writes.extend_from_slice(reply.as_bytes());
+                    }
+                }
+                _ => {}
+            },
+            // DEC-specific status reports (DECDSR); other queries go
+            // unanswered
+            'n' if intermediates == [b'?'] => match first {
+                // Cursor position with the page, always the first (DECXCPR)
+                6 => {
+                    let col = self.cursor_col.min(self.cols - 1) + 1;
+                    let reply = format!("\x1b[?{};{};1R", self.cursor_row + 1, col);
+                    self.pending_writes.extend_from_slice(reply.as_bytes());
+                }
+                // Printer status: not ready, as there is no printer
+                15 => self.pending_writes.extend_from_slice(b"\x1b[?11n"),
+                _ => {}
+            },
             // SGR - Select Graphic Rendition
             'm' => {
                 self.handle_sgr(params);
@@ -598,9 +1718,15 @@ impl Perform for TerminalGrid {
                     .and_then(|p| p.first().copied())
                     .map(|b| if b == 0 { self.rows } else { b as usize })
                     .unwrap_or(self.rows);
-                self.scroll_top = (top - 1).min(self.rows - 1);
-                self.scroll_bottom = (bottom - 1).min(self.rows - 1);
-                self.cursor_row = 0;
+                let top = (top - 1).min(self.rows - 1);
+                let bottom = (bottom - 1).min(self.rows - 1);
+                // DECSTBM requires at least two lines; ignore anything else
+                if top >= bottom {
+                    return;
+                }
+                self.scroll_top = top;
+                self.scroll_bottom = bottom;
+                self.set_cursor_row(0);
                 self.cursor_col = 0;
             }
             // DECSET (private mode set)
@@ -625,6 +1751,15 @@ impl Perform for TerminalGrid {
                         1006 => {
                             self.mouse_sgr = true;
                         }
+                        1016 => self.mouse_pixels = true,
+                        2004 => self.bracketed_paste = true,
+                        2026 => self.synchronized_output = true,
+                        47 | 1047 | 1049 => self.alternate_screen = true,
+                        6 => {
+                            self.origin_mode = true;
+                            self.set_cursor_row(0);
+                            self.cursor_col = 0;
+                        }
                         _ => {}
                     }
                 }
@@ -637,6 +1772,18 @@ impl Perform for TerminalGrid {
                         1002 => self.mouse_drag = false,
                         1003 => self.mouse_motion = false,
                         1006 => self.mouse_sgr = false,
+                        1016 => self.mouse_pixels = false,
+                        2004 => self.bracketed_paste = false,
+                        47 | 1047 | 1049 => self.alternate_screen = false,
+                        2026 => {
+                            self.synchronized_output = false;
+                            self.dirty = true;
+                        }
+                        6 => {
+                            self.origin_mode = false;
+                            self.set_cursor_row(0);
+                            self.cursor_col = 0;
+                        }
                         _ => {}
                     }
                 }
@@ -645,10 +1792,18 @@ impl Perform for TerminalGrid {
             'h' | 'l' => {}
             _ => {}
         }
+        self.debug_assert_row_widths();
     }
 
     fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
         match (byte, intermediates) {
+            // Designate G0 / G1 character set (SCS)
+            (_, [b'(']) | (_, [b')']) => {
+                if let Some(charset) = Charset::from_designator(byte) {
+                    let slot = usize::from(intermediates == [b')']);
+                    self.charsets[slot] = charset;
+                }
+            }
             // Save cursor
             (b'7', _) | (b's', _) => {
                 self.saved_cursor_row = self.cursor_row;
@@ -659,6 +1814,19 @@ impl Perform for TerminalGrid {
                 self.cursor_row = self.saved_cursor_row;
                 self.cursor_col = self.saved_cursor_col;
             }
+            // Index (line feed)
+            (b'D', []) => self.line_feed(),
+            // Next Line
+            (b'E', []) => {
+                self.cursor_col = 0;
+                self.line_feed();
+            }
+            // Horizontal Tab Set
+            (b'H', []) => {
+                if let Some(stop) = self.tab_stops.get_mut(self.cursor_col) {
+                    *stop = true;
+                }
+            }
             // Reverse Index (scroll down if at top)
             (b'M', _) => {
                 if self.cursor_row == self.scroll_top {
@@ -672,17 +1840,136 @@ impl Perform for TerminalGrid {
         }
     }
 
-    fn osc_dispatch(&mut self, _params: &[&[u8]], _bell_terminated: bool) {
-        // OSC sequences (title, colors, etc.) — not needed for basic terminal
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        // Only the window title, working directory, hyperlinks, shell
+        // integration marks and cursor color are tracked; other colors are
+        // not needed for a basic terminal. The parser splits on `;`, which
+        // may legitimately occur in a title or path.
+        match params {
+            [b"0" | b"2", title @ ..] => {
+                let title = String::from_utf8_lossy(&title.join(&b';')).into_owned();
+                self.title = Some(title);
+            }
+            [b"7", url @ ..] => {
+                let url = url.join(&b';');
+                if let Some(path) =
+                    std::str::from_utf8(&url).ok().and_then(parse_file_url)
+                {
+                    self.cwd = Some(path);
+                }
+            }
+            // Hyperlink: `OSC 8 ; params ; uri`, closed by an empty uri
+            [b"8", _params, uri @ ..] => {
+                let uri = uri.join(&b';');
+                self.cur_hyperlink = match std::str::from_utf8(&uri) {
+                    Ok(uri) if !uri.is_empty() && uri.len() <= MAX_HYPERLINK_LEN => {
+                        Some(Arc::from(uri))
+                    }
+                    _ => None,
+                };
+            }
+            // Shell integration: prompt, command and output boundaries
+            [b"133", kind, args @ ..] => self.mark_command(kind, args),
+            [b"12", spec] => {
+                if let Some(color) = std::str::from_utf8(spec).ok().and_then(parse_color)
+                {
+                    self.cursor_color = Some(color);
+                    self.dirty = true;
+                }
+            }
+            [b"112", ..] => {
+                self.cursor_color = self.default_cursor_color;
+                self.dirty = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Text of rows of cells, one line each with trailing blanks trimmed.
+fn lines_text(rows: &[Vec<Cell>]) -> String {
+    rows.iter()
+        .map(|row| {
+            let mut line = String::new();
+            for cell in row {
+                cell.push_text(&mut line);
+            }
+            line.trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Whether `col` has a tab stop by default: every 8 columns
+fn default_tab_stop(col: usize) -> bool {
+    col > 0 && col.is_multiple_of(8)
+}
+
+/// Parse an X11 color spec as used by OSC color sequences: `#rgb`,
+/// `#rrggbb` or `rgb:r/g/b` with one to four hex digits per channel.
+pub fn parse_color(spec: &str) -> Option<[f32; 4]> {
+    let channel = |hex: &str| {
+        if hex.is_empty() || hex.len() > 4 {
+            return None;
+        }
+        let max = (1u32 << (4 * hex.len())) - 1;
+        u32::from_str_radix(hex, 16)
+            .ok()
+            .map(|v| v as f32 / max as f32)
+    };
+    let channels: Vec<&str> = if let Some(hex) = spec.strip_prefix('#') {
+        if !hex.is_ascii() || !matches!(hex.len(), 3 | 6) {
+            return None;
+        }
+        let width = hex.len() / 3;
+        (0..3).map(|i| &hex[i * width..(i + 1) * width]).collect()
+    } else {
+        spec.strip_prefix("rgb:")?.split('/').collect()
+    };
+    match channels[..] {
+        [r, g, b] => Some([channel(r)?, channel(g)?, channel(b)?, 1.0]),
+        _ => None,
     }
 }
 
+/// Extract the decoded path from a `file://host/path` URL.
+fn parse_file_url(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("file://")?;
+    // The host part ends at the path's leading slash
+    let path = &rest[rest.find('/')?..];
+    percent_decode(path)
+}
+
+/// Decode `%XX` escapes. Returns `None` for malformed escapes or when the
+/// result is not UTF-8.
+fn percent_decode(input: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut iter = input.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hi = (iter.next()? as char).to_digit(16)?;
+            let lo = (iter.next()? as char).to_digit(16)?;
+            bytes.push((hi * 16 + lo) as u8);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
 impl TerminalGrid {
     fn handle_sgr(&mut self, params: &Params) {
-        let params_vec: Vec<u16> = params
-            .iter()
-            .flat_map(|subparams| subparams.iter().copied())
-            .collect();
+        let mut params_vec: Vec<u16> = Vec::new();
+        // Underline styles given as `4:n`, by the index of their 4
+        let mut underline_styles = Vec::new();
+        for subparams in params.iter() {
+            if let [4, style] = subparams {
+                underline_styles.push((params_vec.len(), *style));
+                params_vec.push(4);
+            } else {
+                params_vec.extend_from_slice(subparams);
+            }
+        }
 
         if params_vec.is_empty() {
             self.reset_attributes();
@@ -695,12 +1982,22 @@ impl TerminalGrid {
                 0 => self.reset_attributes(),
                 1 => self.cur_bold = true,
                 3 => self.cur_italic = true,
-                4 => self.cur_underline = true,
+                4 => {
+                    self.cur_underline = underline_styles
+                        .iter()
+                        .find(|(at, _)| *at == i)
+                        .map_or(Underline::Single, |&(_, style)| {
+                            Underline::from_sgr(style)
+                        });
+                }
                 7 => self.cur_inverse = true,
+                9 => self.cur_strikethrough = true,
+                21 => self.cur_underline = Underline::Double,
                 22 => self.cur_bold = false,
                 23 => self.cur_italic = false,
-                24 => self.cur_underline = false,
+                24 => self.cur_underline = Underline::None,
                 27 => self.cur_inverse = false,
+                29 => self.cur_strikethrough = false,
                 // Foreground colors
                 30..=37 => self.cur_fg = ansi_color(params_vec[i] - 30),
                 38 => {
@@ -743,6 +2040,8 @@ impl TerminalGrid {
                     }
                 }
                 49 => self.cur_bg = None, // Default bg
+                53 => self.cur_overline = true,
+                55 => self.cur_overline = false,
                 // Bright foreground
                 90..=97 => self.cur_fg = ansi_color(params_vec[i] - 90 + 8),
                 // Bright background
@@ -758,14 +2057,18 @@ impl TerminalGrid {
         self.cur_bg = None;
         self.cur_bold = false;
         self.cur_italic = false;
-        self.cur_underline = false;
+        self.cur_underline = Underline::None;
+        self.cur_strikethrough = false;
+        self.cur_overline = false;
         self.cur_inverse = false;
     }
 
     /// Generate a mouse report and push it to pending_writes.
     ///
     /// Uses SGR encoding when mode 1006 is active, otherwise falls back
-    /// to legacy X10 encoding.
+    /// to legacy X10 encoding. With SGR-Pixels (mode 1016) the top-left
+    /// pixel of the cell is reported; see `mouse_report_pixels` for exact
+    /// positions.
     pub fn mouse_report(
         &mut self,
         button: u8,
@@ -780,19 +2083,1033 @@ impl TerminalGrid {
 
         let col = col.min(self.cols.saturating_sub(1));
         let row = row.min(self.rows.saturating_sub(1));
-        let cb = button | modifiers;
+        let (x, y) = match self.pixel_reporting() {
+            Some((width, height)) => (
+                (col as f32 * width) as usize,
+                (row as f32 * height) as usize,
+            ),
+            None => (col, row),
+        };
+        self.encode_mouse(button | modifiers, x, y, pressed);
+    }
 
+    /// Generate a mouse report for pixel position (x, y) relative to the
+    /// grid's top-left corner. Reports pixels in SGR-Pixels mode (1016),
+    /// otherwise the cell under the position, which needs the cell size
+    /// from `set_cell_size`.
+    pub fn mouse_report_pixels(
+        &mut self,
+        button: u8,
+        modifiers: u8,
+        x: f32,
+        y: f32,
+        pressed: bool,
+    ) {
+        let Some((width, height)) = self.cell_size else {
+            return;
+        };
+        if self.mouse_mode() == MouseMode::None {
+            return;
+        }
+        if self.pixel_reporting().is_none() {
+            let col = (x / width).max(0.0) as usize;
+            let row = (y / height).max(0.0) as usize;
+            self.mouse_report(button, modifiers, col, row, pressed);
+            return;
+        }
+
+        let max_x = (width * self.cols as f32) as usize;
+        let max_y = (height * self.rows as f32) as usize;
+        let x = (x.max(0.0) as usize).min(max_x.saturating_sub(1));
+        let y = (y.max(0.0) as usize).min(max_y.saturating_sub(1));
+        self.encode_mouse(button | modifiers, x, y, pressed);
+    }
+
+    /// Cell size to scale reports by, when the application asked for
+    /// SGR-Pixels and the frontend has given one.
+    fn pixel_reporting(&self) -> Option<(f32, f32)> {
+        self.cell_size
+            .filter(|_| self.mouse_pixels && self.mouse_sgr)
+    }
+
+    /// Write a report for button code `cb` at zero-based position (x, y).
+    fn encode_mouse(&mut self, cb: u8, x: usize, y: usize, pressed: bool) {
         if self.mouse_sgr {
             // SGR encoding: \x1b[<button;col;row{M|m}
             let suffix = if pressed { 'M' } else { 'm' };
-            let seq = format!("\x1b[<{};{};{}{}", cb, col + 1, row + 1, suffix);
+            let seq = format!("\x1b[<{};{};{}{}", cb, x + 1, y + 1, suffix);
             self.pending_writes.extend_from_slice(seq.as_bytes());
         } else {
             // Legacy X10 encoding: \x1b[M(cb+32)(col+33)(row+33)
             self.pending_writes.extend_from_slice(b"\x1b[M");
             self.pending_writes.push(cb + 32);
-            self.pending_writes.push(((col + 33) & 0xFF) as u8);
-            self.pending_writes.push(((row + 33) & 0xFF) as u8);
+            self.pending_writes.push(((x + 33) & 0xFF) as u8);
+            self.pending_writes.push(((y + 33) & 0xFF) as u8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_clamps_dimensions() {
+        let mut grid = TerminalGrid::new(80, 24);
+        grid.resize(70_000, 0);
+        assert_eq!((grid.cols, grid.rows), (MAX_GRID_DIMENSION, 1));
+        assert_eq!(grid.cells.len(), 1);
+        assert_eq!(grid.cells[0].len(), MAX_GRID_DIMENSION);
+        assert_eq!(grid.cursor_row, 0);
+    }
+
+    #[test]
+    fn new_clamps_zero_dimensions() {
+        let grid = TerminalGrid::new(0, 0);
+        assert_eq!((grid.cols, grid.rows), (1, 1));
+    }
+
+    fn feed(grid: &mut TerminalGrid, input: &str) {
+        grid.feed_str(input);
+    }
+
+    fn row_text(row: &[Cell]) -> String {
+        row.iter()
+            .map(|c| c.c)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    fn screen_text(grid: &TerminalGrid) -> Vec<String> {
+        grid.cells.iter().map(|row| row_text(row)).collect()
+    }
+
+    /// Every scrollback line, oldest first.
+    fn history_text(grid: &mut TerminalGrid) -> Vec<String> {
+        let len = grid.scrollback_len();
+        grid.scroll_display(len as i32);
+        let lines = (0..len).map(|i| row_text(&grid.visible_row(i))).collect();
+        grid.scroll_to_bottom();
+        lines
+    }
+
+    #[test]
+    fn reverse_index_in_region_shifts_only_the_region() {
+        let mut grid = TerminalGrid::new(10, 5);
+        feed(&mut grid, "a\r\nb\r\nc\r\nd\r\ne");
+        // Region rows 2..=4 (1-based), cursor to its top, then reverse index
+        feed(&mut grid, "\x1b[2;4r\x1b[2;1H\x1bM");
+
+        assert_eq!(screen_text(&grid), ["a", "", "b", "c", "e"]);
+        assert_eq!(grid.scrollback_len(), 0);
+    }
+
+    #[test]
+    fn full_screen_reverse_index_keeps_history_intact() {
+        let mut grid = TerminalGrid::new(10, 3);
+        feed(&mut grid, "1\r\n2\r\n3\r\n4\r\n5");
+        let before = history_text(&mut grid);
+        assert_eq!(before, ["1", "2"]);
+
+        feed(&mut grid, "\x1b[H\x1bM\x1bM");
+
+        assert_eq!(screen_text(&grid), ["", "", "3"]);
+        assert_eq!(history_text(&mut grid), before);
+    }
+
+    #[test]
+    fn region_scroll_up_skips_history_but_full_screen_saves_it() {
+        let mut grid = TerminalGrid::new(10, 4);
+        feed(&mut grid, "a\r\nb\r\nc\r\nd");
+        feed(&mut grid, "\x1b[2;4r\x1b[S");
+        assert_eq!(screen_text(&grid), ["a", "c", "d", ""]);
+        assert_eq!(grid.scrollback_len(), 0);
+
+        feed(&mut grid, "\x1b[r\x1b[S");
+        assert_eq!(screen_text(&grid), ["c", "d", "", ""]);
+        assert_eq!(history_text(&mut grid), ["a"]);
+    }
+
+    #[test]
+    fn invalid_scroll_region_is_ignored() {
+        let mut grid = TerminalGrid::new(10, 4);
+        feed(&mut grid, "a\r\nb\r\nc\r\nd\x1b[3;2r\x1b[3;1H\x1bM");
+        assert_eq!(screen_text(&grid), ["a", "b", "c", "d"]);
+        assert_eq!(grid.cursor_row, 1);
+    }
+
+    #[test]
+    fn private_m_sequences_do_not_apply_sgr() {
+        let mut grid = TerminalGrid::new(10, 2);
+        feed(&mut grid, "\x1b[>4m\x1b[>4;2m\x1b[=1m\x1b[?4mx");
+
+        let cell = &grid.cells[0][0];
+        assert_eq!(cell.c, 'x');
+        assert_eq!(
+            *cell,
+            Cell {
+                c: 'x',
+                ..Cell::default()
+            }
+        );
+
+        // Plain SGR still applies
+        feed(&mut grid, "\x1b[4my");
+        assert_eq!(grid.cells[0][1].underline, Underline::Single);
+    }
+
+    #[test]
+    fn tracks_keyboard_protocol_requests() {
+        let mut grid = TerminalGrid::new(10, 2);
+        assert_eq!(grid.keyboard_protocol(), KeyboardProtocol::Legacy);
+
+        feed(&mut grid, "\x1b[>4;1m");
+        assert_eq!(grid.keyboard_protocol(), KeyboardProtocol::Legacy);
+        feed(&mut grid, "\x1b[>4;2m");
+        assert_eq!(grid.keyboard_protocol(), KeyboardProtocol::CsiU);
+        feed(&mut grid, "\x1b[>4m");
+        assert_eq!(grid.keyboard_protocol(), KeyboardProtocol::Legacy);
+
+        feed(&mut grid, "\x1b[>1u\x1b[?u");
+        assert_eq!(grid.keyboard_protocol(), KeyboardProtocol::CsiU);
+        assert_eq!(grid.pending_writes, b"\x1b[?1u");
+        feed(&mut grid, "\x1b[<u");
+        assert_eq!(grid.keyboard_protocol(), KeyboardProtocol::Legacy);
+
+        // Popping an empty stack falls back to no flags
+        feed(&mut grid, "\x1b[=1u\x1b[<5u");
+        assert_eq!(grid.keyboard_protocol(), KeyboardProtocol::Legacy);
+    }
+
+    #[test]
+    fn kitty_keyboard_flags_nest_through_push_and_pop() {
+        let mut grid = TerminalGrid::new(10, 2);
+        let query = |grid: &mut TerminalGrid| {
+            feed(grid, "\x1b[?u");
+            String::from_utf8(std::mem::take(&mut grid.pending_writes)).unwrap()
+        };
+        assert_eq!(query(&mut grid), "\x1b[?0u");
+
+        feed(&mut grid, "\x1b[>1u\x1b[>3u\x1b[>31u");
+        assert_eq!(query(&mut grid), "\x1b[?31u");
+        feed(&mut grid, "\x1b[<u");
+        assert_eq!(query(&mut grid), "\x1b[?3u");
+        feed(&mut grid, "\x1b[=8;2u");
+        assert_eq!(query(&mut grid), "\x1b[?11u");
+
+        // Popping two levels at once restores the first push
+        feed(&mut grid, "\x1b[>4u\x1b[<2u");
+        assert_eq!(query(&mut grid), "\x1b[?1u");
+        feed(&mut grid, "\x1b[<9u");
+        assert_eq!(query(&mut grid), "\x1b[?0u");
+        assert_eq!(grid.keyboard_protocol(), KeyboardProtocol::Legacy);
+    }
+
+    #[test]
+    fn osc7_reports_decoded_working_directory() {
+        let mut grid = TerminalGrid::new(10, 2);
+        assert_eq!(grid.cwd(), None);
+
+        feed(&mut grid, "\x1b]7;file://host/home/me/My%20Docs\x07");
+        assert_eq!(grid.cwd(), Some("/home/me/My Docs"));
+
+        feed(&mut grid, "\x1b]7;file:///tmp/a;b/%C3%A9\x1b\\");
+        assert_eq!(grid.cwd(), Some("/tmp/a;b/é"));
+
+        // Malformed reports leave the last good value in place
+        feed(
+            &mut grid,
+            "\x1b]7;file://host/bad%zz\x07\x1b]7;/no/scheme\x07",
+        );
+        assert_eq!(grid.cwd(), Some("/tmp/a;b/é"));
+    }
+
+    #[test]
+    fn grapheme_clusters_share_one_cell() {
+        let mut grid = TerminalGrid::new(10, 2);
+        // Family (ZWJ sequence), then a thumbs up with a skin tone
+        feed(
+            &mut grid,
+            "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}x\u{1f44d}\u{1f3fd}",
+        );
+
+        let row = &grid.cells[0];
+        assert_eq!(row[0].c, '\u{1f468}');
+        assert_eq!(
+            row[0].combining.as_deref(),
+            Some("\u{200d}\u{1f469}\u{200d}\u{1f467}")
+        );
+        assert!(row[1].spacer);
+        assert_eq!(row[2].c, 'x');
+        assert_eq!(row[3].combining.as_deref(), Some("\u{1f3fd}"));
+        assert!(row[4].spacer);
+        assert_eq!(grid.cursor_col, 5);
+
+        // Combining marks join a narrow base without taking a column
+        feed(&mut grid, "e\u{301}!");
+        assert_eq!(grid.cursor_col, 7);
+        assert_eq!(grid.cells[0][5].combining.as_deref(), Some("\u{301}"));
+
+        grid.selection_begin(0, 0);
+        grid.selection_update(9, 0);
+        assert_eq!(
+            grid.selected_text(),
+            "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}x\u{1f44d}\u{1f3fd}e\u{301}!"
+        );
+    }
+
+    #[test]
+    fn wide_characters_wrap_whole_and_split_cleanly() {
+        let mut grid = TerminalGrid::new(5, 2);
+        feed(&mut grid, "abcd\u{6f22}");
+        assert_eq!(row_text(&grid.cells[0]), "abcd");
+        assert_eq!(grid.cells[1][0].c, '\u{6f22}');
+        assert!(grid.cells[1][1].spacer);
+        assert_eq!(grid.cursor_col, 2);
+
+        // Overwriting the right half blanks the left
+        feed(&mut grid, "\x1b[2;2Hz");
+        assert_eq!(grid.cells[1][0].c, ' ');
+        assert!(!grid.cells[1][1].spacer);
+        assert_eq!(grid.cells[1][1].c, 'z');
+    }
+
+    #[test]
+    fn backspace_steps_over_wide_characters() {
+        let mut grid = TerminalGrid::new(10, 2);
+        feed(&mut grid, "a\u{6f22}b\u{5b57}");
+        assert_eq!(grid.cursor_col, 6);
+
+        let mut stops = Vec::new();
+        for _ in 0..4 {
+            feed(&mut grid, "\x08");
+            stops.push(grid.cursor_col);
+        }
+        assert_eq!(stops, [4, 3, 1, 0]);
+
+        // Cursor Back counts characters, not columns
+        feed(&mut grid, "\x1b[1;7H\x1b[3D");
+        assert_eq!(grid.cursor_col, 1);
+
+        // Overwriting after backspace replaces the whole glyph
+        feed(&mut grid, "\x1b[1;7H\x08x");
+        assert_eq!(row_text(&grid.cells[0]), "a\u{6f22} bx");
+        assert!(!grid.cells[0][5].spacer);
+    }
+
+    #[test]
+    fn osc12_sets_cursor_color_and_osc112_restores_configured() {
+        let mut grid = TerminalGrid::new(10, 2);
+        assert_eq!(grid.cursor_color(), None);
+
+        feed(&mut grid, "\x1b]12;#ff8000\x07");
+        assert_eq!(grid.cursor_color(), Some([1.0, 128.0 / 255.0, 0.0, 1.0]));
+        feed(&mut grid, "\x1b]12;rgb:0/ffff/80\x1b\\");
+        assert_eq!(grid.cursor_color(), Some([0.0, 1.0, 128.0 / 255.0, 1.0]));
+
+        // Unparseable specs are ignored
+        feed(&mut grid, "\x1b]12;banana\x07\x1b]12;#12345\x07");
+        assert_eq!(grid.cursor_color(), Some([0.0, 1.0, 128.0 / 255.0, 1.0]));
+
+        let configured = Some([0.2, 0.4, 0.6, 1.0]);
+        grid.set_cursor_color(configured);
+        feed(&mut grid, "\x1b]12;#fff\x07\x1b]112\x07");
+        assert_eq!(grid.cursor_color(), configured);
+    }
+
+    #[test]
+    fn size_queries_report_grid_geometry() {
+        let mut grid = TerminalGrid::new(100, 30);
+        feed(&mut grid, "\x1b[18t");
+        assert_eq!(grid.pending_writes, b"\x1b[8;30;100t");
+
+        // Pixel size is only reported once the cell size is known
+        grid.pending_writes.clear();
+        feed(&mut grid, "\x1b[14t\x1b[16t");
+        assert!(grid.pending_writes.is_empty());
+        grid.set_cell_size(9.5, 20.0);
+        feed(&mut grid, "\x1b[14t");
+        assert_eq!(grid.pending_writes, b"\x1b[4;600;950t");
+        grid.pending_writes.clear();
+        feed(&mut grid, "\x1b[16t");
+        assert_eq!(grid.pending_writes, b"\x1b[6;20;10t");
+
+        // Window moves and resizes are ignored
+        grid.pending_writes.clear();
+        feed(&mut grid, "\x1b[3;10;10t\x1b[8;5;5t");
+        assert!(grid.pending_writes.is_empty());
+        assert_eq!((grid.cols, grid.rows), (100, 30));
+
+        // The pixel size follows the grid's own resizes
+        grid.resize(80, 24);
+        feed(&mut grid, "\x1b[14t");
+        assert_eq!(grid.pending_writes, b"\x1b[4;480;760t");
+    }
+
+    #[test]
+    fn dec_status_reports_include_the_page() {
+        let mut grid = TerminalGrid::new(10, 5);
+        feed(&mut grid, "\x1b[3;4H\x1b[?6n");
+        assert_eq!(grid.pending_writes, b"\x1b[?3;4;1R");
+
+        // A cursor past the last column reports that column
+        grid.pending_writes.clear();
+        feed(&mut grid, "\x1b[5;1H0123456789\x1b[?6n");
+        assert_eq!(grid.pending_writes, b"\x1b[?5;10;1R");
+
+        grid.pending_writes.clear();
+        feed(&mut grid, "\x1b[?15n");
+        assert_eq!(grid.pending_writes, b"\x1b[?11n");
+
+        // Unknown queries are neither answered nor printed
+        grid.pending_writes.clear();
+        feed(&mut grid, "\x1b[1;1H\x1b[?25n\x1b[?53n");
+        assert!(grid.pending_writes.is_empty());
+        assert_eq!(screen_text(&grid)[0], "");
+    }
+
+    #[test]
+    fn selection_range_tracks_scrolling() {
+        let mut grid = TerminalGrid::new(10, 3);
+        feed(&mut grid, "one\r\ntwo\r\nthree\r\nfour\r\nfive");
+        assert_eq!(screen_text(&grid), ["three", "four", "five"]);
+
+        grid.selection_begin(0, 0);
+        grid.selection_update(2, 1);
+        let range = grid.selection_range().unwrap();
+        assert_eq!(
+            (range.start, range.end, range.block),
+            ((0, 0), (2, 1), false)
+        );
+        assert_eq!(grid.selected_text(), "three\nfou");
+
+        // Scrolling back moves the selection down the screen with its text
+        grid.scroll_display(2);
+        let range = grid.selection_range().unwrap();
+        assert_eq!((range.start, range.end), ((0, 2), (2, 3)));
+        assert!(grid.is_selected(0, 2));
+        assert!(!grid.is_selected(0, 0));
+        assert_eq!(grid.selected_text(), "three\nfou");
+
+        grid.selection_clear();
+        assert_eq!(grid.selection_range(), None);
+    }
+
+    #[test]
+    fn select_word_keeps_paths_whole() {
+        let mut grid = TerminalGrid::new(30, 2);
+        feed(&mut grid, "cd /usr/local/bin:rest (x)");
+
+        grid.select_word(8, 0);
+        assert_eq!(grid.selected_text(), "/usr/local/bin");
+
+        // Words at the line edges and separators on their own
+        grid.select_word(0, 0);
+        assert_eq!(grid.selected_text(), "cd");
+        grid.select_word(17, 0);
+        assert_eq!(grid.selected_text(), ":");
+
+        grid.set_word_separators("/");
+        grid.select_word(8, 0);
+        assert_eq!(grid.selected_text(), "local");
+    }
+
+    #[test]
+    fn input_after_scrolling_back_snaps_to_a_clean_bottom() {
+        let mut grid = TerminalGrid::new(10, 3);
+        for i in 0..8 {
+            feed(&mut grid, &format!("line{i}\r\n"));
+        }
+        grid.scroll_display(4);
+        grid.selection_begin(0, 0);
+        grid.selection_update(4, 1);
+        assert!(grid.selection_range().is_some());
+        grid.dirty = false;
+
+        // What the frontends do before writing a key to the PTY
+        grid.scroll_to_bottom();
+        assert!(grid.viewport_at_bottom());
+        assert!(grid.selection_range().is_none());
+        assert!(grid.dirty);
+        assert_eq!(row_text(&grid.visible_row(0)), "line6");
+
+        // A selection on the live screen survives
+        grid.selection_begin(0, 0);
+        grid.selection_update(4, 0);
+        grid.scroll_display(1);
+        grid.scroll_to_bottom();
+        assert_eq!(grid.selected_text(), "line6");
+    }
+
+    #[test]
+    fn erase_display_3_clears_scrollback_but_2_keeps_it() {
+        let mut grid = TerminalGrid::new(10, 3);
+        for i in 0..8 {
+            feed(&mut grid, &format!("line{i}\r\n"));
+        }
+        let history = grid.scrollback_len();
+        assert!(history > 0);
+
+        feed(&mut grid, "\x1b[2J");
+        assert_eq!(grid.scrollback_len(), history);
+        assert_eq!(row_text(&grid.visible_row(0)), "");
+
+        grid.scroll_display(2);
+        feed(&mut grid, "\x1b[3J");
+        assert_eq!(grid.scrollback_len(), 0);
+        assert_eq!(grid.logical_scrollback_len(), 0);
+        assert!(grid.viewport_at_bottom());
+    }
+
+    #[test]
+    fn clear_blanks_the_screen_with_or_without_scrollback() {
+        let mut grid = TerminalGrid::new(10, 3);
+        for i in 0..8 {
+            feed(&mut grid, &format!("line{i}\r\n"));
+        }
+        feed(&mut grid, "\x1b[44mtail");
+        let history = grid.scrollback_len();
+
+        grid.clear(false);
+        assert_eq!(grid.scrollback_len(), history);
+        assert_eq!(screen_text(&grid), ["", "", ""]);
+        assert_eq!((grid.cursor_row, grid.cursor_col), (0, 0));
+
+        feed(&mut grid, "again");
+        grid.scroll_display(2);
+        grid.clear(true);
+        assert_eq!(grid.scrollback_len(), 0);
+        assert!(grid.viewport_at_bottom());
+        assert!(grid
+            .cells
+            .iter()
+            .flatten()
+            .all(|cell| *cell == Cell::default()));
+        assert_eq!((grid.cursor_row, grid.cursor_col), (0, 0));
+    }
+
+    #[test]
+    fn shell_integration_marks_commands() {
+        let mut grid = TerminalGrid::new(20, 4);
+        let prompt =
+            |command: &str| format!("\x1b]133;A\x07$ \x1b]133;B\x07{command}\r\n");
+        let output = |text: &str| format!("\x1b]133;C\x07{text}\x1b]133;D;0\x07");
+        assert_eq!(grid.last_command_output(), None);
+
+        feed(&mut grid, &prompt("ls"));
+        feed(&mut grid, &output("a.txt\r\nb.txt\r\n"));
+        assert_eq!(grid.last_command_output().as_deref(), Some("a.txt\nb.txt"));
+
+        // An empty command line never runs, so the last output stays
+        feed(&mut grid, &format!("{}\x1b]133;D\x07", prompt("")));
+        assert_eq!(grid.last_command_output().as_deref(), Some("a.txt\nb.txt"));
+        feed(&mut grid, &prompt("echo -n hi"));
+        feed(&mut grid, &output("hi"));
+        assert_eq!(grid.last_command_output().as_deref(), Some("hi"));
+
+        // Marks stay on their lines as output scrolls into history
+        feed(&mut grid, "\r\n");
+        feed(&mut grid, &prompt("seq 10"));
+        let lines: String = (1..=10).map(|i| format!("{i}\r\n")).collect();
+        feed(&mut grid, &output(&lines));
+        feed(&mut grid, &prompt(""));
+        assert_eq!(
+            grid.last_command_output().as_deref(),
+            Some("1\n2\n3\n4\n5\n6\n7\n8\n9\n10")
+        );
+
+        assert!(grid.previous_prompt());
+        assert_eq!(row_text(&grid.visible_row(0)), "$ seq 10");
+        assert!(grid.previous_prompt());
+        assert_eq!(row_text(&grid.visible_row(0)), "$ echo -n hi");
+        assert!(grid.previous_prompt());
+        assert!(grid.previous_prompt());
+        assert_eq!(row_text(&grid.visible_row(0)), "$ ls");
+        assert!(!grid.previous_prompt());
+
+        assert!(grid.next_prompt());
+        assert_eq!(row_text(&grid.visible_row(0)), "$");
+        assert!(grid.next_prompt());
+        assert_eq!(row_text(&grid.visible_row(0)), "$ echo -n hi");
+        assert!(grid.next_prompt());
+        assert_eq!(row_text(&grid.visible_row(0)), "$ seq 10");
+        // The last prompt is on the live screen
+        assert!(grid.next_prompt());
+        assert!(grid.viewport_at_bottom());
+        assert!(!grid.next_prompt());
+    }
+
+    #[test]
+    fn finished_commands_report_their_exit_code() {
+        let mut grid = TerminalGrid::new(10, 3);
+        assert_eq!(grid.last_exit_code(), None);
+        feed(&mut grid, "\x1b]133;C\x07\x1b]133;D;1\x07");
+        assert_eq!(grid.last_exit_code(), Some(1));
+
+        // Cleared while the next command runs, and absent codes stay unknown
+        feed(&mut grid, "\x1b]133;A\x07\x1b]133;C\x07");
+        assert_eq!(grid.last_exit_code(), None);
+        feed(&mut grid, "\x1b]133;D;0\x07");
+        assert_eq!(grid.last_exit_code(), Some(0));
+        feed(&mut grid, "\x1b]133;C\x07\x1b]133;D\x07");
+        assert_eq!(grid.last_exit_code(), None);
+    }
+
+    #[test]
+    fn prompt_jumps_stop_at_the_first_and_last_prompt() {
+        let mut grid = TerminalGrid::new(10, 3);
+        // Three commands of four lines each: prompt, then three of output
+        for i in 0..3 {
+            feed(&mut grid, &format!("\x1b]133;A\x07${i}\r\n"));
+            feed(&mut grid, "\x1b]133;C\x07a\r\nb\r\nc\r\n\x1b]133;D\x07");
         }
+        // Prompts on lines 0, 4 and 8 of 13; the live screen starts at 10
+        assert_eq!(grid.scrollback_len(), 10);
+        assert_eq!(grid.prompt_rows().count(), 0);
+
+        assert!(grid.previous_prompt());
+        assert_eq!(grid.display_offset, 2);
+        assert_eq!(grid.prompt_rows().collect::<Vec<_>>(), [0]);
+        assert!(grid.previous_prompt());
+        assert_eq!(grid.display_offset, 6);
+        assert!(grid.previous_prompt());
+        assert_eq!(grid.display_offset, 10);
+        assert!(!grid.previous_prompt());
+        assert_eq!(grid.display_offset, 10);
+
+        assert!(grid.next_prompt());
+        assert_eq!(grid.display_offset, 6);
+        assert!(grid.next_prompt());
+        assert_eq!(grid.display_offset, 2);
+        assert!(!grid.next_prompt());
+        assert_eq!(grid.display_offset, 2);
+    }
+
+    #[test]
+    fn line_position_absolute_and_relative() {
+        let mut grid = TerminalGrid::new(10, 8);
+        feed(&mut grid, "\x1b[3;5H\x1b[6d");
+        assert_eq!((grid.cursor_row, grid.cursor_col), (5, 4));
+        feed(&mut grid, "\x1b[d");
+        assert_eq!(grid.cursor_row, 0);
+        feed(&mut grid, "\x1b[99d");
+        assert_eq!(grid.cursor_row, 7);
+
+        feed(&mut grid, "\x1b[1;3H\x1b[e");
+        assert_eq!((grid.cursor_row, grid.cursor_col), (1, 2));
+        feed(&mut grid, "\x1b[3e");
+        assert_eq!(grid.cursor_row, 4);
+        feed(&mut grid, "\x1b[99e");
+        assert_eq!(grid.cursor_row, 7);
+    }
+
+    #[test]
+    fn line_position_absolute_respects_origin_mode() {
+        let mut grid = TerminalGrid::new(10, 8);
+        // Scroll region rows 3..=6 (1-based), origin mode homes into it
+        feed(&mut grid, "\x1b[3;6r\x1b[?6h");
+        assert_eq!(grid.cursor_row, 2);
+        feed(&mut grid, "\x1b[2d");
+        assert_eq!(grid.cursor_row, 3);
+        feed(&mut grid, "\x1b[9d");
+        assert_eq!(grid.cursor_row, 5);
+        feed(&mut grid, "\x1b[1;1H");
+        assert_eq!(grid.cursor_row, 2);
+
+        feed(&mut grid, "\x1b[?6l\x1b[2d");
+        assert_eq!(grid.cursor_row, 1);
+    }
+
+    #[test]
+    fn character_position_matches_cha_and_cuf() {
+        let mut hpa = TerminalGrid::new(10, 2);
+        let mut cha = TerminalGrid::new(10, 2);
+        for (hpa_seq, cha_seq) in [("`", "G"), ("4`", "4G"), ("99`", "99G")] {
+            feed(&mut hpa, &format!("\x1b[{hpa_seq}"));
+            feed(&mut cha, &format!("\x1b[{cha_seq}"));
+            assert_eq!(hpa.cursor_col, cha.cursor_col);
+        }
+        assert_eq!(hpa.cursor_col, 9);
+
+        let mut hpr = TerminalGrid::new(10, 2);
+        let mut cuf = TerminalGrid::new(10, 2);
+        for (hpr_seq, cuf_seq) in [("a", "C"), ("3a", "3C"), ("99a", "99C")] {
+            feed(&mut hpr, &format!("\x1b[{hpr_seq}"));
+            feed(&mut cuf, &format!("\x1b[{cuf_seq}"));
+            assert_eq!(hpr.cursor_col, cuf.cursor_col);
+        }
+        assert_eq!(hpr.cursor_col, 9);
+
+        feed(&mut hpr, "\x1b[1`x\x1b[2ay");
+        assert_eq!(row_text(&hpr.visible_row(0)), "x  y");
+    }
+
+    #[test]
+    fn backward_tab_lands_on_previous_stop() {
+        let mut grid = TerminalGrid::new(20, 2);
+        feed(&mut grid, "\x1b[16G\x1b[Z");
+        assert_eq!(grid.cursor_col, 8);
+
+        // Custom stops at columns 3 and 11
+        grid.tab_stops = (0..20).map(|col| col == 3 || col == 11).collect();
+        feed(&mut grid, "\x1b[16G\x1b[Z");
+        assert_eq!(grid.cursor_col, 11);
+        feed(&mut grid, "\x1b[Z");
+        assert_eq!(grid.cursor_col, 3);
+        feed(&mut grid, "\x1b[16G\x1b[2Z");
+        assert_eq!(grid.cursor_col, 3);
+        feed(&mut grid, "\x1b[5Z");
+        assert_eq!(grid.cursor_col, 0);
+
+        // Forward tabs use the same stops
+        feed(&mut grid, "\t");
+        assert_eq!(grid.cursor_col, 3);
+        feed(&mut grid, "\t\t");
+        assert_eq!(grid.cursor_col, 19);
+    }
+
+    #[test]
+    fn dec_special_graphics_draws_lines() {
+        let mut grid = TerminalGrid::new(10, 3);
+        feed(&mut grid, "\x1b(0lqqk\r\nx  x\x1b(B\r\nqx");
+        assert_eq!(row_text(&grid.visible_row(0)), "┌──┐");
+        assert_eq!(row_text(&grid.visible_row(1)), "│  │");
+        assert_eq!(row_text(&grid.visible_row(2)), "qx");
+
+        // G1 is only used while shifted out
+        feed(&mut grid, "\r\x1b)0q\x0eq\x0fq");
+        assert_eq!(row_text(&grid.visible_row(2)), "q─q");
+    }
+
+    #[test]
+    fn index_and_next_line_scroll_the_region() {
+        let mut grid = TerminalGrid::new(10, 4);
+        feed(&mut grid, "a\x1bDb\x1bEc");
+        assert_eq!(row_text(&grid.visible_row(0)), "a");
+        assert_eq!(row_text(&grid.visible_row(1)), " b");
+        assert_eq!(row_text(&grid.visible_row(2)), "c");
+
+        // At the bottom of a region, only the region scrolls
+        feed(&mut grid, "\x1b[4;1Hz\x1b[1;3r\x1b[3;1H\x1bD\x1bEd");
+        assert_eq!(grid.cursor_row, 2);
+        assert_eq!(row_text(&grid.visible_row(0)), "c");
+        assert_eq!(row_text(&grid.visible_row(2)), "d");
+        assert_eq!(row_text(&grid.visible_row(3)), "z");
+    }
+
+    #[test]
+    fn horizontal_tab_set_adds_a_stop() {
+        let mut grid = TerminalGrid::new(20, 2);
+        feed(&mut grid, "\x1b[4G\x1bH\r\tx");
+        assert_eq!(grid.cursor_col, 4);
+        feed(&mut grid, "\tx");
+        assert_eq!(row_text(&grid.visible_row(0)), "   x    x");
+    }
+
+    #[test]
+    fn output_at_region_bottom_scrolls_only_the_region() {
+        let mut grid = TerminalGrid::new(4, 6);
+        feed(&mut grid, "top\x1b[5;1Hlow\x1b[6;1Hend");
+        // Region rows 1..=3 (0-based); wrapped text and line feeds run past it
+        feed(&mut grid, "\x1b[2;4r\x1b[2;1H");
+        feed(&mut grid, "aaaabbbb\r\ncccc\r\ndddd");
+        assert_eq!(
+            screen_text(&grid),
+            ["top", "bbbb", "cccc", "dddd", "low", "end"]
+        );
+        assert_eq!(grid.scrollback_len(), 0);
+
+        // A region at the top that stops short of the bottom keeps no history
+        feed(&mut grid, "\x1b[1;3r\x1b[3;1H\r\n\r\n");
+        assert_eq!(screen_text(&grid)[3..], ["dddd", "low", "end"]);
+        assert_eq!(grid.scrollback_len(), 0);
+
+        // Below the region, line feeds stop at the last row
+        feed(&mut grid, "\x1b[6;1H\r\nx");
+        assert_eq!(grid.cursor_row, 5);
+        assert_eq!(screen_text(&grid)[3..], ["dddd", "low", "xnd"]);
+    }
+
+    #[test]
+    fn inserting_and_deleting_keeps_rows_full_width() {
+        let mut grid = TerminalGrid::new(13, 4);
+        // Fixed xorshift seed so failures reproduce
+        let mut state: u32 = 0x9e37_79b9;
+        let mut next = |max: u32| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state % max
+        };
+        for _ in 0..5_000 {
+            let n = match next(4) {
+                0 => 0,
+                1 => 1,
+                2 => next(20),
+                _ => 60_000,
+            };
+            let op = match next(7) {
+                0 => format!("\x1b[{n}@"),
+                1 => format!("\x1b[{n}P"),
+                2 => format!("\x1b[{n}L"),
+                3 => format!("\x1b[{n}M"),
+                4 => format!("\x1b[{};{}H", next(6), next(16)),
+                5 => "界x".to_string(),
+                _ => "ab\r\n".to_string(),
+            };
+            grid.feed(op.as_bytes());
+
+            assert!(
+                grid.cells.iter().all(|row| row.len() == grid.cols),
+                "{op:?}"
+            );
+            for row in &grid.cells {
+                for (col, cell) in row.iter().enumerate() {
+                    if cell.c == '界' {
+                        assert!(row[col + 1].spacer, "{op:?}");
+                    }
+                    if cell.spacer {
+                        assert_eq!(row[col - 1].c, '界', "{op:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn alternate_screen_follows_the_mode() {
+        let mut grid = TerminalGrid::new(80, 24);
+        assert!(!grid.alternate_screen());
+        feed(&mut grid, "\x1b[?1049h");
+        assert!(grid.alternate_screen());
+        feed(&mut grid, "\x1b[?1049l");
+        assert!(!grid.alternate_screen());
+        feed(&mut grid, "\x1b[?2004;47h");
+        assert!(grid.alternate_screen() && grid.bracketed_paste());
+        feed(&mut grid, "\x1b[?1047l");
+        assert!(!grid.alternate_screen());
+    }
+
+    #[test]
+    fn sgr_pixels_reports_pixel_coordinates() {
+        let mut grid = TerminalGrid::new(80, 24);
+        grid.set_cell_size(10.0, 20.0);
+        feed(&mut grid, "\x1b[?1000;1006h");
+
+        // The same click at cell (3, 2), 4.5 and 7 pixels into the cell
+        grid.mouse_report(0, 0, 3, 2, true);
+        grid.mouse_report_pixels(0, 0, 34.5, 47.0, false);
+        assert_eq!(
+            std::mem::take(&mut grid.pending_writes),
+            b"\x1b[<0;4;3M\x1b[<0;4;3m"
+        );
+
+        feed(&mut grid, "\x1b[?1016h");
+        grid.mouse_report(0, 0, 3, 2, true);
+        grid.mouse_report_pixels(0, 0, 34.5, 47.0, false);
+        grid.mouse_report_pixels(0, 0, 5000.0, -3.0, true);
+        assert_eq!(
+            std::mem::take(&mut grid.pending_writes),
+            b"\x1b[<0;31;41M\x1b[<0;35;48m\x1b[<0;800;1M"
+        );
+
+        // Cells again once the mode is reset
+        feed(&mut grid, "\x1b[?1016l");
+        grid.mouse_report_pixels(0, 0, 34.5, 47.0, true);
+        assert_eq!(grid.pending_writes, b"\x1b[<0;4;3M");
+    }
+
+    #[test]
+    fn cell_info_reports_style_link_and_selection() {
+        let mut grid = TerminalGrid::new(10, 2);
+        feed(
+            &mut grid,
+            "\x1b[1;4;32m\x1b]8;id=x;https://example.com/a;b\x1b\\link\x1b]8;;\x1b\\\x1b[0m 界",
+        );
+        let info = grid.cell_info_at(1, 0).unwrap();
+        assert_eq!(info.text, "i");
+        assert!(info.bold && info.underline && !info.italic);
+        assert_eq!(info.fg, ansi_color(2));
+        assert_eq!(info.hyperlink.as_deref(), Some("https://example.com/a;b"));
+        assert!(!info.selected);
+
+        // Text after the link closes has none; a spacer reports its character
+        assert_eq!(grid.cell_info_at(4, 0).unwrap().hyperlink, None);
+        assert_eq!(grid.cell_info_at(6, 0).unwrap().text, "界");
+        assert_eq!(grid.cell_info_at(10, 0), None);
+
+        grid.selection_begin(0, 0);
+        grid.selection_update(2, 0);
+        assert!(grid.cell_info_at(1, 0).unwrap().selected);
+
+        // Taps on scrolled-back content resolve into history
+        feed(&mut grid, "\r\n\r\nnext");
+        grid.scroll_display(1);
+        let info = grid.cell_info_at(0, 0).unwrap();
+        assert_eq!(info.text, "l");
+        assert!(info.hyperlink.is_some() && info.selected);
+    }
+
+    #[test]
+    fn resize_clears_the_selection() {
+        let mut grid = TerminalGrid::new(10, 3);
+        feed(&mut grid, "hello\r\nworld");
+        grid.selection_begin(0, 1);
+        grid.selection_update(4, 1);
+        assert_eq!(grid.selected_text(), "world");
+
+        // Same size keeps it
+        grid.resize(10, 3);
+        assert_eq!(grid.selected_text(), "world");
+
+        grid.resize(6, 3);
+        assert_eq!(grid.selection_range(), None);
+        assert_eq!(grid.selected_text(), "");
+    }
+
+    #[test]
+    fn logical_scrollback_counts_wrapped_rows_once() {
+        let mut grid = TerminalGrid::new(5, 2);
+        // "abcdefghijkl" wraps over three rows; the others fit in one
+        feed(&mut grid, "one\r\nabcdefghijkl\r\ntwo\r\nthree\r\n");
+        assert_eq!(
+            history_text(&mut grid),
+            ["one", "abcde", "fghij", "kl", "two"]
+        );
+        assert_eq!(grid.scrollback_len(), 5);
+        assert_eq!(grid.logical_scrollback_len(), 3);
+
+        // A line still wrapping onto the screen counts where it starts
+        feed(&mut grid, "0123456789X");
+        assert_eq!(grid.scrollback_len(), 7);
+        assert_eq!(grid.logical_scrollback_len(), 5);
+    }
+
+    #[test]
+    fn block_selection_is_rectangular() {
+        let mut grid = TerminalGrid::new(10, 3);
+        feed(&mut grid, "abcdef\r\nghijkl\r\nmnopqr");
+
+        // Dragged up and left; bounds are still normalized
+        grid.selection_begin_block(3, 2);
+        grid.selection_update(1, 0);
+        let range = grid.selection_range().unwrap();
+        assert_eq!(
+            (range.start, range.end, range.block),
+            ((1, 0), (3, 2), true)
+        );
+        assert!(grid.is_selected(2, 1));
+        assert!(!grid.is_selected(5, 1));
+        assert_eq!(grid.selected_text(), "bcd\nhij\nnop");
+    }
+
+    #[test]
+    fn title_stack_restores_pushed_title() {
+        let mut grid = TerminalGrid::new(10, 2);
+        feed(&mut grid, "\x1b]0;shell\x07");
+        assert_eq!(grid.title(), Some("shell"));
+
+        feed(&mut grid, "\x1b[22;0t\x1b]2;vim: a;b\x07");
+        assert_eq!(grid.title(), Some("vim: a;b"));
+
+        grid.dirty = false;
+        feed(&mut grid, "\x1b[23;0t");
+        assert_eq!(grid.title(), Some("shell"));
+        assert!(grid.dirty);
+
+        // Popping an empty stack keeps the current title
+        grid.dirty = false;
+        feed(&mut grid, "\x1b[23;0t");
+        assert_eq!(grid.title(), Some("shell"));
+        assert!(!grid.dirty);
+    }
+
+    #[test]
+    fn sequences_split_across_reads_parse_once() {
+        let mut grid = TerminalGrid::new(10, 2);
+        // Title split mid-character, then ST split between ESC and `\`
+        grid.feed(b"\x1b]0;caf\xc3");
+        assert_eq!(grid.title(), None);
+        grid.feed(b"\xa9 log\x1b");
+        assert_eq!(grid.title(), Some("café log"));
+        // The `\` completing ST is not printed
+        grid.feed(b"\\ok");
+        assert_eq!(grid.title(), Some("café log"));
+        assert_eq!(row_text(&grid.cells[0]), "ok");
+
+        // A DCS body split across reads is swallowed, not printed
+        grid.feed(b"\x1bP$q\"p");
+        grid.feed(b"\x1b\\!");
+        assert_eq!(row_text(&grid.cells[0]), "ok!");
+    }
+
+    #[test]
+    fn bell_is_reported_once() {
+        let mut grid = TerminalGrid::new(10, 2);
+        feed(&mut grid, "done\x07");
+        assert!(grid.take_bell());
+        assert!(!grid.take_bell());
+
+        // BEL terminating an OSC is not a bell
+        feed(&mut grid, "\x1b]2;title\x07");
+        assert!(!grid.take_bell());
+    }
+
+    /// Feed a `ls -la`-style transcript with colored names into the grid.
+    fn feed_ls_transcript(grid: &mut TerminalGrid, lines: usize) {
+        for i in 0..lines {
+            let line = format!(
+                "drwxr-xr-x  2 user staff {:>8} Oct 16 12:{:02} \x1b[1;34mdir_{i}\x1b[0m\r\n",
+                4096 + i,
+                i % 60,
+            );
+            grid.feed(line.as_bytes());
+        }
+    }
+
+    #[test]
+    fn compact_scrollback_renders_like_full() {
+        let mut compact = TerminalGrid::new(80, 5);
+        let mut full = TerminalGrid::new(80, 5);
+        full.set_compact_scrollback(false);
+        feed_ls_transcript(&mut compact, 50);
+        feed_ls_transcript(&mut full, 50);
+
+        compact.scroll_display(20);
+        full.scroll_display(20);
+        assert!(compact.rows_visible().eq(full.rows_visible()));
+        assert!(compact
+            .visible_cells(0)
+            .take(4)
+            .map(|c| c.c)
+            .eq("drwx".chars()));
+
+        // Live rows are lent rather than copied
+        compact.scroll_display(-20);
+        assert!(compact
+            .visible_cells(0)
+            .all(|cell| matches!(cell, Cow::Borrowed(_))));
+    }
+
+    #[test]
+    fn compact_scrollback_memory_on_ls_transcript() {
+        let mut compact = TerminalGrid::new(200, 50);
+        let mut full = TerminalGrid::new(200, 50);
+        full.set_compact_scrollback(false);
+        feed_ls_transcript(&mut compact, 10_000);
+        feed_ls_transcript(&mut full, 10_000);
+
+        // History is capped, so both hold the most recent MAX_SCROLLBACK lines
+        assert_eq!(compact.scrollback_len(), MAX_SCROLLBACK);
+        assert_eq!(full.scrollback_len(), MAX_SCROLLBACK);
+
+        // Measured: ~0.18 MB compact vs ~8.8 MB full
+        let (compact_bytes, full_bytes) =
+            (compact.scrollback_memory(), full.scrollback_memory());
+        assert!(
+            compact_bytes * 20 < full_bytes,
+            "compact {compact_bytes} bytes vs full {full_bytes} bytes"
+        );
     }
 }
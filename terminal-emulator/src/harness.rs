@@ -0,0 +1,185 @@
+use crate::grid::{Cell, TerminalGrid};
+
+/// Drives a `TerminalGrid` from a byte script and reports what ends up on
+/// screen, for end-to-end tests of terminal behavior without a frontend.
+pub struct GridHarness {
+    pub grid: TerminalGrid,
+}
+
+impl GridHarness {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        Self {
+            grid: TerminalGrid::new(cols, rows),
+        }
+    }
+
+    /// Fresh `cols` x `rows` grid with `script` already fed through it.
+    pub fn run(cols: usize, rows: usize, script: &[u8]) -> Self {
+        let mut harness = Self::new(cols, rows);
+        harness.feed(script);
+        harness
+    }
+
+    /// Parse more output. Parser state carries over between calls, so a
+    /// sequence may be split across them.
+    pub fn feed(&mut self, script: &[u8]) -> &mut Self {
+        self.grid.feed(script);
+        self
+    }
+
+    /// Text of each visible row, with trailing blanks trimmed.
+    pub fn screen(&self) -> Vec<String> {
+        (0..self.grid.rows)
+            .map(|row| {
+                let mut text = String::new();
+                for cell in self.grid.visible_cells(row) {
+                    cell.push_text(&mut text);
+                }
+                text.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    /// Cursor position as (col, row).
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.grid.cursor_col, self.grid.cursor_row)
+    }
+
+    /// Cell at (col, row) of the visible screen, for checking attributes.
+    pub fn cell(&self, col: usize, row: usize) -> Cell {
+        self.grid
+            .visible_cells(row)
+            .nth(col)
+            .expect("column on screen")
+            .into_owned()
+    }
+
+    /// Bytes the grid wants written back to the PTY (query replies),
+    /// draining them.
+    pub fn replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.grid.pending_writes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    //! Golden screens for common vttest scenarios
+    use super::*;
+    use crate::grid::{ansi_color, Underline};
+    use crate::snapshot::{RemoteScreen, SnapshotEncoder};
+
+    #[test]
+    fn cursor_movement_draws_a_frame() {
+        // vttest 1: a border drawn with absolute and relative movement
+        let mut script = String::from("\x1b[2J\x1b[1;1H******\x1b[5;1H******");
+        for row in 2..=4 {
+            script.push_str(&format!("\x1b[{row};1H*\x1b[{row};6H*"));
+        }
+        script.push_str("\x1b[3;2HE\x1b[CF\x1b[2DX");
+        let t = GridHarness::run(6, 5, script.as_bytes());
+        assert_eq!(
+            t.screen(),
+            ["******", "*    *", "*EXF *", "*    *", "******"]
+        );
+        assert_eq!(t.cursor(), (3, 2));
+    }
+
+    #[test]
+    fn autowrap_continues_on_the_next_row() {
+        // vttest 1: text wrapping at the right margin and scrolling
+        let t = GridHarness::run(5, 3, b"abcdefghij\r\nkl");
+        assert_eq!(t.screen(), ["abcde", "fghij", "kl"]);
+        let t = GridHarness::run(5, 2, b"abcdefghijk");
+        assert_eq!(t.screen(), ["fghij", "k"]);
+        assert_eq!(t.cursor(), (1, 1));
+    }
+
+    #[test]
+    fn scroll_region_keeps_outer_rows() {
+        // vttest 2: scrolling inside DECSTBM margins
+        let mut t = GridHarness::new(4, 5);
+        t.feed(b"top\x1b[5;1Hbot\x1b[2;4r\x1b[2;1H");
+        for line in ["1", "2", "3", "4"] {
+            t.feed(format!("{line}\r\n").as_bytes());
+        }
+        assert_eq!(t.screen(), ["top", "3", "4", "", "bot"]);
+
+        // Reverse index at the top margin scrolls the region down
+        t.feed(b"\x1b[2;1H\x1bM");
+        assert_eq!(t.screen(), ["top", "", "3", "4", "bot"]);
+    }
+
+    #[test]
+    fn insert_and_delete_edit_in_place() {
+        // vttest 8: ICH, DCH, IL and DL
+        let t = GridHarness::run(8, 3, b"abcdef\x1b[1;3H\x1b[2@XY\x1b[1;7H\x1b[P");
+        assert_eq!(t.screen()[0], "abXYcdf");
+        let t = GridHarness::run(4, 3, b"a\r\nb\r\nc\x1b[2;1H\x1b[L");
+        assert_eq!(t.screen(), ["a", "", "b"]);
+        let t = GridHarness::run(4, 3, b"a\r\nb\r\nc\x1b[1;1H\x1b[M");
+        assert_eq!(t.screen(), ["b", "c", ""]);
+    }
+
+    #[test]
+    fn erase_leaves_the_cursor_in_place() {
+        // vttest 1: ED and EL from the middle of the screen
+        let fill = b"12345\r\n12345\r\n12345";
+        let mut t = GridHarness::run(5, 3, fill);
+        t.feed(b"\x1b[2;3H\x1b[J");
+        assert_eq!(t.screen(), ["12345", "12", ""]);
+        let mut t = GridHarness::run(5, 3, fill);
+        t.feed(b"\x1b[2;3H\x1b[1K");
+        assert_eq!(t.screen(), ["12345", "   45", "12345"]);
+        assert_eq!(t.cursor(), (2, 1));
+    }
+
+    #[test]
+    fn graphic_rendition_sets_attributes() {
+        // vttest 2: SGR attributes and their resets
+        let t = GridHarness::run(10, 1, b"\x1b[1;4;7;32mA\x1b[22;24mB\x1b[0mC");
+        let a = t.cell(0, 0);
+        assert!(a.bold && a.underline == Underline::Single && a.inverse);
+        assert_eq!(a.fg, ansi_color(2));
+        let b = t.cell(1, 0);
+        assert!(!b.bold && b.underline == Underline::None && b.inverse);
+        assert_eq!(
+            t.cell(2, 0),
+            Cell {
+                c: 'C',
+                ..Cell::default()
+            }
+        );
+    }
+
+    #[test]
+    fn headless_session_mirrors_to_a_remote_screen() {
+        // Runs without the render feature: parse, answer and mirror a
+        // session with no GPU in the process
+        let mut t = GridHarness::run(8, 3, b"$ ls\r\n\x1b[32ma.txt\x1b[0m\r\n$ \x1b[18t");
+        assert_eq!(t.replies(), b"\x1b[8;3;8t");
+
+        let mut encoder = SnapshotEncoder::new();
+        let mut remote = RemoteScreen::new();
+        remote.apply(&encoder.full_snapshot(&t.grid)).unwrap();
+        t.feed(b"exit");
+        remote.apply(&encoder.diff(&t.grid).unwrap()).unwrap();
+
+        let text: Vec<String> = remote
+            .cells
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.c).collect::<String>())
+            .map(|row| row.trim_end().to_string())
+            .collect();
+        assert_eq!(text, t.screen());
+        assert_eq!(remote.cells[1][0].fg, ansi_color(2));
+        assert_eq!((remote.cursor_col, remote.cursor_row), t.cursor());
+    }
+
+    #[test]
+    fn size_query_is_answered() {
+        // vttest 11: XTWINOPS text area size report
+        let mut t = GridHarness::run(10, 5, b"\x1b[18t");
+        assert_eq!(t.replies(), b"\x1b[8;5;10t");
+        assert!(t.replies().is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+/// Keyboard encoding requested by the running application
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum KeyboardProtocol {
+    /// Traditional bytes (control characters, ESC prefix for Alt)
+    #[default]
+    Legacy,
+    /// Modified keys are sent as `CSI code ; mods u` so chords such as
+    /// Ctrl+Shift+A or Ctrl+Enter stay distinguishable. Enabled by xterm
+    /// modifyOtherKeys level 2 or the Kitty "disambiguate" flag.
+    CsiU,
+}
+
+/// Modifier keys held during a key press
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct KeyModifiers {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl KeyModifiers {
+    /// xterm modifier parameter: 1 + shift(1) + alt(2) + ctrl(4)
+    fn param(self) -> u8 {
+        1 + self.shift as u8 + (self.alt as u8) * 2 + (self.ctrl as u8) * 4
+    }
+}
+
+/// Key that may be encoded differently depending on the protocol
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    /// Printable character, as produced by the layout (already shifted)
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+}
+
+/// Key without a character, sent as an escape sequence
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FunctionKey {
+    Up,
+    Down,
+    Right,
+    Left,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// F1 to F12
+    F(u8),
+}
+
+/// Encode a function key press, adding xterm's modifier parameter when any
+/// modifier is held (`CSI 1 ; 5 C` for Ctrl+Right). Empty for F keys past
+/// F12.
+pub fn encode_function_key(key: FunctionKey, mods: KeyModifiers) -> Vec<u8> {
+    // Keys end in a letter, or in `~` after their number
+    let (number, end) = match key {
+        FunctionKey::Up => (1, 'A'),
+        FunctionKey::Down => (1, 'B'),
+        FunctionKey::Right => (1, 'C'),
+        FunctionKey::Left => (1, 'D'),
+        FunctionKey::Home => (1, 'H'),
+        FunctionKey::End => (1, 'F'),
+        FunctionKey::Insert => (2, '~'),
+        FunctionKey::Delete => (3, '~'),
+        FunctionKey::PageUp => (5, '~'),
+        FunctionKey::PageDown => (6, '~'),
+        FunctionKey::F(n @ 1..=4) => (1, (b'P' + n - 1) as char),
+        FunctionKey::F(n @ 5..=12) => {
+            ([15, 17, 18, 19, 20, 21, 23, 24][n as usize - 5], '~')
+        }
+        FunctionKey::F(_) => return Vec::new(),
+    };
+    let sequence = if mods != KeyModifiers::default() {
+        format!("\x1b[{number};{}{end}", mods.param())
+    } else if end == '~' {
+        format!("\x1b[{number}~")
+    } else if matches!(key, FunctionKey::F(_)) {
+        format!("\x1bO{end}")
+    } else {
+        format!("\x1b[{end}")
+    };
+    sequence.into_bytes()
+}
+
+/// Encode a key press into the bytes to send to the PTY.
+pub fn encode_key(key: Key, mods: KeyModifiers, protocol: KeyboardProtocol) -> Vec<u8> {
+    if protocol == KeyboardProtocol::CsiU {
+        if let Some(code) = csi_u_code(key, mods) {
+            return format!("\x1b[{code};{}u", mods.param()).into_bytes();
+        }
+    }
+    encode_legacy(key, mods)
+}
+
+/// Key and modifiers for a browser key press whose `key` is the character
+/// `ch`, with its physical `code` (e.g. `KeyQ`) and `altgr` from the
+/// AltGraph modifier state. Ctrl+letter follows the layout's letter, as
+/// AZERTY and Dvorak users expect, and falls back to the key's position on
+/// layouts without Latin letters. AltGr types its character instead of
+/// making a control chord; where the browser reports it only as Ctrl+Alt,
+/// a non-ASCII character is taken to come from AltGr.
+pub fn web_char_key(
+    ch: char,
+    code: &str,
+    mods: KeyModifiers,
+    altgr: bool,
+) -> (Key, KeyModifiers) {
+    if altgr || (mods.ctrl && mods.alt && !ch.is_ascii()) {
+        return (Key::Char(ch), KeyModifiers::default());
+    }
+    if mods.ctrl && !ch.is_ascii_alphabetic() {
+        let letter = code
+            .strip_prefix("Key")
+            .and_then(|letter| letter.parse::<char>().ok())
+            .filter(char::is_ascii_uppercase);
+        if let Some(letter) = letter {
+            let letter = if mods.shift {
+                letter
+            } else {
+                letter.to_ascii_lowercase()
+            };
+            return (Key::Char(letter), mods);
+        }
+    }
+    (Key::Char(ch), mods)
+}
+
+/// Codepoint to report for `key` under CSI u, or `None` when the legacy
+/// bytes are already unambiguous (unmodified keys and plain text).
+fn csi_u_code(key: Key, mods: KeyModifiers) -> Option<u32> {
+    match key {
+        // Text stays text unless Ctrl or Alt changes its meaning; letters
+        // are reported unshifted with Shift carried in the modifiers
+        Key::Char(c) if mods.ctrl || mods.alt => Some(c.to_ascii_lowercase() as u32),
+        Key::Char(_) => None,
+        _ if mods == KeyModifiers::default() => None,
+        Key::Enter => Some(13),
+        Key::Tab => Some(9),
+        Key::Backspace => Some(127),
+        Key::Escape => Some(27),
+    }
+}
+
+fn encode_legacy(key: Key, mods: KeyModifiers) -> Vec<u8> {
+    // Alt prefixes ESC to whatever the key sends, so Alt+Backspace deletes
+    // a word in readline
+    let mut bytes = Vec::new();
+    if mods.alt {
+        bytes.push(0x1b);
+    }
+    match key {
+        Key::Enter => bytes.push(b'\r'),
+        Key::Tab => bytes.push(b'\t'),
+        Key::Backspace => bytes.push(0x7f),
+        Key::Escape => bytes.push(0x1b),
+        Key::Char(c) if mods.ctrl && c.is_ascii_alphabetic() => {
+            bytes.push((c.to_ascii_lowercase() as u8) - b'a' + 1);
+        }
+        Key::Char(c) => {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONE: KeyModifiers = KeyModifiers {
+        shift: false,
+        alt: false,
+        ctrl: false,
+    };
+    const CTRL: KeyModifiers = KeyModifiers { ctrl: true, ..NONE };
+    const CTRL_SHIFT: KeyModifiers = KeyModifiers {
+        shift: true,
+        ..CTRL
+    };
+    const ALT: KeyModifiers = KeyModifiers { alt: true, ..NONE };
+    const SHIFT: KeyModifiers = KeyModifiers {
+        shift: true,
+        ..NONE
+    };
+
+    fn csi_u(key: Key, mods: KeyModifiers) -> String {
+        String::from_utf8(encode_key(key, mods, KeyboardProtocol::CsiU)).unwrap()
+    }
+
+    #[test]
+    fn csi_u_encodes_modified_chords() {
+        assert_eq!(csi_u(Key::Char('a'), CTRL), "\x1b[97;5u");
+        assert_eq!(csi_u(Key::Char('A'), CTRL_SHIFT), "\x1b[97;6u");
+        assert_eq!(csi_u(Key::Char('x'), ALT), "\x1b[120;3u");
+        assert_eq!(csi_u(Key::Enter, CTRL), "\x1b[13;5u");
+        assert_eq!(csi_u(Key::Enter, SHIFT), "\x1b[13;2u");
+        assert_eq!(csi_u(Key::Tab, SHIFT), "\x1b[9;2u");
+        assert_eq!(csi_u(Key::Backspace, ALT), "\x1b[127;3u");
+    }
+
+    #[test]
+    fn csi_u_leaves_unambiguous_keys_alone() {
+        assert_eq!(csi_u(Key::Char('a'), NONE), "a");
+        assert_eq!(csi_u(Key::Char('A'), SHIFT), "A");
+        assert_eq!(csi_u(Key::Enter, NONE), "\r");
+        assert_eq!(csi_u(Key::Backspace, NONE), "\x7f");
+    }
+
+    #[test]
+    fn legacy_encoding_loses_shift_on_ctrl_letters() {
+        let legacy = |key, mods| encode_key(key, mods, KeyboardProtocol::Legacy);
+        assert_eq!(legacy(Key::Char('a'), CTRL), [0x01]);
+        assert_eq!(legacy(Key::Char('A'), CTRL_SHIFT), [0x01]);
+        assert_eq!(legacy(Key::Char('x'), ALT), b"\x1bx");
+        assert_eq!(legacy(Key::Enter, CTRL), b"\r");
+    }
+
+    #[test]
+    fn legacy_alt_prefixes_escape() {
+        let legacy = |key, mods| encode_key(key, mods, KeyboardProtocol::Legacy);
+        assert_eq!(legacy(Key::Char('é'), ALT), "\x1bé".as_bytes());
+        assert_eq!(legacy(Key::Char('😀'), ALT), "\x1b😀".as_bytes());
+        assert_eq!(legacy(Key::Backspace, ALT), b"\x1b\x7f");
+        assert_eq!(legacy(Key::Enter, ALT), b"\x1b\r");
+        assert_eq!(legacy(Key::Backspace, NONE), b"\x7f");
+    }
+
+    #[test]
+    fn function_keys_carry_xterm_modifiers() {
+        let encode =
+            |key, mods| String::from_utf8(encode_function_key(key, mods)).unwrap();
+        assert_eq!(encode(FunctionKey::Up, NONE), "\x1b[A");
+        assert_eq!(encode(FunctionKey::Right, CTRL), "\x1b[1;5C");
+        assert_eq!(encode(FunctionKey::Home, SHIFT), "\x1b[1;2H");
+        assert_eq!(encode(FunctionKey::Delete, NONE), "\x1b[3~");
+        assert_eq!(encode(FunctionKey::PageUp, ALT), "\x1b[5;3~");
+        assert_eq!(encode(FunctionKey::F(1), NONE), "\x1bOP");
+        assert_eq!(encode(FunctionKey::F(4), CTRL_SHIFT), "\x1b[1;6S");
+        assert_eq!(encode(FunctionKey::F(5), NONE), "\x1b[15~");
+        assert_eq!(encode(FunctionKey::F(12), CTRL), "\x1b[24;5~");
+        assert_eq!(encode(FunctionKey::F(13), NONE), "");
+    }
+
+    #[test]
+    fn web_ctrl_chords_follow_the_layout() {
+        let web = |ch, code, mods, altgr| {
+            let (key, mods) = web_char_key(ch, code, mods, altgr);
+            encode_key(key, mods, KeyboardProtocol::Legacy)
+        };
+        let ctrl_alt = KeyModifiers { alt: true, ..CTRL };
+
+        // AZERTY: A sits where QWERTY has Q
+        assert_eq!(web('a', "KeyQ", CTRL, false), [0x01]);
+        assert_eq!(web('q', "KeyA", CTRL, false), [0x11]);
+        // Dvorak: C sits where QWERTY has I
+        assert_eq!(web('c', "KeyI", CTRL, false), [0x03]);
+        assert_eq!(web('C', "KeyI", CTRL_SHIFT, false), [0x03]);
+        // Russian: no Latin letter, so the key's position decides
+        assert_eq!(web('с', "KeyC", CTRL, false), [0x03]);
+
+        // AltGr types its character, reported as Ctrl+Alt or not
+        assert_eq!(web('@', "KeyQ", ctrl_alt, true), b"@");
+        assert_eq!(web('€', "KeyE", ctrl_alt, false), "€".as_bytes());
+        assert_eq!(web('[', "Digit5", NONE, true), b"[");
+        // A real Ctrl+Alt chord still is one
+        assert_eq!(web('a', "KeyA", ctrl_alt, false), [0x1b, 0x01]);
+    }
+}
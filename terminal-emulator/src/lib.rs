@@ -1,5 +1,43 @@
+mod ansi;
+mod charset;
 mod grid;
+#[cfg(any(test, feature = "test-harness"))]
+mod harness;
+mod keyboard;
+mod output;
+mod paste;
+#[cfg(feature = "render")]
 mod renderer;
+mod scroll_mode;
+mod scrollback;
+mod scrollbar;
+mod snapshot;
+mod theme;
+mod wheel;
 
-pub use grid::{Cell, MouseMode, TerminalGrid};
-pub use renderer::render_grid;
+pub use grid::{
+    parse_color, Cell, CellInfo, MouseMode, SelectionRange, TerminalGrid, Underline,
+    DEFAULT_WORD_SEPARATORS, MAX_GRID_DIMENSION,
+};
+#[cfg(feature = "test-harness")]
+pub use harness::GridHarness;
+pub use keyboard::{
+    encode_function_key, encode_key, web_char_key, FunctionKey, Key, KeyModifiers,
+    KeyboardProtocol,
+};
+pub use output::{
+    OutputQueue, ReadBuffer, DEFAULT_MAX_READ_BUFFER, MIN_READ_BUFFER,
+    OUTPUT_BYTES_PER_FRAME,
+};
+pub use paste::{
+    encode_input, prepare_paste, ClipboardType, Clipboards, Paste, PasteMode,
+};
+#[cfg(feature = "render")]
+pub use renderer::{emit_runs, prompt_marker_quads, render_grid, RunSink};
+pub use scroll_mode::{scroll_mode_action, NavKey, ScrollAction};
+#[cfg(feature = "render")]
+pub use scrollbar::scrollbar_quad;
+pub use scrollbar::{scrollbar_thumb, Scrollbar, Thumb};
+pub use snapshot::{RemoteScreen, SnapshotEncoder, SnapshotError, SNAPSHOT_VERSION};
+pub use theme::Theme;
+pub use wheel::{ScrollConfig, DEFAULT_LINES_PER_NOTCH};
@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+
+use crate::grid::TerminalGrid;
+
+/// Bytes of PTY output parsed per frame. A large burst (such as a big paste
+/// echoed back) is spread over several frames rather than stalling one.
+pub const OUTPUT_BYTES_PER_FRAME: usize = 64 * 1024;
+
+/// Size a `ReadBuffer` starts at and shrinks back to
+pub const MIN_READ_BUFFER: usize = 4096;
+
+/// Default cap on a `ReadBuffer`'s growth
+pub const DEFAULT_MAX_READ_BUFFER: usize = 64 * 1024;
+
+/// Consecutive reads that fill the buffer before it doubles
+const FULL_READS_TO_GROW: u8 = 2;
+
+/// Buffer for reading PTY output that grows while reads keep filling it and
+/// shrinks once they come back small. Bulk output such as `cat bigfile` then
+/// moves in fewer, larger reads, while interactive echo stays cheap.
+#[derive(Debug)]
+pub struct ReadBuffer {
+    buf: Vec<u8>,
+    max: usize,
+    full_reads: u8,
+}
+
+impl ReadBuffer {
+    /// Buffer growing up to `max` bytes, which is raised to at least
+    /// `MIN_READ_BUFFER`.
+    pub fn new(max: usize) -> Self {
+        Self {
+            buf: vec![0; MIN_READ_BUFFER],
+            max: max.max(MIN_READ_BUFFER),
+            full_reads: 0,
+        }
+    }
+
+    /// Current size, the most one `read` returns.
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Read once from `reader`, returning the bytes read (empty at EOF)
+    /// and resizing for the next read.
+    pub fn read(&mut self, reader: &mut impl Read) -> io::Result<&[u8]> {
+        let n = reader.read(&mut self.buf)?;
+        let size = self.buf.len();
+        if n == size {
+            self.full_reads += 1;
+            if self.full_reads >= FULL_READS_TO_GROW && size < self.max {
+                self.buf.resize((size * 2).min(self.max), 0);
+                self.full_reads = 0;
+            }
+        } else {
+            self.full_reads = 0;
+            // Output went quiet; the read fits in the lower half
+            if n < size / 4 && size > MIN_READ_BUFFER {
+                self.buf.truncate((size / 2).max(MIN_READ_BUFFER));
+                self.buf.shrink_to_fit();
+            }
+        }
+        Ok(&self.buf[..n])
+    }
+}
+
+impl Default for ReadBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_READ_BUFFER)
+    }
+}
+
+/// PTY output received but not yet parsed, oldest first
+#[derive(Debug, Default)]
+pub struct OutputQueue {
+    pending: VecDeque<u8>,
+}
+
+impl OutputQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue output behind anything still pending.
+    pub fn push(&mut self, data: &[u8]) {
+        self.pending.extend(data);
+    }
+
+    /// Number of bytes waiting to be parsed.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Drop pending output, e.g. when the grid is replaced.
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// Parse up to `budget` queued bytes into `grid`. Returns true when
+    /// output remains for a later frame, in which case the grid is left
+    /// dirty so the frontend keeps drawing frames.
+    pub fn advance(&mut self, grid: &mut TerminalGrid, budget: usize) -> bool {
+        let len = budget.min(self.pending.len());
+        let (front, back) = self.pending.as_slices();
+        let split = len.min(front.len());
+        grid.feed(&front[..split]);
+        grid.feed(&back[..len - split]);
+        self.pending.drain(..len);
+
+        let remaining = !self.pending.is_empty();
+        if remaining {
+            grid.dirty = true;
+        }
+        remaining
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synchronized_update_renders_once_at_the_end() {
+        let mut queue = OutputQueue::new();
+        let mut grid = TerminalGrid::new(10, 2);
+        grid.feed(b"\x1b[?2026hold");
+        grid.dirty = false;
+        queue.push(b"\x1b[2J\x1b[Hnew screen\x1b[?2026l");
+
+        // Render hook: what each drawn frame showed
+        let mut frames: Vec<String> = Vec::new();
+        let mut pending = true;
+        while pending {
+            pending = queue.advance(&mut grid, 4);
+            if grid.dirty && !grid.synchronized_output() {
+                frames.push(grid.cells[0].iter().map(|cell| cell.c).collect());
+                grid.dirty = false;
+            }
+        }
+        assert_eq!(frames, ["new screen"]);
+    }
+
+    #[test]
+    fn read_buffer_grows_under_bulk_output_and_shrinks_when_idle() {
+        let data = vec![b'x'; 1024 * 1024];
+        let mut source = &data[..];
+        let mut buffer = ReadBuffer::new(64 * 1024);
+        let mut reads = 0;
+        let mut total = 0;
+        let mut peak = 0;
+        loop {
+            peak = peak.max(buffer.capacity());
+            let n = buffer.read(&mut source).unwrap().len();
+            if n == 0 {
+                break;
+            }
+            total += n;
+            reads += 1;
+        }
+        assert_eq!(total, data.len());
+        // Fixed 4 KB reads would take 256
+        assert!(reads < 40, "{reads} reads");
+        assert_eq!(peak, 64 * 1024);
+
+        // Small reads halve it back down, but never below the minimum
+        for _ in 0..10 {
+            buffer.read(&mut &b"echo"[..]).unwrap();
+        }
+        assert_eq!(buffer.capacity(), MIN_READ_BUFFER);
+        assert_eq!(ReadBuffer::new(0).capacity(), MIN_READ_BUFFER);
+    }
+
+    #[test]
+    fn large_output_applies_over_several_frames() {
+        // Multi-byte characters make some frames end mid-character
+        let mut data = Vec::new();
+        let mut lines = 0;
+        while data.len() < 1024 * 1024 {
+            data.extend_from_slice(format!("é{lines:06}\r\n").as_bytes());
+            lines += 1;
+        }
+
+        let mut queue = OutputQueue::new();
+        let mut grid = TerminalGrid::new(20, 4);
+        queue.push(&data);
+
+        let budget = OUTPUT_BYTES_PER_FRAME - 3;
+        let mut frames = 1;
+        while queue.advance(&mut grid, budget) {
+            assert!(grid.dirty);
+            grid.dirty = false;
+            frames += 1;
+        }
+        assert!(queue.is_empty());
+        assert_eq!(frames, data.len().div_ceil(budget));
+
+        let text: Vec<String> = grid
+            .cells
+            .iter()
+            .map(|row| row.iter().map(|c| c.c).collect::<String>())
+            .map(|row| row.trim_end().to_string())
+            .collect();
+        let last = lines - 1;
+        assert_eq!(
+            text,
+            [
+                format!("é{:06}", last - 2),
+                format!("é{:06}", last - 1),
+                format!("é{last:06}"),
+                String::new(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use crate::grid::TerminalGrid;
+
+/// How pasted text is treated when the application has not enabled
+/// bracketed paste, so a line break in the clipboard would run a command
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PasteMode {
+    /// Strip control sequences and join lines, so nothing runs on paste
+    #[default]
+    Sanitize,
+    /// Strip control sequences but keep line breaks; the frontend asks for
+    /// confirmation before sending a multi-line paste
+    Warn,
+    /// Send the clipboard exactly as is
+    Raw,
+}
+
+impl std::str::FromStr for PasteMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sanitize" => Ok(Self::Sanitize),
+            "warn" => Ok(Self::Warn),
+            "raw" => Ok(Self::Raw),
+            other => Err(format!(
+                "Invalid paste mode '{other}': expected sanitize, warn or raw"
+            )),
+        }
+    }
+}
+
+/// Pasted text ready to send to the PTY
+#[derive(Debug, Clone, PartialEq)]
+pub struct Paste {
+    pub bytes: Vec<u8>,
+    /// The clipboard held line breaks, which run commands unless bracketed
+    /// paste is on. Frontends warn about (or confirm) such pastes.
+    pub multiline: bool,
+}
+
+/// Prepare clipboard text for sending. With bracketed paste the text is
+/// wrapped in `CSI 200~`/`CSI 201~` and control sequences are stripped so
+/// the paste cannot end the bracket early; otherwise `mode` decides.
+pub fn prepare_paste(text: &str, bracketed: bool, mode: PasteMode) -> Paste {
+    let multiline = text.contains(['\r', '\n']);
+    if bracketed {
+        let mut bytes = b"\x1b[200~".to_vec();
+        bytes.extend_from_slice(strip_controls(text, "\r").as_bytes());
+        bytes.extend_from_slice(b"\x1b[201~");
+        return Paste { bytes, multiline };
+    }
+    let bytes = match mode {
+        PasteMode::Sanitize => {
+            strip_controls(text.trim_end_matches(['\r', '\n']), " ").into_bytes()
+        }
+        PasteMode::Warn => strip_controls(text, "\r").into_bytes(),
+        PasteMode::Raw => text.as_bytes().to_vec(),
+    };
+    Paste { bytes, multiline }
+}
+
+/// Encode text sent programmatically, such as a command an embedder types,
+/// as if it were typed: line breaks press Enter. Text spanning several lines
+/// goes as one bracketed paste when the application enabled it, so it
+/// arrives whole; a final line break still presses Enter after it.
+pub fn encode_input(text: &str, bracketed: bool) -> Vec<u8> {
+    let body = text
+        .strip_suffix("\r\n")
+        .or_else(|| text.strip_suffix(['\r', '\n']));
+    let enter = body.is_some();
+    let body = body.unwrap_or(text);
+    let mut bytes = if bracketed && body.contains(['\r', '\n']) {
+        prepare_paste(body, true, PasteMode::Raw).bytes
+    } else {
+        body.replace("\r\n", "\r").replace('\n', "\r").into_bytes()
+    };
+    if enter {
+        bytes.push(b'\r');
+    }
+    bytes
+}
+
+/// Remove escape sequences and control characters other than tab, replacing
+/// each line break (CRLF, CR or LF) with `newline`.
+fn strip_controls(text: &str, newline: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' | '\n' => {
+                if c == '\r' && chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                out.push_str(newline);
+            }
+            '\t' => out.push(c),
+            '\x1b' => skip_escape(&mut chars),
+            // CSI introduced by its C1 form
+            '\u{9b}' => skip_csi(&mut chars),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Skip the rest of an escape sequence whose ESC was just consumed.
+fn skip_escape(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) {
+    match chars.next() {
+        Some('[') => skip_csi(chars),
+        // OSC, DCS, APC, PM and SOS run until BEL or ST
+        Some(']' | 'P' | '_' | '^' | 'X') => {
+            while let Some(c) = chars.next() {
+                if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                    break;
+                }
+            }
+        }
+        // Two-character sequence such as ESC 7
+        _ => {}
+    }
+}
+
+/// Skip CSI parameters, intermediates and the final byte.
+fn skip_csi(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) {
+    for c in chars.by_ref() {
+        if ('\x40'..='\x7e').contains(&c) {
+            break;
+        }
+    }
+}
+
+/// Clipboard that copied text goes to, as in the desktop backend:
+/// `Clipboard` for explicit copy and paste, `Selection` for the primary
+/// selection that selecting fills and a middle click pastes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardType {
+    Clipboard,
+    Selection,
+}
+
+/// Clipboard contents kept in memory, for frontends whose platform has no
+/// primary selection (browsers, Android)
+#[derive(Debug, Default)]
+pub struct Clipboards {
+    clipboard: String,
+    selection: String,
+}
+
+impl Clipboards {
+    pub const fn new() -> Self {
+        Self {
+            clipboard: String::new(),
+            selection: String::new(),
+        }
+    }
+
+    /// Text last copied to `ty`.
+    pub fn get(&self, ty: ClipboardType) -> &str {
+        match ty {
+            ClipboardType::Clipboard => &self.clipboard,
+            ClipboardType::Selection => &self.selection,
+        }
+    }
+
+    /// Replace the contents of `ty`, leaving the other clipboard alone.
+    pub fn set(&mut self, ty: ClipboardType, text: impl Into<String>) {
+        match ty {
+            ClipboardType::Clipboard => self.clipboard = text.into(),
+            ClipboardType::Selection => self.selection = text.into(),
+        }
+    }
+
+    /// Copy the grid's selected text to `ty` and return it. An empty
+    /// selection leaves the clipboard as it was.
+    pub fn copy_selection(&mut self, grid: &TerminalGrid, ty: ClipboardType) -> String {
+        let text = grid.selected_text();
+        if !text.is_empty() {
+            self.set(ty, text.clone());
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(paste: &Paste) -> &str {
+        std::str::from_utf8(&paste.bytes).unwrap()
+    }
+
+    #[test]
+    fn sanitize_joins_lines_and_strips_escapes() {
+        let paste = prepare_paste(
+            "echo hi\r\nrm -rf ~\x1b[31m/tmp\x1b[0m\n",
+            false,
+            PasteMode::Sanitize,
+        );
+        assert!(paste.multiline);
+        assert_eq!(text(&paste), "echo hi rm -rf ~/tmp");
+
+        let paste =
+            prepare_paste("ls\x1b]0;pwned\x07\tx\x03", false, PasteMode::Sanitize);
+        assert!(!paste.multiline);
+        assert_eq!(text(&paste), "ls\tx");
+    }
+
+    #[test]
+    fn warn_keeps_lines_for_confirmation() {
+        let paste = prepare_paste("a\nb\x1b[2J\n", false, PasteMode::Warn);
+        assert!(paste.multiline);
+        assert_eq!(text(&paste), "a\rb\r");
+    }
+
+    #[test]
+    fn bracketed_paste_cannot_end_the_bracket_early() {
+        let payload = "safe\x1b[201~\nwhoami\n";
+        for mode in [PasteMode::Sanitize, PasteMode::Warn, PasteMode::Raw] {
+            let paste = prepare_paste(payload, true, mode);
+            assert_eq!(text(&paste), "\x1b[200~safe\rwhoami\r\x1b[201~");
+        }
+    }
+
+    #[test]
+    fn raw_sends_the_clipboard_untouched() {
+        let paste = prepare_paste("a\x1b[1mb\n", false, PasteMode::Raw);
+        assert_eq!(paste.bytes, b"a\x1b[1mb\n");
+        assert!(paste.multiline);
+    }
+
+    #[test]
+    fn injected_input_is_typed_line_by_line() {
+        // An embedder running a command
+        assert_eq!(encode_input("cargo test\n", false), b"cargo test\r");
+        assert_eq!(encode_input("cargo test\n", true), b"cargo test\r");
+        assert_eq!(encode_input("y", true), b"y");
+        assert_eq!(encode_input("\x03", false), b"\x03");
+
+        // A script stays one paste when the shell can take it that way
+        let script = "for f in *; do\r\n  echo $f\ndone\n";
+        assert_eq!(
+            encode_input(script, true),
+            b"\x1b[200~for f in *; do\r  echo $f\rdone\x1b[201~\r"
+        );
+        assert_eq!(
+            encode_input(script, false),
+            b"for f in *; do\r  echo $f\rdone\r"
+        );
+    }
+
+    #[test]
+    fn clipboard_and_selection_are_independent() {
+        let mut grid = TerminalGrid::new(20, 1);
+        grid.feed(b"alpha beta");
+        let mut clipboards = Clipboards::new();
+
+        grid.select_word(1, 0);
+        assert_eq!(
+            clipboards.copy_selection(&grid, ClipboardType::Selection),
+            "alpha"
+        );
+        grid.select_word(7, 0);
+        clipboards.copy_selection(&grid, ClipboardType::Clipboard);
+        assert_eq!(clipboards.get(ClipboardType::Selection), "alpha");
+        assert_eq!(clipboards.get(ClipboardType::Clipboard), "beta");
+
+        // Copying nothing keeps what was there
+        grid.selection_clear();
+        assert_eq!(
+            clipboards.copy_selection(&grid, ClipboardType::Selection),
+            ""
+        );
+        assert_eq!(clipboards.get(ClipboardType::Selection), "alpha");
+    }
+}
@@ -1,34 +1,118 @@
-use crate::grid::{Cell, TerminalGrid};
+use crate::grid::{Cell, TerminalGrid, Underline};
+use crate::theme::Theme;
 use sugarloaf::{
-    FragmentStyle, FragmentStyleDecoration, Sugarloaf, UnderlineInfo, UnderlineShape,
+    Content, FragmentStyle, FragmentStyleDecoration, Quad, Sugarloaf, UnderlineInfo,
+    UnderlineShape,
 };
 
-/// Default background color used when a cell has no explicit background
-const DEFAULT_BG: [f32; 4] = [0.05, 0.05, 0.1, 1.0];
+/// Resolve the font for `ch` as `(font_id, is_emoji)`, asking `lookup` for a
+/// fallback only when needed. ASCII, box-drawing and block elements
+/// (U+2500-U+259F) always use the primary monospace font: a proportional
+/// fallback would leave gaps between the cells of TUI borders.
+fn font_for(
+    ch: char,
+    lookup: impl FnOnce(char) -> Option<(usize, bool)>,
+) -> (usize, bool) {
+    if ch.is_ascii() || ('\u{2500}'..='\u{259f}').contains(&ch) {
+        (0, false)
+    } else {
+        lookup(ch).unwrap_or((0, false))
+    }
+}
 
-/// Compute effective fg/bg for a cell, accounting for inverse, selection, and cursor
+/// Font for `cell`, from its cache or else resolved by `font_for`
+fn cell_font(
+    cell: &Cell,
+    lookup: impl FnOnce(char) -> Option<(usize, bool)>,
+) -> (usize, bool) {
+    cell.font.get_or_init(|| font_for(cell.c, lookup))
+}
+
+/// Columns taken by the character at `row[col]`: 2 when a spacer for its
+/// right half follows, else 1
+fn cell_span(row: &[Cell], col: usize) -> usize {
+    if row.get(col + 1).is_some_and(|cell| cell.spacer) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Black or white, whichever reads better on `bg`, judged by its relative
+/// luminance
+fn contrasting_fg(bg: [f32; 4]) -> [f32; 4] {
+    let linear = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let luminance =
+        0.2126 * linear(bg[0]) + 0.7152 * linear(bg[1]) + 0.0722 * linear(bg[2]);
+    // Crossover where black and white have equal contrast ratios
+    if luminance > 0.179 {
+        [0.0, 0.0, 0.0, 1.0]
+    } else {
+        [1.0, 1.0, 1.0, 1.0]
+    }
+}
+
+/// Line drawn with a cell's text. Sugarloaf draws one decoration per
+/// fragment, so an underline wins over strikethrough, and both over overline.
+fn cell_decoration(cell: &Cell) -> Option<FragmentStyleDecoration> {
+    let underline = |is_doubled, shape| {
+        Some(FragmentStyleDecoration::Underline(UnderlineInfo {
+            is_doubled,
+            shape,
+        }))
+    };
+    match cell.underline {
+        Underline::Single => underline(false, UnderlineShape::Regular),
+        Underline::Double => underline(true, UnderlineShape::Regular),
+        Underline::Curly => underline(false, UnderlineShape::Curly),
+        Underline::Dotted => underline(false, UnderlineShape::Dotted),
+        Underline::Dashed => underline(false, UnderlineShape::Dashed),
+        Underline::None if cell.strikethrough => {
+            Some(FragmentStyleDecoration::Strikethrough)
+        }
+        Underline::None if cell.overline => Some(FragmentStyleDecoration::Overline),
+        Underline::None => None,
+    }
+}
+
+/// Compute effective fg/bg for a cell in `theme`, accounting for inverse,
+/// selection, and cursor
 fn cell_colors(
     cell: &Cell,
+    theme: &Theme,
     is_selected: bool,
     is_cursor: bool,
+    cursor_color: Option<[f32; 4]>,
 ) -> ([f32; 4], Option<[f32; 4]>) {
+    let cell_fg = theme.fg(cell.fg);
+    let cell_bg = cell.bg.map(|bg| theme.bg(bg));
+
     // Cell inverse attribute
     let (mut fg, mut bg) = if cell.inverse {
-        (cell.bg.unwrap_or(DEFAULT_BG), Some(cell.fg))
+        (cell_bg.unwrap_or(theme.background), Some(cell_fg))
     } else {
-        (cell.fg, cell.bg)
+        (cell_fg, cell_bg)
     };
 
     // Selection highlight: swap fg/bg
     if is_selected {
-        let tmp = bg.unwrap_or(DEFAULT_BG);
+        let tmp = bg.unwrap_or(theme.background);
         bg = Some(fg);
         fg = tmp;
     }
 
-    // Cursor: swap fg/bg for block cursor
+    // Cursor: a configured color with readable text on it, else swap fg/bg
     if is_cursor {
-        let tmp = bg.unwrap_or(DEFAULT_BG);
+        if let Some(color) = cursor_color {
+            return (contrasting_fg(color), Some(color));
+        }
+        let tmp = bg.unwrap_or(theme.background);
         bg = Some(fg);
         fg = tmp;
     }
@@ -36,6 +120,40 @@ fn cell_colors(
     (fg, bg)
 }
 
+/// Thin lines in the left gutter of the grid area `[x, y, width, height]`
+/// beside each row where a prompt starts, sized for the display `scale`, so
+/// commands stand out when scrolling back
+pub fn prompt_marker_quads(grid: &TerminalGrid, area: [f32; 4], scale: f32) -> Vec<Quad> {
+    let [x, y, _, height] = area;
+    let row_height = height / grid.rows as f32;
+    grid.prompt_rows()
+        .map(|row| Quad {
+            color: [0.4, 0.6, 1.0, 0.8],
+            position: [x, y + row as f32 * row_height],
+            size: [2.0 * scale, row_height],
+            ..Quad::default()
+        })
+        .collect()
+}
+
+/// Receiver of the styled text runs a frame is drawn as
+pub trait RunSink {
+    /// Append a run to the current row
+    fn add_text(&mut self, text: &str, style: FragmentStyle);
+    /// Start the next row
+    fn new_line(&mut self);
+}
+
+impl RunSink for Content {
+    fn add_text(&mut self, text: &str, style: FragmentStyle) {
+        Content::add_text(self, text, style);
+    }
+
+    fn new_line(&mut self) {
+        Content::new_line(self);
+    }
+}
+
 /// Render the terminal grid into sugarloaf content
 pub fn render_grid(sugarloaf: &mut Sugarloaf, grid: &TerminalGrid, rt_id: usize) {
     // Clone the font library (Arc-shared) for per-character font matching.
@@ -45,119 +163,411 @@ pub fn render_grid(sugarloaf: &mut Sugarloaf, grid: &TerminalGrid, rt_id: usize)
     let content = sugarloaf.content();
     content.sel(rt_id).clear();
 
+    // Hold a read lock for font lookups; must be dropped before build()
+    // which acquires a write lock for font metrics
+    {
+        let font_lib = font_library.inner.read();
+        emit_runs(
+            grid,
+            |ch, style| font_lib.find_best_font_match(ch, style),
+            content,
+        );
+    }
+
+    content.build();
+}
+
+/// Feed the visible rows of `grid` to `sink` as runs of text sharing a
+/// style, split further wherever `lookup` picks another font
+pub fn emit_runs(
+    grid: &TerminalGrid,
+    lookup: impl Fn(char, &FragmentStyle) -> Option<(usize, bool)>,
+    sink: &mut impl RunSink,
+) {
     // Cursor is only visible when viewing live output
     let cursor_row = if grid.display_offset == 0 {
         Some(grid.cursor_row)
     } else {
         None
     };
+    let cursor_color = grid.cursor_color();
+    let theme = grid.theme();
 
-    // Hold a read lock for font lookups; must be dropped before build()
-    // which acquires a write lock for font metrics
-    {
-        let font_lib = font_library.inner.read();
+    for (row_idx, row) in grid.rows_visible().enumerate() {
+        // Scrollback rows may have a different column count after resize
+        let cols = grid.cols.min(row.len());
+        let line = &row[..cols];
+        let mut run_start = 0;
+
+        while run_start < cols {
+            let cell = &row[run_start];
+            let is_cursor = cursor_row == Some(row_idx) && run_start == grid.cursor_col;
+            let is_selected = grid.is_selected(run_start, row_idx);
+
+            let (fg, bg) = cell_colors(cell, theme, is_selected, is_cursor, cursor_color);
+
+            let decoration = cell_decoration(cell);
+
+            let style = FragmentStyle {
+                color: fg,
+                background_color: bg,
+                decoration,
+                ..FragmentStyle::default()
+            };
+
+            // Batch consecutive characters with the same visual style
+            let mut run_end = run_start + 1;
+            while run_end < cols {
+                let next = &row[run_end];
+                // The right half of a wide character stays with its left
+                if next.spacer {
+                    run_end += 1;
+                    continue;
+                }
+                let next_is_cursor =
+                    cursor_row == Some(row_idx) && run_end == grid.cursor_col;
+                let next_is_selected = grid.is_selected(run_end, row_idx);
+                let (nfg, nbg) = cell_colors(
+                    next,
+                    theme,
+                    next_is_selected,
+                    next_is_cursor,
+                    cursor_color,
+                );
 
-        for row_idx in 0..grid.rows {
-            let row = grid.visible_row(row_idx);
-            // Scrollback rows may have a different column count after resize
-            let cols = grid.cols.min(row.len());
-            let mut run_start = 0;
-
-            while run_start < cols {
-                let cell = &row[run_start];
-                let is_cursor =
-                    cursor_row == Some(row_idx) && run_start == grid.cursor_col;
-                let is_selected = grid.is_selected(run_start, row_idx);
-
-                let (fg, bg) = cell_colors(cell, is_selected, is_cursor);
-
-                let decoration = if cell.underline {
-                    Some(FragmentStyleDecoration::Underline(UnderlineInfo {
-                        is_doubled: false,
-                        shape: UnderlineShape::Regular,
-                    }))
+                if nfg == fg
+                    && nbg == bg
+                    && next.bold == cell.bold
+                    && next.italic == cell.italic
+                    && cell_decoration(next) == decoration
+                {
+                    run_end += 1;
                 } else {
-                    None
-                };
-
-                let style = FragmentStyle {
-                    color: fg,
-                    background_color: bg,
-                    decoration,
-                    ..FragmentStyle::default()
-                };
-
-                // Batch consecutive characters with the same visual style
-                let mut run_end = run_start + 1;
-                while run_end < cols {
-                    let next = &row[run_end];
-                    let next_is_cursor =
-                        cursor_row == Some(row_idx) && run_end == grid.cursor_col;
-                    let next_is_selected = grid.is_selected(run_end, row_idx);
-                    let (nfg, nbg) = cell_colors(next, next_is_selected, next_is_cursor);
-
-                    if nfg == fg
-                        && nbg == bg
-                        && next.bold == cell.bold
-                        && next.italic == cell.italic
-                        && next.underline == cell.underline
-                    {
-                        run_end += 1;
+                    break;
+                }
+            }
+
+            // Sub-split by font_id so non-ASCII glyphs (Nerd Font icons,
+            // emoji, CJK) resolve to the correct fallback font
+            let mut sub_start = run_start;
+            while sub_start < run_end {
+                let cell = &line[sub_start];
+                let (font_id, _) = cell_font(cell, |ch| lookup(ch, &style));
+                let mut sub_style = style;
+                sub_style.font_id = font_id;
+
+                // A wide character or grapheme cluster (ZWJ sequence,
+                // skin tone, combining mark) is shaped alone and
+                // advances once by the cells it covers
+                let span = cell_span(line, sub_start);
+                if span == 2 || cell.combining.is_some() {
+                    let mut text = String::new();
+                    cell.push_text(&mut text);
+                    sub_style.width = span as f32;
+                    sink.add_text(&text, sub_style);
+                    sub_start += span;
+                    continue;
+                }
+
+                // Extend sub-run while consecutive single-cell chars
+                // share the same font
+                let mut sub_end = sub_start + 1;
+                while sub_end < run_end {
+                    let next = &line[sub_end];
+                    if cell_span(line, sub_end) == 2 || next.combining.is_some() {
+                        break;
+                    }
+                    let (next_font_id, _) = cell_font(next, |ch| lookup(ch, &style));
+                    if next_font_id == font_id {
+                        sub_end += 1;
                     } else {
                         break;
                     }
                 }
 
-                // Sub-split by font_id so non-ASCII glyphs (Nerd Font icons,
-                // emoji, CJK) resolve to the correct fallback font
-                let mut sub_start = run_start;
-                while sub_start < run_end {
-                    let ch = row[sub_start].c;
-                    let (font_id, is_emoji) = if ch.is_ascii() {
-                        (0, false)
-                    } else {
-                        font_lib
-                            .find_best_font_match(ch, &style)
-                            .unwrap_or((0, false))
-                    };
-
-                    // Extend sub-run while consecutive chars share the same font
-                    let mut sub_end = sub_start + 1;
-                    while sub_end < run_end {
-                        let next_ch = row[sub_end].c;
-                        let next_font_id = if next_ch.is_ascii() {
-                            0
-                        } else {
-                            font_lib
-                                .find_best_font_match(next_ch, &style)
-                                .map_or(0, |(id, _)| id)
-                        };
-                        if next_font_id == font_id {
-                            sub_end += 1;
-                        } else {
-                            break;
-                        }
-                    }
+                // A spacer left without its wide character shows as a
+                // blank, keeping later cells in their columns
+                let text: String = line[sub_start..sub_end].iter().map(|c| c.c).collect();
+                sink.add_text(&text, sub_style);
+                sub_start = sub_end;
+            }
 
-                    let text: String =
-                        row[sub_start..sub_end].iter().map(|c| c.c).collect();
+            run_start = run_end;
+        }
 
-                    let mut sub_style = style;
-                    sub_style.font_id = font_id;
-                    if is_emoji {
-                        sub_style.width = 2.0;
-                    }
+        sink.new_line();
+    }
+}
 
-                    content.add_text(&text, sub_style);
-                    sub_start = sub_end;
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::GridHarness;
+
+    #[test]
+    fn box_drawing_uses_the_primary_font() {
+        let fallback = |_| Some((3, false));
+        for ch in ['─', '│', '┌', '╬', '▀', '█', '░', '▟'] {
+            assert_eq!(font_for(ch, fallback), (0, false), "{ch}");
+        }
+        // Other non-ASCII characters still go to the fallback lookup
+        assert_eq!(font_for('漢', fallback), (3, false));
+        assert_eq!(font_for('漢', |_| None), (0, false));
+    }
 
-                run_start = run_end;
+    #[test]
+    fn font_matches_are_cached_per_cell() {
+        let mut grid = TerminalGrid::new(400, 1);
+        grid.feed("\u{1f600}".repeat(200).as_bytes());
+        let lookups = std::cell::Cell::new(0);
+        let lookup = |_| {
+            lookups.set(lookups.get() + 1);
+            Some((2, true))
+        };
+        // Each frame resolves every cell twice: to start a run and to
+        // extend one
+        let frame = |grid: &TerminalGrid| {
+            for cell in grid.cells[0].iter().filter(|cell| cell.c != ' ') {
+                assert_eq!(cell_font(cell, lookup), (2, true));
+                assert_eq!(cell_font(cell, lookup), (2, true));
             }
+        };
+
+        frame(&grid);
+        assert_eq!(lookups.get(), 200);
+        frame(&grid);
+        assert_eq!(lookups.get(), 200);
+
+        // Edited cells and a resize look their fonts up again
+        grid.feed(b"\x1b[1;1H\x1b[2P");
+        frame(&grid);
+        assert_eq!(lookups.get(), 200);
+        grid.feed("\x1b[1;1H\u{1f601}".as_bytes());
+        frame(&grid);
+        assert_eq!(lookups.get(), 201);
+        grid.resize(300, 1);
+        frame(&grid);
+        assert_eq!(lookups.get(), 201 + 150);
+    }
+
+    #[test]
+    fn wide_characters_span_their_spacer() {
+        let mut grid = TerminalGrid::new(10, 1);
+        grid.feed("a漢b".as_bytes());
+        let row = &grid.cells[0];
+        assert_eq!(
+            (0..4).map(|col| cell_span(row, col)).collect::<Vec<_>>(),
+            [1, 2, 1, 1]
+        );
+    }
 
-            content.new_line();
+    #[test]
+    fn decorations_map_to_distinct_sugarloaf_styles() {
+        let mut grid = TerminalGrid::new(10, 1);
+        grid.feed(b"a\x1b[4mb\x1b[21mc\x1b[4:3md\x1b[24;9me\x1b[29;53mf\x1b[4;9mg");
+        let row = &grid.cells[0];
+        let decorations: Vec<_> = row[..7].iter().map(cell_decoration).collect();
+        assert_eq!(
+            decorations,
+            [
+                None,
+                Some(FragmentStyleDecoration::Underline(UnderlineInfo {
+                    is_doubled: false,
+                    shape: UnderlineShape::Regular,
+                })),
+                Some(FragmentStyleDecoration::Underline(UnderlineInfo {
+                    is_doubled: true,
+                    shape: UnderlineShape::Regular,
+                })),
+                Some(FragmentStyleDecoration::Underline(UnderlineInfo {
+                    is_doubled: false,
+                    shape: UnderlineShape::Curly,
+                })),
+                Some(FragmentStyleDecoration::Strikethrough),
+                Some(FragmentStyleDecoration::Overline),
+                // Only one is drawn: the underline
+                decorations[1],
+            ]
+        );
+    }
+
+    #[test]
+    fn cursor_color_overrides_inversion() {
+        let cell = Cell {
+            c: 'x',
+            fg: [0.8, 0.0, 0.0, 1.0],
+            ..Cell::default()
+        };
+        let theme = Theme::default();
+
+        // Unset: the cursor inverts the cell
+        assert_eq!(
+            cell_colors(&cell, &theme, false, true, None),
+            (theme.background, Some(cell.fg))
+        );
+
+        // Set: the configured color, with text contrasting against it
+        let light = [1.0, 0.8, 0.2, 1.0];
+        assert_eq!(
+            cell_colors(&cell, &theme, false, true, Some(light)),
+            ([0.0, 0.0, 0.0, 1.0], Some(light))
+        );
+        let dark = [0.1, 0.1, 0.4, 1.0];
+        assert_eq!(
+            cell_colors(&cell, &theme, true, true, Some(dark)),
+            ([1.0, 1.0, 1.0, 1.0], Some(dark))
+        );
+
+        // Other cells are unaffected
+        assert_eq!(
+            cell_colors(&cell, &theme, false, false, Some(light)),
+            (cell.fg, None)
+        );
+    }
+
+    /// Runs a frame is drawn as, row by row
+    #[derive(Default)]
+    struct Frame {
+        rows: Vec<Vec<(String, FragmentStyle)>>,
+        row: Vec<(String, FragmentStyle)>,
+    }
+
+    impl RunSink for Frame {
+        fn add_text(&mut self, text: &str, style: FragmentStyle) {
+            self.row.push((text.to_string(), style));
+        }
+
+        fn new_line(&mut self) {
+            self.rows.push(std::mem::take(&mut self.row));
         }
     }
 
-    content.build();
+    /// Runs for the screen `script` leaves, with characters outside the BMP
+    /// matched to an emoji font (2) and other fallbacks to font 1
+    fn frame(
+        cols: usize,
+        rows: usize,
+        script: &[u8],
+        setup: impl FnOnce(&mut TerminalGrid),
+    ) -> Vec<Vec<(String, FragmentStyle)>> {
+        let mut harness = GridHarness::run(cols, rows, script);
+        setup(&mut harness.grid);
+        let mut frame = Frame::default();
+        let lookup = |ch, _: &_| {
+            Some(if ch > '\u{ffff}' {
+                (2, true)
+            } else {
+                (1, false)
+            })
+        };
+        emit_runs(&harness.grid, lookup, &mut frame);
+        frame.rows
+    }
+
+    fn run(text: &str, style: FragmentStyle) -> (String, FragmentStyle) {
+        (text.to_string(), style)
+    }
+
+    /// Default colors, and the same swapped as for the cursor
+    fn plain_and_inverted() -> (FragmentStyle, FragmentStyle) {
+        let theme = Theme::default();
+        let plain = FragmentStyle {
+            color: theme.foreground,
+            ..FragmentStyle::default()
+        };
+        let inverted = FragmentStyle {
+            color: theme.background,
+            background_color: Some(theme.foreground),
+            ..FragmentStyle::default()
+        };
+        (plain, inverted)
+    }
+
+    #[test]
+    fn inverse_cells_get_their_own_runs() {
+        let (plain, inverted) = plain_and_inverted();
+        assert_eq!(
+            frame(6, 1, b"ab\x1b[7mcd\x1b[27me", |_| {}),
+            [[
+                run("ab", plain),
+                run("cd", inverted),
+                run("e", plain),
+                // The cursor
+                run(" ", inverted),
+            ]]
+        );
+    }
+
+    #[test]
+    fn selection_splits_runs_across_rows() {
+        let (plain, inverted) = plain_and_inverted();
+        let select = |grid: &mut TerminalGrid| {
+            grid.selection_begin(1, 0);
+            grid.selection_update(2, 1);
+        };
+        let rows = frame(6, 2, b"h\x1b[7me\x1b[27mllo\r\nworld", select);
+        // Selecting inverse text swaps it back, over an explicit background
+        let selected_inverse = FragmentStyle {
+            background_color: Some(Theme::default().background),
+            ..plain
+        };
+        assert_eq!(
+            rows,
+            [
+                vec![
+                    run("h", plain),
+                    run("e", selected_inverse),
+                    run("llo ", inverted),
+                ],
+                vec![run("wor", inverted), run("ld", plain), run(" ", inverted)],
+            ]
+        );
+    }
+
+    #[test]
+    fn underline_styles_break_runs() {
+        let (plain, inverted) = plain_and_inverted();
+        let underlined = |is_doubled, shape| FragmentStyle {
+            decoration: Some(FragmentStyleDecoration::Underline(UnderlineInfo {
+                is_doubled,
+                shape,
+            })),
+            ..plain
+        };
+        assert_eq!(
+            frame(7, 1, b"\x1b[4mab\x1b[4:3mc\x1b[21md\x1b[24me", |_| {}),
+            [[
+                run("ab", underlined(false, UnderlineShape::Regular)),
+                run("c", underlined(false, UnderlineShape::Curly)),
+                run("d", underlined(true, UnderlineShape::Regular)),
+                run("e", plain),
+                run(" ", inverted),
+                run(" ", plain),
+            ]]
+        );
+    }
+
+    #[test]
+    fn fallback_fonts_split_runs() {
+        let (plain, inverted) = plain_and_inverted();
+        let font = |font_id, width| FragmentStyle {
+            font_id,
+            width,
+            ..plain
+        };
+        assert_eq!(
+            frame(11, 1, "a→→b─漢😀".as_bytes(), |_| {}),
+            [[
+                run("a", plain),
+                run("→→", font(1, 1.0)),
+                // Box drawing stays in the primary font
+                run("b─", plain),
+                // Wide characters are shaped alone across both cells
+                run("漢", font(1, 2.0)),
+                run("😀", font(2, 2.0)),
+                run(" ", inverted),
+                run(" ", plain),
+            ]]
+        );
+    }
 }
@@ -0,0 +1,146 @@
+use crate::grid::TerminalGrid;
+
+/// Key pressed while the frontend is in scroll mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NavKey {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Escape,
+    /// Printable character, as produced by the layout
+    Char(char),
+}
+
+/// What a key does in scroll mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScrollAction {
+    /// Move the viewport by lines. Positive = up (into history).
+    Lines(i32),
+    /// Move the viewport by screens. Positive = up.
+    Pages(i32),
+    /// Oldest scrollback line
+    Top,
+    /// Live output, staying in scroll mode
+    Bottom,
+    /// Prompt for text and search upwards with `search_backward`
+    Search,
+    /// Repeat the last search above the current match
+    SearchNext,
+    /// Leave scroll mode and snap back to live output
+    Exit,
+}
+
+/// Map a key to its scroll mode action. Keys with no action are swallowed
+/// rather than sent to the PTY.
+///
+/// | Key                    | Action            |
+/// |------------------------|-------------------|
+/// | Up, `k`                | Up one line       |
+/// | Down, `j`              | Down one line     |
+/// | PageUp, `b`            | Up one screen     |
+/// | PageDown, `f`, Space   | Down one screen   |
+/// | Home, `g`              | Top of history    |
+/// | End, `G`               | Bottom            |
+/// | `/`                    | Search upwards    |
+/// | `n`                    | Next match        |
+/// | Escape, `q`            | Exit scroll mode  |
+pub fn scroll_mode_action(key: NavKey) -> Option<ScrollAction> {
+    let action = match key {
+        NavKey::Up | NavKey::Char('k') => ScrollAction::Lines(1),
+        NavKey::Down | NavKey::Char('j') => ScrollAction::Lines(-1),
+        NavKey::PageUp | NavKey::Char('b') => ScrollAction::Pages(1),
+        NavKey::PageDown | NavKey::Char('f' | ' ') => ScrollAction::Pages(-1),
+        NavKey::Home | NavKey::Char('g') => ScrollAction::Top,
+        NavKey::End | NavKey::Char('G') => ScrollAction::Bottom,
+        NavKey::Char('/') => ScrollAction::Search,
+        NavKey::Char('n') => ScrollAction::SearchNext,
+        NavKey::Escape | NavKey::Char('q') => ScrollAction::Exit,
+        NavKey::Char(_) => return None,
+    };
+    Some(action)
+}
+
+impl ScrollAction {
+    /// Move the viewport of `grid` for the movement actions. Returns false
+    /// for search and exit, which the frontend handles.
+    pub fn apply(self, grid: &mut TerminalGrid) -> bool {
+        let history = grid.scrollback_len() as i32;
+        let delta = match self {
+            Self::Lines(lines) => lines,
+            Self::Pages(pages) => pages.saturating_mul(grid.rows as i32),
+            Self::Top => history,
+            Self::Bottom => -history,
+            Self::Search | Self::SearchNext | Self::Exit => return false,
+        };
+        grid.scroll_display(delta);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(grid: &mut TerminalGrid, key: NavKey) -> Option<ScrollAction> {
+        let action = scroll_mode_action(key)?;
+        action.apply(grid);
+        Some(action)
+    }
+
+    #[test]
+    fn keys_move_the_viewport() {
+        let mut grid = TerminalGrid::new(10, 3);
+        for i in 0..20 {
+            grid.feed(format!("line{i}\r\n").as_bytes());
+        }
+        let history = grid.scrollback_len();
+
+        press(&mut grid, NavKey::Char('k'));
+        press(&mut grid, NavKey::Up);
+        assert_eq!(grid.display_offset, 2);
+        press(&mut grid, NavKey::Down);
+        assert_eq!(grid.display_offset, 1);
+        press(&mut grid, NavKey::PageUp);
+        assert_eq!(grid.display_offset, 4);
+        press(&mut grid, NavKey::Home);
+        assert_eq!(grid.display_offset, history);
+        press(&mut grid, NavKey::Char('G'));
+        assert!(grid.viewport_at_bottom());
+
+        // Search and exit are left to the frontend; other text is swallowed
+        assert_eq!(
+            press(&mut grid, NavKey::Char('/')),
+            Some(ScrollAction::Search)
+        );
+        assert_eq!(press(&mut grid, NavKey::Escape), Some(ScrollAction::Exit));
+        assert_eq!(press(&mut grid, NavKey::Char('x')), None);
+    }
+
+    #[test]
+    fn search_walks_up_through_matches() {
+        let mut grid = TerminalGrid::new(20, 3);
+        for line in ["make: error 1", "ok", "ok", "error 2 error 3", "ok", "ok"] {
+            grid.feed(format!("{line}\r\n").as_bytes());
+        }
+
+        assert!(grid.search_backward("error"));
+        assert_eq!(grid.selected_text(), "error");
+        let range = grid.selection_range().unwrap();
+        assert_eq!(range.start.0, 8);
+        assert!((0..3).contains(&range.start.1), "match scrolled into view");
+
+        assert!(grid.search_backward("error"));
+        assert_eq!(grid.selection_range().unwrap().start.0, 0);
+
+        assert!(grid.search_backward("error"));
+        let range = grid.selection_range().unwrap();
+        assert_eq!((range.start.0, range.start.1), (6, 0));
+        assert_eq!(grid.display_offset, grid.scrollback_len());
+
+        assert!(!grid.search_backward("error"));
+        assert!(!grid.search_backward("missing"));
+    }
+}
@@ -0,0 +1,184 @@
+use std::borrow::Cow;
+
+use crate::grid::{Cell, FontCache, Underline};
+
+/// Visual attributes shared by a run of scrollback cells
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct CellStyle {
+    fg: [f32; 4],
+    bg: Option<[f32; 4]>,
+    bold: bool,
+    italic: bool,
+    underline: Underline,
+    strikethrough: bool,
+    overline: bool,
+    inverse: bool,
+    spacer: bool,
+}
+
+impl CellStyle {
+    fn of(cell: &Cell) -> Self {
+        Self {
+            fg: cell.fg,
+            bg: cell.bg,
+            bold: cell.bold,
+            italic: cell.italic,
+            underline: cell.underline,
+            strikethrough: cell.strikethrough,
+            overline: cell.overline,
+            inverse: cell.inverse,
+            spacer: cell.spacer,
+        }
+    }
+
+    fn cell(&self, c: char) -> Cell {
+        Cell {
+            c,
+            fg: self.fg,
+            bg: self.bg,
+            bold: self.bold,
+            italic: self.italic,
+            underline: self.underline,
+            strikethrough: self.strikethrough,
+            overline: self.overline,
+            inverse: self.inverse,
+            combining: None,
+            spacer: self.spacer,
+            hyperlink: None,
+            font: FontCache::default(),
+        }
+    }
+}
+
+/// Scrollback row stored as its text plus run-length encoded styles, with
+/// trailing default blanks dropped. Typical shell output is short lines in a
+/// handful of styles, so this is a small fraction of a full `Vec<Cell>`.
+#[derive(Clone, Debug)]
+pub(crate) struct CompactRow {
+    text: Box<str>,
+    /// (cell count, style) for consecutive cells sharing a style
+    runs: Box<[(u16, CellStyle)]>,
+    /// Column count of the row when it was saved
+    width: u16,
+}
+
+impl CompactRow {
+    pub(crate) fn compress(row: &[Cell]) -> Self {
+        let default = Cell::default();
+        let len = row
+            .iter()
+            .rposition(|cell| *cell != default)
+            .map_or(0, |idx| idx + 1);
+
+        let text: String = row[..len].iter().map(|cell| cell.c).collect();
+        let mut runs: Vec<(u16, CellStyle)> = Vec::new();
+        for cell in &row[..len] {
+            let style = CellStyle::of(cell);
+            match runs.last_mut() {
+                Some((count, last)) if *last == style && *count < u16::MAX => *count += 1,
+                _ => runs.push((1, style)),
+            }
+        }
+
+        Self {
+            text: text.into_boxed_str(),
+            runs: runs.into_boxed_slice(),
+            width: row.len().min(u16::MAX as usize) as u16,
+        }
+    }
+
+    pub(crate) fn expand(&self) -> Vec<Cell> {
+        let mut cells = Vec::with_capacity(self.width as usize);
+        let mut chars = self.text.chars();
+        for (count, style) in self.runs.iter() {
+            for _ in 0..*count {
+                cells.push(style.cell(chars.next().unwrap_or(' ')));
+            }
+        }
+        cells.resize(self.width as usize, Cell::default());
+        cells
+    }
+
+    fn heap_bytes(&self) -> usize {
+        self.text.len() + std::mem::size_of_val(&*self.runs)
+    }
+}
+
+/// Line of scrollback history, either as saved or compacted
+#[derive(Clone, Debug)]
+pub(crate) enum ScrollbackRow {
+    Full(Vec<Cell>),
+    Compact(CompactRow),
+}
+
+impl ScrollbackRow {
+    pub(crate) fn new(row: Vec<Cell>, compact: bool) -> Self {
+        // Compact rows hold one character per cell and no links, so the
+        // rare row with a grapheme cluster or hyperlink is kept whole
+        if compact
+            && row
+                .iter()
+                .all(|cell| cell.combining.is_none() && cell.hyperlink.is_none())
+        {
+            Self::Compact(CompactRow::compress(&row))
+        } else {
+            Self::Full(row)
+        }
+    }
+
+    pub(crate) fn cells(&self) -> Cow<'_, [Cell]> {
+        match self {
+            Self::Full(cells) => Cow::Borrowed(cells),
+            Self::Compact(row) => Cow::Owned(row.expand()),
+        }
+    }
+
+    /// Forget renderer font matches. Compact rows are expanded into fresh
+    /// cells each time, so hold none.
+    pub(crate) fn clear_font_cache(&mut self) {
+        if let Self::Full(cells) = self {
+            for cell in cells {
+                cell.font.clear();
+            }
+        }
+    }
+
+    /// Approximate memory held by this row, including the enum itself.
+    pub(crate) fn memory_bytes(&self) -> usize {
+        let heap = match self {
+            Self::Full(cells) => cells.capacity() * std::mem::size_of::<Cell>(),
+            Self::Compact(row) => row.heap_bytes(),
+        };
+        std::mem::size_of::<Self>() + heap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_row_round_trips_styled_cells() {
+        let mut row = vec![Cell::default(); 20];
+        for (i, c) in "hello wörld".chars().enumerate() {
+            row[i].c = c;
+        }
+        row[0].bold = true;
+        row[6].fg = [1.0, 0.0, 0.0, 1.0];
+        row[7].bg = Some([0.0, 0.0, 1.0, 1.0]);
+        // Styled blank past the text must survive trimming
+        row[15].inverse = true;
+
+        let compact = CompactRow::compress(&row);
+        assert_eq!(compact.expand(), row);
+        assert_eq!(&*compact.text, "hello wörld     ");
+    }
+
+    #[test]
+    fn blank_row_stores_no_cells() {
+        let row = vec![Cell::default(); 200];
+        let compact = CompactRow::compress(&row);
+        assert_eq!(compact.heap_bytes(), 0);
+        assert_eq!(compact.expand(), row);
+    }
+}
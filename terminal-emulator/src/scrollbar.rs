@@ -0,0 +1,169 @@
+#[cfg(feature = "render")]
+use sugarloaf::Quad;
+
+use crate::grid::TerminalGrid;
+
+/// Seconds the scrollbar stays after the viewport returns to the bottom
+const SCROLLBAR_LINGER: f32 = 1.0;
+
+/// Seconds the scrollbar takes to fade out after lingering
+const SCROLLBAR_FADE: f32 = 0.3;
+
+/// Scrollbar thumb position and size, as fractions of the track height
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thumb {
+    pub top: f32,
+    pub height: f32,
+}
+
+impl Thumb {
+    /// Thumb for `rows` visible rows over `history` lines of scrollback,
+    /// scrolled back `offset` lines. None when there is no history.
+    fn new(rows: usize, history: usize, offset: usize) -> Option<Self> {
+        if history == 0 {
+            return None;
+        }
+        let total = (history + rows) as f32;
+        let offset = offset.min(history);
+        Some(Self {
+            top: (history - offset) as f32 / total,
+            height: rows as f32 / total,
+        })
+    }
+
+    /// Thumb top and height in pixels on a `track` pixel track, keeping it
+    /// at least `min_height` tall.
+    pub fn pixels(&self, track: f32, min_height: f32) -> (f32, f32) {
+        let height = (self.height * track).max(min_height).min(track);
+        let free = 1.0 - self.height;
+        let top = if free > 0.0 {
+            self.top / free * (track - height)
+        } else {
+            0.0
+        };
+        (top, height)
+    }
+}
+
+/// Scrollbar thumb for the grid's viewport, or None without scrollback.
+/// Measured in logical lines so a long wrapped line counts once.
+pub fn scrollbar_thumb(grid: &TerminalGrid) -> Option<Thumb> {
+    Thumb::new(
+        grid.rows,
+        grid.logical_scrollback_len(),
+        grid.logical_display_offset(),
+    )
+}
+
+/// Quad drawing `thumb` at `opacity` along the right edge of the grid area
+/// `[x, y, width, height]`, sized for the display `scale`.
+#[cfg(feature = "render")]
+pub fn scrollbar_quad(thumb: Thumb, opacity: f32, area: [f32; 4], scale: f32) -> Quad {
+    let [x, y, width, height] = area;
+    let bar_width = 3.0 * scale;
+    let (top, thumb_height) = thumb.pixels(height, 24.0 * scale);
+    Quad {
+        color: [1.0, 1.0, 1.0, 0.4 * opacity],
+        position: [x + width - bar_width - scale, y + top],
+        size: [bar_width, thumb_height],
+        border_radius: [bar_width / 2.0; 4],
+        ..Quad::default()
+    }
+}
+
+/// Visibility of the scrollbar overlay: shown while scrolled back, then
+/// lingering and fading out once back at the bottom
+#[derive(Debug, Default)]
+pub struct Scrollbar {
+    /// Seconds until the scrollbar is hidden
+    remaining: f32,
+}
+
+impl Scrollbar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advance by `dt` seconds and return the opacity to draw the scrollbar
+    /// at, 0.0 once hidden.
+    pub fn update(&mut self, scrolled_back: bool, dt: f32) -> f32 {
+        if scrolled_back {
+            self.remaining = SCROLLBAR_LINGER + SCROLLBAR_FADE;
+        } else {
+            self.remaining = (self.remaining - dt).max(0.0);
+        }
+        (self.remaining / SCROLLBAR_FADE).min(1.0)
+    }
+
+    /// Whether the scrollbar is still on screen after returning to the
+    /// bottom, so frames must keep coming until it has faded.
+    pub fn fading(&self) -> bool {
+        self.remaining > 0.0 && self.remaining < SCROLLBAR_LINGER + SCROLLBAR_FADE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thumb_tracks_the_viewport() {
+        // 10 rows over 30 lines of history
+        let top = Thumb::new(10, 30, 30).unwrap();
+        assert_eq!(
+            top,
+            Thumb {
+                top: 0.0,
+                height: 0.25
+            }
+        );
+        let middle = Thumb::new(10, 30, 15).unwrap();
+        assert_eq!(middle.top, 0.375);
+        let bottom = Thumb::new(10, 30, 0).unwrap();
+        assert_eq!(bottom.top + bottom.height, 1.0);
+        assert_eq!(Thumb::new(10, 0, 0), None);
+
+        // Thumbs reach both ends of the track, even at the minimum height
+        assert_eq!(top.pixels(400.0, 0.0), (0.0, 100.0));
+        assert_eq!(bottom.pixels(400.0, 0.0), (300.0, 100.0));
+        let tiny = Thumb::new(10, 10_000, 0).unwrap();
+        assert_eq!(tiny.pixels(400.0, 20.0), (380.0, 20.0));
+    }
+
+    #[test]
+    fn thumb_counts_wrapped_lines_once() {
+        let mut grid = TerminalGrid::new(5, 2);
+        // Three lines of history, one of them wrapped over three rows
+        grid.feed(b"a\r\n0123456789ab\r\nb\r\nc\r\nd");
+        assert_eq!(grid.scrollback_len(), 5);
+
+        grid.scroll_display(i32::MAX);
+        let thumb = scrollbar_thumb(&grid).unwrap();
+        assert_eq!(
+            thumb,
+            Thumb {
+                top: 0.0,
+                height: 2.0 / 5.0
+            }
+        );
+
+        // Two physical rows up from the bottom is one logical line
+        grid.scroll_display(-3);
+        assert_eq!(scrollbar_thumb(&grid).unwrap().top, 2.0 / 5.0);
+    }
+
+    #[test]
+    fn scrollbar_lingers_then_fades_at_the_bottom() {
+        let mut bar = Scrollbar::new();
+        assert_eq!(bar.update(false, 0.016), 0.0);
+        assert_eq!(bar.update(true, 0.016), 1.0);
+        assert!(!bar.fading());
+
+        assert_eq!(bar.update(false, 0.9), 1.0);
+        assert!(bar.fading());
+        let opacity = bar.update(false, 0.25);
+        assert!(opacity > 0.0 && opacity < 1.0);
+        assert_eq!(bar.update(false, 1.0), 0.0);
+        assert!(!bar.fading());
+    }
+}
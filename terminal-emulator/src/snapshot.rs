@@ -0,0 +1,421 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::grid::{Cell, FontCache, TerminalGrid, Underline, MAX_GRID_DIMENSION};
+
+/// Version byte leading every snapshot frame. Bumped whenever the layout
+/// below changes; decoders reject frames of any other version.
+///
+/// Frame layout, integers little-endian:
+///
+/// | Field          | Size | Notes                                  |
+/// |----------------|------|----------------------------------------|
+/// | version        | u8   | `SNAPSHOT_VERSION`                     |
+/// | kind           | u8   | 0 = full screen, 1 = changes only      |
+/// | cols, rows     | u16  | screen size                            |
+/// | cursor col,row | u16  |                                        |
+/// | count          | u32  | number of cell records that follow     |
+///
+/// Each cell record is `col: u16, row: u16, c: u32, flags: u8, underline: u8,
+/// fg: [u8; 4]`, with the underline style numbered as in SGR `4:n`, then
+/// `bg: [u8; 4]` when `FLAG_BG` is set and `len: u16` plus that many UTF-8
+/// bytes when `FLAG_COMBINING` is set. Colors are sent as 8 bits per
+/// channel; hyperlinks are not sent.
+pub const SNAPSHOT_VERSION: u8 = 2;
+
+const KIND_FULL: u8 = 0;
+const KIND_DIFF: u8 = 1;
+
+const FLAG_BOLD: u8 = 1;
+const FLAG_ITALIC: u8 = 1 << 1;
+const FLAG_STRIKETHROUGH: u8 = 1 << 2;
+const FLAG_INVERSE: u8 = 1 << 3;
+const FLAG_SPACER: u8 = 1 << 4;
+const FLAG_BG: u8 = 1 << 5;
+const FLAG_COMBINING: u8 = 1 << 6;
+const FLAG_OVERLINE: u8 = 1 << 7;
+
+/// Error decoding a snapshot frame
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    /// Frame written with a different `SNAPSHOT_VERSION`
+    UnsupportedVersion(u8),
+    /// Frame ended in the middle of a field
+    Truncated,
+    /// Unknown frame kind, a screen size out of range, or a cell record that
+    /// cannot be applied
+    Malformed(&'static str),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {version}")
+            }
+            Self::Truncated => write!(f, "snapshot frame is truncated"),
+            Self::Malformed(reason) => write!(f, "malformed snapshot frame: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// Screen state last sent to a client, to diff the next frame against
+#[derive(Debug)]
+struct Sent {
+    cells: VecDeque<Vec<Cell>>,
+    cursor: (usize, usize),
+}
+
+/// Encodes the live screen of a grid as snapshot frames for a client that
+/// does not parse ANSI itself. Keep one encoder per client: each frame is
+/// relative to what that encoder sent last.
+#[derive(Debug, Default)]
+pub struct SnapshotEncoder {
+    last: Option<Sent>,
+}
+
+impl SnapshotEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Frame holding every cell, for a client's initial sync.
+    pub fn full_snapshot(&mut self, grid: &TerminalGrid) -> Vec<u8> {
+        let frame = encode(grid, KIND_FULL, |_, _| true);
+        self.remember(grid);
+        frame
+    }
+
+    /// Frame holding only the cells changed since the last frame, or the
+    /// full screen when nothing was sent yet or the grid was resized.
+    /// Returns None when neither the cells nor the cursor changed.
+    pub fn diff(&mut self, grid: &TerminalGrid) -> Option<Vec<u8>> {
+        let Some(last) = &self.last else {
+            return Some(self.full_snapshot(grid));
+        };
+        if last.cells.len() != grid.rows
+            || last.cells.front().map_or(0, Vec::len) != grid.cols
+        {
+            return Some(self.full_snapshot(grid));
+        }
+        if last.cells == grid.cells && last.cursor == cursor(grid) {
+            return None;
+        }
+        let frame = encode(grid, KIND_DIFF, |row, col| {
+            last.cells[row][col] != grid.cells[row][col]
+        });
+        self.remember(grid);
+        Some(frame)
+    }
+
+    fn remember(&mut self, grid: &TerminalGrid) {
+        self.last = Some(Sent {
+            cells: grid.cells.clone(),
+            cursor: cursor(grid),
+        });
+    }
+}
+
+fn cursor(grid: &TerminalGrid) -> (usize, usize) {
+    (grid.cursor_col, grid.cursor_row)
+}
+
+/// Write a frame of `kind` with the cells of `grid` accepted by `include`.
+fn encode(
+    grid: &TerminalGrid,
+    kind: u8,
+    include: impl Fn(usize, usize) -> bool,
+) -> Vec<u8> {
+    let mut records = Vec::new();
+    let mut count: u32 = 0;
+    for (row, cells) in grid.cells.iter().enumerate() {
+        for (col, cell) in cells.iter().enumerate() {
+            if include(row, col) {
+                encode_cell(&mut records, col, row, cell);
+                count += 1;
+            }
+        }
+    }
+
+    let mut frame = vec![SNAPSHOT_VERSION, kind];
+    for value in [grid.cols, grid.rows, grid.cursor_col, grid.cursor_row] {
+        frame.extend_from_slice(&(value as u16).to_le_bytes());
+    }
+    frame.extend_from_slice(&count.to_le_bytes());
+    frame.extend_from_slice(&records);
+    frame
+}
+
+fn encode_cell(out: &mut Vec<u8>, col: usize, row: usize, cell: &Cell) {
+    out.extend_from_slice(&(col as u16).to_le_bytes());
+    out.extend_from_slice(&(row as u16).to_le_bytes());
+    out.extend_from_slice(&u32::from(cell.c).to_le_bytes());
+
+    let mut flags = 0;
+    for (set, flag) in [
+        (cell.bold, FLAG_BOLD),
+        (cell.italic, FLAG_ITALIC),
+        (cell.strikethrough, FLAG_STRIKETHROUGH),
+        (cell.inverse, FLAG_INVERSE),
+        (cell.spacer, FLAG_SPACER),
+        (cell.bg.is_some(), FLAG_BG),
+        (cell.combining.is_some(), FLAG_COMBINING),
+        (cell.overline, FLAG_OVERLINE),
+    ] {
+        if set {
+            flags |= flag;
+        }
+    }
+    out.push(flags);
+    out.push(cell.underline as u8);
+    out.extend_from_slice(&pack_color(cell.fg));
+    if let Some(bg) = cell.bg {
+        out.extend_from_slice(&pack_color(bg));
+    }
+    if let Some(combining) = &cell.combining {
+        // Clusters are a handful of code points; anything absurdly long is
+        // cut at a character boundary
+        let mut len = combining.len().min(u16::MAX as usize);
+        while !combining.is_char_boundary(len) {
+            len -= 1;
+        }
+        out.extend_from_slice(&(len as u16).to_le_bytes());
+        out.extend_from_slice(&combining.as_bytes()[..len]);
+    }
+}
+
+fn pack_color(color: [f32; 4]) -> [u8; 4] {
+    color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+fn unpack_color(color: [u8; 4]) -> [f32; 4] {
+    color.map(|c| f32::from(c) / 255.0)
+}
+
+/// Screen mirrored on a thin client from snapshot frames
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RemoteScreen {
+    pub cols: usize,
+    pub rows: usize,
+    pub cells: Vec<Vec<Cell>>,
+    pub cursor_col: usize,
+    pub cursor_row: usize,
+}
+
+impl RemoteScreen {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply a frame from `SnapshotEncoder`. A full frame replaces the
+    /// screen; a diff frame updates the listed cells. On error the screen
+    /// is left as it was.
+    pub fn apply(&mut self, frame: &[u8]) -> Result<(), SnapshotError> {
+        let mut reader = Reader(frame);
+        let version = reader.u8()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let kind = reader.u8()?;
+        let cols = reader.u16()? as usize;
+        let rows = reader.u16()? as usize;
+        let cursor_col = reader.u16()? as usize;
+        let cursor_row = reader.u16()? as usize;
+        let count = reader.u32()?;
+        // Checked before allocating, so a corrupt size can't exhaust memory
+        if !(1..=MAX_GRID_DIMENSION).contains(&cols)
+            || !(1..=MAX_GRID_DIMENSION).contains(&rows)
+        {
+            return Err(SnapshotError::Malformed("screen size out of range"));
+        }
+
+        let mut cells = match kind {
+            KIND_FULL => vec![vec![Cell::default(); cols]; rows],
+            KIND_DIFF if (cols, rows) == (self.cols, self.rows) => self.cells.clone(),
+            KIND_DIFF => return Err(SnapshotError::Malformed("diff for another size")),
+            _ => return Err(SnapshotError::Malformed("unknown frame kind")),
+        };
+        for _ in 0..count {
+            let (col, row, cell) = decode_cell(&mut reader)?;
+            let slot = cells
+                .get_mut(row)
+                .and_then(|cells| cells.get_mut(col))
+                .ok_or(SnapshotError::Malformed("cell outside the screen"))?;
+            *slot = cell;
+        }
+
+        *self = Self {
+            cols,
+            rows,
+            cells,
+            cursor_col,
+            cursor_row,
+        };
+        Ok(())
+    }
+}
+
+fn decode_cell(reader: &mut Reader) -> Result<(usize, usize, Cell), SnapshotError> {
+    let col = reader.u16()? as usize;
+    let row = reader.u16()? as usize;
+    let c = char::from_u32(reader.u32()?)
+        .ok_or(SnapshotError::Malformed("invalid character"))?;
+    let flags = reader.u8()?;
+    let underline = match reader.u8()? {
+        style @ 0..=5 => Underline::from_sgr(style.into()),
+        _ => return Err(SnapshotError::Malformed("unknown underline style")),
+    };
+    let fg = unpack_color(reader.array()?);
+    let bg = if flags & FLAG_BG != 0 {
+        Some(unpack_color(reader.array()?))
+    } else {
+        None
+    };
+    let combining = if flags & FLAG_COMBINING != 0 {
+        let len = reader.u16()? as usize;
+        let text = std::str::from_utf8(reader.take(len)?)
+            .map_err(|_| SnapshotError::Malformed("invalid combining text"))?;
+        Some(text.into())
+    } else {
+        None
+    };
+
+    let cell = Cell {
+        c,
+        fg,
+        bg,
+        bold: flags & FLAG_BOLD != 0,
+        italic: flags & FLAG_ITALIC != 0,
+        underline,
+        strikethrough: flags & FLAG_STRIKETHROUGH != 0,
+        overline: flags & FLAG_OVERLINE != 0,
+        inverse: flags & FLAG_INVERSE != 0,
+        combining,
+        spacer: flags & FLAG_SPACER != 0,
+        hyperlink: None,
+        font: FontCache::default(),
+    };
+    Ok((col, row, cell))
+}
+
+/// Cursor over the bytes of a frame
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.0.len() < len {
+            return Err(SnapshotError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        self.array().map(u32::from_le_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Grid cells as a client sees them, with colors at 8 bits per channel
+    fn quantized(grid: &TerminalGrid) -> Vec<Vec<Cell>> {
+        let mut cells: Vec<_> = grid.cells.iter().cloned().collect();
+        for cell in cells.iter_mut().flatten() {
+            cell.fg = unpack_color(pack_color(cell.fg));
+            cell.bg = cell.bg.map(|bg| unpack_color(pack_color(bg)));
+        }
+        cells
+    }
+
+    #[test]
+    fn diffs_round_trip_edits() {
+        let mut grid = TerminalGrid::new(12, 3);
+        let mut encoder = SnapshotEncoder::new();
+        let mut screen = RemoteScreen::new();
+
+        grid.feed(b"$ ls\r\n");
+        screen.apply(&encoder.full_snapshot(&grid)).unwrap();
+        assert_eq!(screen.cells, quantized(&grid));
+
+        // Styled and decorated text, a wide character and a combining mark
+        let edits: [&[u8]; 3] = [
+            b"\x1b[1;31mred\x1b[0m \x1b[44mbg\x1b[0m \x1b[4:3;9;53mz\x1b[m",
+            "\r\n界e\u{301}".as_bytes(),
+            b"\x1b[1;3Hx\x1b[2;1H",
+        ];
+        for edit in edits {
+            grid.feed(edit);
+            let frame = encoder.diff(&grid).unwrap();
+            assert_eq!(frame[1], KIND_DIFF);
+            screen.apply(&frame).unwrap();
+            assert_eq!(screen.cells, quantized(&grid));
+            assert_eq!(
+                (screen.cursor_col, screen.cursor_row),
+                (grid.cursor_col, grid.cursor_row)
+            );
+        }
+
+        // Only changed cells are sent
+        grid.feed(b"y");
+        let frame = encoder.diff(&grid).unwrap();
+        assert_eq!(u32::from_le_bytes(frame[10..14].try_into().unwrap()), 1);
+        screen.apply(&frame).unwrap();
+        assert_eq!(encoder.diff(&grid), None);
+
+        // A resize sends the whole screen again
+        grid.resize(8, 2);
+        let frame = encoder.diff(&grid).unwrap();
+        assert_eq!(frame[1], KIND_FULL);
+        screen.apply(&frame).unwrap();
+        assert_eq!(screen.cells, quantized(&grid));
+    }
+
+    #[test]
+    fn bad_frames_are_rejected() {
+        let grid = TerminalGrid::new(4, 2);
+        let frame = SnapshotEncoder::new().full_snapshot(&grid);
+        let mut screen = RemoteScreen::new();
+
+        assert_eq!(
+            screen.apply(&frame[..frame.len() - 1]),
+            Err(SnapshotError::Truncated)
+        );
+        let mut future = frame.clone();
+        future[0] = SNAPSHOT_VERSION + 1;
+        assert_eq!(
+            screen.apply(&future),
+            Err(SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION + 1))
+        );
+        // A header claiming a huge (or empty) screen
+        for size in [u16::MAX, 0] {
+            let mut header = frame[..2].to_vec();
+            header.extend_from_slice(&size.to_le_bytes());
+            header.extend_from_slice(&2u16.to_le_bytes());
+            header.extend_from_slice(&[0; 8]);
+            assert_eq!(
+                screen.apply(&header),
+                Err(SnapshotError::Malformed("screen size out of range"))
+            );
+        }
+        assert_eq!(screen, RemoteScreen::new());
+        screen.apply(&frame).unwrap();
+        assert_eq!(screen.cols, 4);
+    }
+}
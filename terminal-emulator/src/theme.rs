@@ -0,0 +1,112 @@
+use crate::grid::{ansi_color, parse_color, Cell};
+
+/// Colors the grid is drawn with. Cells record the colors programs chose
+/// from the built-in palette, and a theme swaps them at render time, so
+/// changing it recolors output already on screen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    /// Text without an explicit color
+    pub foreground: [f32; 4],
+    /// Cells without an explicit background
+    pub background: [f32; 4],
+    /// The 16 ANSI colors
+    pub palette: [[f32; 4]; 16],
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            foreground: Cell::default().fg,
+            background: [0.05, 0.05, 0.1, 1.0],
+            palette: std::array::from_fn(|idx| ansi_color(idx as u16)),
+        }
+    }
+}
+
+impl Theme {
+    /// Theme from X11 color specs (see `parse_color`), keeping the default
+    /// for any color that is missing or invalid. Palette entries past the
+    /// 16th are ignored. Also returns the specs that were rejected.
+    pub fn parse(
+        foreground: Option<&str>,
+        background: Option<&str>,
+        palette: &[String],
+    ) -> (Self, Vec<String>) {
+        let mut theme = Self::default();
+        let mut rejected = Vec::new();
+        let mut apply = |slot: &mut [f32; 4], spec: &str| match parse_color(spec) {
+            Some(color) => *slot = color,
+            None => rejected.push(spec.to_string()),
+        };
+        if let Some(spec) = foreground {
+            apply(&mut theme.foreground, spec);
+        }
+        if let Some(spec) = background {
+            apply(&mut theme.background, spec);
+        }
+        for (slot, spec) in theme.palette.iter_mut().zip(palette) {
+            apply(slot, spec);
+        }
+        (theme, rejected)
+    }
+
+    /// Color to draw a cell's text in. The default foreground is also
+    /// bright white, which therefore follows `foreground`.
+    pub fn fg(&self, color: [f32; 4]) -> [f32; 4] {
+        if color == Cell::default().fg {
+            self.foreground
+        } else {
+            self.palette_color(color)
+        }
+    }
+
+    /// Color to draw a cell's explicit background in
+    pub fn bg(&self, color: [f32; 4]) -> [f32; 4] {
+        self.palette_color(color)
+    }
+
+    /// `color` from this palette when it is one of the built-in 16
+    fn palette_color(&self, color: [f32; 4]) -> [f32; 4] {
+        match (0..16).find(|&idx| ansi_color(idx) == color) {
+            Some(idx) => self.palette[idx as usize],
+            None => color,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_colors_keep_the_defaults() {
+        let palette = ["#282828", "nope", "rgb:cc/24/1d"].map(String::from);
+        let (theme, rejected) = Theme::parse(Some("#ebdbb2"), Some("red"), &palette);
+        assert_eq!(rejected, ["red", "nope"]);
+
+        let default = Theme::default();
+        assert_eq!(theme.background, default.background);
+        assert_ne!(theme.foreground, default.foreground);
+        assert_eq!(theme.palette[0], parse_color("#282828").unwrap());
+        assert_eq!(theme.palette[1], default.palette[1]);
+        assert_eq!(theme.palette[2], parse_color("#cc241d").unwrap());
+        assert_eq!(theme.palette[3..], default.palette[3..]);
+    }
+
+    #[test]
+    fn palette_colors_are_swapped() {
+        let mut theme = Theme {
+            foreground: [0.9, 0.9, 0.8, 1.0],
+            ..Theme::default()
+        };
+        theme.palette[1] = [0.8, 0.1, 0.1, 1.0];
+
+        assert_eq!(theme.fg(Cell::default().fg), theme.foreground);
+        assert_eq!(theme.fg(ansi_color(1)), theme.palette[1]);
+        assert_eq!(theme.bg(ansi_color(1)), theme.palette[1]);
+        // Bright white backgrounds follow the palette, not the foreground
+        assert_eq!(theme.bg(ansi_color(15)), ansi_color(15));
+        assert_eq!(theme.fg(ansi_color(208)), ansi_color(208));
+        assert_eq!(theme.fg([0.1, 0.2, 0.3, 1.0]), [0.1, 0.2, 0.3, 1.0]);
+    }
+}
@@ -0,0 +1,87 @@
+/// Lines a wheel notch scrolls unless configured otherwise
+pub const DEFAULT_LINES_PER_NOTCH: f32 = 3.0;
+
+/// Range `lines_per_notch` is clamped to
+const LINES_PER_NOTCH_RANGE: (f32, f32) = (0.5, 20.0);
+
+/// How far and which way wheel and swipe scrolling moves
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScrollConfig {
+    lines_per_notch: f32,
+    inverted: bool,
+}
+
+impl Default for ScrollConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl ScrollConfig {
+    pub const DEFAULT: Self = Self {
+        lines_per_notch: DEFAULT_LINES_PER_NOTCH,
+        inverted: false,
+    };
+
+    /// Config scrolling `lines_per_notch` lines per wheel notch, clamped to
+    /// a usable range, reversed for natural scrolling when `inverted`.
+    pub fn new(lines_per_notch: f32, inverted: bool) -> Self {
+        let (min, max) = LINES_PER_NOTCH_RANGE;
+        let lines_per_notch = if lines_per_notch.is_nan() {
+            DEFAULT_LINES_PER_NOTCH
+        } else {
+            lines_per_notch.clamp(min, max)
+        };
+        Self {
+            lines_per_notch,
+            inverted,
+        }
+    }
+
+    pub fn lines_per_notch(&self) -> f32 {
+        self.lines_per_notch
+    }
+
+    pub fn inverted(&self) -> bool {
+        self.inverted
+    }
+
+    /// Whole lines to scroll for `notches` of wheel travel (positive = up,
+    /// into history). Fractions of a line are kept in `carry` for the next
+    /// call, so slow scrolling still moves.
+    pub fn lines(&self, notches: f32, carry: &mut f32) -> i32 {
+        let direction = if self.inverted { -1.0 } else { 1.0 };
+        *carry += notches * self.lines_per_notch * direction;
+        let lines = carry.trunc();
+        *carry -= lines;
+        lines as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_scale_with_sensitivity() {
+        let mut carry = 0.0;
+        assert_eq!(ScrollConfig::DEFAULT.lines(1.0, &mut carry), 3);
+        assert_eq!(ScrollConfig::DEFAULT.lines(-2.0, &mut carry), -6);
+        assert_eq!(ScrollConfig::new(7.0, false).lines(1.0, &mut carry), 7);
+        assert_eq!(ScrollConfig::new(3.0, true).lines(1.0, &mut carry), -3);
+
+        // Out of range settings are clamped
+        assert_eq!(ScrollConfig::new(1000.0, false).lines_per_notch(), 20.0);
+        assert_eq!(ScrollConfig::new(0.0, false).lines_per_notch(), 0.5);
+        assert_eq!(ScrollConfig::new(f32::NAN, false), ScrollConfig::DEFAULT);
+    }
+
+    #[test]
+    fn fractions_carry_over() {
+        let slow = ScrollConfig::new(0.5, false);
+        let mut carry = 0.0;
+        let moved: Vec<i32> = (0..4).map(|_| slow.lines(1.0, &mut carry)).collect();
+        assert_eq!(moved, [0, 1, 0, 1]);
+        assert_eq!(carry, 0.0);
+    }
+}